@@ -0,0 +1,528 @@
+//! Execution of destructive actions on duplicate groups.
+//!
+//! Removing instances is never a blind loop of unlink calls. The instance being kept is
+//! verified before anything is touched and again after every batch of removals, and each
+//! instance is re-hashed right before it is removed. The first anomaly stops all further
+//! work on the group so a group is either handled completely or left in a known state.
+
+use crate::copies::CopyPatterns;
+use crate::fileinfo::{hash_file_with, FileMetadata, Fileinfo, HashMode};
+use crate::hasher::{ContentHasher, SipHash128};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Names tried for the temporary link made next to a file being replaced.
+const MAX_TEMPORARY_NAMES: u32 = 100;
+
+/// Reasons for abandoning the remaining work on a group.
+#[derive(Debug)]
+pub enum Anomaly {
+    /// The group has no full hash to verify instances against.
+    Unverified,
+    /// The instance to keep is not part of the group.
+    KeepNotInGroup(PathBuf),
+    /// The instance to keep could not be found or read.
+    KeepMissing(PathBuf),
+    /// The instance to keep no longer matches the group hash.
+    KeepChanged(PathBuf),
+    /// An instance slated for removal resolves to the instance being kept.
+    KeepAliased(PathBuf),
+    /// An instance slated for removal no longer matches the group hash.
+    InstanceChanged(PathBuf),
+    /// An instance slated for hard linking is on another device than the instance being kept.
+    CrossesDevices(PathBuf),
+    /// An instance slated for removal is in a directory of the OS.
+    SystemPath(PathBuf),
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::Unverified => write!(f, "group has no full hash to verify against"),
+            Anomaly::KeepNotInGroup(p) => {
+                write!(f, "{} is not an instance of the group", p.display())
+            }
+            Anomaly::KeepMissing(p) => write!(f, "kept instance {} is missing", p.display()),
+            Anomaly::KeepChanged(p) => write!(f, "kept instance {} has changed", p.display()),
+            Anomaly::KeepAliased(p) => {
+                write!(f, "{} is the kept instance under another name", p.display())
+            }
+            Anomaly::InstanceChanged(p) => {
+                write!(f, "{} has changed since it was hashed", p.display())
+            }
+            Anomaly::CrossesDevices(p) => {
+                write!(
+                    f,
+                    "{} is on another device than the kept instance",
+                    p.display()
+                )
+            }
+            Anomaly::SystemPath(p) => write!(f, "{} is in a system directory", p.display()),
+        }
+    }
+}
+
+/// Rule for choosing the instance of a group which survives a removal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepPolicy {
+    /// Keep the most recently modified instance.
+    Newest,
+    /// Keep the least recently modified instance.
+    Oldest,
+    /// Keep an instance from the earliest search directory given.
+    FirstArgDir,
+    /// Keep the instance with the shortest path.
+    ShortestPath,
+}
+
+impl KeepPolicy {
+    /// Picks the instance of `group` to keep. Ties go to instances whose names `copies`
+    /// doesn't take for copies, then by path order. `search_dirs` are the canonical search
+    /// directories in the order they were given. Instances whose metadata can't be read are
+    /// never picked by the `Newest` and `Oldest` policies.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::actions::KeepPolicy;
+    /// use ddh::copies::CopyPatterns;
+    /// use ddh::fileinfo::Fileinfo;
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// let group = ddh::consolidate(vec![
+    ///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/backup/2019/photos/a.jpg")),
+    ///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/home/photos/a.jpg")),
+    /// ]).remove(0);
+    /// let roots = [PathBuf::from("/backup"), PathBuf::from("/home")];
+    /// let copies = CopyPatterns::default();
+    /// assert_eq!(
+    ///     KeepPolicy::ShortestPath.choose(&group, &roots, &copies),
+    ///     Some(Path::new("/home/photos/a.jpg"))
+    /// );
+    /// assert_eq!(
+    ///     KeepPolicy::FirstArgDir.choose(&group, &roots, &copies),
+    ///     Some(Path::new("/backup/2019/photos/a.jpg"))
+    /// );
+    /// ```
+    pub fn choose<'a>(
+        &self,
+        group: &'a Fileinfo,
+        search_dirs: &[PathBuf],
+        copies: &CopyPatterns,
+    ) -> Option<&'a Path> {
+        let paths = group.get_paths().iter().map(|p| &**p);
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        let tie_break = |path: &'a Path| (copies.is_copy(path), path);
+        match self {
+            KeepPolicy::Newest => paths
+                .filter_map(|path| Some((std::cmp::Reverse(modified(path)?), tie_break(path))))
+                .min()
+                .map(|(_, (_, path))| path),
+            KeepPolicy::Oldest => paths
+                .filter_map(|path| Some((modified(path)?, tie_break(path))))
+                .min()
+                .map(|(_, (_, path))| path),
+            KeepPolicy::FirstArgDir => paths.min_by_key(|path| {
+                let root = search_dirs.iter().position(|dir| path.starts_with(dir));
+                (root.unwrap_or(usize::MAX), tie_break(path))
+            }),
+            KeepPolicy::ShortestPath => {
+                paths.min_by_key(|path| (path.as_os_str().len(), tie_break(path)))
+            }
+        }
+    }
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "newest" => Ok(KeepPolicy::Newest),
+            "oldest" => Ok(KeepPolicy::Oldest),
+            "first-arg-dir" => Ok(KeepPolicy::FirstArgDir),
+            "shortest-path" => Ok(KeepPolicy::ShortestPath),
+            _ => Err(format!(
+                "unknown keep policy {} (expected newest, oldest, first-arg-dir or shortest-path)",
+                s
+            )),
+        }
+    }
+}
+
+/// Reads the names accepted by `FromStr`, as written in policy files.
+impl<'de> Deserialize<'de> for KeepPolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for KeepPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KeepPolicy::Newest => "newest",
+            KeepPolicy::Oldest => "oldest",
+            KeepPolicy::FirstArgDir => "first-arg-dir",
+            KeepPolicy::ShortestPath => "shortest-path",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Outcome of running an action on a single group.
+#[derive(Debug)]
+pub struct GroupOutcome {
+    /// Instance that was kept.
+    pub kept: PathBuf,
+    /// Instances that were removed or replaced (or would have been, in a dry run).
+    pub removed: Vec<PathBuf>,
+    /// Instances whose removal or replacement failed.
+    pub failed: Vec<(PathBuf, io::Error)>,
+    /// Instances left untouched because the group was stopped.
+    pub skipped: Vec<PathBuf>,
+    /// The anomaly which stopped the group, if any.
+    pub anomaly: Option<Anomaly>,
+    /// True if the group was left alone because removing it would reclaim too little space.
+    pub below_min_savings: bool,
+}
+
+impl GroupOutcome {
+    fn new(kept: &Path) -> Self {
+        GroupOutcome {
+            kept: kept.to_path_buf(),
+            removed: Vec::new(),
+            failed: Vec::new(),
+            skipped: Vec::new(),
+            anomaly: None,
+            below_min_savings: false,
+        }
+    }
+}
+
+/// Change made to each instance which isn't kept.
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Remove,
+    Reflink,
+    HardLink,
+}
+
+/// Applies removals to duplicate groups with verification between batches.
+///
+/// The kept file and every instance are hashed again from disk before anything is changed, so
+/// hashes taken from a cache or a saved state are never acted on without being checked.
+#[derive(Debug, Clone)]
+pub struct Executor {
+    batch_size: usize,
+    dry_run: bool,
+    min_savings: u64,
+    hasher: Option<Arc<dyn ContentHasher>>,
+    system_guard: bool,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Executor::new(16, true)
+    }
+}
+
+impl Executor {
+    /// Creates an executor which removes at most `batch_size` instances between checks of the
+    /// kept instance. A dry run performs every check but leaves the filesystem untouched.
+    pub fn new(batch_size: usize, dry_run: bool) -> Self {
+        Executor {
+            batch_size: batch_size.max(1),
+            dry_run,
+            min_savings: 0,
+            hasher: None,
+            system_guard: false,
+        }
+    }
+    /// Leaves groups alone unless acting on them reclaims at least `bytes`.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::actions::Executor;
+    /// use ddh::fileinfo::Fileinfo;
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// let group = ddh::consolidate(vec![
+    ///     Fileinfo::from_existing_hash(1, None, 2048, PathBuf::from("/a/.gitkeep")),
+    ///     Fileinfo::from_existing_hash(1, None, 2048, PathBuf::from("/b/.gitkeep")),
+    /// ]).remove(0);
+    /// let executor = Executor::new(16, true).with_min_savings(1 << 20);
+    /// assert!(!executor.worthwhile(&group));
+    /// let outcome = executor.delete(&group, Path::new("/a/.gitkeep"));
+    /// assert!(outcome.below_min_savings && outcome.removed.is_empty());
+    /// ```
+    pub fn with_min_savings(mut self, bytes: u64) -> Self {
+        self.min_savings = bytes;
+        self
+    }
+    /// Verifies instances with `hasher`, which must be the one the groups were hashed with,
+    /// instead of the default SipHash.
+    pub fn with_hasher(mut self, hasher: Arc<dyn ContentHasher>) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+    /// Stops groups with an instance to change in one of the `SYSTEM_DIRS` of
+    /// `crate::privileges` before anything is touched, when `on` is set.
+    pub fn with_system_guard(mut self, on: bool) -> Self {
+        self.system_guard = on;
+        self
+    }
+    /// Returns true if acting on `group` reclaims at least the minimum savings.
+    pub fn worthwhile(&self, group: &Fileinfo) -> bool {
+        crate::waste::duplicate_bytes(std::iter::once(group)) >= self.min_savings
+    }
+    /// Returns true if this executor does not modify the filesystem.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+    /// Removes every instance of `group` except `keep`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use ddh::actions::Executor;
+    ///
+    /// let (files, _errors) = ddh::deduplicate_dirs(vec!["/home/jon"], vec![], 0);
+    /// for group in files.iter().filter(|f| f.get_paths().len() > 1) {
+    ///     let keep = group.get_paths()[0].clone();
+    ///     let outcome = Executor::new(16, false).delete(group, &keep);
+    ///     if let Some(anomaly) = outcome.anomaly {
+    ///         println!("Stopped: {}", anomaly);
+    ///     }
+    /// }
+    /// ```
+    pub fn delete(&self, group: &Fileinfo, keep: &Path) -> GroupOutcome {
+        self.apply(group, keep, &[], Action::Remove)
+    }
+    /// Removes every instance of `group` except `keep` and the instances in `spared`, with the
+    /// same checks as `delete`. Only `keep` is verified, so it should be one of the instances a
+    /// user chose to keep.
+    pub fn delete_sparing(
+        &self,
+        group: &Fileinfo,
+        keep: &Path,
+        spared: &[PathBuf],
+    ) -> GroupOutcome {
+        self.apply(group, keep, spared, Action::Remove)
+    }
+    /// Replaces every instance of `group` except `keep` with a copy-on-write clone of `keep`,
+    /// with the same checks as `delete`. Instances on filesystems without clone support end
+    /// up in `GroupOutcome::failed` and are left as they were.
+    pub fn reflink(&self, group: &Fileinfo, keep: &Path) -> GroupOutcome {
+        self.apply(group, keep, &[], Action::Reflink)
+    }
+    /// Replaces every instance of `group` except `keep` with a hard link to `keep`, with the
+    /// same checks as `delete`. The instances then share their contents and metadata. As hard
+    /// links can't cross devices, a group with instances on another device than `keep` is
+    /// stopped before anything is touched.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::actions::Executor;
+    ///
+    /// let dir = std::env::temp_dir().join("ddh_hard_link_example");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("a"), "same").unwrap();
+    /// std::fs::write(dir.join("b"), "same").unwrap();
+    /// let (files, _) = ddh::deduplicate_dirs(vec![&dir], vec![], 0);
+    /// // A file which happens to have the name of a temporary link is left alone
+    /// let unrelated = dir.join(format!(".b.ddh-link.{}.0", std::process::id()));
+    /// std::fs::write(&unrelated, "mine").unwrap();
+    ///
+    /// let outcome = Executor::new(16, false).hard_link(&files[0], &dir.join("a"));
+    /// assert_eq!(outcome.removed, [dir.join("b")]);
+    /// assert_eq!(std::fs::read_to_string(&unrelated).unwrap(), "mine");
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn hard_link(&self, group: &Fileinfo, keep: &Path) -> GroupOutcome {
+        self.apply(group, keep, &[], Action::HardLink)
+    }
+    fn apply(
+        &self,
+        group: &Fileinfo,
+        keep: &Path,
+        spared: &[PathBuf],
+        action: Action,
+    ) -> GroupOutcome {
+        let mut outcome = GroupOutcome::new(keep);
+        let mut pending: Vec<PathBuf> = group
+            .get_paths()
+            .iter()
+            .map(|p| p.to_path_buf())
+            .filter(|p| p != keep && !spared.contains(p))
+            .collect();
+        if !self.worthwhile(group) {
+            outcome.skipped = pending;
+            outcome.below_min_savings = true;
+            return outcome;
+        }
+        let expected = match group.get_full_hash() {
+            Some(hash) => hash,
+            None => return stop(outcome, pending, Anomaly::Unverified),
+        };
+        if !group.get_paths().iter().any(|p| **p == *keep) {
+            return stop(
+                outcome,
+                pending,
+                Anomaly::KeepNotInGroup(keep.to_path_buf()),
+            );
+        }
+        if self.system_guard {
+            let system = pending
+                .iter()
+                .find(|path| crate::privileges::is_system_path(path));
+            if let Some(path) = system.cloned() {
+                return stop(outcome, pending, Anomaly::SystemPath(path));
+            }
+        }
+        if let Action::HardLink = action {
+            let device = device_of(keep);
+            let elsewhere = pending.iter().find(|path| {
+                device
+                    .zip(device_of(path))
+                    .is_some_and(|(keep, other)| keep != other)
+            });
+            if let Some(path) = elsewhere.cloned() {
+                return stop(outcome, pending, Anomaly::CrossesDevices(path));
+            }
+        }
+        let hasher = self.hasher.as_deref().unwrap_or(&SipHash128);
+        if let Err(anomaly) = verify_keep(keep, group.get_length(), expected, hasher) {
+            return stop(outcome, pending, anomaly);
+        }
+        let keep_canonical = fs::canonicalize(keep).ok();
+        while !pending.is_empty() {
+            let rest = pending.split_off(self.batch_size.min(pending.len()));
+            let mut batch = pending.into_iter();
+            pending = rest;
+            while let Some(path) = batch.next() {
+                if keep_canonical.is_some() && fs::canonicalize(&path).ok() == keep_canonical {
+                    let skipped = std::iter::once(path.clone())
+                        .chain(batch)
+                        .chain(pending)
+                        .collect();
+                    return stop(outcome, skipped, Anomaly::KeepAliased(path));
+                }
+                if hash_file_with(&path, HashMode::Full, hasher, None) != Some(expected) {
+                    let skipped = std::iter::once(path.clone())
+                        .chain(batch)
+                        .chain(pending)
+                        .collect();
+                    return stop(outcome, skipped, Anomaly::InstanceChanged(path));
+                }
+                if self.dry_run {
+                    outcome.removed.push(path);
+                    continue;
+                }
+                let applied = match action {
+                    Action::Remove => fs::remove_file(&path),
+                    Action::Reflink => crate::reflink::replace_with_clone(keep, &path),
+                    Action::HardLink => replace_with_hard_link(keep, &path),
+                };
+                match applied {
+                    Ok(()) => outcome.removed.push(path),
+                    Err(e) => outcome.failed.push((path, e)),
+                }
+            }
+            if let Err(anomaly) = verify_keep(keep, group.get_length(), expected, hasher) {
+                return stop(outcome, pending, anomaly);
+            }
+        }
+        outcome
+    }
+}
+
+fn verify_keep(
+    keep: &Path,
+    length: u64,
+    expected: u128,
+    hasher: &dyn ContentHasher,
+) -> Result<(), Anomaly> {
+    match fs::metadata(keep) {
+        Ok(meta) if meta.is_file() => {
+            let hash = hash_file_with(keep, HashMode::Full, hasher, None);
+            if meta.len() != length || hash != Some(expected) {
+                Err(Anomaly::KeepChanged(keep.to_path_buf()))
+            } else {
+                Ok(())
+            }
+        }
+        _ => Err(Anomaly::KeepMissing(keep.to_path_buf())),
+    }
+}
+
+/// Gets the device holding the file at `path`, where devices are known.
+fn device_of(path: &Path) -> Option<u64> {
+    fs::metadata(path)
+        .ok()
+        .and_then(|meta| FileMetadata::from(&meta).device)
+}
+
+/// Links `target` to `keep` by renaming a new link over it, so `target` is never missing.
+/// Paths which already are the same file are left alone, since renaming a link over another
+/// link to the same file does nothing and would leave the new link behind.
+fn replace_with_hard_link(keep: &Path, target: &Path) -> io::Result<()> {
+    if same_file(keep, target)? {
+        return Ok(());
+    }
+    /* Linking never replaces an existing file, so names other files took are skipped and only
+    the link made here is ever removed again. */
+    for attempt in 0..MAX_TEMPORARY_NAMES {
+        let suffix = format!(".ddh-link.{}.{}", std::process::id(), attempt);
+        let temporary = sibling_path(target, &suffix)?;
+        match fs::hard_link(keep, &temporary) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+        let renamed = fs::rename(&temporary, target);
+        if renamed.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+        return renamed;
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("no free name for a link next to {}", target.display()),
+    ))
+}
+
+/// Returns true if `a` and `b` are links to the same file. Platforms without inode numbers
+/// compare canonical paths instead.
+fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    let (meta_a, meta_b) = (fs::metadata(a)?, fs::metadata(b)?);
+    let (a_meta, b_meta) = (FileMetadata::from(&meta_a), FileMetadata::from(&meta_b));
+    match (
+        a_meta.device.zip(a_meta.inode),
+        b_meta.device.zip(b_meta.inode),
+    ) {
+        (Some(a_id), Some(b_id)) => Ok(a_id == b_id),
+        _ => Ok(fs::canonicalize(a)? == fs::canonicalize(b)?),
+    }
+}
+
+/// Gets a hidden path next to `target` for building its replacement.
+pub(crate) fn sibling_path(target: &Path, suffix: &str) -> io::Result<PathBuf> {
+    let name = target
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temporary = std::ffi::OsString::from(".");
+    temporary.push(name);
+    temporary.push(suffix);
+    Ok(target.with_file_name(temporary))
+}
+
+fn stop(mut outcome: GroupOutcome, pending: Vec<PathBuf>, anomaly: Anomaly) -> GroupOutcome {
+    outcome.skipped = pending;
+    outcome.anomaly = Some(anomaly);
+    outcome
+}
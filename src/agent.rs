@@ -0,0 +1,198 @@
+//! Comparison of directories on different machines without moving file contents.
+//!
+//! An agent scans its local directories and writes a `HashIndex` holding the size and full hash
+//! of every file. Another instance reads the index (from a file or an ssh pipe), hashes the
+//! local files whose sizes appear in it and merges both sides, so groups span machines.
+//!
+//! The index records the algorithm its hashes were computed with, and the receiving side must
+//! hash its own files with the same one.
+//!
+//! Paths are sent as UTF-8 with directory prefixes stored once. Names which aren't valid UTF-8
+//! are converted lossily since the paths only serve to report remote locations.
+
+use crate::fileinfo::{Fileinfo, HashMode};
+use crate::hasher::{ContentHasher, SipHash128};
+use crate::paths::PathTable;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+/// Version of the index format written by this build.
+const INDEX_VERSION: u32 = 1;
+
+/// Sizes and hashes of the files found by an agent.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HashIndex {
+    version: u32,
+    /// Name of the machine which produced the index, prefixed to its paths in reports.
+    pub host: String,
+    /// Algorithm of the hashes, as given by `ContentHasher::name`.
+    #[serde(default = "default_algorithm")]
+    pub hash_algorithm: String,
+    /// Directories as the index of their parent and their name.
+    dirs: Vec<(Option<usize>, String)>,
+    /// Files as the index of their directory, name, length and hex encoded full hash.
+    files: Vec<(Option<usize>, String, u64, String)>,
+}
+
+impl HashIndex {
+    /// Builds an index of `files`, which should all have a full hash computed with the default
+    /// SipHash. Files without one are left out.
+    pub fn new(host: &str, files: &[Fileinfo]) -> Self {
+        let mut table = PathTable::new();
+        let mut entries = Vec::new();
+        for file in files.iter() {
+            let hash = match file.get_full_hash() {
+                Some(hash) => hash,
+                None => continue,
+            };
+            for path in file.get_paths().iter() {
+                let compact = table.intern(path);
+                entries.push((
+                    compact.dir().map(|dir| dir.index()),
+                    compact.file_name().to_string_lossy().into_owned(),
+                    file.get_length(),
+                    format!("{:032x}", hash),
+                ));
+            }
+        }
+        HashIndex {
+            version: INDEX_VERSION,
+            host: host.to_string(),
+            hash_algorithm: default_algorithm(),
+            dirs: table
+                .dirs()
+                .map(|(parent, name)| {
+                    (
+                        parent.map(|dir| dir.index()),
+                        name.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect(),
+            files: entries,
+        }
+    }
+    /// Records that the hashes of the files were computed with `hasher`.
+    pub fn hashed_with(mut self, hasher: &dyn ContentHasher) -> Self {
+        self.hash_algorithm = hasher.name().to_string();
+        self
+    }
+    /// Writes the index as JSON.
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer(writer, self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+    /// Reads an index written by `write`.
+    pub fn read<R: Read>(reader: R) -> io::Result<Self> {
+        let index: HashIndex = serde_json::from_reader(reader)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if index.version != INDEX_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported index version {}", index.version),
+            ));
+        }
+        Ok(index)
+    }
+    /// Converts the index into collections whose paths read `host:path`.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::agent::HashIndex;
+    /// use ddh::fileinfo::Fileinfo;
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let files = vec![Fileinfo::from_existing_hash(7, None, 3, PathBuf::from("/srv/a.txt"))];
+    /// let mut sent = Vec::new();
+    /// HashIndex::new("server", &files).write(&mut sent)?;
+    /// let received = HashIndex::read(sent.as_slice())?.into_files()?;
+    /// assert_eq!(&*received[0].get_paths()[0], Path::new("server:/srv/a.txt"));
+    /// assert_eq!(received[0].get_full_hash(), Some(7));
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn into_files(self) -> io::Result<Vec<Fileinfo>> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        let mut dirs: Vec<PathBuf> = Vec::with_capacity(self.dirs.len());
+        for (parent, name) in self.dirs.into_iter() {
+            let dir = match parent {
+                Some(parent) => dirs
+                    .get(parent)
+                    .ok_or_else(|| invalid("directory listed before its parent"))?
+                    .join(name),
+                None => PathBuf::from(name),
+            };
+            dirs.push(dir);
+        }
+        let host = self.host;
+        self.files
+            .into_iter()
+            .map(|(dir, name, length, hash)| {
+                let path = match dir {
+                    Some(dir) => dirs
+                        .get(dir)
+                        .ok_or_else(|| invalid("file in unknown directory"))?
+                        .join(name),
+                    None => PathBuf::from(name),
+                };
+                let hash =
+                    u128::from_str_radix(&hash, 16).map_err(|_| invalid("malformed hash"))?;
+                let remote_path = PathBuf::from(format!("{}:{}", host, path.display()));
+                Ok(Fileinfo::from_existing_hash(
+                    hash,
+                    None,
+                    length,
+                    remote_path,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Computes the full hash of every collection missing one, or with `lengths` given, of those
+/// whose length is listed. Agents hash everything since any file may have a remote duplicate.
+pub fn complete_hashes(files: &mut [Fileinfo], lengths: Option<&HashSet<u64>>) {
+    complete_hashes_with(files, lengths, &SipHash128)
+}
+
+/// Same as `complete_hashes`, hashing with `hasher`.
+pub fn complete_hashes_with(
+    files: &mut [Fileinfo],
+    lengths: Option<&HashSet<u64>>,
+    hasher: &dyn ContentHasher,
+) {
+    files.par_iter_mut().for_each(|file| {
+        let wanted = lengths.is_none_or(|lengths| lengths.contains(&file.get_length()));
+        if wanted && file.get_full_hash().is_none() {
+            let hash = file.generate_hash_with(HashMode::Full, hasher);
+            file.set_full_hash(hash);
+        }
+    });
+}
+
+/// Merges collections received from agents into local scan results. Local collections whose
+/// length matches a remote file are hashed first so they can be compared.
+pub fn merge_remote(local: Vec<Fileinfo>, remote: Vec<Fileinfo>) -> Vec<Fileinfo> {
+    merge_remote_with(local, remote, &SipHash128)
+}
+
+/// Same as `merge_remote`, hashing with `hasher`, which must be the algorithm of the remote
+/// hashes.
+pub fn merge_remote_with(
+    mut local: Vec<Fileinfo>,
+    remote: Vec<Fileinfo>,
+    hasher: &dyn ContentHasher,
+) -> Vec<Fileinfo> {
+    let lengths: HashSet<u64> = remote.iter().map(|file| file.get_length()).collect();
+    complete_hashes_with(&mut local, Some(&lengths), hasher);
+    local.extend(remote);
+    crate::consolidate(local)
+}
+
+/* Indexes written before the algorithm was recorded were always hashed with SipHash. */
+fn default_algorithm() -> String {
+    SipHash128.name().to_string()
+}
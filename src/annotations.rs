@@ -0,0 +1,92 @@
+//! Decisions about duplicate groups which persist between runs.
+//!
+//! Groups are identified by their content hash (see `Fileinfo::get_group_id`) so an
+//! annotation keeps applying to a group no matter where its instances live or how often
+//! the directories are rescanned.
+
+use crate::fileinfo::Fileinfo;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Default location of the annotations file, relative to the working directory.
+pub const DEFAULT_ANNOTATIONS_FILE: &str = ".ddh-annotations.json";
+
+/// Set of annotations attached to duplicate groups.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default)]
+    acknowledged: BTreeSet<String>,
+    #[serde(default)]
+    notes: BTreeMap<String, Vec<String>>,
+}
+
+impl Annotations {
+    /// Loads annotations from `path`. A missing file yields an empty set.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use ddh::annotations::Annotations;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let mut annotations = Annotations::load(".ddh-annotations.json")?;
+    /// annotations.acknowledge("0123456789abcdef0123456789abcdef");
+    /// annotations.save(".ddh-annotations.json")?;
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Annotations::default()),
+            Err(e) => Err(e),
+        }
+    }
+    /// Writes annotations to `path`, replacing its previous contents.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, contents)
+    }
+    /// Marks a group as an intentional duplicate. Returns false if it already was.
+    pub fn acknowledge(&mut self, group_id: &str) -> bool {
+        self.acknowledged.insert(group_id.to_lowercase())
+    }
+    /// Removes the acknowledgement of a group. Returns false if it was not acknowledged.
+    pub fn unacknowledge(&mut self, group_id: &str) -> bool {
+        self.acknowledged.remove(&group_id.to_lowercase())
+    }
+    /// Attaches a free text note to a group.
+    pub fn add_note(&mut self, group_id: &str, note: &str) {
+        self.notes
+            .entry(group_id.to_lowercase())
+            .or_default()
+            .push(note.to_string());
+    }
+    /// Removes all notes from a group. Returns false if the group had none.
+    pub fn clear_notes(&mut self, group_id: &str) -> bool {
+        self.notes.remove(&group_id.to_lowercase()).is_some()
+    }
+    /// Gets the notes attached to a group id, oldest first.
+    pub fn notes_for_id(&self, group_id: &str) -> &[String] {
+        self.notes
+            .get(&group_id.to_lowercase())
+            .map_or(&[], |notes| notes.as_slice())
+    }
+    /// Gets the notes attached to a group, oldest first.
+    pub fn notes(&self, group: &Fileinfo) -> &[String] {
+        match group.get_group_id() {
+            Some(id) => self.notes_for_id(&id),
+            None => &[],
+        }
+    }
+    /// Returns true if the group has been acknowledged as an intentional duplicate.
+    pub fn is_acknowledged(&self, group: &Fileinfo) -> bool {
+        group
+            .get_group_id()
+            .is_some_and(|id| self.acknowledged.contains(&id))
+    }
+}
@@ -0,0 +1,114 @@
+//! Pseudonyms for paths, so that reports can be shared without revealing file names.
+//!
+//! Every name in a path is replaced by a keyed hash of it, keeping common extensions, leading
+//! dots and the root, so the shape of the tree, the kinds of files and the lengths of groups stay visible.
+//! Equal names get equal pseudonyms wherever they occur. The key is random for each `Anonymizer`
+//! unless given, so pseudonyms can't be reversed by hashing likely names.
+
+use crate::fileinfo::Fileinfo;
+use siphasher::sip128::{Hasher128, SipHasher};
+use std::collections::hash_map::RandomState;
+use std::ffi::OsString;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Component, Path, PathBuf};
+
+/// Hex digits of a pseudonym, enough to keep millions of names apart.
+const PSEUDONYM_DIGITS: usize = 12;
+
+/// Extensions kept in pseudonyms. Anything else after a dot may be part of a name, as in
+/// `john.doe`, so it is hashed with the rest.
+const KNOWN_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "avi", "bak", "bin", "bmp", "bz2", "c", "cfg", "conf", "cpp", "css", "csv", "db",
+    "deb", "dll", "dmg", "doc", "docx", "epub", "exe", "flac", "gif", "go", "gz", "h", "heic",
+    "htm", "html", "ini", "iso", "jar", "java", "jpeg", "jpg", "js", "json", "log", "m4a", "md",
+    "mkv", "mov", "mp3", "mp4", "msi", "o", "odt", "ogg", "pdf", "png", "ppt", "pptx", "py", "rar",
+    "raw", "rpm", "rs", "sh", "so", "sql", "sqlite", "svg", "tar", "tgz", "tif", "tiff", "toml",
+    "ts", "txt", "wav", "webm", "webp", "wmv", "xls", "xlsx", "xml", "xz", "yaml", "yml", "zip",
+    "zst",
+];
+
+/// Replaces the names in paths with pseudonyms.
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    keys: (u64, u64),
+}
+
+impl Default for Anonymizer {
+    /// Uses a random key.
+    fn default() -> Self {
+        let state = RandomState::new();
+        Anonymizer::with_key(state.hash_one(0u8), state.hash_one(1u8))
+    }
+}
+
+impl Anonymizer {
+    /// Creates an anonymizer whose pseudonyms depend on the key `(k0, k1)`, for reports which
+    /// must be comparable with each other.
+    pub fn with_key(k0: u64, k1: u64) -> Self {
+        Anonymizer { keys: (k0, k1) }
+    }
+    /// Replaces each name in `path` with its pseudonym.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::anonymize::Anonymizer;
+    /// use std::path::Path;
+    ///
+    /// let anonymizer = Anonymizer::with_key(1, 2);
+    /// let a = anonymizer.path(Path::new("/home/jon/taxes 2023.pdf"));
+    /// let b = anonymizer.path(Path::new("/home/jon/.config/notes.txt"));
+    /// assert!(a.starts_with("/"));
+    /// assert_eq!(a.extension().unwrap(), "pdf");
+    /// assert!(!a.to_string_lossy().contains("taxes"));
+    /// assert_eq!(a.parent(), b.parent().unwrap().parent());
+    /// assert!(b.parent().unwrap().file_name().unwrap().to_string_lossy().starts_with('.'));
+    /// let c = anonymizer.path(Path::new("/home/john.doe"));
+    /// assert!(!c.to_string_lossy().contains("doe"));
+    /// ```
+    pub fn path(&self, path: &Path) -> PathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => self.name(&name.to_string_lossy()),
+                other => other.as_os_str().to_os_string(),
+            })
+            .collect()
+    }
+    /// Replaces `paths` with their pseudonyms.
+    pub fn paths<P: AsRef<Path> + From<PathBuf>>(&self, paths: &mut [P]) {
+        for path in paths.iter_mut() {
+            *path = self.path(path.as_ref()).into();
+        }
+    }
+    /// Replaces the paths of `files` with their pseudonyms.
+    pub fn files(&self, files: &mut [Fileinfo]) {
+        for file in files.iter_mut() {
+            self.paths(&mut file.file_paths);
+        }
+    }
+    fn name(&self, name: &str) -> OsString {
+        let (dot, rest) = match name.strip_prefix('.') {
+            Some(rest) => (".", rest),
+            None => ("", name),
+        };
+        let known = |extension: &str| {
+            KNOWN_EXTENSIONS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(extension))
+        };
+        let (stem, extension) = match rest.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() && known(extension) => {
+                (stem, Some(extension))
+            }
+            _ => (rest, None),
+        };
+        let mut hasher = SipHasher::new_with_keys(self.keys.0, self.keys.1);
+        hasher.write(stem.as_bytes());
+        let hash = format!("{:032x}", u128::from(hasher.finish128()));
+        let mut pseudonym = format!("{}{}", dot, &hash[..PSEUDONYM_DIGITS]);
+        if let Some(extension) = extension {
+            pseudonym.push('.');
+            pseudonym.push_str(extension);
+        }
+        pseudonym.into()
+    }
+}
@@ -0,0 +1,611 @@
+//! Persistent cache of file hashes which is safe to share between processes and users.
+//!
+//! Entries are keyed by `(device, inode, size, mtime)` so a file is never re-read while it
+//! stays unchanged. The cache file is only read under a shared lock and only written under an
+//! exclusive lock, and writes merge with whatever other processes stored in the meantime.
+//!
+//! On multi-user hosts an entry is only trusted when it was written by the current user, by
+//! root or by the owner of the file, and only for files the current user can read. Failed
+//! hashes are never stored so an unreadable file can't leave a bad entry behind for others.
+//! The cache file itself is only loaded when it belongs to the current user or root and no one
+//! else can write to it, and it is created readable by its owner only.
+//!
+//! Users sharing one cache keep it in a directory of a group they all belong to, with the
+//! setgid bit set so that files created there belong to that group. The cache is created
+//! readable and writable by the group there, and loaded whoever of the group wrote it last.
+//!
+//! The same file format holds the state of incremental scans. Unlike a shared cache, a saved
+//! state only ever holds the files of the latest scan, so deleted files don't accumulate.
+
+use crate::fileinfo::{FileMetadata, Fileinfo};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identity of a file's contents as far as the cache is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub device: u64,
+    pub inode: u64,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+}
+
+impl CacheKey {
+    /// Builds the key for a file from its metadata, or `None` without a modification time.
+    /// Without an inode number the path stands in for the file identity.
+    pub fn new(path: &Path, meta: &FileMetadata) -> Option<Self> {
+        let inode = meta.inode.unwrap_or_else(|| {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = siphasher::sip::SipHasher::new();
+            path.hash(&mut hasher);
+            hasher.finish()
+        });
+        Some(CacheKey {
+            device: meta.device.unwrap_or(0),
+            inode,
+            size: meta.size,
+            mtime: meta.mtime?,
+            mtime_nsec: meta.mtime_nsec as i64,
+        })
+    }
+}
+
+/// Hashes stored for a single file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// Path the entry was last seen at.
+    pub path: PathBuf,
+    pub partial_hash: Option<u128>,
+    pub full_hash: Option<u128>,
+    /// User which stored the entry.
+    pub owner: u32,
+    /// Seconds since the epoch at which the entry was last stored or used.
+    pub last_used: u64,
+}
+
+/// Version of the hashes stored in the cache. Files written with another version are discarded
+/// since their hashes can't be compared with freshly computed ones.
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    hits: u64,
+    #[serde(default)]
+    misses: u64,
+    #[serde(default)]
+    entries: Vec<(CacheKey, CacheEntry)>,
+}
+
+impl Default for CacheFile {
+    fn default() -> Self {
+        CacheFile {
+            version: CACHE_VERSION,
+            hits: 0,
+            misses: 0,
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// Summary of the contents and effectiveness of a cache.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    /// Number of stored entries.
+    pub entries: usize,
+    /// Size of the cache file in bytes.
+    pub file_size: u64,
+    /// Lookups answered from the cache over its lifetime.
+    pub hits: u64,
+    /// Lookups which had to hash the file over its lifetime.
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups answered from the cache.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+/// Result of pruning a cache.
+#[derive(Debug, Clone, Copy)]
+pub struct PruneReport {
+    /// Entries removed because their file is gone or has changed.
+    pub stale: usize,
+    /// Least recently used entries removed to respect the entry limit.
+    pub evicted: usize,
+    /// Entries left in the cache.
+    pub remaining: usize,
+}
+
+/// Hash cache backed by a file on disk.
+#[derive(Debug)]
+pub struct HashCache {
+    /// Backing file, or `None` for a cache which only lives as long as the process.
+    path: Option<PathBuf>,
+    uid: u32,
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    dirty: Mutex<HashSet<CacheKey>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl HashCache {
+    /// Opens the cache stored at `path`. A missing file yields an empty cache which is created
+    /// on the first call to `persist`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use ddh::cache::HashCache;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let cache = HashCache::open("/var/cache/ddh/hashes.json")?;
+    /// let options = ddh::ScanOptions { cache: Some(cache), ..Default::default() };
+    /// let (files, errors) = ddh::deduplicate_dirs_with_options(vec!["/srv/share"], vec![], &options);
+    /// options.cache.unwrap().persist()?;
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let stored = {
+            let _lock = lock(&path, false)?;
+            read_cache_file(&path)?
+        };
+        Ok(HashCache {
+            path: Some(path),
+            uid: current_uid(),
+            entries: Mutex::new(stored.entries.into_iter().collect()),
+            dirty: Mutex::new(HashSet::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+    /// Gets the per-user cache file: `ddh/hashes.json` below `$XDG_CACHE_HOME`, `~/.cache` or,
+    /// on Windows, `%LOCALAPPDATA%`. `None` when none of these are set.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use ddh::cache::HashCache;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// if let Some(path) = HashCache::default_path() {
+    ///     let cache = HashCache::open(path)?;
+    /// }
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn default_path() -> Option<PathBuf> {
+        let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+        let base = var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| Path::new(&home).join(".cache")))
+            .or_else(|| var("LOCALAPPDATA").map(PathBuf::from))?;
+        Some(base.join("ddh").join("hashes.json"))
+    }
+    /// Creates an empty cache without a backing file, for sharing hashes between scans made by
+    /// the same process. Persisting it does nothing.
+    pub fn in_memory() -> Self {
+        HashCache {
+            path: None,
+            uid: current_uid(),
+            entries: Mutex::new(HashMap::new()),
+            dirty: Mutex::new(HashSet::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+    /// Gets the location of the cache file, or an empty path for an in memory cache.
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().unwrap_or_else(|| Path::new(""))
+    }
+    fn backing_file(&self) -> io::Result<&Path> {
+        self.path
+            .as_deref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "cache has no backing file"))
+    }
+    /// Looks up the hashes of `file`, returning `(partial, full)` on a hit. Files without a
+    /// modification time, such as imported ones, are never looked up.
+    pub fn lookup(&self, file: &Fileinfo) -> Option<(Option<u128>, Option<u128>)> {
+        let path = file.get_paths().first()?;
+        let meta = file.get_metadata();
+        let key = CacheKey::new(path, meta)?;
+        let mut entries = self.entries.lock().unwrap();
+        let hit = match entries.get_mut(&key) {
+            Some(entry) if self.trusts(entry, meta) && readable(path) => {
+                entry.last_used = now();
+                Some((entry.partial_hash, entry.full_hash))
+            }
+            _ => None,
+        };
+        drop(entries);
+        if hit.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            self.dirty.lock().unwrap().insert(key);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        hit
+    }
+    /// Returns true if the cache holds hashes of `file` it would hand out, without counting a
+    /// hit or a miss nor checking that the file is still readable. For scheduling work only.
+    pub fn is_warm(&self, file: &Fileinfo) -> bool {
+        let key = file
+            .get_paths()
+            .first()
+            .and_then(|path| CacheKey::new(path, file.get_metadata()));
+        let entries = self.entries.lock().unwrap();
+        key.and_then(|key| entries.get(&key)).is_some_and(|entry| {
+            entry.partial_hash.is_some() && self.trusts(entry, file.get_metadata())
+        })
+    }
+    /// Stores the hashes of `file`. Files without any hash or modification time are not stored.
+    pub fn insert(&self, file: &Fileinfo) {
+        if file.get_partial_hash().is_none() && file.get_full_hash().is_none() {
+            return;
+        }
+        let path = match file.get_paths().first() {
+            Some(path) => path,
+            None => return,
+        };
+        let key = match CacheKey::new(path, file.get_metadata()) {
+            Some(key) => key,
+            None => return,
+        };
+        let entry = CacheEntry {
+            path: path.to_path_buf(),
+            partial_hash: file.get_partial_hash(),
+            full_hash: file.get_full_hash(),
+            owner: self.uid,
+            last_used: now(),
+        };
+        self.entries.lock().unwrap().insert(key, entry);
+        self.dirty.lock().unwrap().insert(key);
+    }
+    /// Writes entries stored or used since the cache was opened back to disk, merging them
+    /// with changes made by other processes in the meantime.
+    pub fn persist(&self) -> io::Result<()> {
+        let path = match self.path.as_deref() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let _lock = lock(path, true)?;
+        let mut stored = read_cache_file(path)?;
+        let mut merged: HashMap<CacheKey, CacheEntry> = stored.entries.drain(..).collect();
+        let entries = self.entries.lock().unwrap();
+        for key in self.dirty.lock().unwrap().drain() {
+            if let Some(entry) = entries.get(&key) {
+                merged.insert(key, entry.clone());
+            }
+        }
+        stored.hits += self.hits.swap(0, Ordering::Relaxed);
+        stored.misses += self.misses.swap(0, Ordering::Relaxed);
+        stored.entries = merged.into_iter().collect();
+        write_cache_file(path, &stored)
+    }
+
+    /// Reads the current statistics of the cache file.
+    pub fn stats(&self) -> io::Result<CacheStats> {
+        let path = self.backing_file()?;
+        let _lock = lock(path, false)?;
+        let stored = read_cache_file(path)?;
+        let file_size = match fs::metadata(path) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(CacheStats {
+            entries: stored.entries.len(),
+            file_size,
+            hits: stored.hits,
+            misses: stored.misses,
+        })
+    }
+    /// Removes entries for files which no longer exist or have changed, then evicts the least
+    /// recently used entries until at most `max_entries` remain. Entries for files the current
+    /// user can't inspect are left alone.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use ddh::cache::HashCache;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let report = HashCache::open("/var/cache/ddh/hashes.json")?.prune(Some(1_000_000))?;
+    /// println!("{} stale, {} evicted", report.stale, report.evicted);
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn prune(&self, max_entries: Option<usize>) -> io::Result<PruneReport> {
+        let path = self.backing_file()?;
+        let _lock = lock(path, true)?;
+        let mut stored = read_cache_file(path)?;
+        let before = stored.entries.len();
+        stored
+            .entries
+            .retain(|(key, entry)| match fs::metadata(&entry.path) {
+                Ok(meta) => CacheKey::new(&entry.path, &meta.into()) == Some(*key),
+                Err(e) => e.kind() != io::ErrorKind::NotFound,
+            });
+        let stale = before - stored.entries.len();
+        let mut evicted = 0;
+        if let Some(max_entries) = max_entries {
+            if stored.entries.len() > max_entries {
+                stored
+                    .entries
+                    .sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_used));
+                evicted = stored.entries.len() - max_entries;
+                stored.entries.truncate(max_entries);
+            }
+        }
+        write_cache_file(path, &stored)?;
+        let remaining = stored.entries.len();
+        *self.entries.lock().unwrap() = stored.entries.into_iter().collect();
+        self.dirty.lock().unwrap().clear();
+        Ok(PruneReport {
+            stale,
+            evicted,
+            remaining,
+        })
+    }
+    /// Removes every entry and resets the statistics.
+    pub fn clear(&self) -> io::Result<()> {
+        let path = self.backing_file()?;
+        let _lock = lock(path, true)?;
+        write_cache_file(path, &CacheFile::default())?;
+        self.entries.lock().unwrap().clear();
+        self.dirty.lock().unwrap().clear();
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Loads the scan state saved by `save_state` into a cache without a backing file, so a
+    /// scan using it only hashes files which are new or changed since. A missing file yields
+    /// an empty state.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use ddh::cache::HashCache;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let state = HashCache::load_state("/var/lib/ddh/nas.state")?;
+    /// let options = ddh::ScanOptions { cache: Some(state), ..Default::default() };
+    /// let (files, errors) = ddh::deduplicate_dirs_with_options(vec!["/mnt/nas"], vec![], &options);
+    /// options.cache.unwrap().save_state("/var/lib/ddh/nas.state", &files)?;
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn load_state<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let stored = read_cache_file(path.as_ref())?;
+        let state = HashCache::in_memory();
+        *state.entries.lock().unwrap() = stored.entries.into_iter().collect();
+        Ok(state)
+    }
+    /// Replaces the scan state at `path` with the files found by the scans made since it was
+    /// loaded, `files` being their results. Hashed files keep their hashes and other files are
+    /// recorded by path and size only. Files which were deleted or changed since the previous
+    /// state are dropped with their old hashes. Returns the number of files saved.
+    pub fn save_state<P: AsRef<Path>>(&self, path: P, files: &[Fileinfo]) -> io::Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut seen = self.dirty.lock().unwrap();
+        for file in files.iter().filter(|file| file.get_paths().len() == 1) {
+            let key = match CacheKey::new(&file.get_paths()[0], file.get_metadata()) {
+                Some(key) => key,
+                None => continue,
+            };
+            if seen.insert(key) {
+                entries.insert(
+                    key,
+                    CacheEntry {
+                        path: file.get_paths()[0].to_path_buf(),
+                        partial_hash: file.get_partial_hash(),
+                        full_hash: file.get_full_hash(),
+                        owner: self.uid,
+                        last_used: now(),
+                    },
+                );
+            }
+        }
+        let state = CacheFile {
+            entries: seen
+                .iter()
+                .filter_map(|key| Some((*key, entries.get(key)?.clone())))
+                .collect(),
+            ..CacheFile::default()
+        };
+        write_cache_file(path.as_ref(), &state)?;
+        Ok(state.entries.len())
+    }
+
+    fn trusts(&self, entry: &CacheEntry, meta: &FileMetadata) -> bool {
+        entry.owner == self.uid || entry.owner == 0 || Some(entry.owner) == meta.owner
+    }
+}
+
+/// Guard holding a lock on the cache's companion lock file.
+struct CacheLock(File);
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+fn lock(path: &Path, exclusive: bool) -> io::Result<CacheLock> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)?;
+    share_with_group(Path::new(&lock_path), &file)?;
+    if exclusive {
+        file.lock()?;
+    } else {
+        file.lock_shared()?;
+    }
+    Ok(CacheLock(file))
+}
+
+fn read_cache_file(path: &Path) -> io::Result<CacheFile> {
+    let mut contents = String::new();
+    match File::open(path) {
+        Ok(mut f) => {
+            check_ownership(path, &f.metadata()?)?;
+            f.read_to_string(&mut contents)?
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(CacheFile::default()),
+        Err(e) => return Err(e),
+    };
+    let stored: CacheFile = serde_json::from_str(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if stored.version != CACHE_VERSION {
+        return Ok(CacheFile::default());
+    }
+    Ok(stored)
+}
+
+/// Writes through a temporary file so readers never observe a partially written cache.
+fn write_cache_file(path: &Path, cache: &CacheFile) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut tmp = create_private(Path::new(&tmp_path))?;
+    share_with_group(Path::new(&tmp_path), &tmp)?;
+    tmp.write_all(
+        serde_json::to_string(cache)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .as_bytes(),
+    )?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Refuses cache files which another user could have written, since their hashes would decide
+/// which files are reported as duplicates, unless they are members of the group sharing the
+/// directory of the cache.
+#[cfg(unix)]
+fn check_ownership(path: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let euid = unsafe { libc::geteuid() };
+    let shared = shared_group(path) == Some(meta.gid()) && in_group(meta.gid());
+    if meta.uid() != euid && meta.uid() != 0 && !shared {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is owned by another user", path.display()),
+        ));
+    }
+    if meta.mode() & 0o002 != 0 || (meta.mode() & 0o020 != 0 && !shared) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is writable by other users", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_ownership(_path: &Path, _meta: &fs::Metadata) -> io::Result<()> {
+    Ok(())
+}
+
+/// Gets the group sharing the directory of `path`, the group of the directory when it has the
+/// setgid bit set.
+#[cfg(unix)]
+fn shared_group(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::metadata(dir)
+        .ok()
+        .filter(|meta| meta.mode() & 0o2000 != 0)
+        .map(|meta| meta.gid())
+}
+
+/// Tells whether the current user is a member of group `gid`.
+#[cfg(unix)]
+fn in_group(gid: u32) -> bool {
+    if unsafe { libc::getegid() } == gid {
+        return true;
+    }
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count <= 0 {
+        return false;
+    }
+    let mut groups: Vec<libc::gid_t> = vec![0; count as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    count > 0 && groups[..count as usize].contains(&gid)
+}
+
+/// Lets the group sharing the directory of `path` read and write `file`, if it was created by
+/// the current user. The mode is set after creating the file so the umask doesn't narrow it.
+#[cfg(unix)]
+fn share_with_group(path: &Path, file: &File) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let meta = file.metadata()?;
+    if meta.uid() != unsafe { libc::geteuid() } || shared_group(path) != Some(meta.gid()) {
+        return Ok(());
+    }
+    file.set_permissions(fs::Permissions::from_mode(0o660))
+}
+
+#[cfg(not(unix))]
+fn share_with_group(_path: &Path, _file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let _ = fs::remove_file(path);
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<File> {
+    File::create(path)
+}
+
+fn readable(path: &Path) -> bool {
+    File::open(path).is_ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> u32 {
+    0
+}
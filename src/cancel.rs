@@ -0,0 +1,90 @@
+//! Stopping or pausing a running scan from another thread.
+//!
+//! A scan given a `CancellationHandle` in `ScanOptions::cancel` checks it before entering each
+//! directory and before hashing each file. Once cancelled it winds down quickly and returns
+//! what it settled so far, so desktop applications can abort long scans when the user leaves.
+//!
+//! A scan given a `PauseHandle` in `ScanOptions::pause` stops entering directories and starting
+//! reads while the handle is paused, letting backups or maintenance have the disks, and goes on
+//! where it was once resumed. Reads already under way finish first.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Flag asking a scan to stop. Clones share the flag, so one clone can be handed to the scan
+/// while another is kept to cancel it.
+///
+/// # Examples
+/// ```
+/// use ddh::cancel::CancellationHandle;
+///
+/// let handle = CancellationHandle::new();
+/// let options = ddh::ScanOptions { cancel: Some(handle.clone()), ..Default::default() };
+/// handle.cancel();
+/// let (files, errors) = ddh::deduplicate_dirs_with_options(vec!["."], vec![], &options);
+/// assert!(files.is_empty() && errors.is_empty());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks every scan holding a clone of this handle to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true once `cancel` was called on any clone.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Flag holding a scan back until it is cleared. Clones share the flag like those of a
+/// `CancellationHandle`.
+///
+/// # Examples
+/// ```
+/// use ddh::cancel::PauseHandle;
+///
+/// let handle = PauseHandle::new();
+/// assert!(handle.toggle());
+/// assert!(handle.is_paused());
+/// handle.resume();
+/// assert!(!handle.is_paused());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle {
+    paused: Arc<AtomicBool>,
+}
+
+impl PauseHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Holds back every scan holding a clone of this handle.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Lets paused scans go on.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Pauses running scans or resumes paused ones, returning true if they are now paused.
+    pub fn toggle(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// Returns true while scans are held back.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,68 @@
+//! Time as seen by a scan.
+//!
+//! Scans read the time through a `Clock` to measure how long each phase took. The default
+//! `SystemClock` is the real one; `ScanOptions::clock` can swap in a `FixedClock` instead, under
+//! which every phase takes no time at all, so snapshots of reports and tests comparing timings
+//! don't depend on the speed of the machine.
+
+use std::fmt;
+use std::time::Instant;
+
+/// Source of the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// Clock reading the monotonic time of the system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock which always reads the same time.
+///
+/// # Examples
+/// ```
+/// use ddh::clock::{Clock, FixedClock};
+/// use std::sync::Arc;
+///
+/// let dir = std::env::temp_dir().join("ddh_fixed_clock_example");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(dir.join("a"), "same").unwrap();
+/// let options = ddh::ScanOptions {
+///     clock: Some(Arc::new(FixedClock::new())),
+///     ..Default::default()
+/// };
+/// let (_, _, timings) = ddh::deduplicate_dirs_timed(vec![&dir], vec![], &options);
+/// assert!(timings.discovery.is_zero() && timings.hashing.is_zero());
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock {
+    now: Instant,
+}
+
+impl FixedClock {
+    /// Creates a clock stopped at the current time.
+    pub fn new() -> Self {
+        FixedClock {
+            now: Instant::now(),
+        }
+    }
+}
+
+impl Default for FixedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> Instant {
+        self.now
+    }
+}
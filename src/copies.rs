@@ -0,0 +1,287 @@
+//! Detection of file names that look like they were produced by copying another file.
+//!
+//! Desktop environments and browsers name duplicates in predictable ways (`file (1).jpg`,
+//! `file - Copy.docx`, `Copy of file.txt`, `file copy 2.png`, `file.bak`). Knowing which
+//! instance in a duplicate group carries such a name lets reports flag it and lets keep
+//! policies prefer the instance with the clean name.
+
+use std::path::Path;
+
+/// Suffixes appended to a whole file name by editors and backup tools.
+const BACKUP_SUFFIXES: &[&str] = &[".bak", ".backup", ".old", ".orig", "~"];
+
+/// Naming conventions a localized file manager uses for copies.
+#[derive(Debug)]
+pub struct LocalePack {
+    /// Language code used to select the pack.
+    pub code: &'static str,
+    /// Markers appended to the file stem (`photo - Copy.jpg`, `photo copy.jpg`).
+    pub suffixes: &'static [&'static str],
+    /// Markers prepended to the file name (`Copy of photo.jpg`).
+    pub prefixes: &'static [&'static str],
+}
+
+/// Copy naming conventions of Windows Explorer and the macOS Finder by language.
+/// New translations belong in this table rather than in the matching code.
+pub const LOCALE_PACKS: &[LocalePack] = &[
+    LocalePack {
+        code: "en",
+        suffixes: &[" - Copy", " copy"],
+        prefixes: &["Copy of "],
+    },
+    LocalePack {
+        code: "de",
+        suffixes: &[" - Kopie", " Kopie"],
+        prefixes: &["Kopie von "],
+    },
+    LocalePack {
+        code: "fr",
+        suffixes: &[" - Copie", " copie"],
+        prefixes: &["Copie de "],
+    },
+    LocalePack {
+        code: "es",
+        suffixes: &[" - copia", " copia"],
+        prefixes: &["Copia de "],
+    },
+    LocalePack {
+        code: "it",
+        suffixes: &[" - Copia", " copia"],
+        prefixes: &["Copia di "],
+    },
+    LocalePack {
+        code: "pt",
+        suffixes: &[" - Cópia", " cópia"],
+        prefixes: &["Cópia de "],
+    },
+    LocalePack {
+        code: "nl",
+        suffixes: &[" - Kopie", " kopie"],
+        prefixes: &["Kopie van "],
+    },
+    LocalePack {
+        code: "sv",
+        suffixes: &[" - Kopia", " kopia"],
+        prefixes: &["Kopia av "],
+    },
+    LocalePack {
+        code: "pl",
+        suffixes: &[" - Kopia", " kopia"],
+        prefixes: &[],
+    },
+    LocalePack {
+        code: "fi",
+        suffixes: &[" - Kopio", " kopio"],
+        prefixes: &[],
+    },
+    LocalePack {
+        code: "ru",
+        suffixes: &[" - копия", " копия"],
+        prefixes: &["Копия "],
+    },
+    LocalePack {
+        code: "zh",
+        suffixes: &[" - 副本", " 副本", "副本"],
+        prefixes: &[],
+    },
+    LocalePack {
+        code: "ja",
+        suffixes: &[" - コピー", " のコピー", "のコピー"],
+        prefixes: &[],
+    },
+    LocalePack {
+        code: "ko",
+        suffixes: &[" - 복사본", " 복사본"],
+        prefixes: &[],
+    },
+];
+
+/// Set of patterns used to recognise copy-style file names.
+#[derive(Debug, Clone)]
+pub struct CopyPatterns {
+    suffixes: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl Default for CopyPatterns {
+    /// Combines every locale pack.
+    fn default() -> Self {
+        CopyPatterns::from_packs(LOCALE_PACKS.iter())
+    }
+}
+
+impl CopyPatterns {
+    /// Creates a pattern set from stem suffixes and name prefixes (matched case insensitively).
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::copies::CopyPatterns;
+    ///
+    /// let patterns = CopyPatterns::new(vec![" - Copy".to_string()], vec!["Copy of ".to_string()]);
+    /// assert!(patterns.is_copy_name("report - Copy.docx"));
+    /// assert!(patterns.is_copy_name("Copy of report.docx"));
+    /// ```
+    pub fn new(suffixes: Vec<String>, prefixes: Vec<String>) -> Self {
+        CopyPatterns {
+            suffixes: suffixes.into_iter().map(|w| w.to_lowercase()).collect(),
+            prefixes: prefixes.into_iter().map(|w| w.to_lowercase()).collect(),
+        }
+    }
+    /// Creates a pattern set from the locale packs with the given codes.
+    /// An unknown code is returned as the error.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::copies::CopyPatterns;
+    ///
+    /// let patterns = CopyPatterns::for_locales(&["de", "fr"]).unwrap();
+    /// assert!(patterns.is_copy_name("Bericht - Kopie.pdf"));
+    /// assert!(patterns.is_copy_name("Copie de rapport.pdf"));
+    /// assert!(!patterns.is_copy_name("report - Copy.pdf"));
+    /// assert!(CopyPatterns::for_locales(&["xx"]).is_err());
+    /// ```
+    pub fn for_locales<S: AsRef<str>>(codes: &[S]) -> Result<Self, String> {
+        let mut packs = Vec::new();
+        for code in codes {
+            match LOCALE_PACKS
+                .iter()
+                .find(|p| p.code.eq_ignore_ascii_case(code.as_ref()))
+            {
+                Some(pack) => packs.push(pack),
+                None => return Err(code.as_ref().to_string()),
+            }
+        }
+        Ok(CopyPatterns::from_packs(packs.into_iter()))
+    }
+    /// Returns the name the original file most likely had if `name` looks like a copy.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::copies::CopyPatterns;
+    ///
+    /// let patterns = CopyPatterns::default();
+    /// assert_eq!(Some("photo.jpg".to_string()), patterns.original_name("photo (1).jpg"));
+    /// assert_eq!(Some("photo.jpg".to_string()), patterns.original_name("photo - 副本.jpg"));
+    /// assert_eq!(Some("notes.txt".to_string()), patterns.original_name("notes.txt.bak"));
+    /// assert_eq!(None, patterns.original_name("photo.jpg"));
+    /// assert_eq!(None, patterns.original_name("Holidays (2019).jpg"));
+    /// ```
+    pub fn original_name(&self, name: &str) -> Option<String> {
+        for suffix in BACKUP_SUFFIXES {
+            if name.len() > suffix.len() && name.ends_with(suffix) {
+                return Some(name[..name.len() - suffix.len()].to_string());
+            }
+        }
+        let (stem, ext) = match name.rfind('.') {
+            Some(i) if i > 0 => name.split_at(i),
+            _ => (name, ""),
+        };
+        self.original_stem(stem)
+            .map(|original| format!("{}{}", original, ext))
+    }
+    /// Returns true if the final component of `path` looks like a copy.
+    pub fn is_copy(&self, path: &Path) -> bool {
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| self.is_copy_name(n))
+    }
+    /// Returns true if `name` looks like a copy.
+    pub fn is_copy_name(&self, name: &str) -> bool {
+        self.original_name(name).is_some()
+    }
+    /// Orders paths so that clean-named instances come before copy-named ones.
+    /// The relative order within each class is preserved.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::copies::CopyPatterns;
+    /// use std::path::PathBuf;
+    ///
+    /// let paths = vec![PathBuf::from("/a/song (2).mp3"), PathBuf::from("/b/song.mp3")];
+    /// let ordered = CopyPatterns::default().prefer_originals(&paths);
+    /// assert_eq!(&PathBuf::from("/b/song.mp3"), ordered[0]);
+    /// ```
+    pub fn prefer_originals<'a, P: AsRef<Path>>(&self, paths: &'a [P]) -> Vec<&'a P> {
+        let (mut clean, copies): (Vec<&P>, Vec<&P>) =
+            paths.iter().partition(|p| !self.is_copy(p.as_ref()));
+        clean.extend(copies);
+        clean
+    }
+
+    fn from_packs<'a>(packs: impl Iterator<Item = &'a LocalePack>) -> Self {
+        let mut suffixes = Vec::new();
+        let mut prefixes = Vec::new();
+        for pack in packs {
+            suffixes.extend(pack.suffixes.iter().map(|s| s.to_string()));
+            prefixes.extend(pack.prefixes.iter().map(|s| s.to_string()));
+        }
+        CopyPatterns::new(suffixes, prefixes)
+    }
+
+    fn original_stem(&self, stem: &str) -> Option<String> {
+        let stem = stem.trim_end();
+        // "file (1)", "file - Copy (2)", "file copy (3)"
+        if let Some(base) = strip_counter(stem) {
+            if let Some(original) = self.strip_copy_suffix(base) {
+                return Some(original);
+            }
+            if !base.is_empty() {
+                return Some(base.to_string());
+            }
+        }
+        if let Some(original) = self.strip_copy_suffix(stem) {
+            return Some(original);
+        }
+        // "file copy 2" as produced by the macOS Finder
+        if let Some(i) = stem.rfind(' ') {
+            let (base, digits) = stem.split_at(i);
+            let digits = digits.trim_start();
+            if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+                if let Some(original) = self.strip_copy_suffix(base) {
+                    return Some(original);
+                }
+            }
+        }
+        // "Copy of file" as produced by older versions of Windows
+        for prefix in &self.prefixes {
+            if stem.len() <= prefix.len() {
+                continue;
+            }
+            if let (Some(head), Some(rest)) = (stem.get(..prefix.len()), stem.get(prefix.len()..)) {
+                if &head.to_lowercase() == prefix {
+                    return Some(rest.to_string());
+                }
+            }
+        }
+        None
+    }
+
+    fn strip_copy_suffix(&self, stem: &str) -> Option<String> {
+        for suffix in &self.suffixes {
+            if stem.len() <= suffix.len() {
+                continue;
+            }
+            let split = stem.len() - suffix.len();
+            if let (Some(base), Some(tail)) = (stem.get(..split), stem.get(split..)) {
+                if &tail.to_lowercase() == suffix {
+                    return Some(base.to_string());
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Strips a trailing ` (N)` counter from a file stem. Counters have at most three digits, so
+/// years such as `(2019)` are left alone.
+fn strip_counter(stem: &str) -> Option<&str> {
+    if !stem.ends_with(')') {
+        return None;
+    }
+    let open = stem.rfind('(')?;
+    let digits = &stem[open + 1..stem.len() - 1];
+    if digits.is_empty() || digits.len() > 3 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(stem[..open].trim_end())
+}
@@ -0,0 +1,86 @@
+//! Whole machine scans on Windows, for duplicate audits of every local disk.
+//!
+//! `fixed_drives` lists the root of each fixed volume with a drive letter, leaving out
+//! removable, network and optical drives. `SYSTEM_EXCLUDES` holds the parts of a system drive
+//! which only the OS manages and which no one should deduplicate, and `system_excludes` anchors
+//! them to a drive root so they don't skip same named directories elsewhere on the drive.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// OS managed files and directories, relative to the root of a drive.
+pub const SYSTEM_EXCLUDES: &[&str] = &[
+    "Windows",
+    "$Recycle.Bin",
+    "$WinREAgent",
+    "System Volume Information",
+    "Recovery",
+    "ProgramData/Microsoft/Windows/WER",
+    "pagefile.sys",
+    "hiberfil.sys",
+    "swapfile.sys",
+];
+
+/// Ignore entries skipping the `SYSTEM_EXCLUDES` of the drive rooted at `drive`, and nothing
+/// below any other directory.
+///
+/// # Examples
+/// ```
+/// use std::path::Path;
+///
+/// let excludes: Vec<_> = ddh::drives::system_excludes(Path::new("/mnt/c")).collect();
+/// assert!(excludes.contains(&Path::new("/mnt/c/Windows").to_path_buf()));
+/// assert!(excludes.iter().all(|entry| entry.starts_with("/mnt/c")));
+/// ```
+pub fn system_excludes(drive: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    SYSTEM_EXCLUDES.iter().map(move |entry| drive.join(entry))
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetLogicalDrives() -> u32;
+    fn GetDriveTypeW(root_path_name: *const u16) -> u32;
+}
+
+#[cfg(windows)]
+const DRIVE_FIXED: u32 = 3;
+
+/// Lists the roots of the fixed volumes mounted on a drive letter, such as `C:\`, in letter
+/// order. Volumes mounted only on a folder aren't listed; they're scanned through the drive
+/// holding that folder.
+#[cfg(windows)]
+pub fn fixed_drives() -> io::Result<Vec<PathBuf>> {
+    let mask = unsafe { GetLogicalDrives() };
+    if mask == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((0..26u8)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(|bit| char::from(b'A' + bit))
+        .filter(|letter| {
+            let root: Vec<u16> = format!("{}:\\", letter).encode_utf16().chain([0]).collect();
+            unsafe { GetDriveTypeW(root.as_ptr()) == DRIVE_FIXED }
+        })
+        .map(|letter| PathBuf::from(format!("{}:\\", letter)))
+        .collect())
+}
+
+/// Lists the roots of the fixed volumes mounted on a drive letter. Drive letters only exist on
+/// Windows, so this always fails elsewhere.
+///
+/// # Examples
+/// ```
+/// # #[cfg(not(windows))]
+/// assert_eq!(
+///     ddh::drives::fixed_drives().unwrap_err().kind(),
+///     std::io::ErrorKind::Unsupported
+/// );
+/// ```
+#[cfg(not(windows))]
+pub fn fixed_drives() -> io::Result<Vec<PathBuf>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "drive letters only exist on Windows",
+    ))
+}
@@ -0,0 +1,204 @@
+//! Changes between two sets of scan results, for consumers which keep their own state.
+//!
+//! Rather than reading a full snapshot after every scan, a dashboard can apply the events
+//! between consecutive snapshots: a group appearing, gaining or losing an instance, or being
+//! resolved once fewer than two instances are left. Groups are identified by
+//! `Fileinfo::get_group_id`, which stays the same for as long as their contents do, so events
+//! from one scan refer to the groups a consumer learned about from earlier ones.
+//!
+//! Events are written as JSON lines, one object per event with its kind in `event`. People
+//! auditing the same directories now and then get a summary of the changes from `diff_scans`
+//! instead.
+
+use crate::fileinfo::Fileinfo;
+use crate::output::JsonSummary;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Change of a duplicate group between two scans.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GroupEvent {
+    /// A group with at least two instances appeared.
+    GroupAdded {
+        group: String,
+        length: u64,
+        paths: Vec<PathBuf>,
+    },
+    /// A group has fewer than two instances left.
+    GroupResolved { group: String },
+    /// A group which was already there gained an instance.
+    InstanceAdded { group: String, path: PathBuf },
+    /// A group which is still there lost an instance.
+    InstanceRemoved { group: String, path: PathBuf },
+}
+
+/// Lists the events turning the groups of `before` into those of `after`, ordered by group id.
+/// Collections without a full hash or with a single path aren't groups and are ignored.
+///
+/// # Examples
+/// ```
+/// use ddh::events::{diff_groups, GroupEvent};
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::PathBuf;
+///
+/// let file = |hash, path: &str| Fileinfo::from_existing_hash(hash, None, 10, PathBuf::from(path));
+/// let before = ddh::consolidate(vec![file(1, "/a"), file(1, "/b"), file(2, "/c"), file(2, "/d")]);
+/// let after = ddh::consolidate(vec![file(1, "/a"), file(1, "/e"), file(2, "/c")]);
+///
+/// let group = "00000000000000000000000000000001".to_string();
+/// assert_eq!(
+///     diff_groups(&before, &after),
+///     [
+///         GroupEvent::InstanceAdded { group: group.clone(), path: PathBuf::from("/e") },
+///         GroupEvent::InstanceRemoved { group, path: PathBuf::from("/b") },
+///         GroupEvent::GroupResolved { group: "00000000000000000000000000000002".to_string() },
+///     ]
+/// );
+/// ```
+pub fn diff_groups(before: &[Fileinfo], after: &[Fileinfo]) -> Vec<GroupEvent> {
+    let before = groups_by_id(before);
+    let after = groups_by_id(after);
+    let ids: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let mut events = Vec::new();
+    for id in ids {
+        match (before.get(id), after.get(id)) {
+            (None, Some(group)) => events.push(GroupEvent::GroupAdded {
+                group: id.clone(),
+                length: group.get_length(),
+                paths: sorted_paths(group).into_iter().collect(),
+            }),
+            (Some(_), None) => events.push(GroupEvent::GroupResolved { group: id.clone() }),
+            (Some(old), Some(new)) => {
+                let (old, new) = (sorted_paths(old), sorted_paths(new));
+                events.extend(new.difference(&old).map(|path| GroupEvent::InstanceAdded {
+                    group: id.clone(),
+                    path: path.clone(),
+                }));
+                events.extend(
+                    old.difference(&new)
+                        .map(|path| GroupEvent::InstanceRemoved {
+                            group: id.clone(),
+                            path: path.clone(),
+                        }),
+                );
+            }
+            (None, None) => {}
+        }
+    }
+    events
+}
+
+/// Changes between two scans for people to read.
+#[derive(Debug, Clone, Default)]
+pub struct ScanDiff {
+    /// Groups with at least two instances only found by the later scan, sorted by group id.
+    pub new_groups: Vec<Fileinfo>,
+    /// Groups of the earlier scan with fewer than two instances left, sorted by group id.
+    pub resolved_groups: Vec<Fileinfo>,
+    /// Paths only found by the later scan, in sorted order.
+    pub appeared: Vec<PathBuf>,
+    /// Paths only found by the earlier scan, in sorted order.
+    pub disappeared: Vec<PathBuf>,
+}
+
+/// Tells whether the saved results `files`, with their `summary` if they have one, hold every
+/// file of their scan, single files as well as groups, as saved with `-v all`. Results without
+/// a summary are taken to hold groups alone when they hold no single file.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::JsonSummary;
+/// use std::path::PathBuf;
+///
+/// let file = |hash, path: &str| Fileinfo::from_existing_hash(hash, None, 10, PathBuf::from(path));
+/// let groups = ddh::consolidate(vec![file(1, "/a"), file(1, "/b")]);
+/// assert!(!ddh::events::holds_all_files(&groups, None));
+/// let summary = JsonSummary { total_files: 2, ..Default::default() };
+/// assert!(ddh::events::holds_all_files(&groups, Some(&summary)));
+/// ```
+pub fn holds_all_files(files: &[Fileinfo], summary: Option<&JsonSummary>) -> bool {
+    match summary {
+        Some(summary) => {
+            let paths: u64 = files.iter().map(|file| file.get_paths().len() as u64).sum();
+            paths == summary.total_files
+        }
+        None => files.is_empty() || files.iter().any(|file| file.get_paths().len() == 1),
+    }
+}
+
+/// Summarizes the changes from `before` to `after`. Paths can only be told to appear or
+/// disappear when both scans hold every file, as `holds_all_files` tells, so they are only
+/// compared with `compare_paths`.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::PathBuf;
+///
+/// let file = |hash, path: &str| Fileinfo::from_existing_hash(hash, None, 10, PathBuf::from(path));
+/// let before = ddh::consolidate(vec![file(1, "/a"), file(1, "/b"), file(2, "/c")]);
+/// let after = ddh::consolidate(vec![file(1, "/a"), file(2, "/c"), file(2, "/d")]);
+///
+/// let diff = ddh::events::diff_scans(&before, &after, true);
+/// assert_eq!(diff.new_groups[0].get_full_hash(), Some(2));
+/// assert_eq!(diff.resolved_groups[0].get_full_hash(), Some(1));
+/// assert_eq!(diff.appeared, [PathBuf::from("/d")]);
+/// assert_eq!(diff.disappeared, [PathBuf::from("/b")]);
+/// ```
+pub fn diff_scans(before: &[Fileinfo], after: &[Fileinfo], compare_paths: bool) -> ScanDiff {
+    let (old_groups, new_groups) = (groups_by_id(before), groups_by_id(after));
+    let only_in = |groups: &BTreeMap<String, &Fileinfo>, others: &BTreeMap<String, &Fileinfo>| {
+        groups
+            .iter()
+            .filter(|(id, _)| !others.contains_key(*id))
+            .map(|(_, group)| (*group).clone())
+            .collect()
+    };
+    let all_paths = |files: &[Fileinfo]| -> BTreeSet<PathBuf> {
+        files
+            .iter()
+            .flat_map(|file| file.get_paths().iter().map(|path| path.to_path_buf()))
+            .collect()
+    };
+    let mut diff = ScanDiff {
+        new_groups: only_in(&new_groups, &old_groups),
+        resolved_groups: only_in(&old_groups, &new_groups),
+        ..Default::default()
+    };
+    if compare_paths {
+        let (old_paths, new_paths) = (all_paths(before), all_paths(after));
+        diff.appeared = new_paths.difference(&old_paths).cloned().collect();
+        diff.disappeared = old_paths.difference(&new_paths).cloned().collect();
+    }
+    diff
+}
+
+/// Writes `events` as JSON lines.
+pub fn write_events<W: Write>(mut writer: W, events: &[GroupEvent]) -> io::Result<()> {
+    for event in events.iter() {
+        serde_json::to_writer(&mut writer, event)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+fn groups_by_id(files: &[Fileinfo]) -> BTreeMap<String, &Fileinfo> {
+    files
+        .iter()
+        .filter(|file| file.get_paths().len() > 1)
+        .filter_map(|file| file.get_group_id().map(|id| (id, file)))
+        .collect()
+}
+
+fn sorted_paths(group: &Fileinfo) -> BTreeSet<PathBuf> {
+    group
+        .get_paths()
+        .iter()
+        .map(|path| path.to_path_buf())
+        .collect()
+}
@@ -0,0 +1,70 @@
+//! Export of scan results for other storage tools.
+//!
+//! The content index lists every distinct content once as its full hash, its length and one
+//! canonical path, in the spirit of `sha256sum` style CHK files. Backup and content-addressable
+//! storage tools can use it to find what to store without hashing the tree again.
+
+use crate::copies::CopyPatterns;
+use crate::fileinfo::Fileinfo;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Writes one line per collection with a full hash: the hash as 32 hex digits, the length and
+/// the canonical path, separated by tabs and sorted by hash. The canonical path is the one a
+/// duplicate report keeps, the first clean-named path in sorted order. Backslashes, tabs and
+/// newlines in paths are escaped as `\\`, `\t` and `\n`. Collections without a full hash are
+/// skipped, so hash them first with `agent::complete_hashes`.
+///
+/// # Examples
+/// ```
+/// use ddh::copies::CopyPatterns;
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::PathBuf;
+///
+/// let files = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(0xab, None, 3, PathBuf::from("/b/report (1).pdf")),
+///     Fileinfo::from_existing_hash(0xab, None, 3, PathBuf::from("/b/report.pdf")),
+///     Fileinfo::from_existing_hash(0x1, None, 5, PathBuf::from("/a/notes\ttodo.txt")),
+/// ]);
+/// let mut out = Vec::new();
+/// ddh::export::write_content_index(&mut out, &files, &CopyPatterns::default()).unwrap();
+/// assert_eq!(
+///     String::from_utf8(out).unwrap(),
+///     "00000000000000000000000000000001\t5\t/a/notes\\ttodo.txt\n\
+///      000000000000000000000000000000ab\t3\t/b/report.pdf\n"
+/// );
+/// ```
+pub fn write_content_index<W: Write>(
+    mut writer: W,
+    files: &[Fileinfo],
+    patterns: &CopyPatterns,
+) -> io::Result<()> {
+    let mut entries: Vec<(u128, u64, PathBuf)> = files
+        .iter()
+        .filter_map(|file| {
+            let hash = file.get_full_hash()?;
+            let mut paths: Vec<PathBuf> =
+                file.get_paths().iter().map(|p| p.to_path_buf()).collect();
+            paths.sort();
+            let canonical = patterns.prefer_originals(&paths).first()?.to_path_buf();
+            Some((hash, file.get_length(), canonical))
+        })
+        .collect();
+    entries.sort();
+    for (hash, length, path) in entries {
+        writeln!(
+            writer,
+            "{:032x}\t{}\t{}",
+            hash,
+            length,
+            escape(&path.to_string_lossy())
+        )?;
+    }
+    Ok(())
+}
+
+fn escape(path: &str) -> String {
+    path.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
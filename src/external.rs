@@ -0,0 +1,139 @@
+//! Hashing with an external command, for matching the digests of an existing checksum pipeline.
+//!
+//! The command runs once per file and its digest is read from its output, so `xxhsum -H128`,
+//! `b3sum` and the `sha*sum` tools work as they are. Digests longer than 128 bits are cut to
+//! their first 128 bits. Digests made this way can't be compared with those of ddh's own
+//! hasher, so scans using a command never consult the hash cache.
+
+use crate::fileinfo::Fileinfo;
+use crate::throttle::ReadLimiter;
+use rayon::prelude::*;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Most hex digits of a digest which fit in a hash.
+const DIGEST_DIGITS: usize = 32;
+
+/// Command line producing the digest of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl HashCommand {
+    /// Runs the command on `path` and parses its digest.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[cfg(unix)]
+    /// # fn main() -> std::io::Result<()> {
+    /// use ddh::external::HashCommand;
+    /// use std::path::Path;
+    ///
+    /// let command: HashCommand = "echo 00ff {}".parse().unwrap();
+    /// assert_eq!(command.hash(Path::new("/any/file"))?, 0xff);
+    /// let failing: HashCommand = "false".parse().unwrap();
+    /// assert!(failing.hash(Path::new("/any/file")).is_err());
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(not(unix))]
+    /// # fn main() {}
+    /// ```
+    pub fn hash(&self, path: &Path) -> io::Result<u128> {
+        self.hash_limited(path, None)
+    }
+
+    /// Runs the command on `path` while holding a permit from `limiter`, so that the number of
+    /// commands reading files at once is bounded like the reads of the built-in hasher.
+    pub(crate) fn hash_limited(
+        &self,
+        path: &Path,
+        limiter: Option<&ReadLimiter>,
+    ) -> io::Result<u128> {
+        let mut command = Command::new(&self.program);
+        let mut placed = false;
+        for arg in self.args.iter() {
+            if arg == "{}" {
+                command.arg(path);
+                placed = true;
+            } else {
+                command.arg(OsStr::new(arg));
+            }
+        }
+        if !placed {
+            command.arg(path);
+        }
+        let output = {
+            let _permit = limiter.map(|l| l.acquire());
+            command.output()?
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let mut message = format!("{} failed ({})", self.program, output.status);
+            if let Some(line) = stderr.lines().find(|line| !line.trim().is_empty()) {
+                message.push_str(": ");
+                message.push_str(line.trim());
+            }
+            return Err(io::Error::other(message));
+        }
+        parse_digest(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Hashes every collection missing a full hash, like `agent::complete_hashes`. Collections
+    /// the command fails on keep no full hash and are returned with the error.
+    pub fn complete_hashes(&self, files: &mut [Fileinfo]) -> Vec<(PathBuf, io::Error)> {
+        let failures = Mutex::new(Vec::new());
+        files.par_iter_mut().for_each(|file| {
+            if file.get_full_hash().is_some() {
+                return;
+            }
+            let path = &file.get_paths()[0];
+            match self.hash(path) {
+                Ok(hash) => file.set_full_hash(Some(hash)),
+                Err(e) => failures.lock().unwrap().push((path.to_path_buf(), e)),
+            }
+        });
+        failures.into_inner().unwrap()
+    }
+}
+
+/// Parses a command line in which `{}` stands for the path of the file. Without a `{}` word the
+/// path is appended. Words are separated by whitespace; quoting isn't supported.
+impl FromStr for HashCommand {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace().map(String::from);
+        let program = words
+            .next()
+            .ok_or_else(|| "empty hash command".to_string())?;
+        Ok(HashCommand {
+            program,
+            args: words.collect(),
+        })
+    }
+}
+
+/// Reads the digest from the first line of a command's output: the first word as printed by
+/// `sha256sum` and friends, or the last one as in their BSD style `--tag` output.
+fn parse_digest(output: &str) -> io::Result<u128> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no hex digest in output {:?}", output.trim()),
+        )
+    };
+    let line = output.lines().next().ok_or_else(invalid)?;
+    let is_hex = |word: &&str| !word.is_empty() && word.bytes().all(|b| b.is_ascii_hexdigit());
+    let digest = line
+        .split_whitespace()
+        .next()
+        .filter(is_hex)
+        .or_else(|| line.split_whitespace().last().filter(is_hex))
+        .ok_or_else(invalid)?;
+    u128::from_str_radix(&digest[..digest.len().min(DIGEST_DIGITS)], 16).map_err(|_| invalid())
+}
@@ -1,26 +1,117 @@
+use crate::hasher::{ContentHasher, HashState, SipHash128};
+use crate::throttle::ReadLimiter;
+use crate::utils::ChunkIter;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
-use siphasher::sip128::Hasher128;
+use serde::Deserialize;
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::fs::{self, Metadata};
-use std::hash::Hasher;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
 
 const BLOCK_SIZE: usize = 4096;
+/* We want a read call to be "large" for two reasons
+1) Force filesystem read ahead behavior
+2) Fewer system calls for a given file.
+Currently 16KB  */
+const HASH_BUFFER_SIZE: usize = BLOCK_SIZE * 4;
+thread_local! {
+    /// Read buffer reused by every hash computed on the current thread.
+    static HASH_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+/// Files larger than this are read ahead on a second thread while being hashed.
+const PIPELINE_THRESHOLD: u64 = (HASH_BUFFER_SIZE * 64) as u64;
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HashMode {
     Full,
     Partial,
 }
 
+/// Owned, serializable subset of a file's metadata. Fields other than the size are optional so
+/// that metadata can come from sources other than the local filesystem.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, Deserialize)]
+pub struct FileMetadata {
+    /// Length of the file in bytes.
+    pub size: u64,
+    /// Modification time in seconds since the epoch.
+    pub mtime: Option<i64>,
+    /// Nanoseconds past `mtime`.
+    pub mtime_nsec: u32,
+    pub inode: Option<u64>,
+    pub device: Option<u64>,
+    /// Permission bits, as in `st_mode`.
+    pub mode: Option<u32>,
+    /// User id of the owner.
+    pub owner: Option<u32>,
+}
+
+impl FileMetadata {
+    /// Creates metadata which only records a size.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::fileinfo::FileMetadata;
+    ///
+    /// let meta = FileMetadata::with_size(3);
+    /// assert_eq!(3, meta.size);
+    /// assert_eq!(None, meta.mtime);
+    /// ```
+    pub fn with_size(size: u64) -> Self {
+        FileMetadata {
+            size,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&Metadata> for FileMetadata {
+    #[cfg(unix)]
+    fn from(meta: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        FileMetadata {
+            size: meta.len(),
+            mtime: Some(meta.mtime()),
+            mtime_nsec: meta.mtime_nsec() as u32,
+            inode: Some(meta.ino()),
+            device: Some(meta.dev()),
+            mode: Some(meta.mode()),
+            owner: Some(meta.uid()),
+        }
+    }
+    #[cfg(not(unix))]
+    fn from(meta: &Metadata) -> Self {
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+        FileMetadata {
+            size: meta.len(),
+            mtime: modified.map(|d| d.as_secs() as i64),
+            mtime_nsec: modified.map_or(0, |d| d.subsec_nanos()),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Metadata> for FileMetadata {
+    fn from(meta: Metadata) -> Self {
+        FileMetadata::from(&meta)
+    }
+}
+
 /// Serializable struct containing entries for a specific file. These structs will identify individual files as a collection of paths and associated hash and length data.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Fileinfo {
-    full_hash: Option<u128>,
-    partial_hash: Option<u128>,
-    metadata: Metadata,
-    pub(crate) file_paths: Vec<PathBuf>,
+    /* Hashes are stored as big endian bytes rather than u128 so that, without the 16 byte
+    alignment of u128, an entry for a file stays small even in scans of millions of files.
+    Big endian keeps the ordering of the packed hashes the same as that of the numbers. */
+    full_hash: Option<[u8; 16]>,
+    partial_hash: Option<[u8; 16]>,
+    metadata: FileMetadata,
+    pub(crate) file_paths: Vec<Box<Path>>,
 }
 
 impl Fileinfo {
@@ -42,12 +133,42 @@ impl Fileinfo {
     /// Ok(())
     /// }
     /// ```
-    pub fn new(full: Option<u128>, partial: Option<u128>, meta: Metadata, path: PathBuf) -> Self {
+    pub fn new<M: Into<FileMetadata>>(
+        full: Option<u128>,
+        partial: Option<u128>,
+        meta: M,
+        path: PathBuf,
+    ) -> Self {
+        Fileinfo {
+            full_hash: full.map(u128::to_be_bytes),
+            partial_hash: partial.map(u128::to_be_bytes),
+            metadata: meta.into(),
+            file_paths: vec![compact(path)],
+        }
+    }
+    /// Creates a Fileinfo collection struct from a hash and length computed elsewhere, such as
+    /// a manifest or a remote scan. No filesystem access takes place.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use ddh::fileinfo::Fileinfo;
+    ///
+    /// let fi = Fileinfo::from_existing_hash(0x7b, None, 3, PathBuf::from("/remote/foo/bar.txt"));
+    /// assert_eq!(3, fi.get_length());
+    /// assert_eq!(Some(0x7b), fi.get_full_hash());
+    /// ```
+    pub fn from_existing_hash(
+        full: u128,
+        partial: Option<u128>,
+        length: u64,
+        path: PathBuf,
+    ) -> Self {
         Fileinfo {
-            full_hash: full,
-            partial_hash: partial,
-            metadata: meta,
-            file_paths: vec![path],
+            full_hash: Some(full.to_be_bytes()),
+            partial_hash: partial.map(u128::to_be_bytes),
+            metadata: FileMetadata::with_size(length),
+            file_paths: vec![compact(path)],
         }
     }
     /// Gets the length of the files in the current collection.
@@ -66,7 +187,11 @@ impl Fileinfo {
     /// }
     /// ```
     pub fn get_length(&self) -> u64 {
-        self.metadata.len()
+        self.metadata.size
+    }
+    /// Gets the metadata of the first file inserted into the collection.
+    pub fn get_metadata(&self) -> &FileMetadata {
+        &self.metadata
     }
     /// Gets the hash of the full file if available.
     ///
@@ -84,10 +209,10 @@ impl Fileinfo {
     /// }
     /// ```
     pub fn get_full_hash(&self) -> Option<u128> {
-        self.full_hash
+        self.full_hash.map(u128::from_be_bytes)
     }
     pub(crate) fn set_full_hash(&mut self, hash: Option<u128>) {
-        self.full_hash = hash
+        self.full_hash = hash.map(u128::to_be_bytes)
     }
     /// Gets the hash of the partially read file if available.
     ///
@@ -105,10 +230,28 @@ impl Fileinfo {
     /// }
     /// ```
     pub fn get_partial_hash(&self) -> Option<u128> {
-        self.partial_hash
+        self.partial_hash.map(u128::from_be_bytes)
     }
     pub(crate) fn set_partial_hash(&mut self, hash: Option<u128>) {
-        self.partial_hash = hash
+        self.partial_hash = hash.map(u128::to_be_bytes)
+    }
+    /// Gets a stable identifier for the content of the files in the current collection.
+    /// This is the hex encoded full hash and is only available once the full hash is known.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::path::Path;
+    /// use ddh::fileinfo::Fileinfo;
+    /// use std::fs;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let fi = Fileinfo::new(Some(123), None, fs::metadata("./foo/bar.txt")?, Path::new("./foo/bar.txt").to_path_buf());
+    /// assert_eq!(Some("0000000000000000000000000000007b".to_string()), fi.get_group_id());
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn get_group_id(&self) -> Option<String> {
+        self.get_full_hash().map(|hash| format!("{:032x}", hash))
     }
     /// Gets a candidate name. This will be the name of the first file inserted into the collection and so can vary.
     ///
@@ -146,44 +289,173 @@ impl Fileinfo {
     /// fn main() -> std::io::Result<()> {
     /// let fi = Fileinfo::new(None, None, fs::metadata("./foo/bar.txt")?, Path::new("./foo/bar.txt").to_path_buf());
     /// let all_files = fi.get_paths();
-    /// assert_eq!(&vec![Path::new("./foo/bar.txt").to_path_buf()],
-    ///            all_files);
+    /// assert_eq!(all_files, &[Path::new("./foo/bar.txt").into()]);
     /// Ok(())
     /// }
     /// ```
-    pub fn get_paths(&self) -> &Vec<PathBuf> {
+    pub fn get_paths(&self) -> &[Box<Path>] {
         &self.file_paths
     }
+    /// Estimates the number of bytes held by the collection, including its paths.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::path::PathBuf;
+    /// use ddh::fileinfo::Fileinfo;
+    ///
+    /// let fi = Fileinfo::from_existing_hash(1, None, 3, PathBuf::from("/foo/bar.txt"));
+    /// let path = std::mem::size_of::<Box<std::path::Path>>() + "/foo/bar.txt".len();
+    /// assert_eq!(fi.memory_footprint(), std::mem::size_of::<Fileinfo>() + path);
+    /// ```
+    pub fn memory_footprint(&self) -> usize {
+        std::mem::size_of::<Fileinfo>()
+            + self.file_paths.capacity() * std::mem::size_of::<Box<Path>>()
+            + self
+                .file_paths
+                .iter()
+                .map(|path| path.as_os_str().len())
+                .sum::<usize>()
+    }
 
     pub fn generate_hash(&mut self, mode: HashMode) -> Option<u128> {
-        let mut hasher = siphasher::sip128::SipHasher::new();
-        match fs::File::open(
+        self.generate_hash_with(mode, &SipHash128)
+    }
+    /// Hashes the first file of the collection with `hasher` instead of the default SipHash.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::path::Path;
+    /// use ddh::fileinfo::{Fileinfo, HashMode};
+    /// use ddh::hasher::Blake3;
+    /// use std::fs;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let mut fi = Fileinfo::new(None, None, fs::metadata("./foo/bar.txt")?, Path::new("./foo/bar.txt").to_path_buf());
+    /// let hash = fi.generate_hash_with(HashMode::Full, &Blake3);
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn generate_hash_with(
+        &mut self,
+        mode: HashMode,
+        hasher: &dyn ContentHasher,
+    ) -> Option<u128> {
+        self.generate_hash_limited(mode, hasher, None)
+    }
+    pub(crate) fn generate_hash_limited(
+        &mut self,
+        mode: HashMode,
+        hasher: &dyn ContentHasher,
+        limiter: Option<&ReadLimiter>,
+    ) -> Option<u128> {
+        hash_file_with(
             self.file_paths
-                .get(0)
+                .first()
                 .expect("Cannot read file path from struct"),
-        ) {
-            Ok(mut f) => {
-                /* We want a read call to be "large" for two reasons
-                1) Force filesystem read ahead behavior
-                2) Fewer system calls for a given file.
-                Currently 16KB  */
-                let mut hash_buffer = [0; BLOCK_SIZE * 4];
-                loop {
-                    match f.read(&mut hash_buffer) {
-                        Ok(n) if n > 0 => hasher.write(&hash_buffer),
-                        Ok(n) if n == 0 => break,
-                        Err(_e) => return None,
-                        _ => panic!("Negative length read in hashing"),
-                    }
-                    if mode == HashMode::Partial {
-                        return Some(hasher.finish128().into());
-                    }
-                }
-                Some(hasher.finish128().into())
+            mode,
+            hasher,
+            limiter,
+        )
+    }
+}
+
+/// Stores a path without the spare capacity left by building it up component by component,
+/// and without a capacity field of its own.
+pub(crate) fn compact(path: PathBuf) -> Box<Path> {
+    path.into_boxed_path()
+}
+
+/// Hashes the file at `path` the same way `Fileinfo::generate_hash` does.
+/// Useful for checking that a file still matches a previously computed hash.
+pub fn hash_file(path: &Path, mode: HashMode) -> Option<u128> {
+    hash_file_limited(path, mode, None)
+}
+
+/// Hashes the file at `path`, holding a permit from `limiter` while the file is read so that
+/// the number of files read at once stays within the limit.
+pub fn hash_file_limited(
+    path: &Path,
+    mode: HashMode,
+    limiter: Option<&ReadLimiter>,
+) -> Option<u128> {
+    hash_file_with(path, mode, &SipHash128, limiter)
+}
+
+/// Hashes the file at `path` with `hasher`, holding a permit from `limiter` like
+/// `hash_file_limited`.
+pub fn hash_file_with(
+    path: &Path,
+    mode: HashMode,
+    hasher: &dyn ContentHasher,
+    limiter: Option<&ReadLimiter>,
+) -> Option<u128> {
+    let f = fs::File::open(path).ok()?;
+    let _permit = limiter.map(|l| l.acquire());
+    let mut hasher = hasher.begin();
+    let pipelined = mode == HashMode::Full
+        && f.metadata()
+            .is_ok_and(|meta| meta.len() > PIPELINE_THRESHOLD);
+    if pipelined {
+        hash_pipelined(f, hasher.as_mut())?;
+        return Some(hasher.finish());
+    }
+    let buffer = HASH_BUFFER.with(|buffer| buffer.take());
+    let mut chunks = ChunkIter::with_buffer(f, HASH_BUFFER_SIZE, buffer);
+    let mut complete = true;
+    loop {
+        match chunks.next_chunk() {
+            Some(Ok(chunk)) => hasher.update(chunk),
+            Some(Err(_e)) => {
+                complete = false;
+                break;
             }
-            Err(_e) => None,
+            None => break,
+        }
+        if mode == HashMode::Partial {
+            break;
         }
     }
+    HASH_BUFFER.with(|buffer| buffer.replace(chunks.into_buffer()));
+    if complete {
+        Some(hasher.finish())
+    } else {
+        None
+    }
+}
+
+/// Hashes the rest of `f` while a second thread reads ahead, so that one buffer is filled from
+/// disk while the previous one is hashed. Two buffers travel back and forth between the stages.
+/// These are allocated per file, which is negligible next to reading a file this large.
+fn hash_pipelined(mut f: fs::File, hasher: &mut dyn HashState) -> Option<()> {
+    let (filled_tx, filled_rx) = mpsc::sync_channel::<std::io::Result<(Vec<u8>, usize)>>(1);
+    let (empty_tx, empty_rx) = mpsc::sync_channel::<Vec<u8>>(2);
+    for _ in 0..2 {
+        empty_tx
+            .send(vec![0; HASH_BUFFER_SIZE])
+            .expect("Error queueing hash buffer");
+    }
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            while let Ok(mut buffer) = empty_rx.recv() {
+                let read = f.read(&mut buffer);
+                let done = !matches!(read, Ok(n) if n > 0);
+                if filled_tx.send(read.map(|n| (buffer, n))).is_err() || done {
+                    return;
+                }
+            }
+        });
+        while let Ok(read) = filled_rx.recv() {
+            match read {
+                Ok((_, 0)) => return Some(()),
+                Ok((buffer, n)) => {
+                    hasher.update(&buffer[..n]);
+                    let _ = empty_tx.send(buffer);
+                }
+                Err(_e) => return None,
+            }
+        }
+        None
+    })
 }
 
 impl Serialize for Fileinfo {
@@ -191,15 +463,42 @@ impl Serialize for Fileinfo {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Fileinfo", 4)?;
-        state.serialize_field("partial_hash", &self.partial_hash)?;
-        state.serialize_field("full_hash", &self.full_hash)?;
+        let mut state = serializer.serialize_struct("Fileinfo", 5)?;
+        state.serialize_field("group_id", &self.get_group_id())?;
+        state.serialize_field("partial_hash", &self.get_partial_hash())?;
+        state.serialize_field("full_hash", &self.get_full_hash())?;
         state.serialize_field("file_length", &self.get_length())?;
         state.serialize_field("file_paths", &self.file_paths)?;
         state.end()
     }
 }
 
+/* Mirrors the fields written by `Serialize`. The group id is derived from the full hash. */
+#[derive(Deserialize)]
+struct SavedFileinfo {
+    partial_hash: Option<u128>,
+    full_hash: Option<u128>,
+    file_length: u64,
+    file_paths: Vec<PathBuf>,
+}
+
+/// Reads collections saved as JSON output. Only the length of the files is restored from
+/// their metadata.
+impl<'de> Deserialize<'de> for Fileinfo {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let saved = SavedFileinfo::deserialize(deserializer)?;
+        Ok(Fileinfo {
+            full_hash: saved.full_hash.map(u128::to_be_bytes),
+            partial_hash: saved.partial_hash.map(u128::to_be_bytes),
+            metadata: FileMetadata::with_size(saved.file_length),
+            file_paths: saved.file_paths.into_iter().map(compact).collect(),
+        })
+    }
+}
+
 impl PartialEq for Fileinfo {
     fn eq(&self, other: &Fileinfo) -> bool {
         (self.get_length() == other.get_length())
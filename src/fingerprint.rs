@@ -0,0 +1,292 @@
+//! Merkle fingerprints of directory trees, for comparing trees which aren't both at hand.
+//!
+//! A fingerprint holds the hash and size of every file and directory below a root. The hash of
+//! a directory covers the names, kinds and hashes of its entries, so two subtrees with equal
+//! hashes are identical and a comparison never needs to look below them. Fingerprints are
+//! written as text, one entry per line, and hold no file contents, so a fingerprint of an
+//! archive shipped offsite can be checked against one taken later on the other side.
+//!
+//! Symlinks are left out. Names which aren't valid UTF-8 are stored lossily.
+
+use crate::fileinfo::{hash_file, HashMode};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, BufRead, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+/// First line of a fingerprint file, naming its format version.
+const HEADER: &str = "ddh-fingerprint 1";
+
+/// Kind of an entry of a fingerprint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    File,
+    Dir,
+}
+
+/// Hash and size of a file, or of everything below a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub hash: u128,
+    pub size: u64,
+}
+
+/// Nodes of a tree keyed by their path relative to its root, which is the empty path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    nodes: BTreeMap<PathBuf, Node>,
+}
+
+/// Differences between two fingerprints, each reported at the highest path it applies to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Comparison {
+    /// Subtrees and files which are the same in both.
+    pub identical: Vec<PathBuf>,
+    /// Paths only in the first fingerprint.
+    pub missing: Vec<PathBuf>,
+    /// Paths only in the second fingerprint.
+    pub added: Vec<PathBuf>,
+    /// Files which differ, or paths which are a file in one and a directory in the other.
+    pub changed: Vec<PathBuf>,
+}
+
+impl Fingerprint {
+    /// Fingerprints the tree below `root`, hashing files in parallel. Entries which can't be
+    /// read are left out and returned with their error.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::fingerprint::{compare, Fingerprint};
+    /// use std::path::PathBuf;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let dir = std::env::temp_dir().join("ddh_fingerprint_example");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// for tree in ["a", "b"].iter() {
+    ///     std::fs::create_dir_all(dir.join(tree).join("docs"))?;
+    ///     std::fs::create_dir_all(dir.join(tree).join("photos"))?;
+    ///     std::fs::write(dir.join(tree).join("docs/report.txt"), "q3")?;
+    ///     std::fs::write(dir.join(tree).join("photos/cat.jpg"), "meow")?;
+    /// }
+    /// std::fs::write(dir.join("b/photos/cat.jpg"), "purr")?;
+    /// std::fs::write(dir.join("b/photos/dog.jpg"), "woof")?;
+    ///
+    /// let (a, errors) = Fingerprint::of_dir(&dir.join("a"))?;
+    /// assert!(errors.is_empty());
+    /// let mut saved = Vec::new();
+    /// a.write_to(&mut saved)?;
+    /// let a = Fingerprint::read_from(&saved[..])?;
+    /// let (b, _) = Fingerprint::of_dir(&dir.join("b"))?;
+    ///
+    /// let comparison = compare(&a, &b);
+    /// assert_eq!(comparison.identical, vec![PathBuf::from("docs")]);
+    /// assert_eq!(comparison.changed, vec![PathBuf::from("photos/cat.jpg")]);
+    /// assert_eq!(comparison.added, vec![PathBuf::from("photos/dog.jpg")]);
+    /// assert!(comparison.missing.is_empty());
+    /// std::fs::remove_dir_all(&dir)?;
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn of_dir(root: &Path) -> io::Result<(Self, Vec<(PathBuf, io::Error)>)> {
+        let mut dirs = vec![PathBuf::new()];
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        let mut pending = vec![PathBuf::new()];
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(root.join(&dir)) {
+                Ok(entries) => entries,
+                Err(e) if dir.as_os_str().is_empty() => return Err(e),
+                Err(e) => {
+                    errors.push((root.join(&dir), e));
+                    continue;
+                }
+            };
+            for entry in entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        errors.push((root.join(&dir), e));
+                        continue;
+                    }
+                };
+                let relative = dir.join(entry.file_name().to_string_lossy().as_ref());
+                match entry.file_type() {
+                    Ok(kind) if kind.is_dir() => {
+                        dirs.push(relative.clone());
+                        pending.push(relative);
+                    }
+                    Ok(kind) if kind.is_file() => files.push(relative),
+                    Ok(_) => {}
+                    Err(e) => errors.push((entry.path(), e)),
+                }
+            }
+        }
+        let hashed: Vec<(PathBuf, io::Result<Node>)> = files
+            .into_par_iter()
+            .map(|relative| {
+                let path = root.join(&relative);
+                let node = fs::metadata(&path).and_then(|meta| {
+                    let hash = hash_file(&path, HashMode::Full)
+                        .ok_or_else(|| io::Error::other("could not read file"))?;
+                    Ok(Node {
+                        kind: NodeKind::File,
+                        hash,
+                        size: meta.len(),
+                    })
+                });
+                (relative, node)
+            })
+            .collect();
+        let mut fingerprint = Fingerprint::default();
+        for (relative, node) in hashed {
+            match node {
+                Ok(node) => {
+                    fingerprint.nodes.insert(relative, node);
+                }
+                Err(e) => errors.push((root.join(relative), e)),
+            }
+        }
+        /* Deepest directories first, so every child is done before its parent. */
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        for dir in dirs {
+            let node = fingerprint.hash_dir(&dir);
+            fingerprint.nodes.insert(dir, node);
+        }
+        Ok((fingerprint, errors))
+    }
+
+    /// Gets the node of the whole tree.
+    pub fn root(&self) -> Option<&Node> {
+        self.nodes.get(Path::new(""))
+    }
+
+    /// Gets the node at `path`, relative to the root.
+    pub fn get(&self, path: &Path) -> Option<&Node> {
+        self.nodes.get(path)
+    }
+
+    /// Writes one line per node sorted by path: the hash as 32 hex digits, the size, `d` or `f`
+    /// and the relative path, separated by tabs. Backslashes, tabs and newlines in paths are
+    /// escaped as `\\`, `\t` and `\n`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        for (path, node) in self.nodes.iter() {
+            let kind = match node.kind {
+                NodeKind::File => 'f',
+                NodeKind::Dir => 'd',
+            };
+            writeln!(
+                writer,
+                "{:032x}\t{}\t{}\t{}",
+                node.hash,
+                node.size,
+                kind,
+                escape(&path.to_string_lossy())
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads a fingerprint written by `write_to`.
+    pub fn read_from<R: BufRead>(reader: R) -> io::Result<Self> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid("not a ddh fingerprint"));
+        }
+        let mut fingerprint = Fingerprint::default();
+        for line in lines {
+            let line = line?;
+            let mut fields = line.splitn(4, '\t');
+            let mut field = || fields.next().ok_or_else(|| invalid("truncated line"));
+            let hash = u128::from_str_radix(field()?, 16).map_err(|_| invalid("malformed hash"))?;
+            let size = field()?.parse().map_err(|_| invalid("malformed size"))?;
+            let kind = match field()? {
+                "f" => NodeKind::File,
+                "d" => NodeKind::Dir,
+                _ => return Err(invalid("unknown entry kind")),
+            };
+            let path = PathBuf::from(unescape(field()?));
+            fingerprint.nodes.insert(path, Node { kind, hash, size });
+        }
+        Ok(fingerprint)
+    }
+
+    /// Gets the names of the entries directly below `dir`.
+    fn children<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = &'a Path> + 'a {
+        /* Paths sort component by component, so everything below `dir` directly follows it. */
+        self.nodes
+            .range::<Path, _>((Bound::Excluded(dir), Bound::Unbounded))
+            .map(|(path, _)| path.as_path())
+            .take_while(move |path| path.starts_with(dir))
+            .filter(move |path| path.parent() == Some(dir))
+    }
+
+    fn hash_dir(&self, dir: &Path) -> Node {
+        let mut hasher = siphasher::sip128::SipHasher::new();
+        let mut size = 0;
+        for child in self.children(dir) {
+            let node = self.nodes[child];
+            let name = child.file_name().unwrap_or_default().to_string_lossy();
+            hasher.write(name.as_bytes());
+            hasher.write_u8(0);
+            hasher.write_u8(node.kind as u8);
+            hasher.write(&node.hash.to_be_bytes());
+            size += node.size;
+        }
+        Node {
+            kind: NodeKind::Dir,
+            hash: siphasher::sip128::Hasher128::finish128(&hasher).into(),
+            size,
+        }
+    }
+}
+
+/// Compares fingerprint `a` with `b`, descending only into directories whose hashes differ.
+pub fn compare(a: &Fingerprint, b: &Fingerprint) -> Comparison {
+    let mut comparison = Comparison::default();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(path) = pending.pop() {
+        match (a.get(&path), b.get(&path)) {
+            (Some(x), Some(y)) if x == y => comparison.identical.push(path),
+            (Some(x), Some(y)) if x.kind == NodeKind::Dir && y.kind == NodeKind::Dir => {
+                let children: BTreeSet<&Path> =
+                    a.children(&path).chain(b.children(&path)).collect();
+                pending.extend(children.into_iter().rev().map(Path::to_path_buf));
+            }
+            (Some(_), Some(_)) => comparison.changed.push(path),
+            (Some(_), None) => comparison.missing.push(path),
+            (None, Some(_)) => comparison.added.push(path),
+            (None, None) => {}
+        }
+    }
+    comparison
+}
+
+fn escape(path: &str) -> String {
+    path.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
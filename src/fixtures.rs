@@ -0,0 +1,368 @@
+//! Reproducible directory trees for testing and for reproducing bug reports.
+//!
+//! A tree is fully determined by its `FixtureSpec`, so the same seed always yields the same
+//! names, contents and links. Trees mix duplicate groups spread over nested directories,
+//! unique files sharing a length with a group (so partial hashing is exercised), hardlinks,
+//! symlinks, sparse files and names which are awkward for shells and terminals.
+//!
+//! Only built with the `dev-tools` feature.
+
+use crate::ScanOptions;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Directories below the fixture root which files are spread over.
+const DIRS: &[&str] = &["originals", "backup", "backup/old", "misc/nested/deep"];
+
+/// Prefixes and suffixes wrapped around a counter to build awkward file names.
+const WEIRD_NAMES: &[(&str, &str)] = &[
+    (" leading-space-", ""),
+    ("trailing-space-", " "),
+    ("-dash-", ""),
+    ("ünïcødé-", ""),
+    ("日本語-", ""),
+    ("tab\t", ""),
+    ("semi;colon-", ""),
+    ("comma,", ""),
+    ("#hash-", ""),
+    ("..dots-", ""),
+    ("", ".tar.gz.part"),
+];
+
+/// Shape of a generated tree.
+#[derive(Debug, Clone)]
+pub struct FixtureSpec {
+    /// Seed for names, sizes and contents.
+    pub seed: u64,
+    /// Number of duplicate groups.
+    pub groups: usize,
+    /// Instances per duplicate group, at least two.
+    pub copies: usize,
+    /// Number of files without a duplicate.
+    pub unique: usize,
+    /// Largest size of a regular file in bytes.
+    pub max_size: usize,
+    /// Add a hardlink to the first instance of every fourth group.
+    pub hardlinks: bool,
+    /// Add symlinks to files and a symlink looping back to the root (unix only).
+    pub symlinks: bool,
+    /// Length of a pair of identical sparse files plus one differing in its last byte.
+    pub sparse_size: Option<u64>,
+    /// Give every third group and unique file an awkward name.
+    pub weird_names: bool,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        FixtureSpec {
+            seed: 0,
+            groups: 8,
+            copies: 3,
+            unique: 8,
+            max_size: 64 * 1024,
+            hardlinks: true,
+            symlinks: true,
+            sparse_size: None,
+            weird_names: true,
+        }
+    }
+}
+
+/// What a generated tree holds.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FixtureReport {
+    /// Regular files written, not counting hardlinks.
+    pub files: usize,
+    /// Hardlinks created.
+    pub hardlinks: usize,
+    /// Symlinks created.
+    pub symlinks: usize,
+    /// Number of duplicate groups a scan of the tree should find.
+    pub duplicate_groups: usize,
+}
+
+/// Builds the tree described by `spec` below `root`, which must not exist yet or be empty.
+///
+/// # Examples
+/// ```
+/// use ddh::fixtures::{generate, FixtureSpec};
+///
+/// fn main() -> std::io::Result<()> {
+/// let root = std::env::temp_dir().join("ddh_fixture_example");
+/// let _ = std::fs::remove_dir_all(&root);
+/// let spec = FixtureSpec { seed: 42, ..Default::default() };
+/// let report = generate(&root, &spec)?;
+/// let (files, errors) = ddh::deduplicate_dirs(vec![&root], vec![], 0);
+/// assert!(errors.is_empty());
+/// let groups = files.iter().filter(|f| f.get_paths().len() > 1).count();
+/// assert_eq!(groups, report.duplicate_groups);
+/// std::fs::remove_dir_all(&root)?;
+/// Ok(())
+/// }
+/// ```
+pub fn generate(root: &Path, spec: &FixtureSpec) -> io::Result<FixtureReport> {
+    let mut rng = SplitMix64(spec.seed);
+    let mut report = FixtureReport::default();
+    let mut counter = 0;
+    let mut name = |rng: &mut SplitMix64, weird: bool| {
+        counter += 1;
+        let dir = root.join(DIRS[rng.below(DIRS.len() as u64) as usize]);
+        dir.join(file_name(counter, weird, rng))
+    };
+    fs::create_dir_all(root)?;
+    for dir in DIRS.iter() {
+        fs::create_dir_all(root.join(dir))?;
+    }
+
+    let mut group_contents = Vec::with_capacity(spec.groups);
+    for group in 0..spec.groups {
+        let contents = rng.contents(spec.max_size);
+        let weird = spec.weird_names && group % 3 == 0;
+        let mut first = None;
+        for _ in 0..spec.copies.max(2) {
+            let path = name(&mut rng, weird);
+            fs::write(&path, &contents)?;
+            report.files += 1;
+            first.get_or_insert(path);
+        }
+        let first = first.unwrap_or_default();
+        if spec.hardlinks && group % 4 == 0 {
+            fs::hard_link(&first, name(&mut rng, false))?;
+            report.hardlinks += 1;
+        }
+        if spec.symlinks && group % 4 == 1 {
+            report.symlinks += symlink(root, &first, &name(&mut rng, false))?;
+        }
+        group_contents.push(contents);
+        report.duplicate_groups += 1;
+    }
+
+    for unique in 0..spec.unique {
+        let mut contents = match group_contents.get(unique % group_contents.len().max(1)) {
+            /* Same length and start as a group but a different last byte. */
+            Some(group) if unique % 2 == 0 => group.clone(),
+            _ => rng.contents(spec.max_size),
+        };
+        if let Some(last) = contents.last_mut() {
+            *last = last.wrapping_add(1 + unique as u8);
+        }
+        fs::write(
+            name(&mut rng, spec.weird_names && unique % 3 == 0),
+            &contents,
+        )?;
+        report.files += 1;
+    }
+
+    if let Some(size) = spec.sparse_size.filter(|&size| size > 0) {
+        for last_byte in [0u8, 0, 1].iter() {
+            let file = File::create(name(&mut rng, false))?;
+            file.set_len(size)?;
+            if *last_byte != 0 {
+                let mut file = file;
+                file.seek(SeekFrom::End(-1))?;
+                file.write_all(&[*last_byte])?;
+            }
+            report.files += 1;
+        }
+        report.duplicate_groups += 1;
+    }
+
+    if spec.symlinks {
+        report.symlinks += symlink(root, root, &root.join("misc/loop"))?;
+    }
+    Ok(report)
+}
+
+/// Lists every path below `root` in sorted order, for comparing generated trees.
+///
+/// # Examples
+/// ```
+/// use ddh::fixtures::{generate, list_tree, FixtureSpec};
+///
+/// fn main() -> std::io::Result<()> {
+/// let dir = std::env::temp_dir();
+/// let (a, b) = (dir.join("ddh_fixture_same_a"), dir.join("ddh_fixture_same_b"));
+/// let _ = (std::fs::remove_dir_all(&a), std::fs::remove_dir_all(&b));
+/// let spec = FixtureSpec { seed: 7, symlinks: false, ..Default::default() };
+/// generate(&a, &spec)?;
+/// generate(&b, &spec)?;
+/// let names = |root| -> std::io::Result<Vec<_>> {
+///     Ok(list_tree(root)?.into_iter().map(|p| p.strip_prefix(root).unwrap().to_owned()).collect())
+/// };
+/// assert_eq!(names(&a)?, names(&b)?);
+/// std::fs::remove_dir_all(&a)?;
+/// std::fs::remove_dir_all(&b)?;
+/// Ok(())
+/// }
+/// ```
+pub fn list_tree(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            }
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Scans `root` with `options` and describes the results in a form which only depends on the
+/// tree, for snapshot tests of output which must not regress: paths are relative to `root` and
+/// sorted, groups are ordered by length and hash, and nothing depends on timing or thread
+/// scheduling.
+///
+/// # Examples
+/// ```
+/// use ddh::fixtures::{generate, stable_report, FixtureSpec};
+///
+/// fn main() -> std::io::Result<()> {
+/// let dir = std::env::temp_dir();
+/// let (a, b) = (dir.join("ddh_fixture_report_a"), dir.join("ddh_fixture_report_b"));
+/// let _ = (std::fs::remove_dir_all(&a), std::fs::remove_dir_all(&b));
+/// let spec = FixtureSpec { seed: 3, symlinks: false, ..Default::default() };
+/// generate(&a, &spec)?;
+/// generate(&b, &spec)?;
+/// let options = ddh::ScanOptions::default();
+/// let report = stable_report(&a, &options)?;
+/// assert!(report.starts_with("duplicates\n"));
+/// assert_eq!(report, stable_report(&b, &options)?);
+/// std::fs::remove_dir_all(&a)?;
+/// std::fs::remove_dir_all(&b)?;
+/// Ok(())
+/// }
+/// ```
+pub fn stable_report(root: &Path, options: &ScanOptions) -> io::Result<String> {
+    let root = root.canonicalize()?;
+    let relative = |path: &Path| {
+        path.strip_prefix(&root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    };
+    let (files, errors) = crate::deduplicate_dirs_with_options(vec![&root], Vec::new(), options);
+    let mut groups: Vec<(u64, String, Vec<String>)> = files
+        .iter()
+        .map(|file| {
+            let mut paths: Vec<String> = file.get_paths().iter().map(|p| relative(p)).collect();
+            paths.sort();
+            (
+                file.get_length(),
+                file.get_group_id().unwrap_or_default(),
+                paths,
+            )
+        })
+        .collect();
+    groups.sort();
+    let mut errors: Vec<String> = errors
+        .iter()
+        .map(|(path, e)| format!("{}: {:?}", relative(path), e.kind()))
+        .collect();
+    errors.sort();
+    let mut report = String::from("duplicates\n");
+    for (length, id, paths) in groups.iter().filter(|group| group.2.len() > 1) {
+        report.push_str(&format!("{} {}\n", length, id));
+        for path in paths.iter() {
+            report.push_str(&format!("\t{}\n", path));
+        }
+    }
+    report.push_str("singletons\n");
+    for (length, _, paths) in groups.iter().filter(|group| group.2.len() == 1) {
+        report.push_str(&format!("{} {}\n", length, paths[0]));
+    }
+    report.push_str("errors\n");
+    for error in errors.iter() {
+        report.push_str(&format!("{}\n", error));
+    }
+    Ok(report)
+}
+
+fn file_name(counter: usize, weird: bool, rng: &mut SplitMix64) -> OsString {
+    if !weird {
+        return format!("file-{:04}.bin", counter).into();
+    }
+    match rng.below(WEIRD_NAMES.len() as u64 + 2) as usize {
+        n if n < WEIRD_NAMES.len() => {
+            let (prefix, suffix) = WEIRD_NAMES[n];
+            format!("{}{}{}", prefix, counter, suffix).into()
+        }
+        n => unix_only_name(counter, n == WEIRD_NAMES.len()),
+    }
+}
+
+/// Builds a name containing a newline or, without `newline`, one which isn't valid UTF-8.
+#[cfg(unix)]
+fn unix_only_name(counter: usize, newline: bool) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    if newline {
+        return format!("new\nline-{}", counter).into();
+    }
+    let mut name = b"invalid-\xff-".to_vec();
+    name.extend(counter.to_string().bytes());
+    OsString::from_vec(name)
+}
+
+#[cfg(not(unix))]
+fn unix_only_name(counter: usize, _newline: bool) -> OsString {
+    format!("not-unix-{}", counter).into()
+}
+
+/// Links `link` to `target`, both below `root`, with a relative target so trees built in
+/// different places are identical.
+#[cfg(unix)]
+fn symlink(root: &Path, target: &Path, link: &Path) -> io::Result<usize> {
+    let outside = || io::Error::new(io::ErrorKind::InvalidInput, "link outside of fixture root");
+    let link_dir = link
+        .parent()
+        .and_then(|dir| dir.strip_prefix(root).ok())
+        .ok_or_else(outside)?;
+    let mut relative: PathBuf = link_dir.components().map(|_| "..").collect();
+    let target = target.strip_prefix(root).map_err(|_| outside())?;
+    if !target.as_os_str().is_empty() {
+        relative.push(target);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    std::os::unix::fs::symlink(relative, link).map(|_| 1)
+}
+
+#[cfg(not(unix))]
+fn symlink(_root: &Path, _target: &Path, _link: &Path) -> io::Result<usize> {
+    Ok(0)
+}
+
+/// Small deterministic generator so fixtures don't depend on a random number crate.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+    /// Gets between one and `max_len` random bytes.
+    fn contents(&mut self, max_len: usize) -> Vec<u8> {
+        let len = 1 + self.below(max_len.max(1) as u64) as usize;
+        self.bytes(len)
+    }
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(len + 8);
+        while bytes.len() < len {
+            bytes.extend_from_slice(&self.next().to_le_bytes());
+        }
+        bytes.truncate(len);
+        bytes
+    }
+}
@@ -0,0 +1,333 @@
+//! Formatting of counts, sizes and durations for human readable output.
+//!
+//! Counts are grouped by thousands using the separator of the user's locale, taken from
+//! `LC_ALL`, `LC_NUMERIC` or `LANG` in that order. SI style grouping with spaces gives the same
+//! output regardless of locale.
+//!
+//! Points in time given by users are parsed by `parse_time`, either as an age or as a date.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Languages which group digits with a period.
+const DOT_LANGUAGES: &[&str] = &[
+    "da", "de", "el", "es", "hr", "id", "it", "nl", "pt", "ro", "sl", "tr",
+];
+/// Languages which group digits with a space.
+const SPACE_LANGUAGES: &[&str] = &[
+    "bg", "cs", "fi", "fr", "hu", "nb", "nn", "no", "pl", "ru", "sk", "sv", "uk",
+];
+
+/// How large counts are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    separator: Option<char>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            separator: Some(','),
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Creates a format which groups digits with `separator`.
+    pub fn with_separator(separator: char) -> Self {
+        NumberFormat {
+            separator: Some(separator),
+        }
+    }
+    /// Creates a format which writes counts without grouping.
+    pub fn plain() -> Self {
+        NumberFormat { separator: None }
+    }
+    /// Creates the SI format, which groups digits with spaces.
+    pub fn si() -> Self {
+        NumberFormat::with_separator(' ')
+    }
+    /// Picks the separator used by `locale`, given as in `LANG` (e.g. `de_DE.UTF-8`). The C and
+    /// POSIX locales don't group digits, and unknown languages use a comma.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::format::NumberFormat;
+    ///
+    /// assert_eq!(NumberFormat::from_locale("en_US.UTF-8").format(4812), "4,812");
+    /// assert_eq!(NumberFormat::from_locale("de_DE.UTF-8").format(1234567), "1.234.567");
+    /// assert_eq!(NumberFormat::from_locale("fr_FR").format(1234567), "1 234 567");
+    /// assert_eq!(NumberFormat::from_locale("de_CH").format(4812), "4'812");
+    /// assert_eq!(NumberFormat::from_locale("C").format(4812), "4812");
+    /// ```
+    pub fn from_locale(locale: &str) -> Self {
+        let locale = locale.split(['.', '@']).next().unwrap_or_default();
+        if locale.is_empty() || locale == "C" || locale == "POSIX" {
+            return NumberFormat::plain();
+        }
+        let (language, region) = locale.split_once('_').unwrap_or((locale, ""));
+        match (language, region) {
+            ("de", "CH") | ("it", "CH") | ("rm", _) => NumberFormat::with_separator('\''),
+            _ if DOT_LANGUAGES.contains(&language) => NumberFormat::with_separator('.'),
+            _ if SPACE_LANGUAGES.contains(&language) => NumberFormat::si(),
+            _ => NumberFormat::default(),
+        }
+    }
+    /// Picks the separator of the locale set in the environment.
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .map(|locale| NumberFormat::from_locale(&locale))
+            .unwrap_or_default()
+    }
+    /// Writes `n` with its digits grouped by thousands.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::format::NumberFormat;
+    ///
+    /// assert_eq!(NumberFormat::default().format(999), "999");
+    /// assert_eq!(NumberFormat::default().format(1000), "1,000");
+    /// assert_eq!(NumberFormat::si().format(18446744073709551615), "18 446 744 073 709 551 615");
+    /// ```
+    pub fn format(&self, n: u64) -> String {
+        let digits = n.to_string();
+        let separator = match self.separator {
+            Some(separator) => separator,
+            None => return digits,
+        };
+        let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+}
+
+/// Writes `duration` with its two largest units, as in `2m10s` or `1h03m`. Durations under a
+/// second are written in milliseconds.
+///
+/// # Examples
+/// ```
+/// use ddh::format::format_duration;
+/// use std::time::Duration;
+///
+/// assert_eq!(format_duration(Duration::from_millis(850)), "850ms");
+/// assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+/// assert_eq!(format_duration(Duration::from_secs(130)), "2m10s");
+/// assert_eq!(format_duration(Duration::from_secs(18 * 60 + 42)), "18m42s");
+/// assert_eq!(format_duration(Duration::from_secs(3780)), "1h03m");
+/// ```
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0 => format!("{}ms", duration.subsec_millis()),
+        1..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Writes `bytes` in the largest binary unit it makes at least one of, with one decimal past
+/// bytes, as in `512 B`, `1.5 KiB` or `2.0 GiB`.
+///
+/// # Examples
+/// ```
+/// use ddh::format::format_size;
+///
+/// assert_eq!(format_size(512), "512 B");
+/// assert_eq!(format_size(1536), "1.5 KiB");
+/// assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Writes a time given in seconds since the Unix epoch as a UTC date and time, as in
+/// `2024-02-29 12:30:00`.
+///
+/// # Examples
+/// ```
+/// use ddh::format::format_time;
+///
+/// assert_eq!(format_time(0), "1970-01-01 00:00:00");
+/// assert_eq!(format_time(1_709_209_800), "2024-02-29 12:30:00");
+/// assert_eq!(format_time(-1), "1969-12-31 23:59:59");
+/// ```
+pub fn format_time(secs: i64) -> String {
+    let (days, clock) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        clock / 3600,
+        clock % 3600 / 60,
+        clock % 60
+    )
+}
+
+/// Parses `spec` as a point in time: an age before `now` such as `30d`, in seconds (`s`),
+/// minutes (`m`), hours (`h`), days (`d`), weeks (`w`) or years of 365 days (`y`), or an RFC
+/// 3339 date such as `2024-01-31`, `2024-01-31T18:00:00Z` or `2024-01-31T18:00:00+02:00`.
+/// Dates without a time mean midnight UTC.
+///
+/// # Examples
+/// ```
+/// use ddh::format::parse_time;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let now = UNIX_EPOCH + Duration::from_secs(100 * 86_400);
+/// assert_eq!(parse_time("30d", now), Ok(UNIX_EPOCH + Duration::from_secs(70 * 86_400)));
+/// assert_eq!(parse_time("12h", now), Ok(now - Duration::from_secs(12 * 3600)));
+/// assert_eq!(parse_time("1970-01-02", now), Ok(UNIX_EPOCH + Duration::from_secs(86_400)));
+/// assert_eq!(
+///     parse_time("1970-01-02T01:00:00+01:00", now),
+///     Ok(UNIX_EPOCH + Duration::from_secs(86_400))
+/// );
+/// assert_eq!(
+///     parse_time("2024-02-29T12:30:00Z", now),
+///     Ok(UNIX_EPOCH + Duration::from_secs(1_709_209_800))
+/// );
+/// assert!(parse_time("30 days", now).is_err());
+/// assert!(parse_time("2024-02-30", now).is_err());
+/// ```
+pub fn parse_time(spec: &str, now: SystemTime) -> Result<SystemTime, String> {
+    let invalid = || {
+        format!(
+            "invalid time {:?}, expected an age like 30d or a date",
+            spec
+        )
+    };
+    let spec = spec.trim();
+    if let Some(unit) = spec.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        let amount = &spec[..spec.len() - 1];
+        if !amount.is_empty() && amount.bytes().all(|b| b.is_ascii_digit()) {
+            let unit_secs: u64 = match unit {
+                's' => 1,
+                'm' => 60,
+                'h' => 3600,
+                'd' => 86_400,
+                'w' => 7 * 86_400,
+                'y' => 365 * 86_400,
+                _ => return Err(invalid()),
+            };
+            let age = amount
+                .parse::<u64>()
+                .ok()
+                .and_then(|amount| amount.checked_mul(unit_secs))
+                .ok_or_else(invalid)?;
+            return now
+                .checked_sub(Duration::from_secs(age))
+                .ok_or_else(invalid);
+        }
+    }
+    let secs = parse_rfc3339(spec).ok_or_else(invalid)?;
+    let time = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+    };
+    time.ok_or_else(invalid)
+}
+
+/// Seconds since the Unix epoch of a date, or a date and time with an offset. Fractions of
+/// seconds are dropped.
+fn parse_rfc3339(spec: &str) -> Option<i64> {
+    let number = |digits: &str| -> Option<i64> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    let (date, time) = match spec.find(['T', 't', ' ']) {
+        Some(split) => (&spec[..split], Some(&spec[split + 1..])),
+        None => (spec, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year = number(parts.next()?)?;
+    let month = number(parts.next()?)?;
+    let day = number(parts.next()?)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut secs = days_from_civil(year, month, day) * 86_400;
+    if let Some(time) = time {
+        let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(split) => (&time[..split], &time[split..]),
+            None => return None,
+        };
+        let clock = clock.split('.').next()?;
+        let mut fields = clock.splitn(3, ':');
+        let hours = number(fields.next()?)?;
+        let minutes = number(fields.next()?)?;
+        let seconds = number(fields.next()?)?;
+        if hours > 23 || minutes > 59 || seconds > 60 {
+            return None;
+        }
+        secs += hours * 3600 + minutes * 60 + seconds;
+        if !offset.eq_ignore_ascii_case("z") {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (offset_hours, offset_minutes) = offset[1..].split_once(':')?;
+            let (offset_hours, offset_minutes) = (number(offset_hours)?, number(offset_minutes)?);
+            if offset_hours > 23 || offset_minutes > 59 {
+                return None;
+            }
+            secs -= sign * (offset_hours * 3600 + offset_minutes * 60);
+        }
+    }
+    Some(secs)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Date of the proleptic Gregorian calendar lying `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,289 @@
+//! Checksums kept by the filesystem, to tell files apart without reading them (experimental).
+//!
+//! btrfs stores a checksum of every data sector in its checksum tree. Files of equal length
+//! whose sector checksums differ can't have equal contents, so a scan can leave files with a
+//! unique sequence of checksums out of hashing altogether; equal sequences still have their
+//! contents hashed, since checksums such as crc32c collide easily. On CoW filesystems full of
+//! large files of equal length this spares most reads.
+//!
+//! Reading the checksum tree needs `CAP_SYS_ADMIN`. Files on other filesystems, files which
+//! are compressed, inline, sparse, preallocated, not yet written back or stored without
+//! checksums, and every file where the ioctls fail, have no checksums and are hashed as usual.
+//! ZFS offers no way to get at its block checksums short of `zdb`, so it isn't supported.
+//!
+//! Looking up checksums counts as a read for the read limits and pauses of a scan.
+
+use crate::fileinfo::Fileinfo;
+use crate::HashContext;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Hashes the checksums btrfs keeps for the sectors of the file at `path`, in file order,
+/// along with the checksum algorithm and sector size they were computed with. Files with equal
+/// contents and lengths on filesystems using the same algorithm and sector size get the same
+/// value, files on filesystems using different ones never do. `None` where the checksums aren't
+/// available, as described in the module.
+///
+/// # Examples
+/// ```no_run
+/// use std::path::Path;
+///
+/// let a = ddh::fschecksums::content_checksum(Path::new("/mnt/btrfs/a.iso"));
+/// let b = ddh::fschecksums::content_checksum(Path::new("/mnt/btrfs/b.iso"));
+/// if let (Some(a), Some(b)) = (a, b) {
+///     println!("the contents {} differ", if a == b { "may not" } else { "do" });
+/// }
+/// ```
+#[cfg(target_os = "linux")]
+pub fn content_checksum(path: &Path) -> Option<u128> {
+    use siphasher::sip128::Hasher128;
+    use std::fs::File;
+    use std::hash::Hasher;
+    use std::os::unix::io::AsRawFd;
+    let file = File::open(path).ok()?;
+    let length = file.metadata().ok()?.len();
+    let fd = file.as_raw_fd();
+    let info = btrfs::fs_info(fd)?;
+    let extents = btrfs::data_extents(fd, length, info.sector_size)?;
+    let mut hasher = siphasher::sip128::SipHasher::new();
+    hasher.write_u16(info.checksum_type);
+    hasher.write_u64(info.sector_size);
+    for (physical, sectors) in extents {
+        hasher.write(&btrfs::sector_checksums(fd, &info, physical, sectors)?);
+    }
+    Some(Hasher128::finish128(&hasher).into())
+}
+
+/// Hashes the checksums the filesystem keeps for the file at `path`. Only btrfs on Linux
+/// keeps usable ones, so this is always `None` here.
+#[cfg(not(target_os = "linux"))]
+pub fn content_checksum(_path: &Path) -> Option<u128> {
+    None
+}
+
+/// Takes the files out of `files`, all of length `length`, whose filesystem checksums no other
+/// file shares and which therefore have unique contents. Nothing is taken when any file lacks
+/// checksums, since its contents could match any other file. Lookups wait for the pauses and
+/// read permits of `context`.
+pub(crate) fn take_distinct(
+    length: u64,
+    files: &mut Vec<Fileinfo>,
+    context: &HashContext,
+) -> Vec<Fileinfo> {
+    /* A file of one sector is read as quickly as its checksums are looked up. */
+    if length <= 4096 || files.len() < 2 {
+        return Vec::new();
+    }
+    let checksums: Option<Vec<u128>> = files
+        .par_iter()
+        .map(|file| {
+            context.wait_while_paused();
+            let _permit = context.limiter.map(|limiter| limiter.acquire());
+            content_checksum(&file.get_paths()[0])
+        })
+        .collect();
+    let checksums = match checksums {
+        Some(checksums) => checksums,
+        None => return Vec::new(),
+    };
+    let mut counts: HashMap<u128, usize> = HashMap::new();
+    checksums
+        .iter()
+        .for_each(|checksum| *counts.entry(*checksum).or_default() += 1);
+    let (distinct, shared): (Vec<_>, Vec<_>) = std::mem::take(files)
+        .into_iter()
+        .zip(checksums)
+        .partition(|(_, checksum)| counts[checksum] == 1);
+    *files = shared.into_iter().map(|(file, _)| file).collect();
+    distinct.into_iter().map(|(file, _)| file).collect()
+}
+
+#[cfg(target_os = "linux")]
+mod btrfs {
+    use std::convert::TryInto;
+
+    /* _IOR(0x94, 31, struct btrfs_ioctl_fs_info_args) from linux/btrfs.h. */
+    const BTRFS_IOC_FS_INFO: libc::c_ulong = 0x8400_941f;
+    /* _IOWR(0x94, 17, struct btrfs_ioctl_search_args). */
+    const BTRFS_IOC_TREE_SEARCH: libc::c_ulong = 0xd000_9411;
+    /* _IOWR('f', 11, struct fiemap) from linux/fs.h. */
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xc020_660b;
+
+    const FS_INFO_FLAG_CSUM_INFO: u64 = 1;
+    const CSUM_TREE_OBJECTID: u64 = 7;
+    const EXTENT_CSUM_OBJECTID: u64 = -10i64 as u64;
+    const EXTENT_CSUM_KEY: u32 = 128;
+    const SEARCH_KEY_SIZE: usize = 104;
+    const SEARCH_HEADER_SIZE: usize = 32;
+
+    const FIEMAP_SIZE: usize = 32;
+    const FIEMAP_EXTENT_SIZE: usize = 56;
+    const FIEMAP_BATCH: usize = 64;
+    const FIEMAP_EXTENT_LAST: u32 = 0x1;
+    /* Unknown location, delayed allocation, encoded or encrypted, unaligned, inline, tail
+    packed and unwritten extents have no sector checksums to go by. */
+    const FIEMAP_EXTENT_UNUSABLE: u32 = 0x2 | 0x4 | 0x8 | 0x80 | 0x100 | 0x200 | 0x400 | 0x800;
+
+    pub(super) struct FsInfo {
+        pub(super) node_size: u64,
+        pub(super) sector_size: u64,
+        /// `BTRFS_CSUM_TYPE_*` of the checksums, crc32c being zero.
+        pub(super) checksum_type: u16,
+        pub(super) checksum_size: u64,
+    }
+
+    fn read_u16(buffer: &[u8], at: usize) -> u16 {
+        u16::from_ne_bytes(buffer[at..at + 2].try_into().unwrap())
+    }
+
+    fn read_u32(buffer: &[u8], at: usize) -> u32 {
+        u32::from_ne_bytes(buffer[at..at + 4].try_into().unwrap())
+    }
+
+    fn read_u64(buffer: &[u8], at: usize) -> u64 {
+        u64::from_ne_bytes(buffer[at..at + 8].try_into().unwrap())
+    }
+
+    fn write_u32(buffer: &mut [u8], at: usize, value: u32) {
+        buffer[at..at + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    fn write_u64(buffer: &mut [u8], at: usize, value: u64) {
+        buffer[at..at + 8].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    fn ioctl(fd: libc::c_int, request: libc::c_ulong, buffer: &mut [u8]) -> Option<()> {
+        match unsafe { libc::ioctl(fd, request as _, buffer.as_mut_ptr()) } {
+            -1 => None,
+            _ => Some(()),
+        }
+    }
+
+    /// Gets the sizes of the btrfs filesystem holding `fd`, failing on other filesystems.
+    pub(super) fn fs_info(fd: libc::c_int) -> Option<FsInfo> {
+        let mut args = [0u8; 1024];
+        write_u64(&mut args, 48, FS_INFO_FLAG_CSUM_INFO);
+        ioctl(fd, BTRFS_IOC_FS_INFO, &mut args)?;
+        let checksum_size = match read_u16(&args, 46) {
+            /* Kernels before 5.8 don't report it, and only know crc32c, whose type is zero. */
+            0 => 4,
+            size => u64::from(size),
+        };
+        let info = FsInfo {
+            node_size: u64::from(read_u32(&args, 32)),
+            sector_size: u64::from(read_u32(&args, 36)),
+            checksum_type: read_u16(&args, 44),
+            checksum_size,
+        };
+        (info.node_size > 0 && info.sector_size > 0).then_some(info)
+    }
+
+    /// Lists where the data of a file of `length` bytes is stored, as the logical address of
+    /// each extent and the number of sectors of the file in it, in file order. Fails when the
+    /// file has holes or extents without checksums.
+    pub(super) fn data_extents(
+        fd: libc::c_int,
+        length: u64,
+        sector_size: u64,
+    ) -> Option<Vec<(u64, u64)>> {
+        let end = length.div_ceil(sector_size) * sector_size;
+        let mut extents = Vec::new();
+        let mut next = 0;
+        let mut buffer = vec![0u8; FIEMAP_SIZE + FIEMAP_BATCH * FIEMAP_EXTENT_SIZE];
+        while next < end {
+            buffer.iter_mut().for_each(|byte| *byte = 0);
+            write_u64(&mut buffer, 0, next);
+            write_u64(&mut buffer, 8, end - next);
+            write_u32(&mut buffer, 24, FIEMAP_BATCH as u32);
+            ioctl(fd, FS_IOC_FIEMAP, &mut buffer)?;
+            let mapped = read_u32(&buffer, 20) as usize;
+            if mapped == 0 {
+                return None;
+            }
+            for index in 0..mapped {
+                let extent = &buffer[FIEMAP_SIZE + index * FIEMAP_EXTENT_SIZE..];
+                let (logical, physical, size) = (
+                    read_u64(extent, 0),
+                    read_u64(extent, 8),
+                    read_u64(extent, 16),
+                );
+                let flags = read_u32(extent, 40);
+                if logical != next || flags & FIEMAP_EXTENT_UNUSABLE != 0 {
+                    return None;
+                }
+                let size = size.min(end - logical);
+                extents.push((physical, size.div_ceil(sector_size)));
+                next = logical + size;
+                if flags & FIEMAP_EXTENT_LAST != 0 || next >= end {
+                    return (next >= end).then_some(extents);
+                }
+            }
+        }
+        Some(extents)
+    }
+
+    /// Gets the checksums of `sectors` sectors from logical address `start` out of the
+    /// checksum tree, failing unless every sector has one.
+    pub(super) fn sector_checksums(
+        fd: libc::c_int,
+        info: &FsInfo,
+        start: u64,
+        sectors: u64,
+    ) -> Option<Vec<u8>> {
+        let end = start + sectors * info.sector_size;
+        let mut checksums = vec![0u8; (sectors * info.checksum_size) as usize];
+        let mut found = 0;
+        /* An item holds the checksums of as many sectors as fit into a node, so the one
+        covering `start` begins at most that far before it. */
+        let span = info.node_size / info.checksum_size * info.sector_size;
+        let mut from = start.saturating_sub(span);
+        let mut args = [0u8; 4096];
+        loop {
+            args.iter_mut().for_each(|byte| *byte = 0);
+            write_u64(&mut args, 0, CSUM_TREE_OBJECTID);
+            write_u64(&mut args, 8, EXTENT_CSUM_OBJECTID);
+            write_u64(&mut args, 16, EXTENT_CSUM_OBJECTID);
+            write_u64(&mut args, 24, from);
+            write_u64(&mut args, 32, end - 1);
+            write_u64(&mut args, 48, u64::MAX);
+            write_u32(&mut args, 56, EXTENT_CSUM_KEY);
+            write_u32(&mut args, 60, EXTENT_CSUM_KEY);
+            write_u32(&mut args, 64, u32::MAX);
+            ioctl(fd, BTRFS_IOC_TREE_SEARCH, &mut args)?;
+            let items = read_u32(&args, 64);
+            if items == 0 {
+                break;
+            }
+            let mut at = SEARCH_KEY_SIZE;
+            for _ in 0..items {
+                let header = &args[at..at + SEARCH_HEADER_SIZE];
+                let (offset, kind, size) = (
+                    read_u64(header, 16),
+                    read_u32(header, 24),
+                    read_u32(header, 28) as usize,
+                );
+                let item = &args[at + SEARCH_HEADER_SIZE..at + SEARCH_HEADER_SIZE + size];
+                at += SEARCH_HEADER_SIZE + size;
+                from = offset + 1;
+                if kind != EXTENT_CSUM_KEY {
+                    continue;
+                }
+                let covered_end = offset + size as u64 / info.checksum_size * info.sector_size;
+                let (first, last) = (offset.max(start), covered_end.min(end));
+                if first >= last {
+                    continue;
+                }
+                let skip = ((first - offset) / info.sector_size * info.checksum_size) as usize;
+                let into = ((first - start) / info.sector_size * info.checksum_size) as usize;
+                let count = ((last - first) / info.sector_size * info.checksum_size) as usize;
+                checksums[into..into + count].copy_from_slice(&item[skip..skip + count]);
+                found += (last - first) / info.sector_size;
+            }
+            if from >= end {
+                break;
+            }
+        }
+        (found == sectors).then_some(checksums)
+    }
+}
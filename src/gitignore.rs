@@ -0,0 +1,191 @@
+//! `.gitignore` and `.ignore` files of the scanned trees.
+//!
+//! Scans with `ScanOptions::respect_gitignore` read the `.gitignore` and `.ignore` files of each
+//! directory they enter, along with `.git/info/exclude` in repositories and the global excludes
+//! file of git, and skip what they exclude the way git does: later lines override earlier ones,
+//! files deeper in the tree override those above, `.ignore` overrides `.gitignore` and `!`
+//! re-includes a path. Patterns without a `/` match names at any depth, others match paths
+//! relative to the directory of their file, and a trailing `/` only matches directories.
+//!
+//! Unlike git, ignore files apply outside of repositories too.
+
+use crate::ignore::components_match;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Ignore files in the directory holding them, read in increasing order of precedence.
+const IGNORE_FILES: &[&str] = &[".git/info/exclude", ".gitignore", ".ignore"];
+
+#[derive(Debug)]
+struct Rule {
+    /// Directory the pattern is relative to.
+    base: PathBuf,
+    pattern: Vec<String>,
+    /// Matched against the path below `base` rather than the name alone.
+    anchored: bool,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// Rules of the ignore files of a directory and all directories above it in a scan.
+#[derive(Debug, Default)]
+pub struct GitIgnore {
+    parent: Option<Arc<GitIgnore>>,
+    rules: Vec<Rule>,
+}
+
+impl GitIgnore {
+    /// Reads the global excludes file of git and the ignore files of the search directory
+    /// `root`. Patterns of the global file are relative to `root`.
+    pub fn root(root: &Path) -> Arc<Self> {
+        let mut rules = Vec::new();
+        if let Some(global) = global_excludes_file() {
+            read_rules(&global, root, &mut rules);
+        }
+        let global = Arc::new(GitIgnore {
+            parent: None,
+            rules,
+        });
+        GitIgnore::child(&global, root)
+    }
+
+    /// Reads the ignore files of `dir`, a directory below the one of `parent`.
+    pub fn child(parent: &Arc<Self>, dir: &Path) -> Arc<Self> {
+        let mut rules = Vec::new();
+        for name in IGNORE_FILES.iter() {
+            read_rules(&dir.join(name), dir, &mut rules);
+        }
+        if rules.is_empty() {
+            return Arc::clone(parent);
+        }
+        Arc::new(GitIgnore {
+            parent: Some(Arc::clone(parent)),
+            rules,
+        })
+    }
+
+    /// Returns true if the entry at `path` is excluded and not re-included again.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::gitignore::GitIgnore;
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let dir = std::env::temp_dir().join("ddh_gitignore_example");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// std::fs::create_dir_all(dir.join("src"))?;
+    /// std::fs::write(dir.join(".gitignore"), "target/\n*.o\n/notes.txt\n")?;
+    /// std::fs::write(dir.join("src/.gitignore"), "!keep.o\n")?;
+    ///
+    /// let root = GitIgnore::root(&dir);
+    /// assert!(root.is_ignored(&dir.join("target"), true));
+    /// assert!(!root.is_ignored(&dir.join("target"), false));
+    /// assert!(root.is_ignored(&dir.join("notes.txt"), false));
+    /// let src = GitIgnore::child(&root, &dir.join("src"));
+    /// assert!(src.is_ignored(&dir.join("src/main.o"), false));
+    /// assert!(!src.is_ignored(&dir.join("src/keep.o"), false));
+    /// assert!(!src.is_ignored(&dir.join("src/notes.txt"), false));
+    /// std::fs::remove_dir_all(&dir)?;
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut current = Some(self);
+        while let Some(ignore) = current {
+            let decision = ignore
+                .rules
+                .iter()
+                .rev()
+                .find(|rule| rule.matches(path, is_dir));
+            if let Some(rule) = decision {
+                return !rule.negated;
+            }
+            current = ignore.parent.as_deref();
+        }
+        false
+    }
+}
+
+impl Rule {
+    fn parse(line: &str, base: &Path) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let pattern: Vec<String> = line
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(str::to_string)
+            .collect();
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            base: base.to_path_buf(),
+            pattern,
+            anchored,
+            negated,
+            dir_only,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => return false,
+        };
+        let components: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        if self.anchored {
+            components_match(&self.pattern, &components)
+        } else {
+            components_match(&self.pattern, &components[components.len() - 1..])
+        }
+    }
+}
+
+fn read_rules(file: &Path, base: &Path, rules: &mut Vec<Rule>) {
+    if let Ok(contents) = fs::read_to_string(file) {
+        rules.extend(contents.lines().filter_map(|line| Rule::parse(line, base)));
+    }
+}
+
+/// Finds the global excludes file: `core.excludesFile` of the user's git config, or else
+/// `git/ignore` in the XDG config directory.
+fn global_excludes_file() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let configured = home.as_ref().and_then(|home| {
+        let config = fs::read_to_string(home.join(".gitconfig")).ok()?;
+        let value = config.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            key.trim()
+                .eq_ignore_ascii_case("excludesfile")
+                .then(|| value.trim().trim_matches('"').to_string())
+        })?;
+        Some(match value.strip_prefix("~/") {
+            Some(rest) => home.join(rest),
+            None => PathBuf::from(value),
+        })
+    });
+    configured.or_else(|| {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|home| home.join(".config")))?;
+        Some(config_dir.join("git").join("ignore"))
+    })
+}
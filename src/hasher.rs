@@ -0,0 +1,188 @@
+//! Algorithms hashing the contents of files.
+//!
+//! Scans hash with SipHash-1-3 (128 bit) unless given another `ContentHasher`. xxHash3 is
+//! faster on large files, while SHA-256 and BLAKE3 match digests computed by other tools.
+//! Digests longer than 128 bits are cut to their first 128 bits, the leading 32 hex digits of
+//! the usual notation. Library users can plug in their own algorithm by implementing the traits.
+
+use siphasher::sip128::Hasher128;
+use std::fmt;
+use std::hash::Hasher;
+use std::sync::Arc;
+
+/// Algorithm hashing the contents of files.
+///
+/// # Examples
+/// ```
+/// use ddh::hasher::{ContentHasher, HashState};
+///
+/// /// Sums the bytes of a file, for illustration only.
+/// #[derive(Debug)]
+/// struct ByteSum;
+/// struct Sum(u128);
+///
+/// impl ContentHasher for ByteSum {
+///     fn name(&self) -> &str {
+///         "bytesum"
+///     }
+///     fn begin(&self) -> Box<dyn HashState> {
+///         Box::new(Sum(0))
+///     }
+/// }
+/// impl HashState for Sum {
+///     fn update(&mut self, bytes: &[u8]) {
+///         self.0 += bytes.iter().map(|&b| b as u128).sum::<u128>();
+///     }
+///     fn finish(self: Box<Self>) -> u128 {
+///         self.0
+///     }
+/// }
+///
+/// assert_eq!(ByteSum.hash_bytes(&[1, 2, 3]), 6);
+/// ```
+pub trait ContentHasher: fmt::Debug + Send + Sync {
+    /// Gets the name recorded with saved results, so that hashes of different algorithms are
+    /// never compared.
+    fn name(&self) -> &str;
+
+    /// Starts hashing one file.
+    fn begin(&self) -> Box<dyn HashState>;
+
+    /// Hashes `bytes` in one go.
+    fn hash_bytes(&self, bytes: &[u8]) -> u128 {
+        let mut state = self.begin();
+        state.update(bytes);
+        state.finish()
+    }
+}
+
+/// Hash of one file in progress.
+pub trait HashState: Send {
+    /// Adds the next bytes of the file.
+    fn update(&mut self, bytes: &[u8]);
+    /// Gets the hash of every byte added.
+    fn finish(self: Box<Self>) -> u128;
+}
+
+/// SipHash-1-3 with 128 bit output, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SipHash128;
+
+/// xxHash3 with 128 bit output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Xxh3;
+
+/// SHA-256, cut to its first 128 bits.
+///
+/// # Examples
+/// ```
+/// use ddh::hasher::{ContentHasher, Sha256};
+///
+/// // First half of the well known digest of "abc".
+/// assert_eq!(Sha256.hash_bytes(b"abc"), 0xba7816bf8f01cfea414140de5dae2223);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256;
+
+/// BLAKE3, cut to its first 128 bits.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3;
+
+impl ContentHasher for SipHash128 {
+    fn name(&self) -> &str {
+        "sip128"
+    }
+    fn begin(&self) -> Box<dyn HashState> {
+        Box::new(siphasher::sip128::SipHasher::new())
+    }
+}
+
+impl HashState for siphasher::sip128::SipHasher {
+    fn update(&mut self, bytes: &[u8]) {
+        self.write(bytes);
+    }
+    fn finish(self: Box<Self>) -> u128 {
+        self.finish128().into()
+    }
+}
+
+impl ContentHasher for Xxh3 {
+    fn name(&self) -> &str {
+        "xxh3"
+    }
+    fn begin(&self) -> Box<dyn HashState> {
+        Box::new(xxhash_rust::xxh3::Xxh3::new())
+    }
+}
+
+impl HashState for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, bytes: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, bytes);
+    }
+    fn finish(self: Box<Self>) -> u128 {
+        self.digest128()
+    }
+}
+
+impl ContentHasher for Sha256 {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+    fn begin(&self) -> Box<dyn HashState> {
+        Box::new(<sha2::Sha256 as sha2::Digest>::new())
+    }
+}
+
+impl HashState for sha2::Sha256 {
+    fn update(&mut self, bytes: &[u8]) {
+        sha2::Digest::update(self, bytes);
+    }
+    fn finish(self: Box<Self>) -> u128 {
+        leading_bits(&sha2::Digest::finalize(*self))
+    }
+}
+
+impl ContentHasher for Blake3 {
+    fn name(&self) -> &str {
+        "blake3"
+    }
+    fn begin(&self) -> Box<dyn HashState> {
+        Box::new(blake3::Hasher::new())
+    }
+}
+
+impl HashState for blake3::Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        blake3::Hasher::update(self, bytes);
+    }
+    fn finish(self: Box<Self>) -> u128 {
+        leading_bits(self.finalize().as_bytes())
+    }
+}
+
+/// Gets the built-in hasher called `name`, as returned by `ContentHasher::name`.
+///
+/// # Examples
+/// ```
+/// use ddh::hasher::{by_name, ContentHasher};
+///
+/// assert_eq!(by_name("xxh3").unwrap().name(), "xxh3");
+/// assert!(by_name("md5").is_none());
+/// ```
+pub fn by_name(name: &str) -> Option<Arc<dyn ContentHasher>> {
+    let hasher: Arc<dyn ContentHasher> = match name {
+        "sip128" => Arc::new(SipHash128),
+        "xxh3" => Arc::new(Xxh3),
+        "sha256" => Arc::new(Sha256),
+        "blake3" => Arc::new(Blake3),
+        _ => return None,
+    };
+    Some(hasher)
+}
+
+/// Reads the first 128 bits of a longer digest.
+fn leading_bits(digest: &[u8]) -> u128 {
+    let mut leading = [0; 16];
+    leading.copy_from_slice(&digest[..16]);
+    u128::from_be_bytes(leading)
+}
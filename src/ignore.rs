@@ -0,0 +1,501 @@
+//! Rules for skipping parts of the searched directories.
+//!
+//! An ignore entry is either a path or a glob pattern. Paths are resolved once up front and
+//! everything beneath them is skipped. Absolute patterns are matched against the full path of
+//! each file and directory, relative ones against the path below each search root. Patterns
+//! support `*` and `?` within a path component, character classes such as `[a-z]` or `[!0-9]`,
+//! and `**` for any number of components.
+//!
+//! An entry of the form `root:entry` is scoped to a single root and only applies below it, so
+//! `/data:tmp` skips `/data/tmp` while leaving a `tmp` directory under any other root alone.
+//!
+//! Entries which don't resolve to anything are reported through `IgnoreRules::missing` instead
+//! of failing, since ignore lists tend to outlive the paths they name.
+//!
+//! `DEV_PRESET`, `MACOS_PRESET` and `WINDOWS_PRESET` hold entries for the build output, caches
+//! and metadata files which developers and each OS scatter through their trees.
+//!
+//! `FileFilters` select files rather than parts of the tree: include and exclude globs match
+//! file names, or full paths when they hold a `/`, regular expressions match full paths,
+//! extension lists keep or skip files by extension and bounds on the modification time keep
+//! recent or old files.
+//!
+//! Hidden entries, whose names start with a dot or which have the hidden attribute on Windows,
+//! are recognized by `is_hidden`.
+
+use regex::Regex;
+use std::fs::{DirEntry, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Ignore entries skipping version control metadata, dependency trees, build output and
+/// caches of development tools.
+///
+/// # Examples
+/// ```
+/// use ddh::ignore::{IgnoreRules, DEV_PRESET};
+/// use std::path::Path;
+///
+/// let rules = IgnoreRules::new(&["/"], DEV_PRESET);
+/// assert!(rules.is_ignored(Path::new("/home/jon/app/node_modules/left-pad/index.js")));
+/// assert!(!rules.is_ignored(Path::new("/home/jon/app/src/index.js")));
+/// ```
+pub const DEV_PRESET: &[&str] = &[
+    "**/.git",
+    "**/.hg",
+    "**/.svn",
+    "**/node_modules",
+    "**/bower_components",
+    "**/target",
+    "**/__pycache__",
+    "**/.venv",
+    "**/.tox",
+    "**/.mypy_cache",
+    "**/.pytest_cache",
+    "**/.gradle",
+    "**/.next",
+    "**/.terraform",
+];
+
+/// Ignore entries skipping the metadata files Finder and Spotlight leave in directories.
+pub const MACOS_PRESET: &[&str] = &[
+    "**/.DS_Store",
+    "**/._*",
+    "**/.AppleDouble",
+    "**/.Spotlight-V100",
+    "**/.fseventsd",
+    "**/.TemporaryItems",
+    "**/.DocumentRevisions-V100",
+];
+
+/// Ignore entries skipping the thumbnail caches, folder settings and recycle bins of Windows.
+pub const WINDOWS_PRESET: &[&str] = &[
+    "**/Thumbs.db",
+    "**/ehthumbs.db",
+    "**/desktop.ini",
+    "**/$RECYCLE.BIN",
+    "**/System Volume Information",
+];
+
+/// Set of resolved ignore entries.
+#[derive(Debug, Default, Clone)]
+pub struct IgnoreRules {
+    paths: Vec<PathBuf>,
+    absolute_patterns: Vec<Vec<String>>,
+    relative_patterns: Vec<Vec<String>>,
+    scoped_patterns: Vec<(PathBuf, Vec<String>)>,
+    roots: Vec<PathBuf>,
+    missing: Vec<PathBuf>,
+}
+
+impl IgnoreRules {
+    /// Resolves `entries` for a search of `search_dirs`. A relative entry applies below every
+    /// search root, and also to the path it names relative to the working directory.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::ignore::IgnoreRules;
+    /// use std::path::Path;
+    ///
+    /// let rules = IgnoreRules::new(&["/srv/share"], &["/srv/**/*.tmp", "/no/such/dir"]);
+    /// assert!(rules.is_ignored(Path::new("/srv/share/build/out.tmp")));
+    /// assert!(!rules.is_ignored(Path::new("/srv/share/build/out.txt")));
+    /// assert_eq!(rules.missing(), [Path::new("/no/such/dir")]);
+    ///
+    /// let scoped = IgnoreRules::new(&["/"], &["/:tmp"]);
+    /// assert!(scoped.is_ignored(Path::new("/tmp/build")));
+    /// assert!(!scoped.is_ignored(Path::new("/var/tmp")));
+    /// ```
+    pub fn new<S: AsRef<Path>, I: AsRef<Path>>(search_dirs: &[S], entries: &[I]) -> Self {
+        let mut rules = IgnoreRules {
+            roots: search_dirs
+                .iter()
+                .filter_map(|dir| dir.as_ref().canonicalize().ok())
+                .collect(),
+            ..Default::default()
+        };
+        for entry in entries.iter().map(|entry| entry.as_ref()) {
+            if let Some((root, scoped)) = split_scope(entry) {
+                match root.canonicalize() {
+                    Ok(root) => {
+                        if !is_pattern(scoped) && !root.join(scoped).exists() {
+                            rules.missing.push(entry.to_path_buf());
+                        }
+                        rules.scoped_patterns.push((root, components(scoped)));
+                    }
+                    Err(_) => rules.missing.push(entry.to_path_buf()),
+                }
+                continue;
+            }
+            let components = components(entry);
+            if entry.is_absolute() {
+                if is_pattern(entry) {
+                    rules.absolute_patterns.push(components);
+                } else {
+                    match entry.canonicalize() {
+                        Ok(path) => rules.paths.push(path),
+                        Err(_) => rules.missing.push(entry.to_path_buf()),
+                    }
+                }
+                continue;
+            }
+            let in_working_dir = entry.canonicalize().ok();
+            let in_roots = rules.roots.iter().any(|root| root.join(entry).exists());
+            if !is_pattern(entry) && in_working_dir.is_none() && !in_roots {
+                rules.missing.push(entry.to_path_buf());
+            }
+            rules.paths.extend(in_working_dir);
+            rules.relative_patterns.push(components);
+        }
+        rules
+    }
+    /// Gets the entries which are neither patterns nor paths which exist.
+    pub fn missing(&self) -> &[PathBuf] {
+        &self.missing
+    }
+    /// Returns true if the canonical path `path` should be skipped.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        if self.paths.iter().any(|ignored| path.starts_with(ignored)) {
+            return true;
+        }
+        let path_components = components(path);
+        if self
+            .absolute_patterns
+            .iter()
+            .any(|pattern| matches_ancestor(pattern, &path_components))
+        {
+            return true;
+        }
+        if self.scoped_patterns.iter().any(|(root, pattern)| {
+            path.strip_prefix(root).is_ok_and(|relative| {
+                !relative.as_os_str().is_empty() && matches_ancestor(pattern, &components(relative))
+            })
+        }) {
+            return true;
+        }
+        if self.relative_patterns.is_empty() {
+            return false;
+        }
+        self.roots
+            .iter()
+            .filter_map(|root| path.strip_prefix(root).ok())
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .any(|relative| {
+                let relative = components(relative);
+                self.relative_patterns
+                    .iter()
+                    .any(|pattern| matches_ancestor(pattern, &relative))
+            })
+    }
+}
+
+/// Globs, regular expressions and modification times selecting the files a scan considers.
+#[derive(Debug, Default, Clone)]
+pub struct FileFilters {
+    include: Vec<Vec<String>>,
+    exclude: Vec<Vec<String>>,
+    exclude_regex: Vec<Regex>,
+    /// Lowercase extensions without their dot.
+    extensions: Vec<String>,
+    skipped_extensions: Vec<String>,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+}
+
+impl FileFilters {
+    /// Creates filters keeping files which match one of `include` (or any file if it's empty)
+    /// and none of `exclude` or `exclude_regex`. A glob without a `/` matches file names
+    /// anywhere, while other globs match full paths, relative ones below any directory.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::ignore::FileFilters;
+    /// use std::path::Path;
+    ///
+    /// let filters = FileFilters::new(&[], &["*.tmp", "Thumbs.db"], &[]).unwrap();
+    /// assert!(!filters.allows(Path::new("/home/jon/photos/Thumbs.db")));
+    /// assert!(!filters.allows(Path::new("/home/jon/build/out.tmp")));
+    /// assert!(filters.allows(Path::new("/home/jon/photos/beach.jpg")));
+    ///
+    /// let filters = FileFilters::new(&["photos/*.jpg"], &[], &["^/home/jon/old/"]).unwrap();
+    /// assert!(filters.allows(Path::new("/home/jon/photos/beach.jpg")));
+    /// assert!(!filters.allows(Path::new("/home/jon/photos/notes.txt")));
+    /// assert!(!filters.allows(Path::new("/home/jon/old/photos/beach.jpg")));
+    /// ```
+    pub fn new<S: AsRef<str>>(
+        include: &[S],
+        exclude: &[S],
+        exclude_regex: &[S],
+    ) -> Result<Self, regex::Error> {
+        Ok(FileFilters {
+            include: include
+                .iter()
+                .map(|glob| glob_components(glob.as_ref()))
+                .collect(),
+            exclude: exclude
+                .iter()
+                .map(|glob| glob_components(glob.as_ref()))
+                .collect(),
+            exclude_regex: exclude_regex
+                .iter()
+                .map(|re| Regex::new(re.as_ref()))
+                .collect::<Result<_, _>>()?,
+            ..Default::default()
+        })
+    }
+
+    /// Also keeps only files with one of `extensions` (any extension if it's empty) and none
+    /// of `skipped`. Extensions are compared ignoring case and may be given with their dot.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::ignore::FileFilters;
+    /// use std::path::Path;
+    ///
+    /// let none: &[&str] = &[];
+    /// let filters = FileFilters::default().with_extensions(&["jpg", ".RAW"], none);
+    /// assert!(filters.allows(Path::new("/photos/a.JPG")));
+    /// assert!(filters.allows(Path::new("/photos/a.raw")));
+    /// assert!(!filters.allows(Path::new("/photos/a.mov")));
+    /// assert!(!filters.allows(Path::new("/photos/README")));
+    ///
+    /// let filters = FileFilters::default().with_extensions(none, &["xmp"]);
+    /// assert!(!filters.allows(Path::new("/photos/a.xmp")));
+    /// assert!(filters.allows(Path::new("/photos/README")));
+    /// ```
+    pub fn with_extensions<S: AsRef<str>>(mut self, extensions: &[S], skipped: &[S]) -> Self {
+        let normalize = |extension: &S| extension.as_ref().trim_start_matches('.').to_lowercase();
+        self.extensions = extensions.iter().map(normalize).collect();
+        self.skipped_extensions = skipped.iter().map(normalize).collect();
+        self
+    }
+
+    /// Also keeps only files last modified after `after` and before `before`, where given.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::ignore::FileFilters;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let meta = std::fs::metadata(std::env::current_exe().unwrap()).unwrap();
+    /// let year_ago = SystemTime::now() - Duration::from_secs(365 * 86_400);
+    /// assert!(FileFilters::default().with_modified(Some(year_ago), None).allows_modified(&meta));
+    /// assert!(!FileFilters::default().with_modified(None, Some(year_ago)).allows_modified(&meta));
+    /// ```
+    pub fn with_modified(mut self, after: Option<SystemTime>, before: Option<SystemTime>) -> Self {
+        self.modified_after = after;
+        self.modified_before = before;
+        self
+    }
+
+    /// Returns true if the modification time of `meta` is within the bounds of the filters.
+    /// Files whose modification time isn't known only pass without bounds.
+    pub fn allows_modified(&self, meta: &Metadata) -> bool {
+        if self.modified_after.is_none() && self.modified_before.is_none() {
+            return true;
+        }
+        let modified = match meta.modified() {
+            Ok(modified) => modified,
+            Err(_) => return false,
+        };
+        self.modified_after.is_none_or(|after| modified > after)
+            && self.modified_before.is_none_or(|before| modified < before)
+    }
+
+    /// Returns true if the file at `path` passes the filters other than the modification time.
+    pub fn allows(&self, path: &Path) -> bool {
+        if !self.extensions.is_empty() || !self.skipped_extensions.is_empty() {
+            let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+            let listed = |list: &Vec<String>| extension.as_ref().is_some_and(|e| list.contains(e));
+            if !self.extensions.is_empty() && !listed(&self.extensions) {
+                return false;
+            }
+            if listed(&self.skipped_extensions) {
+                return false;
+            }
+        }
+        if self.include.is_empty() && self.exclude.is_empty() && self.exclude_regex.is_empty() {
+            return true;
+        }
+        let path_components = components(path);
+        let matches = |glob: &Vec<String>| components_match(glob, &path_components);
+        if !self.include.is_empty() && !self.include.iter().any(matches) {
+            return false;
+        }
+        if self.exclude.iter().any(matches) {
+            return false;
+        }
+        let text = path.to_string_lossy();
+        !self.exclude_regex.iter().any(|re| re.is_match(&text))
+    }
+}
+
+/// Splits a file glob into components, anchoring globs which aren't absolute at any depth.
+fn glob_components(glob: &str) -> Vec<String> {
+    let path = Path::new(glob);
+    let mut glob = components(path);
+    if !path.is_absolute() {
+        glob.insert(0, "**".to_string());
+    }
+    glob
+}
+
+/// Returns true if `entry` is hidden: its name starts with a dot, or on Windows it has the
+/// hidden attribute.
+///
+/// # Examples
+/// ```
+/// let dir = std::env::temp_dir().join("ddh_hidden_example");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// std::fs::create_dir_all(dir.join(".cache")).unwrap();
+/// std::fs::write(dir.join("notes.txt"), "").unwrap();
+///
+/// let mut hidden: Vec<(String, bool)> = std::fs::read_dir(&dir)
+///     .unwrap()
+///     .map(|entry| entry.unwrap())
+///     .map(|entry| (entry.file_name().to_string_lossy().into_owned(), ddh::ignore::is_hidden(&entry)))
+///     .collect();
+/// hidden.sort();
+/// assert_eq!(hidden, [(".cache".to_string(), true), ("notes.txt".to_string(), false)]);
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn is_hidden(entry: &DirEntry) -> bool {
+    if entry.file_name().to_string_lossy().starts_with('.') {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        /* Listing a directory reads the attributes of its entries, so this doesn't touch them. */
+        entry
+            .metadata()
+            .is_ok_and(|meta| meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+    }
+    #[cfg(not(windows))]
+    false
+}
+
+/// Splits a comma separated list of ignore entries. A scope given on the first entry applies
+/// to every entry in the list, so `/data:tmp,cache` yields `/data:tmp` and `/data:cache`.
+///
+/// # Examples
+/// ```
+/// assert_eq!(
+///     ddh::ignore::parse_ignore_list("/data:tmp,cache"),
+///     ["/data:tmp", "/data:cache"]
+/// );
+/// assert_eq!(ddh::ignore::parse_ignore_list("tmp,cache"), ["tmp", "cache"]);
+/// ```
+pub fn parse_ignore_list(list: &str) -> Vec<String> {
+    let mut entries = list.split(',').filter(|entry| !entry.is_empty());
+    let first = match entries.next() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    match split_scope(Path::new(first)) {
+        Some((root, _)) => std::iter::once(first.to_string())
+            .chain(entries.map(|entry| format!("{}:{}", root.display(), entry)))
+            .collect(),
+        None => std::iter::once(first)
+            .chain(entries)
+            .map(|entry| entry.to_string())
+            .collect(),
+    }
+}
+
+/// Splits `root:entry` at the first colon which doesn't start a path (as in `C:\`). Entries
+/// which exist as written are never split, and neither are scopes whose entry is absolute.
+fn split_scope(entry: &Path) -> Option<(&Path, &Path)> {
+    if entry.exists() {
+        return None;
+    }
+    let entry = entry.to_str()?;
+    let split = entry.char_indices().find(|&(i, c)| {
+        c == ':' && !matches!(entry[i + 1..].chars().next(), Some('/') | Some('\\'))
+    })?;
+    let (root, scoped) = (&entry[..split.0], Path::new(&entry[split.0 + 1..]));
+    if root.is_empty() || scoped.as_os_str().is_empty() || scoped.is_absolute() {
+        return None;
+    }
+    Some((Path::new(root), scoped))
+}
+
+fn is_pattern(entry: &Path) -> bool {
+    entry.to_string_lossy().contains(['*', '?', '['])
+}
+
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Returns true if `pattern` matches the path or one of its ancestors.
+fn matches_ancestor(pattern: &[String], path: &[String]) -> bool {
+    (1..=path.len()).any(|len| components_match(pattern, &path[..len]))
+}
+
+/// Returns true if `pattern` matches the whole of `path`, with `**` standing for any number of
+/// components.
+pub(crate) fn components_match(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| components_match(rest, &path[skip..]))
+        }
+        Some((first, rest)) => path.split_first().is_some_and(|(component, path_rest)| {
+            let pattern: Vec<char> = first.chars().collect();
+            let component: Vec<char> = component.chars().collect();
+            wildcard_match(&pattern, &component) && components_match(rest, path_rest)
+        }),
+    }
+}
+
+fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| wildcard_match(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && wildcard_match(rest, &text[1..]),
+        Some(('[', rest)) => match (class_end(rest), text.split_first()) {
+            (Some(end), Some((&c, text_rest))) => {
+                class_matches(&rest[..end], c) && wildcard_match(&rest[end + 1..], text_rest)
+            }
+            (Some(_), None) => false,
+            (None, _) => text.first() == Some(&'[') && wildcard_match(rest, &text[1..]),
+        },
+        Some((c, rest)) => text.first() == Some(c) && wildcard_match(rest, &text[1..]),
+    }
+}
+
+/// Finds the closing bracket of a character class, allowing `]` as its first member.
+fn class_end(class: &[char]) -> Option<usize> {
+    let start = match class.first() {
+        Some('!') => 2,
+        _ => 1,
+    };
+    class
+        .iter()
+        .skip(start)
+        .position(|&c| c == ']')
+        .map(|i| i + start)
+}
+
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some(('!', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    matched != negated
+}
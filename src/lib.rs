@@ -2,27 +2,202 @@
 //!
 //! `ddh` is a collection of functions and structs to aid in analysing filesystem directories.
 
+pub mod actions;
+pub mod agent;
+pub mod anonymize;
+pub mod annotations;
+pub mod cache;
+pub mod cancel;
+pub mod clock;
+pub mod copies;
+pub mod drives;
+pub mod events;
+pub mod export;
+pub mod external;
 pub mod fileinfo;
-use fileinfo::{Fileinfo, HashMode};
+pub mod fingerprint;
+#[cfg(feature = "dev-tools")]
+pub mod fixtures;
+pub mod format;
+pub mod fschecksums;
+pub mod gitignore;
+pub mod hasher;
+pub mod ignore;
+pub mod links;
+pub mod macos;
+pub mod names;
+pub mod output;
+mod paths;
+pub mod placeholders;
+pub mod policy;
+pub mod privileges;
+pub mod progress;
+pub mod reflink;
+pub mod results;
+pub mod roots;
+pub mod selftest;
+pub mod tenants;
+pub mod throttle;
+pub mod transfer;
+pub mod trees;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod usage;
+pub mod utils;
+pub mod waste;
+pub mod warnings;
+pub mod watch;
+pub mod xattrs;
+use cache::HashCache;
+use cancel::{CancellationHandle, PauseHandle};
+use clock::{Clock, SystemClock};
+use external::HashCommand;
+use fileinfo::{FileMetadata, Fileinfo, HashMode};
+use gitignore::GitIgnore;
+use hasher::{ContentHasher, SipHash128};
+use ignore::{FileFilters, IgnoreRules};
+use progress::{Progress, DISCOVERY_STEP};
+use throttle::{PauseWindow, ReadLimiter, PAUSE_POLL};
+use utils::Unreadable;
+use warnings::{Warning, WarningKind};
 
 use nohash_hasher::IntMap;
 use rayon::prelude::*;
+use std::cmp::Reverse;
 use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashSet;
 use std::fs::{self, DirEntry};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Number of directory entries listed before they are handed to the thread pool, which bounds
+/// the memory used for directories with millions of entries.
+const ENTRY_BATCH: usize = 4096;
 
 enum ChannelPackage {
     Success(Fileinfo),
     Fail(PathBuf, std::io::Error),
 }
 
+/// Settings which control how directories are scanned.
+/// `ScanOptions::default()` scans every file and hashes without a cache.
+#[derive(Debug, Default)]
+pub struct ScanOptions {
+    /// Minimum file size in bytes to consider.
+    pub min_size: u64,
+    /// Cache consulted before hashing a file and updated with newly computed hashes. Lengths
+    /// whose candidates are all in the cache are settled before the others.
+    pub cache: Option<HashCache>,
+    /// Maximum number of file reads in flight at once, independent of the number of hashing
+    /// threads. Unlimited when `None`.
+    pub max_concurrent_reads: Option<usize>,
+    /// Daily hours during which no file reads start, so hashing pauses and resumes once the
+    /// window is over. Directories are still walked.
+    pub pause_during: Option<PauseWindow>,
+    /// Scan symlinks to files as their targets, under the path of the link, instead of skipping
+    /// them. Symlinks to directories are only followed with `follow_symlinks`.
+    pub resolve_symlinks: bool,
+    /// Follow symlinks to directories as well as files. Files in linked directories are listed
+    /// under their canonical paths and every directory is entered once, identified by device
+    /// and inode, so link cycles and directories reached several ways are scanned once.
+    pub follow_symlinks: bool,
+    /// Stay on the filesystem of each search directory, skipping directories which other
+    /// filesystems are mounted on, like `du -x`. Has no effect where devices aren't known.
+    pub one_file_system: bool,
+    /// Skip what the `.gitignore` and `.ignore` files in the scanned trees and the global
+    /// excludes file of git exclude, as described in the `gitignore` module.
+    pub respect_gitignore: bool,
+    /// Skip hidden files and directories below the search directories, as told by
+    /// `ignore::is_hidden`. Search directories are scanned even if they are hidden.
+    pub skip_hidden: bool,
+    /// Globs and regular expressions selecting the files scanned.
+    pub file_filters: FileFilters,
+    /// Only scan this many levels of directories: files directly in the search directories are
+    /// at depth one. Unlimited when `None`.
+    pub max_depth: Option<usize>,
+    /// Hash online-only files of cloud storage clients, downloading them, instead of leaving
+    /// them out and reporting them as described in the `placeholders` module.
+    pub hydrate_placeholders: bool,
+    /// Hash candidates in directories modified most recently first, so that a scan reports the
+    /// groups of recently changed areas early in its progress and settles them first if it is
+    /// cancelled, leaving cold archives for last.
+    pub recent_dirs_first: bool,
+    /// Tell files of equal length apart by the checksums their filesystem keeps, as described
+    /// in the `fschecksums` module, and leave those with unique checksums out of hashing.
+    /// Experimental.
+    pub filesystem_checksums: bool,
+    /// Take full hashes from the extended attributes written by `xattrs::tag_files` where they
+    /// are still current, instead of reading files. Not used with another hasher or command.
+    pub xattr_hashes: bool,
+    /// External command computing full hashes in place of the built-in hasher. Files it fails
+    /// on are left out of the results and reported as errors. The cache isn't used with it.
+    pub hash_command: Option<HashCommand>,
+    /// Algorithm hashing files in place of the default SipHash. The cache isn't used with it,
+    /// since it holds SipHash hashes.
+    pub hasher: Option<Arc<dyn ContentHasher>>,
+    /// Compare files byte by byte once their hashes match, so that only files with identical
+    /// contents are grouped even in the event of a hash collision.
+    pub paranoid: bool,
+    /// Channel receiving the progress of the scan as it runs.
+    pub progress: Option<Sender<Progress>>,
+    /// Send a `Progress::FileDiscovered` event for every file found. Off by default, since
+    /// scans of millions of files would copy every path into an event nobody reads.
+    pub report_discovered_files: bool,
+    /// Channel receiving warnings about files which deserve a look, as described in the
+    /// `warnings` module.
+    pub warnings: Option<Sender<Warning>>,
+    /// Channel receiving each group of files with equal contents as soon as the scan settled
+    /// it, while other groups are still being hashed. The groups are returned as well.
+    pub settled: Option<Sender<Fileinfo>>,
+    /// Handle stopping the scan early. A cancelled scan returns the files found and the groups
+    /// settled before it stopped; groups still being hashed are left out.
+    pub cancel: Option<CancellationHandle>,
+    /// Handle pausing the scan until it is resumed.
+    pub pause: Option<PauseHandle>,
+    /// Clock timing the phases of the scan. The system clock when `None`.
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Return the results in a fixed order, as `sort_results` leaves them, instead of the order
+    /// the threads happened to settle them in. Groups sent through `settled` still arrive as
+    /// they are settled.
+    pub deterministic: bool,
+}
+
+/// Time spent in each phase of a scan.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanTimings {
+    /// Walking the directories and reading metadata.
+    pub discovery: Duration,
+    /// Hashing candidates and grouping them.
+    pub hashing: Duration,
+}
+
+/// What a scan would process, as told by `estimate_dirs` or `Discovery::estimate`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScanEstimate {
+    /// Files passing the filters of the scan.
+    pub files: u64,
+    /// Total size of those files.
+    pub bytes: u64,
+    /// Files which share their length with another and would be hashed, hard links counting
+    /// once.
+    pub candidates: u64,
+    /// Bytes of the candidates, at most all of which would be read.
+    pub candidate_bytes: u64,
+    /// Files and directories which couldn't be read.
+    pub errors: u64,
+}
+
 /// Constructs a list of unique files from a list of directories.
+/// Ignore entries may be paths or glob patterns as described in the `ignore` module.
+/// Hard links to one file are read once and share its collection, which has no hash when no
+/// other file has the same length; `links::split_hard_links` tells them apart from copies.
 ///
 /// # Examples
 /// ```no_run
 /// let search_dirs = vec!["/home/jon", "/home/doe"];
-/// let ignore_dirs = vec!["/home/jon/.git", "/home/doe/scratch"];
+/// let ignore_dirs = vec!["/home/jon/.git", "/home/doe/scratch", "**/*.tmp"];
 /// let min_size = 1024;
 /// let (files, errors) = ddh::deduplicate_dirs(search_dirs, ignore_dirs, min_size);
 /// ```
@@ -31,38 +206,511 @@ pub fn deduplicate_dirs<P: AsRef<Path> + Sync>(
     search_dirs: Vec<P>, 
     ignore_dirs: Vec<P>, 
     min_size: u64) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>) {
+    let options = ScanOptions {
+        min_size,
+        ..Default::default()
+    };
+    deduplicate_dirs_with_options(search_dirs, ignore_dirs, &options)
+}
+
+/// Constructs a list of unique files from a list of directories using the given options.
+///
+/// # Examples
+/// ```no_run
+/// let options = ddh::ScanOptions { min_size: 1024, ..Default::default() };
+/// let (files, errors) = ddh::deduplicate_dirs_with_options(vec!["/home/jon"], vec![], &options);
+/// ```
+pub fn deduplicate_dirs_with_options<P: AsRef<Path> + Sync>(
+    search_dirs: Vec<P>,
+    ignore_dirs: Vec<P>,
+    options: &ScanOptions,
+) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>) {
+    let (complete_files, errors, _) = deduplicate_dirs_timed(search_dirs, ignore_dirs, options);
+    (complete_files, errors)
+}
+
+/// Same as `deduplicate_dirs_with_options`, also reporting how long each phase took.
+pub fn deduplicate_dirs_timed<P: AsRef<Path> + Sync>(
+    search_dirs: Vec<P>,
+    ignore_dirs: Vec<P>,
+    options: &ScanOptions,
+) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>, ScanTimings) {
+    scan(search_dirs, ignore_dirs, options, options.progress.as_ref())
+}
+
+/// Same as `deduplicate_dirs_with_options`, handing every event of the scan to `on_event` as
+/// it happens, in place of `options.progress`. The scan runs on other threads while `on_event`
+/// runs on the calling one, so it may update state which isn't `Send`, such as a GUI.
+///
+/// # Examples
+/// ```
+/// use ddh::progress::Progress;
+///
+/// let dir = std::env::temp_dir().join("ddh_progress_example");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(dir.join("a"), "same").unwrap();
+/// std::fs::write(dir.join("b"), "same").unwrap();
+///
+/// let mut found = 0;
+/// let mut hashed = 0;
+/// let options = ddh::ScanOptions { report_discovered_files: true, ..Default::default() };
+/// let (files, errors) = ddh::deduplicate_dirs_with_progress(
+///     vec![&dir],
+///     vec![],
+///     &options,
+///     |event| match event {
+///         Progress::FileDiscovered(_) => found += 1,
+///         Progress::HashFinished { .. } => hashed += 1,
+///         _ => {}
+///     },
+/// );
+/// assert_eq!((found, hashed), (2, 2));
+/// assert!(errors.is_empty());
+/// assert_eq!(files[0].get_paths().len(), 2);
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn deduplicate_dirs_with_progress<P, F>(
+    search_dirs: Vec<P>,
+    ignore_dirs: Vec<P>,
+    options: &ScanOptions,
+    mut on_event: F,
+) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>)
+where
+    P: AsRef<Path> + Sync + Send,
+    F: FnMut(Progress),
+{
+    let (sender, receiver) = channel();
+    std::thread::scope(|scope| {
+        let scanning =
+            scope.spawn(move || scan(search_dirs, ignore_dirs, options, Some(&sender)));
+        receiver.iter().for_each(&mut on_event);
+        let (complete_files, errors, _) = scanning.join().expect("Error joining scan thread");
+        (complete_files, errors)
+    })
+}
+
+/// Files found by walking the directories of a scan, which `deduplicate_discovered` hashes
+/// without walking them again.
+#[derive(Debug)]
+pub struct Discovery {
+    files_of_lengths: FilesOfLengths,
+    errors: Vec<(PathBuf, std::io::Error)>,
+    files: u64,
+    duration: Duration,
+}
+
+impl Discovery {
+    /// Counts the files the scan would consider and those it would hash.
+    pub fn estimate(&self) -> ScanEstimate {
+        let mut estimate = ScanEstimate {
+            files: self.files,
+            errors: self.errors.len() as u64,
+            ..Default::default()
+        };
+        for (length, files) in self.files_of_lengths.iter() {
+            let paths: u64 = files.iter().map(|file| file.get_paths().len() as u64).sum();
+            estimate.bytes += length * paths;
+            if *length > 0 && files.len() > 1 {
+                estimate.candidates += files.len() as u64;
+                estimate.candidate_bytes += length * files.len() as u64;
+            }
+        }
+        estimate
+    }
+}
+
+/// Walks the directories a scan with `options` would, without reading any file, so the files
+/// found can be estimated before `deduplicate_discovered` hashes them.
+///
+/// # Examples
+/// ```
+/// let dir = std::env::temp_dir().join("ddh_discovery_example");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(dir.join("a"), "same").unwrap();
+/// std::fs::write(dir.join("b"), "same").unwrap();
+///
+/// let options = ddh::ScanOptions::default();
+/// let discovery = ddh::discover_dirs(&[&dir], &[], &options);
+/// assert_eq!(discovery.estimate().candidates, 2);
+/// let (files, errors, _) = ddh::deduplicate_discovered(discovery, &options);
+/// assert!(errors.is_empty());
+/// assert_eq!(files[0].get_paths().len(), 2);
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn discover_dirs<P: AsRef<Path> + Sync>(
+    search_dirs: &[P],
+    ignore_dirs: &[P],
+    options: &ScanOptions,
+) -> Discovery {
+    discover_timed(search_dirs, ignore_dirs, options, options.progress.as_ref())
+}
+
+/// Hashes the files of `discovery` as `deduplicate_dirs_timed` would, with the time spent
+/// walking the directories as the discovery time.
+pub fn deduplicate_discovered(
+    discovery: Discovery,
+    options: &ScanOptions,
+) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>, ScanTimings) {
+    hash_discovered(discovery, options, options.progress.as_ref())
+}
+
+fn scan<P: AsRef<Path> + Sync>(
+    search_dirs: Vec<P>,
+    ignore_dirs: Vec<P>,
+    options: &ScanOptions,
+    progress: Option<&Sender<Progress>>,
+) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>, ScanTimings) {
+    let discovery = discover_timed(&search_dirs, &ignore_dirs, options, progress);
+    hash_discovered(discovery, options, progress)
+}
+
+fn discover_timed<P: AsRef<Path> + Sync>(
+    search_dirs: &[P],
+    ignore_dirs: &[P],
+    options: &ScanOptions,
+    progress: Option<&Sender<Progress>>,
+) -> Discovery {
+    let clock = options.clock.as_deref().unwrap_or(&SystemClock);
+    let started = clock.now();
+    let (files_of_lengths, errors, files) = discover(search_dirs, ignore_dirs, options, progress);
+    Discovery {
+        files_of_lengths,
+        errors,
+        files,
+        duration: clock.now().saturating_duration_since(started),
+    }
+}
+
+fn hash_discovered(
+    discovery: Discovery,
+    options: &ScanOptions,
+    progress: Option<&Sender<Progress>>,
+) -> (Vec<Fileinfo>, Vec<(PathBuf, std::io::Error)>, ScanTimings) {
+    let clock = options.clock.as_deref().unwrap_or(&SystemClock);
+    let Discovery {
+        files_of_lengths,
+        mut errors,
+        files: discovered_files,
+        duration: discovery,
+    } = discovery;
+    let limiter = match (options.max_concurrent_reads, options.pause_during) {
+        (None, None) => None,
+        (max_concurrent, window) => {
+            let limiter = ReadLimiter::new(max_concurrent.unwrap_or(usize::MAX));
+            Some(match window {
+                Some(window) => limiter.paused_during(window, options.cancel.clone()),
+                None => limiter,
+            })
+        }
+    };
+    let discovered = clock.now();
+    if progress.is_some() {
+        let total_bytes = files_of_lengths
+            .iter()
+            .filter(|(length, files)| **length > 0 && files.len() > 1)
+            .map(|(length, files)| length * files.len() as u64)
+            .sum();
+        report(progress, Progress::Discovered(discovered_files));
+        report(progress, Progress::Hashing { total_bytes });
+    }
+    let context = HashContext {
+        cache: options
+            .cache
+            .as_ref()
+            .filter(|_| options.hash_command.is_none() && options.hasher.is_none()),
+        hasher: options.hasher.as_deref().unwrap_or(&SipHash128),
+        limiter: limiter.as_ref(),
+        hash_command: options.hash_command.as_ref(),
+        progress,
+        warnings: options.warnings.as_ref(),
+        cancel: options.cancel.as_ref(),
+        pause: options.pause.as_ref(),
+        xattrs: options.xattr_hashes && options.hash_command.is_none() && options.hasher.is_none(),
+        failures: Mutex::new(Vec::new()),
+    };
+    let settle = |(length, mut candidates): (u64, Vec<Fileinfo>)| {
+        let distinct = if options.filesystem_checksums && !context.cancelled() {
+            fschecksums::take_distinct(length, &mut candidates, &context)
+        } else {
+            Vec::new()
+        };
+        if !distinct.is_empty() {
+            report(progress, Progress::Hashed(length * distinct.len() as u64));
+        }
+        let files = differentiate_and_consolidate(length, candidates, &context);
+        let mut files = if options.paranoid {
+            split_unequal(files, &context)
+        } else {
+            files
+        };
+        if let Some(settled) = options.settled.as_ref() {
+            files
+                .iter()
+                .filter(|file| file.get_paths().len() > 1 && file.get_full_hash().is_some())
+                .for_each(|group| {
+                    let _ = settled.send(group.clone());
+                });
+        }
+        files.extend(distinct);
+        files
+    };
+    let settle_all = |lengths: FilesOfLengths| -> Vec<Fileinfo> {
+        if options.recent_dirs_first {
+            /* Bridging hands out the lengths in order as threads become free. */
+            recent_dirs_first(lengths)
+                .into_iter()
+                .par_bridge()
+                .map(&settle)
+                .flatten()
+                .collect()
+        } else {
+            lengths.into_par_iter().map(&settle).flatten().collect()
+        }
+    };
+    /* Lengths whose candidates are all in the cache settle first, as they take next to no
+    reading, so their groups are ready before the cold files are hashed. */
+    let (warm, cold): (FilesOfLengths, FilesOfLengths) = match context.cache {
+        Some(cache) => files_of_lengths.into_iter().partition(|(_, files)| {
+            files.len() > 1 && files.iter().all(|file| cache.is_warm(file))
+        }),
+        None => (FilesOfLengths::default(), files_of_lengths),
+    };
+    let mut complete_files = settle_all(warm);
+    complete_files.extend(settle_all(cold));
+    errors.extend(context.failures.into_inner().unwrap());
+    if options.deterministic {
+        sort_results(&mut complete_files, &mut errors);
+    }
+    let timings = ScanTimings {
+        discovery,
+        hashing: clock.now().saturating_duration_since(discovered),
+    };
+    (complete_files, errors, timings)
+}
+
+/// Puts scan results in an order which only depends on what was found: the paths of each
+/// collection sorted, collections by length, hashes and first path, and errors by path.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::{Path, PathBuf};
+///
+/// let mut files = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(2, None, 10, PathBuf::from("/b/y")),
+///     Fileinfo::from_existing_hash(2, None, 10, PathBuf::from("/a/y")),
+///     Fileinfo::from_existing_hash(1, None, 20, PathBuf::from("/a/x")),
+/// ]);
+/// let mut errors = Vec::new();
+/// ddh::sort_results(&mut files, &mut errors);
+/// assert_eq!(files[0].get_paths(), &[Path::new("/a/y").into(), Path::new("/b/y").into()]);
+/// assert_eq!(files[1].get_length(), 20);
+/// ```
+pub fn sort_results(files: &mut [Fileinfo], errors: &mut [(PathBuf, std::io::Error)]) {
+    files.iter_mut().for_each(|file| file.file_paths.sort());
+    files.sort_by_cached_key(|file| {
+        (
+            file.get_length(),
+            file.get_full_hash(),
+            file.get_partial_hash(),
+            file.get_paths().first().cloned(),
+        )
+    });
+    errors.sort_by(|a, b| a.0.cmp(&b.0));
+}
+
+/// Files found by a scan, by length.
+type FilesOfLengths = IntMap<u64, Vec<Fileinfo>>;
+
+/// Walks `search_dirs`, sorting the files found by length. Files of a length shared with
+/// another file are merged with their hard links.
+fn discover<P: AsRef<Path> + Sync>(
+    search_dirs: &[P],
+    ignore_dirs: &[P],
+    options: &ScanOptions,
+    progress: Option<&Sender<Progress>>,
+) -> (FilesOfLengths, Vec<(PathBuf, std::io::Error)>, u64) {
     let (sender, receiver) = channel();
-    let ignore_paths = ignore_dirs.iter().map(|x| x.as_ref().canonicalize().unwrap()).collect();
+    let ignore_rules = IgnoreRules::new(search_dirs, ignore_dirs);
+    let visited = Mutex::new(HashSet::new());
     search_dirs
         .par_iter()
         .for_each_with(sender, |s, search_dir| {
-            traverse_and_spawn(search_dir.as_ref(), &ignore_paths, s.clone(), min_size);
+            traverse_and_spawn(search_dir.as_ref(), &ignore_rules, s.clone(), options, &visited, &Inherited::default());
         });
-    let mut files_of_lengths: IntMap<u64, Vec<Fileinfo>> = IntMap::default();
+    let mut files_of_lengths: FilesOfLengths = IntMap::default();
     let mut errors = Vec::new();
+    let mut discovered_files = 0;
     receiver.iter().for_each(|pkg| match pkg {
-        ChannelPackage::Success(entry) => match files_of_lengths.entry(entry.get_length()) {
-            Entry::Vacant(e) => {
-                e.insert(vec![entry]);
+        ChannelPackage::Success(entry) => {
+            discovered_files += 1;
+            if progress.is_some() {
+                if options.report_discovered_files {
+                    report(progress, Progress::FileDiscovered(entry.get_paths()[0].to_path_buf()));
+                }
+                if discovered_files % DISCOVERY_STEP == 0 {
+                    report(progress, Progress::Discovered(discovered_files));
+                }
             }
-            Entry::Occupied(mut e) => {
-                e.get_mut().push(entry);
+            match files_of_lengths.entry(entry.get_length()) {
+                Entry::Vacant(e) => {
+                    e.insert(vec![entry]);
+                }
+                Entry::Occupied(mut e) => {
+                    e.get_mut().push(entry);
+                }
             }
-        },
+        }
         ChannelPackage::Fail(entry, error) => {
+            report(progress, Progress::Error {
+                path: entry.clone(),
+                message: error.to_string(),
+            });
             errors.push((entry, error));
         }
     });
-    let complete_files: Vec<Fileinfo> = files_of_lengths
-        .into_par_iter()
-        .map(|x| differentiate_and_consolidate(x.0, x.1))
-        .flatten()
+    files_of_lengths
+        .iter_mut()
+        .filter(|(length, files)| **length > 0 && files.len() > 1)
+        .for_each(|(_, files)| *files = merge_hard_links(std::mem::take(files)));
+    (files_of_lengths, errors, discovered_files)
+}
+
+/// Walks the directories a scan with `options` would, counting the files it would consider
+/// and those it would hash, without reading any of them.
+///
+/// # Examples
+/// ```
+/// let dir = std::env::temp_dir().join("ddh_estimate_example");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(dir.join("a"), "same").unwrap();
+/// std::fs::write(dir.join("b"), "same").unwrap();
+/// std::fs::write(dir.join("c"), "longer").unwrap();
+///
+/// let estimate = ddh::estimate_dirs(vec![&dir], vec![], &ddh::ScanOptions::default());
+/// assert_eq!((estimate.files, estimate.bytes), (3, 14));
+/// assert_eq!((estimate.candidates, estimate.candidate_bytes), (2, 8));
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn estimate_dirs<P: AsRef<Path> + Sync>(
+    search_dirs: Vec<P>,
+    ignore_dirs: Vec<P>,
+    options: &ScanOptions,
+) -> ScanEstimate {
+    discover_dirs(&search_dirs, &ignore_dirs, options).estimate()
+}
+
+/// Orders the files of each length by the newest modification time of the directories holding
+/// them, newest first. Lengths of a single file can't hold duplicates and go last.
+fn recent_dirs_first(files_of_lengths: IntMap<u64, Vec<Fileinfo>>) -> Vec<(u64, Vec<Fileinfo>)> {
+    let mut dir_times: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+    let mut lengths: Vec<(u64, Vec<Fileinfo>)> = files_of_lengths.into_iter().collect();
+    lengths.sort_by_cached_key(|(_, files)| {
+        if files.len() < 2 {
+            return Reverse(None);
+        }
+        Reverse(files
+            .iter()
+            .flat_map(|file| file.get_paths().iter())
+            .filter_map(|path| path.parent())
+            .filter_map(|dir| {
+                *dir_times
+                    .entry(dir.to_path_buf())
+                    .or_insert_with(|| fs::metadata(dir).and_then(|meta| meta.modified()).ok())
+            })
+            .max())
+    });
+    lengths
+}
+
+/// Merges collections whose full hashes and lengths match, such as scan results combined with
+/// entries built by `Fileinfo::from_existing_hash`. Collections without a full hash are
+/// returned unchanged since they can't be compared without reading the files.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::PathBuf;
+///
+/// let files = vec![
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/a/x")),
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/b/x")),
+///     Fileinfo::from_existing_hash(2, None, 10, PathBuf::from("/c/x")),
+/// ];
+/// let merged = ddh::consolidate(files);
+/// assert_eq!(merged.len(), 2);
+/// assert!(merged.iter().any(|f| f.get_paths().len() == 2));
+/// ```
+pub fn consolidate(files: Vec<Fileinfo>) -> Vec<Fileinfo> {
+    let (hashed, mut unhashed): (Vec<Fileinfo>, Vec<Fileinfo>) =
+        files.into_iter().partition(|x| x.get_full_hash().is_some());
+    let mut groups: HashMap<(u64, Option<u128>), Fileinfo> = HashMap::new();
+    let mut order = Vec::new();
+    hashed.into_iter().for_each(|mut file| {
+        match groups.entry((file.get_length(), file.get_full_hash())) {
+            Entry::Vacant(e) => {
+                order.push(*e.key());
+                e.insert(file);
+            }
+            Entry::Occupied(mut e) => {
+                e.get_mut().file_paths.append(&mut file.file_paths);
+            }
+        }
+    });
+    let mut merged: Vec<Fileinfo> = order
+        .iter()
+        .filter_map(|key| groups.remove(key))
         .collect();
-    (complete_files, errors)
+    merged.append(&mut unhashed);
+    merged
+}
+
+/// Merges the entries of files which are hard links to one another, so each file is read once
+/// and all its links end up in one collection. Files without a device and inode are kept apart.
+fn merge_hard_links(files: Vec<Fileinfo>) -> Vec<Fileinfo> {
+    let mut merged: Vec<Fileinfo> = Vec::with_capacity(files.len());
+    let mut seen: HashMap<(u64, u64), usize> = HashMap::new();
+    for mut file in files {
+        let meta = file.get_metadata();
+        if let (Some(device), Some(inode)) = (meta.device, meta.inode) {
+            match seen.entry((device, inode)) {
+                Entry::Occupied(e) => {
+                    merged[*e.get()].file_paths.append(&mut file.file_paths);
+                    continue;
+                }
+                Entry::Vacant(e) => {
+                    e.insert(merged.len());
+                }
+            }
+        }
+        merged.push(file);
+    }
+    merged
 }
 
-fn traverse_and_spawn(current_path: impl AsRef<Path>, ignore_dirs: &Vec<PathBuf>, sender: Sender<ChannelPackage>, min_size: u64) {
-    if current_path.as_ref().canonicalize().is_ok() && ignore_dirs.iter().any(|x| current_path.as_ref().canonicalize().unwrap().starts_with(x)){
+/// What the traversal of a directory hands down to its entries.
+#[derive(Default)]
+struct Inherited {
+    /// Device of the directory, for `ScanOptions::one_file_system`.
+    device: Option<u64>,
+    /// Depth of the entries, the search directories being at depth zero.
+    depth: usize,
+    /// Ignore files of the directory and those above it, for `ScanOptions::respect_gitignore`.
+    gitignore: Option<Arc<GitIgnore>>,
+}
+
+/// Directories entered so far when following symlinks, by device and inode.
+type VisitedDirs = Mutex<HashSet<(u64, u64)>>;
+
+fn traverse_and_spawn(current_path: impl AsRef<Path>, ignore_rules: &IgnoreRules, sender: Sender<ChannelPackage>, options: &ScanOptions, visited: &VisitedDirs, inherited: &Inherited) {
+    let cancelled = || options.cancel.as_ref().is_some_and(|c| c.is_cancelled());
+    while options.pause.as_ref().is_some_and(|p| p.is_paused()) && !cancelled() {
+        std::thread::sleep(PAUSE_POLL);
+    }
+    if cancelled() {
+        return;
+    }
+    if current_path.as_ref().canonicalize().is_ok_and(|x| ignore_rules.is_ignored(&x)) {
         return;
     }
     let current_path_metadata = match fs::symlink_metadata(&current_path) {
@@ -74,6 +722,7 @@ fn traverse_and_spawn(current_path: impl AsRef<Path>, ignore_dirs: &Vec<PathBuf>
         }
         Ok(meta) => meta,
     };
+    let entry_path = current_path.as_ref().to_path_buf();
     let current_path = match fs::canonicalize(&current_path) {
         Err(e) => {
             sender
@@ -84,7 +733,13 @@ fn traverse_and_spawn(current_path: impl AsRef<Path>, ignore_dirs: &Vec<PathBuf>
         Ok(canonical_path) => canonical_path,
     };
     match current_path_metadata {
-        meta if meta.is_file() && meta.len() >= min_size => {
+        meta if meta.is_file() && !(options.file_filters.allows(&current_path) && options.file_filters.allows_modified(&meta)) => {}
+        meta if meta.is_file() && !options.hydrate_placeholders && placeholders::is_placeholder(&meta) => {
+            sender
+                .send(ChannelPackage::Fail(current_path, placeholders::skipped_error()))
+                .expect("Error sending new ChannelPackage::Fail");
+        }
+        meta if meta.is_file() && meta.len() >= options.min_size => {
             sender
                 .send(ChannelPackage::Success(Fileinfo::new(
                     None,
@@ -94,23 +749,59 @@ fn traverse_and_spawn(current_path: impl AsRef<Path>, ignore_dirs: &Vec<PathBuf>
                 )))
                 .expect("Error sending new ChannelPackage::Success");
         }
+        meta if meta.is_dir() && options.follow_symlinks
+            && !visited.lock().unwrap().insert(dir_identity(&current_path, &meta)) => {
+            /* Entered before, through a symlink or a link cycle. */
+        }
+        meta if meta.is_dir() && options.one_file_system
+            && inherited.device.is_some_and(|parent| FileMetadata::from(&meta).device != Some(parent)) => {
+            /* Mount point of another filesystem. */
+        }
+        meta if meta.is_dir() && options.max_depth.is_some_and(|max| inherited.depth >= max) => {}
         meta if meta.is_dir() => match fs::read_dir(&current_path) {
             Ok(read_dir_results) => {
-                let good_entries: Vec<_> = read_dir_results
-                    .filter(|x| x.is_ok())
-                    .map(|x| x.unwrap())
-                    .collect();
-                let (files, dirs): (Vec<&DirEntry>, Vec<&DirEntry>) =
-                    good_entries.par_iter().partition(|&x| {
-                        x.file_type()
-                            .expect("Error reading DirEntry file type")
-                            .is_file()
+                let below = Inherited {
+                    device: FileMetadata::from(&meta).device,
+                    depth: inherited.depth + 1,
+                    gitignore: options.respect_gitignore.then(|| match inherited.gitignore.as_ref() {
+                        Some(above) => GitIgnore::child(above, &current_path),
+                        None => GitIgnore::root(&current_path),
+                    }),
+                };
+                let skipped = |entry: &DirEntry, is_dir: bool| {
+                    (options.skip_hidden && ignore::is_hidden(entry))
+                        || below.gitignore.as_ref().is_some_and(|g| g.is_ignored(&entry.path(), is_dir))
+                };
+                let mut good_entries = read_dir_results.filter_map(Result::ok);
+                let mut subdirs: Vec<PathBuf> = Vec::new();
+                loop {
+                    let batch: Vec<DirEntry> = good_entries.by_ref().take(ENTRY_BATCH).collect();
+                    if batch.is_empty() {
+                        break;
+                    }
+                    let (files, dirs): (Vec<&DirEntry>, Vec<&DirEntry>) =
+                        batch.par_iter().partition(|&x| {
+                            x.file_type()
+                                .expect("Error reading DirEntry file type")
+                                .is_file()
+                        });
+                    files.par_iter().for_each_with(sender.clone(), |sender, x| {
+                        if skipped(x, false) {
+                            return;
+                        }
+                        traverse_and_spawn(x.path(), ignore_rules, sender.clone(), options, visited, &below)
                     });
-                files.par_iter().for_each_with(sender.clone(), |sender, x| {
-                    traverse_and_spawn(&x.path(), ignore_dirs, sender.clone(), min_size)
-                });
-                dirs.into_par_iter().for_each_with(sender, |sender, x| {
-                    traverse_and_spawn(x.path().as_path(), ignore_dirs, sender.clone(), min_size);
+                    subdirs.extend(
+                        dirs.into_iter()
+                            .filter(|x| !skipped(x, x.file_type().is_ok_and(|t| t.is_dir())))
+                            .map(DirEntry::path),
+                    );
+                }
+                /* Entries hold on to the listing, so it is closed before descending. Otherwise
+                every directory on the way down keeps a handle open and deep trees run out. */
+                drop(good_entries);
+                subdirs.into_par_iter().for_each_with(sender.clone(), |sender, path| {
+                    traverse_and_spawn(&path, ignore_rules, sender.clone(), options, visited, &below);
                 })
             }
             Err(e) => {
@@ -119,25 +810,180 @@ fn traverse_and_spawn(current_path: impl AsRef<Path>, ignore_dirs: &Vec<PathBuf>
                     .expect("Error sending new ChannelPackage::Fail");
             }
         },
-        _ => { /*Symlinks not yet handled*/ }
+        meta if meta.file_type().is_symlink() && (options.resolve_symlinks || options.follow_symlinks) => {
+            match fs::metadata(&entry_path) {
+                Ok(target) if target.is_dir() && options.follow_symlinks => {
+                    traverse_and_spawn(&current_path, ignore_rules, sender, options, visited, inherited);
+                }
+                Ok(target) if target.is_file() && !(options.file_filters.allows(&entry_path) && options.file_filters.allows_modified(&target)) => {}
+                Ok(target) if target.is_file() && !options.hydrate_placeholders && placeholders::is_placeholder(&target) => {
+                    sender
+                        .send(ChannelPackage::Fail(entry_path, placeholders::skipped_error()))
+                        .expect("Error sending new ChannelPackage::Fail");
+                }
+                Ok(target) if target.is_file() && target.len() >= options.min_size => {
+                    sender
+                        .send(ChannelPackage::Success(Fileinfo::new(
+                            None,
+                            None,
+                            target,
+                            entry_path,
+                        )))
+                        .expect("Error sending new ChannelPackage::Success");
+                }
+                Ok(target) if !target.is_file() && !target.is_dir() => warn(options, entry_path, WarningKind::SpecialFile),
+                Ok(_) => {}
+                Err(e) => {
+                    sender
+                        .send(ChannelPackage::Fail(entry_path, e))
+                        .expect("Error sending new ChannelPackage::Fail");
+                }
+            }
+        }
+        meta if !meta.is_file() && !meta.is_dir() && !meta.file_type().is_symlink() => {
+            warn(options, current_path, WarningKind::SpecialFile);
+        }
+        _ => { /*Other symlinks not yet handled*/ }
+    }
+}
+
+/// Identifies a directory by device and inode, or by its canonical path where there are none.
+fn dir_identity(path: &Path, meta: &fs::Metadata) -> (u64, u64) {
+    let meta = FileMetadata::from(meta);
+    match (meta.device, meta.inode) {
+        (Some(device), Some(inode)) => (device, inode),
+        _ => {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = siphasher::sip::SipHasher::new();
+            path.hash(&mut hasher);
+            (0, hasher.finish())
+        }
+    }
+}
+
+/// Settings and shared state for hashing the candidates of a scan.
+struct HashContext<'a> {
+    cache: Option<&'a HashCache>,
+    hasher: &'a dyn ContentHasher,
+    limiter: Option<&'a ReadLimiter>,
+    hash_command: Option<&'a HashCommand>,
+    progress: Option<&'a Sender<Progress>>,
+    warnings: Option<&'a Sender<Warning>>,
+    cancel: Option<&'a CancellationHandle>,
+    /// Handle holding reads back while paused. Only its flag is polled, so reads don't contend
+    /// for a lock while it isn't paused.
+    pause: Option<&'a PauseHandle>,
+    /// Take full hashes from extended attributes where they are current.
+    xattrs: bool,
+    /// Files left out of the results because they couldn't be hashed or compared.
+    failures: Mutex<Vec<(PathBuf, std::io::Error)>>,
+}
+
+impl HashContext<'_> {
+    fn cancelled(&self) -> bool {
+        self.cancel.is_some_and(|c| c.is_cancelled())
+    }
+
+    /// Blocks while the scan is paused, until it is resumed or cancelled.
+    fn wait_while_paused(&self) {
+        while self.pause.is_some_and(|p| p.is_paused()) && !self.cancelled() {
+            std::thread::sleep(PAUSE_POLL);
+        }
+    }
+
+    /// Hashes the first file of `file` with the built-in hasher, reporting the progress.
+    fn hash(&self, file: &mut Fileinfo, mode: HashMode) -> Option<u128> {
+        self.wait_while_paused();
+        let path = self.progress.map(|_| file.get_paths()[0].to_path_buf());
+        if let Some(path) = path.clone() {
+            report(self.progress, Progress::HashStarted { path, mode });
+        }
+        let hash = file.generate_hash_limited(mode, self.hasher, self.limiter);
+        if let Some(path) = path {
+            report(self.progress, Progress::HashFinished { path, mode, hash });
+        }
+        if let (Some(warnings), Some(_)) = (self.warnings, hash) {
+            let found = file.get_metadata();
+            let path = &file.get_paths()[0];
+            let changed = fs::metadata(path).is_ok_and(|meta| {
+                let now = FileMetadata::from(&meta);
+                (now.size, now.mtime, now.mtime_nsec) != (found.size, found.mtime, found.mtime_nsec)
+            });
+            if changed {
+                let _ = warnings.send(Warning {
+                    path: path.to_path_buf(),
+                    kind: WarningKind::ChangedDuringScan,
+                });
+            }
+        }
+        hash
+    }
+
+    /// Gets the full hash of `file` from its extended attributes, if they are read and current.
+    fn stored_hash(&self, file: &Fileinfo) -> Option<u128> {
+        if !self.xattrs {
+            return None;
+        }
+        xattrs::read_hash(&file.get_paths()[0], file.get_metadata())
+    }
+
+    /// Records that `path` is left out of the results because of `error`.
+    fn fail(&self, path: PathBuf, error: std::io::Error) {
+        report(self.progress, Progress::Error {
+            path: path.clone(),
+            message: error.to_string(),
+        });
+        self.failures.lock().unwrap().push((path, error));
     }
 }
 
-fn differentiate_and_consolidate(file_length: u64, mut files: Vec<Fileinfo>) -> Vec<Fileinfo> {
+fn differentiate_and_consolidate(
+    file_length: u64,
+    mut files: Vec<Fileinfo>,
+    context: &HashContext,
+) -> Vec<Fileinfo> {
     if file_length == 0 || files.is_empty() {
         return files;
     }
+    let HashContext {
+        cache,
+        limiter,
+        hash_command,
+        progress,
+        ..
+    } = *context;
     match files.len() {
         1 => return files,
         n if n > 1 => {
+            /* A cancelled scan leaves out groups it couldn't settle. */
+            if context.cancelled() {
+                return Vec::new();
+            }
             files.par_iter_mut().for_each(|file_ref| {
-                let hash = file_ref.generate_hash(HashMode::Partial);
+                if context.cancelled() {
+                    return;
+                }
+                if let Some((partial, full)) = cache.and_then(|c| c.lookup(file_ref)) {
+                    if partial.is_some() {
+                        file_ref.set_partial_hash(partial);
+                        file_ref.set_full_hash(full);
+                        return;
+                    }
+                }
+                let hash = context.hash(file_ref, HashMode::Partial);
                 file_ref.set_partial_hash(hash);
             });
-            if file_length <= 4096 {
+            if context.cancelled() {
+                return Vec::new();
+            }
+            if file_length <= 4096 && hash_command.is_none() {
                 files.par_iter_mut().for_each(|x| {
                     x.set_full_hash(x.get_partial_hash());
                 });
+                report(progress, Progress::Hashed(file_length * files.len() as u64));
+                if let Some(cache) = cache {
+                    files.iter().for_each(|x| cache.insert(x));
+                }
                 return dedupe(files);
             }
             let mut partial_hashes: HashMap<Option<u128>, u64> = HashMap::new();
@@ -156,12 +1002,40 @@ fn differentiate_and_consolidate(file_length: u64, mut files: Vec<Fileinfo>) ->
                 .filter(|x| x.1 > 0)
                 .map(|y| y.0)
                 .collect();
+            let failed = Mutex::new(Vec::new());
             files.par_iter_mut().for_each(|x| {
-                if dedupe_hashes.contains(&x.get_partial_hash()) {
-                    let hash = x.generate_hash(HashMode::Full);
+                if context.cancelled() {
+                    return;
+                }
+                report(progress, Progress::Hashed(file_length));
+                if dedupe_hashes.contains(&x.get_partial_hash()) && x.get_full_hash().is_none() {
+                    context.wait_while_paused();
+                    let hash = match hash_command {
+                        Some(command) => match command.hash_limited(&x.get_paths()[0], limiter) {
+                            Ok(hash) => Some(hash),
+                            Err(e) => {
+                                failed.lock().unwrap().push((x.get_paths()[0].to_path_buf(), e));
+                                return;
+                            }
+                        },
+                        None => context
+                            .stored_hash(x)
+                            .or_else(|| context.hash(x, HashMode::Full)),
+                    };
                     x.set_full_hash(hash);
                 }
             });
+            if context.cancelled() {
+                return Vec::new();
+            }
+            let failed = failed.into_inner().unwrap();
+            if !failed.is_empty() {
+                files.retain(|x| failed.iter().all(|(path, _)| **path != *x.get_paths()[0]));
+                failed.into_iter().for_each(|(path, e)| context.fail(path, e));
+            }
+            if let Some(cache) = cache {
+                files.iter().for_each(|x| cache.insert(x));
+            }
         }
         _ => {
             panic!("Somehow a vector of negative length was created. Please report this as a bug");
@@ -170,6 +1044,79 @@ fn differentiate_and_consolidate(file_length: u64, mut files: Vec<Fileinfo>) ->
     dedupe(files)
 }
 
+/// Sends `event` to the progress channel of the scan, if it has one. A receiver which went away
+/// doesn't stop the scan.
+fn report(progress: Option<&Sender<Progress>>, event: Progress) {
+    if let Some(progress) = progress {
+        let _ = progress.send(event);
+    }
+}
+
+fn warn(options: &ScanOptions, path: PathBuf, kind: WarningKind) {
+    if let Some(warnings) = options.warnings.as_ref() {
+        let _ = warnings.send(Warning { path, kind });
+    }
+}
+
+/// Splits collections whose files don't all have the same contents despite matching hashes.
+/// The files split off keep the colliding hashes. Files which can't be read for the comparison
+/// are left out and reported as failures, while the others are still compared.
+fn split_unequal(files: Vec<Fileinfo>, context: &HashContext) -> Vec<Fileinfo> {
+    let mut verified = Vec::with_capacity(files.len());
+    for mut file in files {
+        if file.get_paths().len() < 2 {
+            verified.push(file);
+            continue;
+        }
+        if context.cancelled() {
+            continue;
+        }
+        let mut classes: Vec<Vec<Box<Path>>> = Vec::new();
+        'paths: for path in file.file_paths.drain(..) {
+            let mut index = 0;
+            while index < classes.len() {
+                context.wait_while_paused();
+                match utils::compare_files(&classes[index][0], &path, context.limiter) {
+                    Ok(true) => {
+                        classes[index].push(path);
+                        continue 'paths;
+                    }
+                    Ok(false) => index += 1,
+                    Err(Unreadable::Second(e)) => {
+                        context.fail(path.into_path_buf(), e);
+                        continue 'paths;
+                    }
+                    /* The rest of the class still compares equal through its next file. */
+                    Err(Unreadable::First(e)) => {
+                        let unreadable = classes[index].remove(0);
+                        context.fail(unreadable.into_path_buf(), e);
+                        if classes[index].is_empty() {
+                            classes.remove(index);
+                        }
+                    }
+                }
+            }
+            classes.push(vec![path]);
+        }
+        let mut classes = classes.into_iter();
+        let first = classes.next().unwrap_or_default();
+        for class in classes {
+            let mut paths = class.into_iter();
+            let mut split = Fileinfo::new(
+                file.get_full_hash(),
+                file.get_partial_hash(),
+                *file.get_metadata(),
+                paths.next().map(Path::into_path_buf).unwrap_or_default(),
+            );
+            split.file_paths.extend(paths);
+            verified.push(split);
+        }
+        file.file_paths = first;
+        verified.push(file);
+    }
+    verified
+}
+
 fn dedupe(mut files: Vec<Fileinfo>) -> Vec<Fileinfo> {
     let mut cache: HashMap<(Option<u128>, Option<u128>), &mut Fileinfo> = HashMap::new();
     files.iter_mut().for_each(|file| {
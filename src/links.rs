@@ -0,0 +1,169 @@
+//! Symlinks and hard links among duplicate groups.
+//!
+//! Package manager stores and dotfile managers build link farms: many symlinks resolving to
+//! one file, as found by scans with `ScanOptions::resolve_symlinks`. Backup tools and package
+//! managers also hard link files, and scans read each file once however many names it has,
+//! putting all of them in its group. Links share the contents of their file without taking any
+//! space, so they are reported apart from true copies, which are distinct files holding the
+//! same contents.
+
+use crate::fileinfo::{FileMetadata, Fileinfo};
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Symlinks of a group which resolve to the same file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkFarm {
+    /// Canonical path of the file the links resolve to.
+    pub target: PathBuf,
+    /// Links to `target`, in the order of the group.
+    pub links: Vec<PathBuf>,
+}
+
+/// Splits `group` into its link farms, sorted by target, and the collection of distinct files
+/// it holds: the paths which aren't symlinks plus one link for each target outside the group.
+/// The group holds true copies when the collection has more than one path.
+///
+/// # Examples
+/// ```
+/// # #[cfg(unix)]
+/// # fn main() -> std::io::Result<()> {
+/// use ddh::fileinfo::Fileinfo;
+/// use std::os::unix::fs::symlink;
+///
+/// let dir = std::env::temp_dir().join("ddh_link_farm_example");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// std::fs::create_dir_all(&dir)?;
+/// let dir = dir.canonicalize()?;
+/// let (store, a, b) = (dir.join("store"), dir.join("a"), dir.join("b"));
+/// std::fs::write(&store, b"shared")?;
+/// symlink(&store, &a)?;
+/// symlink(&store, &b)?;
+/// let group = ddh::consolidate(
+///     [&store, &a, &b]
+///         .iter()
+///         .map(|path| Fileinfo::from_existing_hash(1, None, 6, path.to_path_buf()))
+///         .collect(),
+/// );
+/// let (farms, copies) = ddh::links::split_links(&group[0]);
+/// assert_eq!(farms[0].target, store);
+/// assert_eq!(farms[0].links, vec![a, b]);
+/// assert_eq!(copies.get_paths(), &[store.into()]);
+/// std::fs::remove_dir_all(&dir)?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub fn split_links(group: &Fileinfo) -> (Vec<LinkFarm>, Fileinfo) {
+    let mut farms: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in group.get_paths().iter() {
+        let target = fs::symlink_metadata(path)
+            .ok()
+            .filter(|meta| meta.file_type().is_symlink())
+            .and_then(|_| fs::canonicalize(path).ok());
+        match target {
+            Some(target) => farms.entry(target).or_default().push(path.to_path_buf()),
+            None => files.push(path.to_path_buf()),
+        }
+    }
+    for (target, links) in farms.iter() {
+        if !files.contains(target) {
+            files.push(links[0].clone());
+        }
+    }
+    let farms = farms
+        .into_iter()
+        .map(|(target, links)| LinkFarm { target, links })
+        .collect();
+    let mut files = files.into_iter();
+    let mut distinct = Fileinfo::new(
+        group.get_full_hash(),
+        group.get_partial_hash(),
+        *group.get_metadata(),
+        files.next().unwrap_or_default(),
+    );
+    distinct
+        .file_paths
+        .extend(files.map(PathBuf::into_boxed_path));
+    (farms, distinct)
+}
+
+/// Paths of a group which are hard links to the same file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HardLinkSet {
+    /// Names of the file, in the order of the group.
+    pub paths: Vec<PathBuf>,
+}
+
+/// Splits `group` into its sets of hard links to one file and the collection of distinct files
+/// it holds, where the first path of each set stands for its file. Files are told apart by
+/// device and inode, so nothing is split on platforms which don't have them.
+///
+/// # Examples
+/// ```
+/// # #[cfg(unix)]
+/// # fn main() -> std::io::Result<()> {
+/// use ddh::fileinfo::Fileinfo;
+///
+/// let dir = std::env::temp_dir().join("ddh_hard_link_example");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// std::fs::create_dir_all(&dir)?;
+/// let (file, link, copy) = (dir.join("file"), dir.join("link"), dir.join("copy"));
+/// std::fs::write(&file, b"shared")?;
+/// std::fs::hard_link(&file, &link)?;
+/// std::fs::write(&copy, b"shared")?;
+/// let group = ddh::consolidate(
+///     [&file, &link, &copy]
+///         .iter()
+///         .map(|path| Fileinfo::from_existing_hash(1, None, 6, path.to_path_buf()))
+///         .collect(),
+/// );
+/// let (sets, copies) = ddh::links::split_hard_links(&group[0]);
+/// assert_eq!(sets[0].paths, vec![file.clone(), link]);
+/// assert_eq!(copies.get_paths(), &[file.into(), copy.into()]);
+/// std::fs::remove_dir_all(&dir)?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+pub fn split_hard_links(group: &Fileinfo) -> (Vec<HardLinkSet>, Fileinfo) {
+    let mut sets: Vec<Vec<PathBuf>> = Vec::new();
+    let mut seen: HashMap<(u64, u64), usize> = HashMap::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in group.get_paths().iter() {
+        let identity = fs::symlink_metadata(path)
+            .ok()
+            .map(FileMetadata::from)
+            .and_then(|meta| Some((meta.device?, meta.inode?)));
+        match identity.map(|identity| seen.entry(identity)) {
+            Some(Entry::Occupied(e)) => sets[*e.get()].push(path.to_path_buf()),
+            Some(Entry::Vacant(e)) => {
+                e.insert(sets.len());
+                sets.push(vec![path.to_path_buf()]);
+                files.push(path.to_path_buf());
+            }
+            None => files.push(path.to_path_buf()),
+        }
+    }
+    let sets = sets
+        .into_iter()
+        .filter(|paths| paths.len() > 1)
+        .map(|paths| HardLinkSet { paths })
+        .collect();
+    let mut files = files.into_iter();
+    let mut distinct = Fileinfo::new(
+        group.get_full_hash(),
+        group.get_partial_hash(),
+        *group.get_metadata(),
+        files.next().unwrap_or_default(),
+    );
+    distinct
+        .file_paths
+        .extend(files.map(PathBuf::into_boxed_path));
+    (sets, distinct)
+}
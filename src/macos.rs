@@ -0,0 +1,43 @@
+//! Parts of a Mac which scans skip by default.
+//!
+//! Time Machine mounts local snapshots and backup disks as ordinary looking trees holding a
+//! copy of every file, and trash folders of removable volumes hold files already on their way
+//! out. `SYSTEM_EXCLUDES` holds ignore entries for them. Files which iCloud Drive keeps only in
+//! the cloud are dataless: reading them downloads them, so scans leave them out as described
+//! in the `placeholders` module.
+
+use std::fs::Metadata;
+
+/// Ignore entries skipping Time Machine snapshots and backups and the trash folders of volumes.
+pub const SYSTEM_EXCLUDES: &[&str] = &[
+    "/Volumes/com.apple.TimeMachine.localsnapshots",
+    "/Volumes/.timemachine",
+    "**/.MobileBackups",
+    "**/Backups.backupdb",
+    "**/.Trashes",
+];
+
+/// Flag of files whose contents are only stored remotely, from `sys/stat.h`.
+#[cfg(target_os = "macos")]
+const SF_DATALESS: u32 = 0x4000_0000;
+
+/// Returns true if the file of `meta` has no local contents, so that reading it would download
+/// it first. Only iCloud Drive on macOS keeps such files.
+///
+/// # Examples
+/// ```
+/// let meta = std::fs::metadata(std::env::current_exe().unwrap()).unwrap();
+/// assert!(!ddh::macos::is_dataless(&meta));
+/// ```
+pub fn is_dataless(meta: &Metadata) -> bool {
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::macos::fs::MetadataExt;
+        meta.st_flags() & SF_DATALESS != 0
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = meta;
+        false
+    }
+}
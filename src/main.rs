@@ -1,14 +1,49 @@
-use clap::{Parser, ValueEnum};
-use ddh::fileinfo::Fileinfo;
+use clap::{Parser, Subcommand, ValueEnum};
+use ddh::actions::{Anomaly, Executor, KeepPolicy};
+use ddh::agent::{complete_hashes, complete_hashes_with, merge_remote_with, HashIndex};
+use ddh::annotations::{Annotations, DEFAULT_ANNOTATIONS_FILE};
+use ddh::anonymize::Anonymizer;
+use ddh::cache::HashCache;
+use ddh::cancel::PauseHandle;
+use ddh::copies::CopyPatterns;
+use ddh::external::HashCommand;
+use ddh::fileinfo::{FileMetadata, Fileinfo};
+use ddh::fingerprint::Fingerprint;
+use ddh::format::{format_duration, format_size, format_time, parse_time, NumberFormat};
+use ddh::hasher::{Blake3, ContentHasher, Sha256, SipHash128, Xxh3};
+use ddh::ignore::{parse_ignore_list, FileFilters, IgnoreRules};
+use ddh::links::{HardLinkSet, LinkFarm};
+use ddh::output::{
+    read_json_report, CsvSink, EdgeListSink, FdupesSink, GraphmlSink, HtmlSink, JsonLinesSink,
+    JsonSink, JsonSummary, OutputSink,
+};
+use ddh::policy::{Policy, PolicyAction, PolicyDecision};
+use ddh::progress::Progress;
+use ddh::roots::{ReferenceDirs, RootLabels, ScanRoot};
+use ddh::tenants::Scopes;
+use ddh::throttle::{PauseWindow, ReadLimiter};
+use ddh::transfer::{copy_tree, LinkMode};
+use ddh::usage::Attribution;
+use ddh::warnings::Warning;
+use ddh::watch::RescanQueue;
+use ddh::{ScanEstimate, ScanOptions, ScanTimings};
+use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self};
 use std::io::prelude::*;
-use std::io::stdin;
-use std::path::PathBuf;
+use std::io::{stderr, stdin, stdout};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about=DDH_ABOUT)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Minimum file size in bytes to consider
     #[arg(short, long("minimum"), num_args(0..=1), default_value_t = 0)]
     min_size: u64,
@@ -24,18 +59,660 @@ struct Args {
     /// Set output format
     #[arg(short('f'), long("format"), ignore_case(true), value_enum, num_args(0..=1), default_value_t = PrintFmt::Standard)]
     fmt: PrintFmt,
-    /// Directories to ignore (comma separated list)
-    #[arg(short, long("ignore"), value_delimiter(','))]
+    /// Paths or glob patterns to ignore (comma separated list). Relative entries apply below each searched directory, or only below ROOT when written as ROOT:entry,...
+    #[arg(short, long("ignore"))]
     ignore_dirs: Vec<String>,
-    /// Directories to parse
-    #[arg(short, long("directories"), value_delimiter(' '), num_args(1..), required = true)]
+    /// Also ignore the entries of these presets (comma separated list)
+    #[arg(long, value_enum, value_delimiter(','))]
+    preset_ignores: Vec<IgnorePreset>,
+    /// Languages whose copy naming conventions mark instances as copies (comma separated, default all)
+    #[arg(long, value_delimiter(','))]
+    copy_locales: Vec<String>,
+    /// File holding acknowledged groups and group notes
+    #[arg(long, default_value = DEFAULT_ANNOTATIONS_FILE)]
+    annotations: String,
+    /// Include acknowledged duplicate groups in reports
+    #[arg(long)]
+    show_acked: bool,
+    /// Hash cache file to reuse hashes of unchanged files between runs [default: ~/.cache/ddh/hashes.json]
+    #[arg(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+    /// Compute full hashes with an external command instead, e.g. 'xxhsum -H128 {}' where {} stands for the file. Disables the hash cache
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        conflicts_with_all(["cache", "state", "ingest", "delete", "link"])
+    )]
+    hash_cmd: Option<HashCommand>,
+    /// Compare candidate duplicates byte by byte after their hashes match, for certainty beyond hash collisions
+    #[arg(long)]
+    paranoid: bool,
+    /// Hash algorithm. Algorithms other than sip128 disable the hash cache
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Sip128, conflicts_with("hash_cmd"))]
+    hash: HashAlgorithm,
+    /// Also report names in the same directory which differ only by case or Unicode normalization, as they collide when synced to other filesystems
+    #[arg(long)]
+    name_collisions: bool,
+    /// Also report directories whose whole trees hold the same names with the same contents, counting the files the scan found
+    #[arg(long)]
+    duplicate_dirs: bool,
+    /// Also report pairs of directories whose trees share at least PERCENT of the bytes of the larger one, such as backups where a few files changed
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    similar_dirs: Option<f64>,
+    /// Handling of symlinks: skip them, report links sharing a target (link farms) apart from true copies, or follow links to directories too
+    #[arg(long, ignore_case(true), value_enum, default_value_t = SymlinkMode::Skip)]
+    symlinks: SymlinkMode,
+    /// Same as --symlinks report, for scripts written before --symlinks
+    #[arg(long, hide = true, conflicts_with("symlinks"))]
+    resolve_symlinks: bool,
+    /// Don't descend into directories on other filesystems than the searched directory they are below, like du -x
+    #[arg(long, short('x'))]
+    one_file_system: bool,
+    /// Skip files and directories excluded by .gitignore and .ignore files in the searched trees and by git's global excludes file
+    #[arg(long)]
+    respect_gitignore: bool,
+    /// Skip dotfiles and dot-directories, and on Windows files and directories with the hidden attribute
+    #[arg(long)]
+    skip_hidden: bool,
+    /// Only scan files with one of these extensions (comma separated list), as in --ext jpg,png,raw
+    #[arg(long, value_name = "EXTENSIONS", value_delimiter(','))]
+    ext: Vec<String>,
+    /// Skip files with one of these extensions (comma separated list)
+    #[arg(long, value_name = "EXTENSIONS", value_delimiter(','))]
+    skip_ext: Vec<String>,
+    /// Only scan files modified within AGE, such as 30d, 12h or 2w, or after a date such as 2024-01-31
+    #[arg(long, value_name = "AGE|DATE", value_parser = parse_cutoff)]
+    newer_than: Option<SystemTime>,
+    /// Only scan files not modified within AGE, such as 1y, or before a date such as 2024-01-31
+    #[arg(long, value_name = "AGE|DATE", value_parser = parse_cutoff)]
+    older_than: Option<SystemTime>,
+    /// Skip files matching GLOB, repeatable. Globs without a / match file names anywhere, as in --exclude '*.tmp'
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Only scan files matching one of these globs, repeatable. Globs without a / match file names anywhere
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// Skip files whose full path matches REGEX, repeatable
+    #[arg(long, value_name = "REGEX")]
+    exclude_regex: Vec<String>,
+    /// Only search N levels of directories, 1 being the files directly in the searched directories
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_depth: Option<u64>,
+    /// Only scan the files directly in the searched directories, without descending into subdirectories. Same as --max-depth 1
+    #[arg(long, conflicts_with("max_depth"))]
+    no_recurse: bool,
+    /// Hash online-only files of OneDrive, Dropbox and iCloud Drive, downloading them, instead of listing them as skipped
+    #[arg(long)]
+    hydrate: bool,
+    /// Hash files in the most recently modified directories first, leaving cold archive corners for last
+    #[arg(long)]
+    recent_first: bool,
+    /// Count the files and bytes the scan would process without hashing anything, and ask before going on with the scan
+    #[arg(long)]
+    estimate: bool,
+    /// Experimental: skip reading files whose btrfs sector checksums no other file of their size shares. Needs root, and falls back to hashing where checksums are unavailable
+    #[arg(long)]
+    fs_checksums: bool,
+    /// Store full hashes and the scan time in user.ddh.hash and user.ddh.scanned extended attributes of the files, and take hashes from there on later scans if the files weren't modified since and are owned by the user scanning. Only with the sip128 hash. Remove them with `ddh strip-xattrs`
+    #[arg(long, conflicts_with("hash_cmd"))]
+    xattrs: bool,
+    /// Hash every file from scratch without reading or updating the hash cache
+    #[arg(long, conflicts_with("cache"))]
+    no_cache: bool,
+    /// Only hash files which are new or changed since the scan state saved in FILE, then save this scan's state to FILE. Used instead of the hash cache
+    #[arg(long, value_name = "FILE", conflicts_with_all(["cache", "no_cache", "hash"]))]
+    state: Option<PathBuf>,
+    /// Don't show scan progress on stderr. Progress is only shown when stderr is a terminal
+    #[arg(long)]
+    no_progress: bool,
+    /// Report results in a fixed order and with a scan duration of zero, for snapshot tests of the output
+    #[arg(long, hide = true)]
+    deterministic: bool,
+    /// Reuse the results of the previous scan of the same directories with the same options if it is less than SECONDS old and none of the files it found changed
+    #[arg(long, value_name = "SECONDS", conflicts_with("state"))]
+    reuse_results: Option<u64>,
+    /// List at most N instances per group in human readable output. Machine formats stay complete
+    #[arg(long, value_name = "N")]
+    max_paths_per_group: Option<usize>,
+    /// Summarize duplicate groups of files smaller than BYTES in one line of human readable output. Machine formats stay complete
+    #[arg(long, value_name = "BYTES")]
+    collapse_below: Option<u64>,
+    /// Order of the groups in reports, largest first. Without it groups come in no particular order
+    #[arg(long, ignore_case(true), value_enum)]
+    sort: Option<GroupSort>,
+    /// Reverse the order given by --sort
+    #[arg(long, requires("sort"))]
+    reverse: bool,
+    /// Group digits of large counts with spaces instead of the separator of the current locale
+    #[arg(long)]
+    si: bool,
+    /// Directories to scan and report on first, before the rest of the scan (repeatable)
+    #[arg(long, value_name = "DIR")]
+    first: Vec<PathBuf>,
+    /// Maximum number of files read at once, independent of the number of hashing threads
+    #[arg(long, value_name = "N")]
+    max_concurrent_reads: Option<usize>,
+    /// Hold off reading files during these local hours each day, such as 09:00-17:00, and resume
+    /// afterwards. A window may run over midnight, such as 22:00-06:00
+    #[arg(long, value_name = "HH:MM-HH:MM")]
+    pause_during: Option<PauseWindow>,
+    /// Index written by `ddh agent` to compare against, or - for stdin (repeatable)
+    #[arg(long, value_name = "FILE")]
+    ingest: Vec<String>,
+    /// Only print the duplication ratio (duplicate bytes / total bytes)
+    #[arg(long)]
+    ratio: bool,
+    /// Only list the files under ROOT, a searched directory or its label, whose contents have no copy under the other searched directories, such as files missing from a backup
+    #[arg(long, value_name = "ROOT", conflicts_with("ratio"))]
+    unique_to: Option<String>,
+    /// Exit with status 1 when the duplication ratio exceeds this fraction (e.g. 0.05)
+    #[arg(long, value_name = "RATIO")]
+    max_duplicate_ratio: Option<f64>,
+    /// Only report duplicate groups with this placement across labelled roots (e.g. backup-only, both)
+    #[arg(long)]
+    placement: Option<String>,
+    /// Only report groups with instances in different directories DEPTH levels below the searched directories (1 for top-level subdirectories), hiding sibling duplicates
+    #[arg(long, value_name = "DEPTH")]
+    cross_branch: Option<usize>,
+    /// Replace every name in reported paths with a pseudonym, keeping extensions and the shape of the tree, so reports can be shared
+    #[arg(long, conflicts_with_all(["delete", "link", "first", "unique_to"]))]
+    anonymize: bool,
+    /// Remove all but one instance of each reported duplicate group. Only lists what would be removed unless --execute is given
+    #[arg(long)]
+    delete: bool,
+    /// Replace all but one instance of each reported duplicate group with links to it. Only lists what would be replaced unless --execute is given
+    #[arg(long, ignore_case(true), value_enum, conflicts_with("delete"))]
+    link: Option<LinkKind>,
+    /// Walk through each reported duplicate group, choosing the instances to keep and removing the others, as fdupes -d does. Only lists what would be removed unless --execute is given
+    #[arg(long, conflicts_with_all(["delete", "link", "anonymize"]))]
+    interactive: bool,
+    /// Instance of each group kept by --delete or --link: newest, oldest, first-arg-dir or shortest-path
+    #[arg(long, value_name = "POLICY", default_value = "first-arg-dir")]
+    keep: KeepPolicy,
+    /// Change files for real with --delete or --link, after a confirmation prompt
+    #[arg(long)]
+    execute: bool,
+    /// Let --execute or --tui change files when running as root or Administrator
+    #[arg(long)]
+    allow_root: bool,
+    /// Leave groups alone with --delete or --link unless doing so reclaims at least BYTES
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    min_savings: u64,
+    /// Hash every file and write one line per distinct content (hash, length, canonical path) to FILE
+    #[arg(long, value_name = "FILE")]
+    export_index: Option<PathBuf>,
+    /// Write paths which could not be read for lack of permission to FILE, for `ddh retry`
+    #[arg(long, value_name = "FILE")]
+    denied_paths: Option<PathBuf>,
+    /// Review duplicate groups interactively and mark them for deletion or hard linking
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with_all(["delete", "link", "hash_cmd", "anonymize", "interactive", "reference"]))]
+    tui: bool,
+    /// Directories to parse, optionally labelled for reports as LABEL=DIR
+    #[arg(short, long("directories"), visible_alias("dir"), value_delimiter(' '), num_args(1..), required_unless_present("all_fixed_drives"))]
     directories: Vec<String>,
+    /// Directories searched for matches whose files are never listed for removal nor counted as waste, to clean the other directories against (repeatable)
+    #[arg(long, value_name = "DIR")]
+    reference: Vec<PathBuf>,
+    /// Also search the root of every fixed drive, skipping the directories Windows manages itself (Windows only)
+    #[arg(long)]
+    all_fixed_drives: bool,
+    /// Search the locations the OS manages which are skipped by default: Time Machine snapshots and backups and volume trash folders on macOS, system directories of drives searched with --all-fixed-drives
+    #[arg(long)]
+    no_system_excludes: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Acknowledge duplicate groups as intentional so reports hide them
+    Ack {
+        /// Group ids as shown in reports
+        #[arg(required = true)]
+        group_ids: Vec<String>,
+        /// Remove the acknowledgement instead
+        #[arg(long)]
+        undo: bool,
+        /// File holding acknowledged groups
+        #[arg(long, default_value = DEFAULT_ANNOTATIONS_FILE)]
+        annotations: String,
+    },
+    /// Attach a note to a duplicate group which is shown in reports
+    Tag {
+        /// Group id as shown in reports
+        group_id: String,
+        /// Note to attach. Existing notes are listed when omitted
+        note: Option<String>,
+        /// Remove all notes from the group
+        #[arg(long, conflicts_with("note"))]
+        clear: bool,
+        /// File holding group notes
+        #[arg(long, default_value = DEFAULT_ANNOTATIONS_FILE)]
+        annotations: String,
+    },
+    /// Hash local directories and write an index for another instance to --ingest
+    Agent {
+        /// Directories to hash
+        #[arg(short, long("directories"), value_delimiter(' '), num_args(1..), required = true)]
+        directories: Vec<String>,
+        /// Paths or glob patterns to ignore (comma separated list)
+        #[arg(short, long("ignore"))]
+        ignore_dirs: Vec<String>,
+        /// Minimum file size in bytes to consider
+        #[arg(short, long("minimum"), default_value_t = 0)]
+        min_size: u64,
+        /// Name of this machine as shown in the ingesting instance's reports
+        #[arg(long, default_value = "remote")]
+        host: String,
+        /// Hash algorithm, which the ingesting instance must pass to --hash as well
+        #[arg(long, value_enum, default_value_t = HashAlgorithm::Sip128)]
+        hash: HashAlgorithm,
+        /// File to write the index to instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Report duplicates across several indexes written by `ddh agent`
+    Merge {
+        /// Index files to merge
+        #[arg(required = true)]
+        indexes: Vec<PathBuf>,
+        /// Set the display blocksize to Bytes, Kilobytes, Megabytes or Gigabytes
+        #[arg(long, short, ignore_case(true), value_enum, default_value_t = Blocksize::Kilobytes)]
+        blocksize: Blocksize,
+        /// Set verbosity for printed output
+        #[arg(long, short, ignore_case(true), value_enum, default_value_t = Verbosity::Duplicates)]
+        verbosity: Verbosity,
+        /// Set file to save all output. Use 'no' for no file output
+        #[arg(long, short, default_value = "no")]
+        output: String,
+        /// Set output format
+        #[arg(short('f'), long("format"), ignore_case(true), value_enum, default_value_t = PrintFmt::Standard)]
+        fmt: PrintFmt,
+    },
+    /// Inspect and maintain a hash cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+        /// Hash cache file [default: ~/.cache/ddh/hashes.json]
+        #[arg(long, value_name = "FILE")]
+        cache: Option<PathBuf>,
+    },
+    /// Rehash changed files into a hash cache as their paths are read from stdin, one per line, e.g. from `inotifywait -mr -e close_write --format %w%f DIR`
+    Watch {
+        /// Hash cache file [default: ~/.cache/ddh/hashes.json]
+        #[arg(long, value_name = "FILE")]
+        cache: Option<PathBuf>,
+        /// Milliseconds a path must go without events before it is rehashed
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        quiet: u64,
+        /// Most paths rehashed at once
+        #[arg(long, value_name = "N", default_value_t = 1000)]
+        max_batch: usize,
+        /// Most files read at once
+        #[arg(long, value_name = "N")]
+        max_concurrent_reads: Option<usize>,
+    },
+    /// Rescan paths a previous scan was denied access to, e.g. under sudo, and merge them into its saved JSON results
+    Retry {
+        /// Results file of the previous scan, as written with -f json -o FILE
+        #[arg(long, required = true)]
+        scan: PathBuf,
+        /// File listing the denied paths, as written with --denied-paths. Paths still denied are written back to it
+        #[arg(long, required = true)]
+        denied: PathBuf,
+        /// File to write the merged results to instead of replacing the saved scan
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the changes between two saved scans as JSON lines of group events, for consumers keeping their own state
+    Events {
+        /// Results file of the earlier scan, as written with -f json -o FILE
+        before: PathBuf,
+        /// Results file of the later scan
+        after: PathBuf,
+    },
+    /// Summarize the changes between two saved scans: new and resolved duplicate groups, and files which appeared or disappeared
+    Diff {
+        /// Results file of the earlier scan, as written with -f json -o FILE. Files can only be told to appear or disappear in scans saved with -v all
+        before: PathBuf,
+        /// Results file of the later scan
+        after: PathBuf,
+    },
+    /// Print the duplicate groups of a saved scan visible to the client whose token is read from the first line of stdin, as JSON lines
+    Query {
+        /// Scopes file listing the directories and token of each client
+        #[arg(long, required = true)]
+        scopes: PathBuf,
+        /// Results file of the scan, as written with -f json -o FILE
+        results: PathBuf,
+    },
+    /// Write a Merkle fingerprint of a directory tree for comparing it later with `ddh compare`
+    Fingerprint {
+        /// Directory to fingerprint
+        dir: PathBuf,
+        /// File to write the fingerprint to
+        #[arg(short, long, required = true)]
+        output: PathBuf,
+    },
+    /// Compare two fingerprints, listing identical, missing, added and changed subtrees
+    Compare {
+        /// Fingerprint of the original tree
+        a: PathBuf,
+        /// Fingerprint of the tree to check against it
+        b: PathBuf,
+    },
+    /// Scan directories and act on each duplicate group as the first matching rule of a policy file says
+    Apply {
+        /// Policy file of rules mapping groups to actions
+        #[arg(long, required = true)]
+        policy: PathBuf,
+        /// Directories to scan
+        #[arg(required = true)]
+        directories: Vec<PathBuf>,
+        /// Change files for real, after a confirmation prompt. Only lists what would change otherwise
+        #[arg(long)]
+        execute: bool,
+        /// Let --execute change files when running as root or Administrator
+        #[arg(long, requires("execute"))]
+        allow_root: bool,
+    },
+    /// Copy a directory tree, linking files whose contents the destination already holds
+    Cp {
+        /// Directory to copy
+        src: PathBuf,
+        /// Directory to copy into, created if missing
+        dst: PathBuf,
+        /// How to create files whose contents are already at the destination
+        #[arg(long, value_enum, default_value_t = LinkKind::Reflink)]
+        link: LinkKind,
+    },
+    /// Show disk usage per directory with duplicated contents counted once, and what deleting each directory would free
+    Du {
+        /// Directory to measure
+        dir: PathBuf,
+        /// Split the bytes of duplicated contents evenly between their paths, instead of counting them at the first path in sorted order
+        #[arg(long)]
+        split: bool,
+        /// Levels of directories below DIR to list
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        depth: usize,
+    },
+    /// Remove the extended attributes written by --xattrs from every file below the directories
+    StripXattrs {
+        /// Directories to strip
+        #[arg(required = true)]
+        directories: Vec<PathBuf>,
+    },
+    /// Check that this build hashes and groups files correctly, with every hash algorithm, on files written to a temporary directory
+    Selftest,
+    /// Build a reproducible directory tree with known duplicates for tests and bug reports
+    #[cfg(feature = "dev-tools")]
+    GenFixture {
+        /// Directory to create the tree in
+        root: PathBuf,
+        /// Seed for names, sizes and contents
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Number of duplicate groups
+        #[arg(long, default_value_t = 8)]
+        groups: usize,
+        /// Instances per duplicate group
+        #[arg(long, default_value_t = 3)]
+        copies: usize,
+        /// Number of files without a duplicate
+        #[arg(long, default_value_t = 8)]
+        unique: usize,
+        /// Largest size of a regular file in bytes
+        #[arg(long, default_value_t = 64 * 1024)]
+        max_size: usize,
+        /// Length in bytes of sparse files to add
+        #[arg(long, value_name = "BYTES")]
+        sparse_size: Option<u64>,
+        /// Leave out hardlinks
+        #[arg(long)]
+        no_hardlinks: bool,
+        /// Leave out symlinks
+        #[arg(long)]
+        no_symlinks: bool,
+        /// Only use plain file names
+        #[arg(long)]
+        plain_names: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Show the size and hit rate of the cache
+    Stats,
+    /// Remove entries for missing or changed files and enforce an entry limit
+    Prune {
+        /// Evict least recently used entries beyond this many
+        #[arg(long)]
+        max_entries: Option<usize>,
+    },
+    /// Remove every entry from the cache
+    Clear,
+}
+
+/// Extra information shown alongside duplicate groups in human readable output.
+struct GroupDecorations<'a> {
+    copy_patterns: &'a CopyPatterns,
+    annotations: &'a Annotations,
+    roots: &'a RootLabels,
+    /// Index each instance was read from, for merged reports.
+    sources: &'a HashMap<PathBuf, String>,
+    /// Number of instances listed per group before the rest are summarized.
+    max_paths_per_group: Option<usize>,
+    /// File length below which groups are only counted in a summary line.
+    collapse_below: Option<u64>,
+    /// How counts are written.
+    numbers: NumberFormat,
+    /// Directories whose instances are never removed.
+    references: &'a ReferenceDirs,
+    /// Algorithm of the full hashes, `None` for hashes of an external command.
+    hash_algorithm: Option<&'a str>,
+}
+
+impl GroupDecorations<'_> {
+    fn instance_marker(&self, path: &Path) -> String {
+        let mut marker = String::new();
+        if self.references.contains(path) {
+            marker.push_str(" (reference)");
+        }
+        if self.copy_patterns.is_copy(path) {
+            marker.push_str(" (copy)");
+        }
+        if let Some(source) = self.sources.get(path) {
+            marker.push_str(&format!(" [from {}]", source));
+        }
+        marker
+    }
+    /// Splits the instances of `group` into those to list and the number left out.
+    fn listed_paths<'b>(&self, group: &'b Fileinfo) -> (&'b [Box<Path>], usize) {
+        let paths = group.get_paths();
+        let listed = self
+            .max_paths_per_group
+            .unwrap_or(paths.len())
+            .min(paths.len());
+        (&paths[..listed], paths.len() - listed)
+    }
+    /// Splits `groups` into those to list and a summary of the groups too small to list.
+    fn collapse_small<'b>(&self, groups: &[&'b Fileinfo]) -> (Vec<&'b Fileinfo>, Option<String>) {
+        let threshold = match self.collapse_below {
+            Some(threshold) => threshold,
+            None => return (groups.to_vec(), None),
+        };
+        let (small, listed): (Vec<&Fileinfo>, Vec<&Fileinfo>) = groups
+            .iter()
+            .partition(|group| group.get_length() < threshold);
+        if small.is_empty() {
+            return (listed, None);
+        }
+        let summary = self.collapsed_summary(
+            small.len() as u64,
+            small
+                .iter()
+                .map(|group| group.get_paths().len() as u64)
+                .sum(),
+            ddh::waste::duplicate_bytes(small.iter().copied()),
+        );
+        (listed, Some(summary))
+    }
+    /// Describes the groups too small to list by their number, instances and wasted bytes.
+    fn collapsed_summary(&self, groups: u64, instances: u64, wasted: u64) -> String {
+        format!(
+            "{} groups of files under {} bytes collapsed ({} instances, {} bytes wasted)",
+            self.numbers.format(groups),
+            self.numbers.format(self.collapse_below.unwrap_or_default()),
+            self.numbers.format(instances),
+            self.numbers.format(wasted)
+        )
+    }
+    fn placement_marker(&self, group: &Fileinfo) -> String {
+        match self.roots.placement(group) {
+            Some(placement) if self.roots.is_labelled() => format!(" [{}]", placement),
+            _ => String::new(),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, ValueEnum)]
 pub enum PrintFmt {
     Standard,
     Json,
+    /// One JSON object per duplicate group and line, written as soon as the scan settles each group
+    Jsonl,
+    /// One row per path with its group id, hash, length and the number of paths in its group
+    Csv,
+    /// Duplicate groups as fdupes prints them, one path per line with a blank line after each group
+    Fdupes,
+    /// Duplicate groups as fdupes prints them, but with paths and groups ending in NUL bytes for xargs -0
+    Print0,
+    /// Wasted bytes per directory as folded stacks for flamegraph or treemap tools
+    Folded,
+    /// Wasted bytes per directory, counting the whole tree below it and the files directly in it, largest first
+    Dirstat,
+    /// Standalone HTML report with sortable tables of duplicate groups and wasted space per directory
+    Html,
+    /// Duplicate relationships as a CSV edge list of source, target and weight, the length in bytes
+    Edges,
+    /// Duplicate relationships as a GraphML graph of paths linked by weighted edges
+    Graphml,
+}
+
+impl PrintFmt {
+    /// Returns true for the formats scripts parse, whose stdout holds nothing but the results.
+    /// Notes for people, such as the hard links found, go to stderr instead.
+    fn is_machine_readable(self) -> bool {
+        matches!(
+            self,
+            PrintFmt::Json
+                | PrintFmt::Jsonl
+                | PrintFmt::Csv
+                | PrintFmt::Fdupes
+                | PrintFmt::Print0
+                | PrintFmt::Edges
+                | PrintFmt::Graphml
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum IgnorePreset {
+    /// Version control metadata, node_modules, target and other build output and tool caches
+    Dev,
+    /// .DS_Store, AppleDouble files and other Finder and Spotlight metadata
+    Macos,
+    /// Thumbs.db, desktop.ini and recycle bins
+    Windows,
+}
+
+impl IgnorePreset {
+    fn entries(self) -> &'static [&'static str] {
+        match self {
+            IgnorePreset::Dev => ddh::ignore::DEV_PRESET,
+            IgnorePreset::Macos => ddh::ignore::MACOS_PRESET,
+            IgnorePreset::Windows => ddh::ignore::WINDOWS_PRESET,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum HashAlgorithm {
+    /// SipHash-1-3, 128 bit
+    Sip128,
+    /// xxHash3, 128 bit
+    Xxh3,
+    /// SHA-256, first 128 bits
+    Sha256,
+    /// BLAKE3, first 128 bits
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Gets the hasher to pass to the library, `None` for the default.
+    fn hasher(self) -> Option<Arc<dyn ContentHasher>> {
+        match self {
+            HashAlgorithm::Sip128 => None,
+            HashAlgorithm::Xxh3 => Some(Arc::new(Xxh3)),
+            HashAlgorithm::Sha256 => Some(Arc::new(Sha256)),
+            HashAlgorithm::Blake3 => Some(Arc::new(Blake3)),
+        }
+    }
+    /// Gets the hasher, including the default.
+    fn content_hasher(self) -> Arc<dyn ContentHasher> {
+        self.hasher().unwrap_or_else(|| Arc::new(SipHash128))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum GroupSort {
+    /// Most bytes taken up by duplicate instances first
+    Wasted,
+    /// Largest files first
+    Size,
+    /// Most instances first
+    Count,
+    /// By the first path of each group in sorted order
+    Path,
+}
+
+impl GroupSort {
+    /// Sorts `groups` in this order, or the reverse of it, breaking ties by first path.
+    fn sort(self, groups: &mut [Fileinfo], reverse: bool) {
+        let first_path = |group: &Fileinfo| group.get_paths().iter().min().cloned();
+        groups.sort_by_cached_key(|group| {
+            let key = match self {
+                GroupSort::Wasted => {
+                    group.get_length() * (group.get_paths().len() as u64).saturating_sub(1)
+                }
+                GroupSort::Size => group.get_length(),
+                GroupSort::Count => group.get_paths().len() as u64,
+                GroupSort::Path => 0,
+            };
+            (std::cmp::Reverse(key), first_path(group))
+        });
+        if reverse {
+            groups.reverse();
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum LinkKind {
+    /// Copy-on-write clones on btrfs, XFS or APFS, which stay independent files
+    Reflink,
+    /// Hard links, which share contents and metadata with the kept instance
+    Hard,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum SymlinkMode {
+    /// Leave symlinks out of the scan
+    Skip,
+    /// Hash the targets of symlinks to files and list links sharing a target apart from copies
+    Report,
+    /// Like report, also scanning the directories symlinks point to, each once
+    Follow,
 }
 
 #[derive(Debug, Copy, Clone, ValueEnum)]
@@ -45,140 +722,2339 @@ pub enum Verbosity {
     All,
 }
 
-#[derive(Debug, Copy, Clone, ValueEnum)]
-pub enum Blocksize {
-    #[clap(name("B"), alias("Bytes"))]
-    Bytes,
-    #[clap(name("K"), alias("Kilobytes"))]
-    Kilobytes,
-    #[clap(name("M"), alias("Megabytes"))]
-    Megabytes,
-    #[clap(name("G"), alias("Gigabytes"))]
-    Gigabytes,
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum Blocksize {
+    #[clap(name("B"), alias("Bytes"))]
+    Bytes,
+    #[clap(name("K"), alias("Kilobytes"))]
+    Kilobytes,
+    #[clap(name("M"), alias("Megabytes"))]
+    Megabytes,
+    #[clap(name("G"), alias("Gigabytes"))]
+    Gigabytes,
+}
+
+impl Blocksize {
+    /// Number of bytes in one unit.
+    fn divisor(self) -> u64 {
+        let power = match self {
+            Blocksize::Bytes => 0,
+            Blocksize::Kilobytes => 1,
+            Blocksize::Megabytes => 2,
+            Blocksize::Gigabytes => 3,
+        };
+        1024u64.pow(power)
+    }
+}
+
+/// Number of directories listed in the summary of the standard output by their wasted bytes.
+const TOP_WASTE_DIRECTORIES: usize = 10;
+
+static DDH_ABOUT: &str = "Compare and contrast directories.\nExample invocation: ddh -d /home/jon/downloads /home/jon/documents -v duplicates\nExample pipe: ddh -d ~/Downloads/ -o no -v all -f json | someJsonParser.bin";
+
+fn main() {
+    let mut arguments = Args::parse();
+    if arguments.resolve_symlinks {
+        arguments.symlinks = SymlinkMode::Report;
+    }
+    if ddh::privileges::is_elevated() {
+        eprintln!("*** Running as root or Administrator ***");
+        eprintln!(
+            "Files are only changed with --allow-root as well as --execute, and never in system \
+             directories"
+        );
+    }
+    if let Some(command) = arguments.command {
+        run_command(command);
+        return;
+    }
+    let annotations = match Annotations::load(&arguments.annotations) {
+        Ok(annotations) => annotations,
+        Err(e) => {
+            eprintln!(
+                "Error reading annotations from {}. Err: {}",
+                arguments.annotations, e
+            );
+            Annotations::default()
+        }
+    };
+    refuse_elevated_changes(arguments.execute, arguments.allow_root);
+    let copy_patterns = if arguments.copy_locales.is_empty() {
+        CopyPatterns::default()
+    } else {
+        match CopyPatterns::for_locales(&arguments.copy_locales) {
+            Ok(patterns) => patterns,
+            Err(code) => {
+                eprintln!("Unknown copy locale {}", code);
+                std::process::exit(1);
+            }
+        }
+    };
+    if arguments.xattrs && arguments.hash != HashAlgorithm::Sip128 {
+        eprintln!("--xattrs only stores sip128 hashes");
+        std::process::exit(1);
+    }
+
+    let cache = match (arguments.state.as_ref(), arguments.cache.as_ref()) {
+        (Some(path), _) => match HashCache::load_state(path) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                eprintln!("Error reading scan state {}. Err: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        (None, Some(path)) => match HashCache::open(path) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                eprintln!("Error opening cache {}. Err: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        (None, None)
+            if !arguments.no_cache
+                && arguments.hash_cmd.is_none()
+                && arguments.hash == HashAlgorithm::Sip128 =>
+        {
+            match HashCache::default_path().map(HashCache::open) {
+                Some(Ok(cache)) => Some(cache),
+                Some(Err(e)) => {
+                    eprintln!("Scanning without the default cache. Err: {}", e);
+                    None
+                }
+                None => None,
+            }
+        }
+        (None, None) => None,
+    };
+    // Lets the full scan reuse the hashes computed for priority directories
+    let cache = match cache {
+        None if !arguments.first.is_empty() => Some(HashCache::in_memory()),
+        cache => cache,
+    };
+    let mut roots: Vec<ScanRoot> = arguments
+        .directories
+        .iter()
+        .map(|dir| ScanRoot::parse(dir))
+        .collect();
+    let mut fixed_drives: Vec<PathBuf> = Vec::new();
+    if arguments.all_fixed_drives {
+        match ddh::drives::fixed_drives() {
+            Ok(drives) => {
+                eprintln!(
+                    "Searching fixed drives {}",
+                    drives
+                        .iter()
+                        .map(|drive| drive.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                roots.extend(drives.iter().map(|path| ScanRoot {
+                    label: None,
+                    path: path.clone(),
+                }));
+                fixed_drives = drives;
+            }
+            Err(e) => {
+                eprintln!("Error listing fixed drives. Err: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let anonymizer = arguments.anonymize.then(Anonymizer::default);
+    let root_labels = match anonymizer.as_ref() {
+        Some(anonymizer) => RootLabels::resolve_with(&roots, |path| {
+            Some(anonymizer.path(&path.canonicalize().ok()?))
+        }),
+        None => RootLabels::new(&roots),
+    };
+    let mut search_dirs: Vec<PathBuf> = roots.into_iter().map(|root| root.path).collect();
+    let content_hasher = arguments.hash.content_hasher();
+    let references = ReferenceDirs::new(&arguments.reference);
+    let reported_references = match anonymizer.as_ref() {
+        Some(anonymizer) => references.map_paths(|path| anonymizer.path(path)),
+        None => references.clone(),
+    };
+    search_dirs.extend(arguments.reference.iter().cloned());
+    let sources = HashMap::new();
+    let decorations = GroupDecorations {
+        copy_patterns: &copy_patterns,
+        annotations: &annotations,
+        roots: &root_labels,
+        sources: &sources,
+        max_paths_per_group: arguments.max_paths_per_group,
+        collapse_below: arguments.collapse_below,
+        numbers: if arguments.si {
+            NumberFormat::si()
+        } else {
+            NumberFormat::from_env()
+        },
+        references: &reported_references,
+        hash_algorithm: arguments.hash_cmd.is_none().then(|| content_hasher.name()),
+    };
+    let mut ignore_entries: Vec<PathBuf> = arguments
+        .ignore_dirs
+        .iter()
+        .flat_map(|list| parse_ignore_list(list))
+        .map(PathBuf::from)
+        .collect();
+    IgnoreRules::new(&search_dirs, &ignore_entries)
+        .missing()
+        .iter()
+        .for_each(|entry| {
+            eprintln!(
+                "Warning: ignore entry {} does not exist and was skipped",
+                entry.display()
+            )
+        });
+    for preset in arguments.preset_ignores.iter() {
+        ignore_entries.extend(preset.entries().iter().map(PathBuf::from));
+    }
+    if !arguments.no_system_excludes {
+        if arguments.all_fixed_drives {
+            for drive in fixed_drives.iter() {
+                ignore_entries.extend(ddh::drives::system_excludes(drive));
+            }
+        }
+        if cfg!(target_os = "macos") {
+            ignore_entries.extend(ddh::macos::SYSTEM_EXCLUDES.iter().map(PathBuf::from));
+        }
+    }
+    let file_filters = match FileFilters::new(
+        &arguments.include,
+        &arguments.exclude,
+        &arguments.exclude_regex,
+    ) {
+        Ok(filters) => filters
+            .with_extensions(&arguments.ext, &arguments.skip_ext)
+            .with_modified(arguments.newer_than, arguments.older_than),
+        Err(e) => {
+            eprintln!("Invalid --exclude-regex. Err: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut options = ScanOptions {
+        min_size: arguments.min_size,
+        cache,
+        max_concurrent_reads: arguments.max_concurrent_reads,
+        pause_during: arguments.pause_during,
+        resolve_symlinks: arguments.symlinks != SymlinkMode::Skip,
+        follow_symlinks: arguments.symlinks == SymlinkMode::Follow,
+        one_file_system: arguments.one_file_system,
+        respect_gitignore: arguments.respect_gitignore,
+        skip_hidden: arguments.skip_hidden,
+        file_filters,
+        max_depth: arguments
+            .max_depth
+            .map(|depth| depth as usize)
+            .or(arguments.no_recurse.then_some(1)),
+        hydrate_placeholders: arguments.hydrate,
+        recent_dirs_first: arguments.recent_first,
+        filesystem_checksums: arguments.fs_checksums,
+        xattr_hashes: arguments.xattrs,
+        hash_command: arguments.hash_cmd.clone(),
+        hasher: arguments.hash.hasher(),
+        paranoid: arguments.paranoid,
+        progress: None,
+        // Per volume counts of the progress display need every file found
+        report_discovered_files: fixed_drives.len() > 1 && !arguments.no_progress,
+        warnings: None,
+        settled: None,
+        cancel: None,
+        pause: pause_on_sigusr1(),
+        clock: arguments
+            .deterministic
+            .then(|| Arc::new(ddh::clock::FixedClock::new()) as Arc<dyn ddh::clock::Clock>),
+        deterministic: arguments.deterministic,
+    };
+    let mut warnings: Vec<Warning> = Vec::new();
+    let mut discovery = None;
+    if arguments.estimate {
+        let (warnings_sender, warnings_receiver) = channel();
+        options.warnings = Some(warnings_sender);
+        let discovered = ddh::discover_dirs(&search_dirs, &ignore_entries, &options);
+        options.warnings = None;
+        warnings.extend(warnings_receiver.try_iter());
+        let estimate = discovered.estimate();
+        print_estimate(&estimate, arguments.blocksize, decorations.numbers);
+        if !confirm() {
+            return;
+        }
+        discovery = Some(discovered);
+    }
+    let remote_files = read_indexes(&arguments.ingest, content_hasher.name());
+    if !arguments.first.is_empty() {
+        let searched = search_dirs.len();
+        scan_priority_dirs(
+            &arguments,
+            &mut search_dirs,
+            &ignore_entries,
+            &options,
+            &decorations,
+        );
+        /* Priority directories outside the searched ones weren't walked for the estimate. */
+        if search_dirs.len() > searched {
+            discovery = None;
+        }
+    }
+    let mut canonical_roots: Vec<PathBuf> = search_dirs
+        .iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .collect();
+    let results_file = HashCache::default_path().map(|path| path.with_file_name("results.json"));
+    let results_key = ddh::results::scan_key(&search_dirs, &ignore_entries, &options);
+    let reused = arguments
+        .reuse_results
+        .zip(results_file.as_ref())
+        .and_then(|(max_age, path)| {
+            ddh::results::load(path, &results_key, Duration::from_secs(max_age))
+        });
+    let mut streamed = false;
+    let (mut complete_files, read_errors, timings) = match reused {
+        Some((files, errors)) => {
+            eprintln!("Reusing the results of the previous scan, as nothing changed since");
+            (files, errors, ScanTimings::default())
+        }
+        None => {
+            let progress = (!arguments.no_progress).then(|| {
+                let (sender, receiver) = channel();
+                options.progress = Some(sender);
+                let volumes = fixed_drives.clone();
+                std::thread::spawn(move || show_progress(receiver, volumes))
+            });
+            let (warnings_sender, warnings_receiver) = channel();
+            options.warnings = Some(warnings_sender);
+            /* Groups can't be written as they settle if they are sorted, anonymized, merged
+            or narrowed to their references afterwards, nor if no groups are listed at all. */
+            streamed = matches!(arguments.fmt, PrintFmt::Jsonl)
+                && !matches!(arguments.verbosity, Verbosity::Quiet)
+                && arguments.sort.is_none()
+                && anonymizer.is_none()
+                && remote_files.is_empty()
+                && !arguments.deterministic
+                && arguments.reference.is_empty()
+                && !arguments.ratio
+                && arguments.unique_to.is_none();
+            let started = SystemTime::now();
+            let scanned = std::thread::scope(|scope| {
+                if streamed {
+                    let (sender, receiver) = channel();
+                    options.settled = Some(sender);
+                    let decorations = &decorations;
+                    let arguments = &arguments;
+                    scope.spawn(move || {
+                        let mut sink = JsonLinesSink::new(stdout().lock());
+                        for group in receiver {
+                            if let Some(group) = reportable_group(group, arguments, decorations) {
+                                let _ = sink.write_group(&group);
+                            }
+                        }
+                    });
+                }
+                let scanned = match discovery {
+                    Some(discovery) => ddh::deduplicate_discovered(discovery, &options),
+                    None => ddh::deduplicate_dirs_timed(search_dirs, ignore_entries, &options),
+                };
+                options.settled = None;
+                scanned
+            });
+            options.progress = None;
+            options.warnings = None;
+            warnings.extend(warnings_receiver.try_iter());
+            if let Some(progress) = progress {
+                let _ = progress.join();
+            }
+            if let (Some(_), Some(path)) = (arguments.reuse_results, results_file.as_ref()) {
+                if let Err(e) = ddh::results::save(path, &results_key, &scanned.0, &scanned.1) {
+                    eprintln!("Error writing results {}. Err: {}", path.display(), e);
+                }
+            }
+            if arguments.xattrs {
+                for (path, e) in ddh::xattrs::tag_files(&scanned.0, started) {
+                    eprintln!("Error tagging {}. Err: {}", path.display(), e);
+                }
+            }
+            scanned
+        }
+    };
+    let (mut placeholders, mut read_errors): (Vec<_>, Vec<_>) = read_errors
+        .into_iter()
+        .partition(|(_, e)| ddh::placeholders::is_skipped(e));
+    let executor = |dry_run| {
+        let executor = Executor::new(16, dry_run)
+            .with_min_savings(arguments.min_savings)
+            .with_system_guard(ddh::privileges::is_elevated());
+        match options.hasher.clone() {
+            Some(hasher) => executor.with_hasher(hasher),
+            None => executor,
+        }
+    };
+    if !remote_files.is_empty() {
+        complete_files = merge_remote_with(complete_files, remote_files, content_hasher.as_ref());
+    }
+    if let Some(index_file) = arguments.export_index.as_ref() {
+        match options.hash_command.as_ref() {
+            Some(command) => read_errors.extend(command.complete_hashes(&mut complete_files)),
+            None => match options.hasher.as_deref() {
+                Some(hasher) => complete_hashes_with(&mut complete_files, None, hasher),
+                None => complete_hashes(&mut complete_files, None),
+            },
+        }
+        let mut anonymized;
+        let exported = match anonymizer.as_ref() {
+            Some(anonymizer) => {
+                anonymized = complete_files.clone();
+                anonymizer.files(&mut anonymized);
+                &anonymized
+            }
+            None => &complete_files,
+        };
+        let written = fs::File::create(index_file).and_then(|f| {
+            ddh::export::write_content_index(std::io::BufWriter::new(f), exported, &copy_patterns)
+        });
+        if let Err(e) = written {
+            eprintln!(
+                "Error writing index to {}. Err: {}",
+                index_file.display(),
+                e
+            );
+        }
+    }
+    if let Some(denied_file) = arguments.denied_paths.as_ref() {
+        if let Err(e) = write_denied_paths(&read_errors, denied_file, anonymizer.as_ref()) {
+            eprintln!("Error writing {}. Err: {}", denied_file.display(), e);
+        }
+    }
+    if let (Some(state), Some(path)) = (options.cache.as_ref(), arguments.state.as_ref()) {
+        if let Err(e) = state.save_state(path, &complete_files) {
+            eprintln!("Error writing scan state {}. Err: {}", path.display(), e);
+        }
+    } else if let Some(cache) = options.cache.as_ref() {
+        if let Err(e) = cache.persist() {
+            eprintln!("Error writing cache {}. Err: {}", cache.path().display(), e);
+        }
+    }
+    let mut notes: Box<dyn Write> = if arguments.fmt.is_machine_readable() {
+        Box::new(stderr())
+    } else {
+        Box::new(stdout())
+    };
+    if arguments.symlinks != SymlinkMode::Skip {
+        let mut link_farms = Vec::new();
+        complete_files = complete_files
+            .into_iter()
+            .map(|file| {
+                if file.get_paths().len() < 2 {
+                    return file;
+                }
+                let (farms, distinct) = ddh::links::split_links(&file);
+                link_farms.extend(farms);
+                distinct
+            })
+            .collect();
+        if let Some(anonymizer) = anonymizer.as_ref() {
+            for farm in link_farms.iter_mut() {
+                farm.target = anonymizer.path(&farm.target);
+                anonymizer.paths(&mut farm.links);
+            }
+        }
+        let _ = print_link_farms(&link_farms, &mut notes);
+    }
+    let (mut complete_files, mut hard_links) = without_hard_links(complete_files);
+    if !references.is_empty() {
+        complete_files = complete_files
+            .into_iter()
+            .map(|file| references.narrow(file))
+            .collect();
+    }
+    if let Some(order) = arguments.sort {
+        order.sort(&mut complete_files, arguments.reverse);
+    }
+    if arguments.name_collisions {
+        print_name_collisions(&complete_files, anonymizer.as_ref());
+    }
+    if let Some(anonymizer) = anonymizer.as_ref() {
+        anonymizer.files(&mut complete_files);
+        for set in hard_links.iter_mut() {
+            anonymizer.paths(&mut set.paths);
+        }
+        for (path, _) in placeholders.iter_mut().chain(read_errors.iter_mut()) {
+            *path = anonymizer.path(path);
+        }
+        for warning in warnings.iter_mut() {
+            warning.path = anonymizer.path(&warning.path);
+        }
+        anonymizer.paths(&mut canonical_roots);
+    }
+    let _ = print_hard_links(&hard_links, arguments.verbosity, &mut notes);
+    let _ = print_placeholders(&placeholders, arguments.verbosity, &mut notes);
+    let (mut shared_files, unique_files): (Vec<&Fileinfo>, Vec<&Fileinfo>) = complete_files
+        .par_iter()
+        .partition(|&x| x.get_paths().len() > 1);
+    if !arguments.show_acked {
+        let shared_count = shared_files.len();
+        shared_files.retain(|x| !annotations.is_acknowledged(x));
+        if shared_files.len() < shared_count {
+            let _ = writeln!(
+                notes,
+                "{} acknowledged duplicate groups hidden (use --show-acked to list them)",
+                shared_count - shared_files.len()
+            );
+        }
+    }
+    if let Some(placement) = arguments.placement.as_ref() {
+        shared_files.retain(|x| root_labels.placement(x).as_ref() == Some(placement));
+    }
+    if let Some(depth) = arguments.cross_branch {
+        shared_files.retain(|x| root_labels.spans_branches(x, depth));
+    }
+    let duplicate_bytes = ddh::waste::duplicate_bytes(shared_files.iter().copied());
+    let total_bytes =
+        ddh::waste::total_bytes(shared_files.iter().chain(unique_files.iter()).copied());
+    let ratio = if total_bytes == 0 {
+        0.0
+    } else {
+        duplicate_bytes as f64 / total_bytes as f64
+    };
+    if arguments.duplicate_dirs {
+        print_duplicate_trees(&complete_files, &canonical_roots);
+    }
+    if let Some(threshold) = arguments.similar_dirs {
+        print_similar_dirs(&complete_files, &canonical_roots, threshold);
+    }
+    #[cfg(feature = "tui")]
+    if arguments.tui {
+        let executor = executor(false);
+        let worthwhile: Vec<&Fileinfo> = shared_files
+            .iter()
+            .copied()
+            .filter(|group| executor.worthwhile(group))
+            .collect();
+        match ddh::tui::review(&worthwhile, &copy_patterns) {
+            Ok(decisions) => {
+                let plan: Vec<(&Fileinfo, &Path, Option<LinkKind>)> = decisions
+                    .iter()
+                    .map(|decision| {
+                        let link = match decision.mark {
+                            ddh::tui::Mark::Delete => None,
+                            ddh::tui::Mark::HardLink => Some(LinkKind::Hard),
+                        };
+                        (decision.group, decision.keep, link)
+                    })
+                    .collect();
+                refuse_elevated_changes(true, arguments.allow_root);
+                apply_plan(&plan, &executor, 0);
+            }
+            Err(e) => {
+                eprintln!("Could not run the review. Err: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if arguments.ratio {
+        println!(
+            "Duplicate ratio: {:.4} ({} of {} bytes)",
+            ratio, duplicate_bytes, total_bytes
+        );
+    } else if let Some(root) = arguments.unique_to.as_ref() {
+        print_unique_to(&complete_files, &root_labels, root);
+    } else {
+        let context = OutputContext {
+            fmt: arguments.fmt,
+            verbosity: arguments.verbosity,
+            blocksize: arguments.blocksize,
+            warnings: &warnings,
+            summary: json_summary(
+                &complete_files,
+                &shared_files,
+                read_errors.len(),
+                Some(&timings),
+                decorations.hash_algorithm,
+            ),
+            timings: Some(&timings),
+            decorations: &decorations,
+        };
+        process_full_output(
+            &shared_files,
+            &unique_files,
+            &complete_files,
+            &read_errors,
+            streamed,
+            arguments.output.as_str(),
+            &context,
+        );
+    }
+    let executor = executor(!arguments.execute);
+    if arguments.interactive {
+        run_interactive(&shared_files, &references, &executor);
+    } else if arguments.delete || arguments.link.is_some() {
+        run_actions(
+            &shared_files,
+            arguments.link,
+            arguments.keep,
+            &canonical_roots,
+            &references,
+            &executor,
+            &copy_patterns,
+        );
+    } else if arguments.execute {
+        eprintln!("--execute has no effect without --delete, --link or --interactive");
+    }
+    if let Some(max_ratio) = arguments.max_duplicate_ratio {
+        if ratio > max_ratio {
+            eprintln!(
+                "Duplicate ratio {:.4} exceeds the maximum of {:.4}",
+                ratio, max_ratio
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exits when files would be changed with elevated privileges without `--allow-root`.
+fn refuse_elevated_changes(execute: bool, allow_root: bool) {
+    if execute && !allow_root && ddh::privileges::is_elevated() {
+        eprintln!("Refusing to change files as root or Administrator without --allow-root");
+        std::process::exit(1);
+    }
+}
+
+/// Removes all but the instance chosen by `policy` from each of `groups`, or replaces them
+/// with links of kind `link`, asking for confirmation first unless `executor` is a dry run.
+fn run_actions(
+    groups: &[&Fileinfo],
+    link: Option<LinkKind>,
+    policy: KeepPolicy,
+    search_dirs: &[PathBuf],
+    references: &ReferenceDirs,
+    executor: &Executor,
+    copy_patterns: &CopyPatterns,
+) {
+    let worthwhile: Vec<&Fileinfo> = groups
+        .iter()
+        .copied()
+        .filter(|group| executor.worthwhile(group))
+        .collect();
+    let instances: usize = worthwhile.iter().map(|x| x.get_paths().len() - 1).sum();
+    if instances == 0 {
+        println!("No duplicates to remove");
+        return;
+    }
+    if !executor.is_dry_run() {
+        println!("---");
+        println!(
+            "{} {} duplicate instances from {} groups, keeping the {} instance of each?",
+            match link {
+                Some(LinkKind::Reflink) => "Replace with clones",
+                Some(LinkKind::Hard) => "Replace with hard links",
+                None => "Remove",
+            },
+            instances,
+            worthwhile.len(),
+            policy
+        );
+        if !confirm() {
+            return;
+        }
+    }
+    let mut plan = Vec::with_capacity(worthwhile.len());
+    let mut skipped = 0;
+    for group in worthwhile.iter() {
+        let keep = references
+            .instance_of(group)
+            .or_else(|| policy.choose(group, search_dirs, copy_patterns));
+        match keep {
+            Some(keep) => plan.push((*group, keep, link)),
+            None => {
+                eprintln!(
+                    "Skipping group {}: no instance can be kept under the {} policy",
+                    group.get_group_id().unwrap_or_default(),
+                    policy
+                );
+                skipped += 1;
+            }
+        }
+    }
+    apply_plan(&plan, executor, skipped);
+}
+
+/// Scans `directories` and applies the first rule of the policy file matching each duplicate
+/// group, asking for confirmation first when `execute` is set.
+fn run_apply(policy_file: &Path, directories: Vec<PathBuf>, execute: bool) {
+    let policy = match Policy::load(policy_file) {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("Error reading policy {}. Err: {}", policy_file.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let canonical_roots: Vec<PathBuf> = directories
+        .iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .collect();
+    let (files, read_errors) = ddh::deduplicate_dirs(directories, Vec::new(), 0);
+    read_errors
+        .iter()
+        .for_each(|(path, e)| eprintln!("Could not process {} due to error {}", path.display(), e));
+    let (files, _) = without_hard_links(files);
+    let groups: Vec<&Fileinfo> = files.iter().filter(|x| x.get_paths().len() > 1).collect();
+    let decisions = policy.decide(&groups, &canonical_roots, &CopyPatterns::default());
+    for (index, rule) in policy.rules.iter().enumerate() {
+        let matched: Vec<&PolicyDecision> = decisions.iter().filter(|d| d.rule == index).collect();
+        println!(
+            "Rule {} ({:?}): {} groups, {} instances",
+            rule.label(index),
+            rule.action,
+            matched.len(),
+            matched
+                .iter()
+                .map(|d| d.group.get_paths().len() - 1)
+                .sum::<usize>()
+        );
+    }
+    if decisions.is_empty() {
+        println!("No duplicates to remove");
+        return;
+    }
+    if execute {
+        println!("---");
+        println!("Apply the policy to {} duplicate groups?", decisions.len());
+        if !confirm() {
+            return;
+        }
+    }
+    let plan: Vec<(&Fileinfo, &Path, Option<LinkKind>)> = decisions
+        .iter()
+        .map(|decision| {
+            let link = match decision.action {
+                PolicyAction::Delete => None,
+                PolicyAction::HardLink => Some(LinkKind::Hard),
+                PolicyAction::Reflink => Some(LinkKind::Reflink),
+            };
+            (&decision.group, decision.keep.as_path(), link)
+        })
+        .collect();
+    let executor = Executor::new(16, !execute).with_system_guard(ddh::privileges::is_elevated());
+    apply_plan(&plan, &executor, 0);
+}
+
+/// Prints what a scan would process.
+fn print_estimate(estimate: &ScanEstimate, blocksize: Blocksize, numbers: NumberFormat) {
+    let divisor = blocksize.divisor();
+    println!(
+        "{} files to scan: {} {:?}",
+        numbers.format(estimate.files),
+        numbers.format(estimate.bytes / divisor),
+        blocksize
+    );
+    println!(
+        "{} files sharing their size to hash: up to {} {:?} read",
+        numbers.format(estimate.candidates),
+        numbers.format(estimate.candidate_bytes / divisor),
+        blocksize
+    );
+    if estimate.errors > 0 {
+        println!("{} paths couldn't be read", numbers.format(estimate.errors));
+    }
+}
+
+/// Asks the user to confirm an action on stdin.
+fn confirm() -> bool {
+    println!("Proceed? Y/N");
+    let mut input = String::new();
+    match stdin().read_line(&mut input) {
+        Ok(_n) => {
+            let confirmed = matches!(input.chars().next(), Some('y') | Some('Y'));
+            if !confirmed {
+                println!("Exiting.");
+            }
+            confirmed
+        }
+        Err(e) => {
+            println!("Error encountered reading user input. Err: {}", e);
+            false
+        }
+    }
+}
+
+fn action_verb(link: Option<LinkKind>, dry_run: bool) -> &'static str {
+    match (link, dry_run) {
+        (Some(LinkKind::Reflink), true) => "Would replace with a clone",
+        (Some(LinkKind::Reflink), false) => "Replaced with a clone",
+        (Some(LinkKind::Hard), true) => "Would replace with a hard link",
+        (Some(LinkKind::Hard), false) => "Replaced with a hard link",
+        (None, true) => "Would remove",
+        (None, false) => "Removed",
+    }
+}
+
+/// Keeps the given instance of each group and removes or links the others. `stopped` counts
+/// groups already left alone while planning.
+fn apply_plan(
+    plan: &[(&Fileinfo, &Path, Option<LinkKind>)],
+    executor: &Executor,
+    mut stopped: usize,
+) {
+    let (mut changed, mut reclaimed, mut across_devices) = (0, 0, 0);
+    for &(group, keep, link) in plan.iter() {
+        let outcome = match link {
+            Some(LinkKind::Reflink) => executor.reflink(group, keep),
+            Some(LinkKind::Hard) => executor.hard_link(group, keep),
+            None => executor.delete(group, keep),
+        };
+        let verb = action_verb(link, executor.is_dry_run());
+        for path in outcome.removed.iter() {
+            println!("{} {} (keeping {})", verb, path.display(), keep.display());
+        }
+        for (path, e) in outcome.failed.iter() {
+            eprintln!("Could not change {}. Err: {}", path.display(), e);
+        }
+        if let Some(anomaly) = outcome.anomaly {
+            eprintln!(
+                "Stopped group {}: {}",
+                group.get_group_id().unwrap_or_default(),
+                anomaly
+            );
+            if let Anomaly::CrossesDevices(_) = anomaly {
+                across_devices += 1;
+            }
+            stopped += 1;
+        }
+        changed += outcome.removed.len();
+        reclaimed += outcome.removed.len() as u64 * group.get_length();
+    }
+    let summary = match plan.first() {
+        Some(&(_, _, link)) if plan.iter().all(|&(_, _, other)| other == link) => {
+            action_verb(link, executor.is_dry_run())
+        }
+        _ if executor.is_dry_run() => "Would change",
+        _ => "Changed",
+    };
+    println!(
+        "{}: {} files ({} bytes){}",
+        summary,
+        changed,
+        reclaimed,
+        if across_devices > 0 {
+            format!(
+                " ({} groups stopped, {} of them spanning devices)",
+                stopped, across_devices
+            )
+        } else if stopped > 0 {
+            format!(" ({} groups stopped)", stopped)
+        } else {
+            String::new()
+        }
+    );
+}
+
+/// Asks which instances of each of `groups` to keep, as fdupes -d does, and removes the
+/// others. The instance standing in for the reference directories is always kept.
+fn run_interactive(groups: &[&Fileinfo], references: &ReferenceDirs, executor: &Executor) {
+    let (mut changed, mut reclaimed, mut stopped) = (0, 0, 0);
+    let verb = action_verb(None, executor.is_dry_run());
+    'groups: for (index, group) in groups.iter().enumerate() {
+        let paths = group.get_paths();
+        println!("---");
+        println!(
+            "[{} of {}] {} instances of {} bytes",
+            index + 1,
+            groups.len(),
+            paths.len(),
+            group.get_length()
+        );
+        for (number, path) in paths.iter().enumerate() {
+            let modified = fs::metadata(path)
+                .ok()
+                .and_then(|meta| FileMetadata::from(&meta).mtime)
+                .map_or_else(|| "unknown time".to_string(), format_time);
+            println!("  [{}] {}  {}", number + 1, modified, path.display());
+        }
+        let chosen = loop {
+            print!("Keep [1-{}, all, first, quit]: ", paths.len());
+            let _ = stdout().flush();
+            let mut input = String::new();
+            match stdin().read_line(&mut input) {
+                Ok(0) => break 'groups,
+                Ok(_) => match input.trim() {
+                    "q" | "quit" => break 'groups,
+                    input => match parse_kept(input, paths.len()) {
+                        Some(chosen) => break chosen,
+                        None => {
+                            println!("Enter the numbers of the instances to keep, all or first")
+                        }
+                    },
+                },
+                Err(e) => {
+                    println!("Error encountered reading user input. Err: {}", e);
+                    break 'groups;
+                }
+            }
+        };
+        let mut kept: Vec<PathBuf> = chosen.iter().map(|&i| paths[i].to_path_buf()).collect();
+        if let Some(reference) = references.instance_of(group) {
+            if !kept.iter().any(|path| path == reference) {
+                kept.push(reference.to_path_buf());
+            }
+        }
+        if kept.len() == paths.len() {
+            continue;
+        }
+        let outcome = executor.delete_sparing(group, &kept[0], &kept[1..]);
+        for path in outcome.removed.iter() {
+            println!("{} {}", verb, path.display());
+        }
+        for (path, e) in outcome.failed.iter() {
+            eprintln!("Could not change {}. Err: {}", path.display(), e);
+        }
+        if let Some(anomaly) = outcome.anomaly {
+            eprintln!("Stopped group {}: {}", index + 1, anomaly);
+            stopped += 1;
+        }
+        changed += outcome.removed.len();
+        reclaimed += outcome.removed.len() as u64 * group.get_length();
+    }
+    println!(
+        "{}: {} files ({} bytes){}",
+        verb,
+        changed,
+        reclaimed,
+        if stopped > 0 {
+            format!(" ({} groups stopped)", stopped)
+        } else {
+            String::new()
+        }
+    );
+}
+
+/// Parses a choice of the instances to keep out of `count`: `all`, `first` or their numbers
+/// separated by spaces or commas. Gets their indexes in ascending order.
+fn parse_kept(input: &str, count: usize) -> Option<Vec<usize>> {
+    match input {
+        "a" | "all" => return Some((0..count).collect()),
+        "f" | "first" => return Some(vec![0]),
+        _ => {}
+    }
+    let mut kept: Vec<usize> = input
+        .split([' ', ','])
+        .filter(|number| !number.is_empty())
+        .map(|number| {
+            number
+                .parse::<usize>()
+                .ok()
+                .filter(|number| (1..=count).contains(number))
+                .map(|number| number - 1)
+        })
+        .collect::<Option<_>>()?;
+    kept.sort_unstable();
+    kept.dedup();
+    (!kept.is_empty()).then_some(kept)
+}
+
+/// Scans the priority directories of `arguments` on their own and writes their duplicate
+/// groups right away, before the full scan: as JSON lines ahead of the full results with
+/// `-f jsonl`, as text on stdout for text formats and on stderr for other machine readable
+/// formats. Priority directories outside of `search_dirs` are added to it.
+fn scan_priority_dirs(
+    arguments: &Args,
+    search_dirs: &mut Vec<PathBuf>,
+    ignore_entries: &[PathBuf],
+    options: &ScanOptions,
+    decorations: &GroupDecorations,
+) {
+    let priority_dirs = &arguments.first;
+    let started = SystemTime::now();
+    let (priority_files, _) = ddh::deduplicate_dirs_with_options(
+        priority_dirs.to_vec(),
+        ignore_entries.to_vec(),
+        options,
+    );
+    if options.xattr_hashes {
+        for (path, e) in ddh::xattrs::tag_files(&priority_files, started) {
+            eprintln!("Error tagging {}. Err: {}", path.display(), e);
+        }
+    }
+    let (priority_files, _) = without_hard_links(priority_files);
+    let priority_groups: Vec<&Fileinfo> = priority_files
+        .iter()
+        .filter(|x| x.get_paths().len() > 1)
+        .collect();
+    if matches!(arguments.fmt, PrintFmt::Jsonl) {
+        let mut sink = JsonLinesSink::new(stdout().lock());
+        for group in priority_groups.into_iter() {
+            if let Some(group) = reportable_group(group.clone(), arguments, decorations) {
+                let _ = sink.write_group(&group);
+            }
+        }
+    } else {
+        let mut notes: Box<dyn Write> = if arguments.fmt.is_machine_readable() {
+            Box::new(stderr())
+        } else {
+            Box::new(stdout())
+        };
+        let _ = writeln!(
+            notes,
+            "{} Shared instance files in priority directories",
+            priority_groups.len()
+        )
+        .and_then(|_| print_shared_groups(&priority_groups, decorations, &mut notes));
+    }
+    let canonical_roots: Vec<PathBuf> = search_dirs
+        .iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .collect();
+    for dir in priority_dirs.iter() {
+        let covered = dir
+            .canonicalize()
+            .is_ok_and(|dir| canonical_roots.iter().any(|root| dir.starts_with(root)));
+        if !covered {
+            search_dirs.push(dir.clone());
+        }
+    }
+}
+
+fn run_command(command: Command) {
+    match command {
+        Command::Ack {
+            group_ids,
+            undo,
+            annotations: annotations_file,
+        } => {
+            let mut annotations = load_annotations(&annotations_file);
+            for id in group_ids.iter() {
+                let changed = if undo {
+                    annotations.unacknowledge(id)
+                } else {
+                    annotations.acknowledge(id)
+                };
+                if !changed {
+                    println!(
+                        "Group {} was already {}",
+                        id,
+                        if undo {
+                            "unacknowledged"
+                        } else {
+                            "acknowledged"
+                        }
+                    );
+                }
+            }
+            save_annotations(&annotations, &annotations_file);
+        }
+        Command::Tag {
+            group_id,
+            note,
+            clear,
+            annotations: annotations_file,
+        } => {
+            let mut annotations = load_annotations(&annotations_file);
+            match note {
+                Some(note) => annotations.add_note(&group_id, &note),
+                None if clear => {
+                    if !annotations.clear_notes(&group_id) {
+                        println!("Group {} has no notes", group_id);
+                    }
+                }
+                None => {
+                    annotations
+                        .notes_for_id(&group_id)
+                        .iter()
+                        .for_each(|note| println!("{}", note));
+                    return;
+                }
+            }
+            save_annotations(&annotations, &annotations_file);
+        }
+        Command::Cache { action, cache } => match cache.or_else(HashCache::default_path) {
+            Some(cache) => run_cache_action(action, &cache),
+            None => {
+                eprintln!("No default cache location, pass --cache");
+                std::process::exit(1);
+            }
+        },
+        Command::Watch {
+            cache,
+            quiet,
+            max_batch,
+            max_concurrent_reads,
+        } => match cache.or_else(HashCache::default_path) {
+            Some(cache) => run_watch(
+                &cache,
+                Duration::from_millis(quiet),
+                max_batch,
+                max_concurrent_reads,
+            ),
+            None => {
+                eprintln!("No default cache location, pass --cache");
+                std::process::exit(1);
+            }
+        },
+        Command::Retry {
+            scan,
+            denied,
+            output,
+        } => run_retry(&scan, &denied, output.as_deref().unwrap_or(&scan)),
+        Command::Events { before, after } => run_events(&before, &after),
+        Command::Diff { before, after } => run_diff(&before, &after),
+        Command::Query { scopes, results } => run_query(&scopes, &results),
+        Command::Fingerprint { dir, output } => run_fingerprint(&dir, &output),
+        Command::Compare { a, b } => run_compare(&a, &b),
+        Command::Apply {
+            policy,
+            directories,
+            execute,
+            allow_root,
+        } => {
+            refuse_elevated_changes(execute, allow_root);
+            run_apply(&policy, directories, execute)
+        }
+        Command::Cp { src, dst, link } => run_cp(&src, &dst, link),
+        Command::Du { dir, split, depth } => run_du(&dir, split, depth),
+        Command::StripXattrs { directories } => run_strip_xattrs(&directories),
+        Command::Selftest => run_selftest(),
+        #[cfg(feature = "dev-tools")]
+        Command::GenFixture {
+            root,
+            seed,
+            groups,
+            copies,
+            unique,
+            max_size,
+            sparse_size,
+            no_hardlinks,
+            no_symlinks,
+            plain_names,
+        } => {
+            let spec = ddh::fixtures::FixtureSpec {
+                seed,
+                groups,
+                copies,
+                unique,
+                max_size,
+                hardlinks: !no_hardlinks,
+                symlinks: !no_symlinks,
+                sparse_size,
+                weird_names: !plain_names,
+            };
+            match ddh::fixtures::generate(&root, &spec) {
+                Ok(report) => println!(
+                    "{} files, {} hardlinks and {} symlinks written to {} ({} duplicate groups)",
+                    report.files,
+                    report.hardlinks,
+                    report.symlinks,
+                    root.display(),
+                    report.duplicate_groups
+                ),
+                Err(e) => {
+                    eprintln!("Error generating fixture in {}. Err: {}", root.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Merge {
+            indexes,
+            blocksize,
+            verbosity,
+            output,
+            fmt,
+        } => run_merge(&indexes, blocksize, verbosity, &output, fmt),
+        Command::Agent {
+            directories,
+            ignore_dirs,
+            min_size,
+            host,
+            hash,
+            output,
+        } => {
+            let ignore_entries: Vec<String> = ignore_dirs
+                .iter()
+                .flat_map(|list| parse_ignore_list(list))
+                .collect();
+            let hasher = hash.content_hasher();
+            let options = ScanOptions {
+                min_size,
+                hasher: Some(hasher.clone()),
+                ..Default::default()
+            };
+            let (mut files, read_errors) =
+                ddh::deduplicate_dirs_with_options(directories, ignore_entries, &options);
+            read_errors.iter().for_each(|(path, e)| {
+                eprintln!("Could not process {} due to error {}", path.display(), e)
+            });
+            complete_hashes_with(&mut files, None, hasher.as_ref());
+            let index = HashIndex::new(&host, &files).hashed_with(hasher.as_ref());
+            let written = match output {
+                Some(path) => fs::File::create(&path).and_then(|f| index.write(f)),
+                None => index.write(std::io::stdout().lock()),
+            };
+            if let Err(e) = written {
+                eprintln!("Error writing index. Err: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Writes the paths of `errors` caused by missing permissions, one per line.
+fn write_denied_paths(
+    errors: &[(PathBuf, std::io::Error)],
+    file: &Path,
+    anonymizer: Option<&Anonymizer>,
+) -> std::io::Result<()> {
+    let mut output = fs::File::create(file)?;
+    for (path, _) in errors
+        .iter()
+        .filter(|(_, e)| e.kind() == std::io::ErrorKind::PermissionDenied)
+    {
+        match anonymizer {
+            Some(anonymizer) => writeln!(output, "{}", anonymizer.path(path).display())?,
+            None => writeln!(output, "{}", path.display())?,
+        }
+    }
+    Ok(())
+}
+
+/// Lists symlinks sharing a target apart from duplicate groups, since they take no space.
+fn print_link_farms(farms: &[LinkFarm], output: &mut dyn Write) -> std::io::Result<()> {
+    if farms.is_empty() {
+        return Ok(());
+    }
+    let links: usize = farms.iter().map(|farm| farm.links.len()).sum();
+    writeln!(
+        output,
+        "{} symlinks resolve to {} shared targets (link farms, not copies):",
+        links,
+        farms.len()
+    )?;
+    for farm in farms.iter() {
+        writeln!(
+            output,
+            "{} ({} links)",
+            farm.target.display(),
+            farm.links.len()
+        )?;
+        for link in farm.links.iter() {
+            writeln!(output, "\t{}", link.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Takes the hard links out of the groups of `files`, since removing them frees no space.
+fn without_hard_links(files: Vec<Fileinfo>) -> (Vec<Fileinfo>, Vec<HardLinkSet>) {
+    let mut hard_links = Vec::new();
+    let files = files
+        .into_iter()
+        .map(|file| {
+            if file.get_paths().len() < 2 {
+                return file;
+            }
+            let (sets, distinct) = ddh::links::split_hard_links(&file);
+            hard_links.extend(sets);
+            distinct
+        })
+        .collect();
+    (files, hard_links)
+}
+
+/// Applies the filters of the results to a group the scan just settled, so that `-f jsonl` can
+/// write it right away. Returns `None` for groups the results wouldn't list.
+fn reportable_group(
+    group: Fileinfo,
+    arguments: &Args,
+    decorations: &GroupDecorations,
+) -> Option<Fileinfo> {
+    let group = match arguments.symlinks {
+        SymlinkMode::Skip => group,
+        _ => ddh::links::split_links(&group).1,
+    };
+    let (_, group) = ddh::links::split_hard_links(&group);
+    let listed = group.get_paths().len() > 1
+        && (arguments.show_acked || !decorations.annotations.is_acknowledged(&group))
+        && arguments.placement.as_ref().is_none_or(|placement| {
+            decorations.roots.placement(&group).as_ref() == Some(placement)
+        })
+        && arguments
+            .cross_branch
+            .is_none_or(|depth| decorations.roots.spans_branches(&group, depth));
+    listed.then_some(group)
+}
+
+/// Counts hard links apart from duplicate groups, since they take no space, listing them unless
+/// `verbosity` is quiet.
+fn print_hard_links(
+    sets: &[HardLinkSet],
+    verbosity: Verbosity,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    if sets.is_empty() {
+        return Ok(());
+    }
+    let links: usize = sets.iter().map(|set| set.paths.len()).sum();
+    writeln!(
+        output,
+        "{} hard links name {} files (shared storage, not copies)",
+        links,
+        sets.len()
+    )?;
+    if let Verbosity::Quiet = verbosity {
+        return Ok(());
+    }
+    for set in sets.iter() {
+        writeln!(output, "{} links:", set.paths.len())?;
+        for path in set.paths.iter() {
+            writeln!(output, "\t{}", path.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Counts the online-only files left out of the scan, listing them unless `verbosity` is quiet.
+fn print_placeholders(
+    placeholders: &[(PathBuf, std::io::Error)],
+    verbosity: Verbosity,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    if placeholders.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        output,
+        "{} online-only files skipped to avoid downloading them (use --hydrate to hash them)",
+        placeholders.len()
+    )?;
+    if let Verbosity::Quiet = verbosity {
+        return Ok(());
+    }
+    for (path, _) in placeholders.iter() {
+        writeln!(output, "\t{}", path.display())?;
+    }
+    Ok(())
+}
+
+/// Lists names which only differ by case or normalization, grouped by directory. Collisions
+/// are found among the real names, then printed as pseudonyms when `anonymizer` is given.
+fn print_name_collisions(files: &[Fileinfo], anonymizer: Option<&Anonymizer>) {
+    let collisions = ddh::names::name_collisions(
+        files
+            .iter()
+            .flat_map(|file| file.get_paths().iter().map(|path| &**path)),
+    );
+    if collisions.is_empty() {
+        return;
+    }
+    println!(
+        "{} sets of names differ only by case or normalization:",
+        collisions.len()
+    );
+    let shown = |path: &Path| match anonymizer {
+        Some(anonymizer) => anonymizer.path(path),
+        None => path.to_path_buf(),
+    };
+    for collision in collisions.iter() {
+        println!("{}", shown(&collision.dir).display());
+        for name in collision.names.iter() {
+            println!("\t{}", shown(Path::new(name)).display());
+        }
+    }
+}
+
+fn print_unique_to(files: &[Fileinfo], roots: &RootLabels, root: &str) {
+    let unique = match roots.unique_to(files, root) {
+        Some(unique) => unique,
+        None => {
+            eprintln!(
+                "{} is neither a searched directory nor the label of one",
+                root
+            );
+            std::process::exit(1);
+        }
+    };
+    let bytes: u64 = unique.iter().map(|(_, length)| length).sum();
+    println!(
+        "{} files ({}) under {} have no copy elsewhere:",
+        unique.len(),
+        format_size(bytes),
+        root
+    );
+    for (path, _) in unique {
+        println!("{}", path.display());
+    }
+}
+
+fn print_duplicate_trees(files: &[Fileinfo], roots: &[PathBuf]) {
+    let groups = ddh::trees::duplicate_trees(files, roots);
+    if groups.is_empty() {
+        return;
+    }
+    println!("{} sets of identical directories:", groups.len());
+    for group in groups.iter() {
+        println!(
+            "{} files, {} each, {} wasted",
+            group.files,
+            format_size(group.size),
+            format_size(group.wasted())
+        );
+        for dir in group.dirs.iter() {
+            println!("\t{}", dir.display());
+        }
+    }
+}
+
+fn print_similar_dirs(files: &[Fileinfo], roots: &[PathBuf], threshold: f64) {
+    let pairs = ddh::trees::similar_dirs(files, roots, threshold);
+    if pairs.is_empty() {
+        return;
+    }
+    println!("{} pairs of similar directories:", pairs.len());
+    for pair in pairs.iter() {
+        println!(
+            "{:.0}% shared ({} of {} and {})",
+            pair.similarity() * 100.0,
+            format_size(pair.shared),
+            format_size(pair.a_size),
+            format_size(pair.b_size)
+        );
+        println!("\t{}\n\t{}", pair.a.display(), pair.b.display());
+    }
+}
+
+/// Draws the progress of a scan on stderr until every sender of `events` is dropped. When
+/// several `volumes` are searched, the files found and hashed on each are counted separately.
+fn show_progress(events: Receiver<Progress>, volumes: Vec<PathBuf>) {
+    let volumes: Vec<(PathBuf, PathBuf)> = volumes
+        .into_iter()
+        .map(|volume| {
+            (
+                volume.canonicalize().unwrap_or_else(|_| volume.clone()),
+                volume,
+            )
+        })
+        .collect();
+    let mut discovered = vec![0u64; volumes.len()];
+    let mut hashed = vec![0u64; volumes.len()];
+    let volume_of = |path: &Path| {
+        volumes
+            .iter()
+            .position(|(canonical, volume)| path.starts_with(canonical) || path.starts_with(volume))
+    };
+    let per_volume = |counts: &[u64]| {
+        volumes
+            .iter()
+            .zip(counts)
+            .map(|((_, volume), count)| format!("{} {}", volume.display(), count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+        ProgressStyle::with_template("{spinner} Discovered {pos} files {msg}")
+            .expect("Error in progress template"),
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+    for event in events {
+        match event {
+            Progress::FileDiscovered(path) => {
+                if let Some(volume) = volume_of(&path) {
+                    discovered[volume] += 1;
+                }
+            }
+            Progress::Discovered(files) => {
+                bar.set_position(files);
+                if !volumes.is_empty() {
+                    bar.set_message(format!("({})", per_volume(&discovered)));
+                }
+            }
+            Progress::Hashing { total_bytes } => {
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "[{elapsed}] {wide_bar} {bytes}/{total_bytes} hashed, {eta} left {msg}",
+                    )
+                    .expect("Error in progress template"),
+                );
+                bar.set_length(total_bytes);
+                bar.set_position(0);
+                bar.set_message("");
+            }
+            Progress::HashFinished { path, .. } => {
+                if let Some(volume) = volume_of(&path) {
+                    hashed[volume] += 1;
+                    bar.set_message(format!("(files hashed: {})", per_volume(&hashed)));
+                }
+            }
+            Progress::Hashed(bytes) => bar.inc(bytes),
+            _ => {}
+        }
+    }
+    bar.finish_and_clear();
+}
+
+/// Reads the saved results of a scan and their summary, where they have one, exiting on
+/// errors.
+fn read_scan(path: &Path) -> (Vec<Fileinfo>, Option<JsonSummary>) {
+    match fs::File::open(path).and_then(|f| read_json_report(std::io::BufReader::new(f))) {
+        Ok((summary, files)) => (files, summary),
+        Err(e) => {
+            eprintln!("Error reading scan {}. Err: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads the saved results `before` and `after`, exiting when they were hashed with different
+/// algorithms since none of their hashes would match. Also tells whether both hold every file
+/// of their scans, without which paths can't be compared.
+fn read_comparable_scans(before: &Path, after: &Path) -> (Vec<Fileinfo>, Vec<Fileinfo>, bool) {
+    let ((before_files, before_summary), (after_files, after_summary)) =
+        (read_scan(before), read_scan(after));
+    let algorithm = |summary: &Option<JsonSummary>| {
+        summary
+            .as_ref()
+            .and_then(|summary| summary.hash_algorithm.clone())
+    };
+    if let (Some(a), Some(b)) = (algorithm(&before_summary), algorithm(&after_summary)) {
+        if a != b {
+            eprintln!(
+                "{} was hashed with {} and {} with {}, so their hashes can't be compared",
+                before.display(),
+                a,
+                after.display(),
+                b
+            );
+            std::process::exit(1);
+        }
+    }
+    let complete = ddh::events::holds_all_files(&before_files, before_summary.as_ref())
+        && ddh::events::holds_all_files(&after_files, after_summary.as_ref());
+    (before_files, after_files, complete)
+}
+
+/// Prints the group events between the saved scans `before` and `after` as JSON lines.
+fn run_events(before: &Path, after: &Path) {
+    let (before, after, _) = read_comparable_scans(before, after);
+    let events = ddh::events::diff_groups(&before, &after);
+    if let Err(e) = ddh::events::write_events(stdout().lock(), &events) {
+        eprintln!("Error writing events. Err: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Prints the groups of the saved scan `results` which the client presenting a token on stdin
+/// may see. Clients with an unknown token are told nothing about the scan.
+fn run_query(scopes: &Path, results: &Path) {
+    let scopes = match Scopes::load(scopes) {
+        Ok(scopes) => scopes,
+        Err(e) => {
+            eprintln!("Error reading scopes {}. Err: {}", scopes.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let mut token = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut token) {
+        eprintln!("Error reading token. Err: {}", e);
+        std::process::exit(1);
+    }
+    let scope = match scopes.authorize(token.trim_end_matches(['\r', '\n'])) {
+        Some(scope) => scope,
+        None => {
+            eprintln!("Error: unknown token");
+            std::process::exit(1);
+        }
+    };
+    let (files, _) = read_scan(results);
+    let mut sink = JsonLinesSink::new(stdout().lock());
+    for group in files.iter().filter_map(|group| scope.restrict(group)) {
+        if let Err(e) = sink.write_group(&group) {
+            eprintln!("Error writing groups. Err: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints how the wasted space, the duplicate groups and the paths changed between the saved
+/// scans `before` and `after`.
+fn run_diff(before: &Path, after: &Path) {
+    let (before, after, complete) = read_comparable_scans(before, after);
+    let diff = ddh::events::diff_scans(&before, &after, complete);
+    let wasted = |files: &[Fileinfo]| ddh::waste::duplicate_bytes(files.iter());
+    let (old_waste, new_waste) = (wasted(&before), wasted(&after));
+    println!(
+        "Wasted space: {} -> {} ({}{})",
+        format_size(old_waste),
+        format_size(new_waste),
+        if new_waste < old_waste { "-" } else { "+" },
+        format_size(new_waste.abs_diff(old_waste))
+    );
+    for (heading, groups) in [
+        ("new duplicate groups", &diff.new_groups),
+        ("resolved duplicate groups", &diff.resolved_groups),
+    ] {
+        println!("{} {}", groups.len(), heading);
+        for group in groups.iter() {
+            println!(
+                "  {} ({} x {})",
+                group.get_group_id().unwrap_or_default(),
+                group.get_paths().len(),
+                format_size(group.get_length())
+            );
+            let mut paths = group.get_paths().to_vec();
+            paths.sort();
+            for path in paths {
+                println!("\t{}", path.display());
+            }
+        }
+    }
+    if !complete {
+        eprintln!("Paths aren't compared, as a scan doesn't list every file like -v all does");
+        return;
+    }
+    for (heading, paths) in [
+        ("files appeared", &diff.appeared),
+        ("files disappeared", &diff.disappeared),
+    ] {
+        println!("{} {}", paths.len(), heading);
+        for path in paths.iter() {
+            println!("\t{}", path.display());
+        }
+    }
+}
+
+fn run_fingerprint(dir: &Path, output: &Path) {
+    let (fingerprint, errors) = match Fingerprint::of_dir(dir) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            eprintln!("Error reading {}. Err: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    for (path, e) in errors.iter() {
+        eprintln!("Left out {}. Err: {}", path.display(), e);
+    }
+    let written =
+        fs::File::create(output).and_then(|f| fingerprint.write_to(std::io::BufWriter::new(f)));
+    if let Err(e) = written {
+        eprintln!("Error writing {}. Err: {}", output.display(), e);
+        std::process::exit(1);
+    }
+    let root = fingerprint
+        .root()
+        .copied()
+        .unwrap_or(ddh::fingerprint::Node {
+            kind: ddh::fingerprint::NodeKind::Dir,
+            hash: 0,
+            size: 0,
+        });
+    println!(
+        "Fingerprint {:032x} of {} ({} bytes) written to {}",
+        root.hash,
+        dir.display(),
+        root.size,
+        output.display()
+    );
+}
+
+/// Creates a handle which SIGUSR1 pauses and resumes, so that a long scan can make way for
+/// backups or maintenance without being killed: `kill -USR1 <pid>` once to pause, again to go on.
+#[cfg(unix)]
+fn pause_on_sigusr1() -> Option<PauseHandle> {
+    static HANDLE: OnceLock<PauseHandle> = OnceLock::new();
+    extern "C" fn toggle(_signal: libc::c_int) {
+        if let Some(handle) = HANDLE.get() {
+            handle.toggle();
+        }
+    }
+    let handle = HANDLE.get_or_init(PauseHandle::new).clone();
+    unsafe {
+        libc::signal(
+            libc::SIGUSR1,
+            toggle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        )
+    };
+    Some(handle)
+}
+
+/// Scans can only be paused by signal on Unix.
+#[cfg(not(unix))]
+fn pause_on_sigusr1() -> Option<PauseHandle> {
+    None
+}
+
+/// Parses the time of `--newer-than` and `--older-than`, taking ages relative to now.
+fn parse_cutoff(spec: &str) -> Result<SystemTime, String> {
+    parse_time(spec, SystemTime::now())
+}
+
+/// Parses the percentage of `--similar-dirs` as a fraction.
+fn parse_percent(spec: &str) -> Result<f64, String> {
+    match spec.trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent / 100.0),
+        _ => Err(format!("{} is not a percentage from 0 to 100", spec)),
+    }
+}
+
+/// Copies `src` into `dst` and reports what was written and what was linked.
+fn run_cp(src: &Path, dst: &Path, link: LinkKind) {
+    let mode = match link {
+        LinkKind::Reflink => LinkMode::Reflink,
+        LinkKind::Hard => LinkMode::Hard,
+    };
+    let report = match copy_tree(src, dst, mode) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error copying {}. Err: {}", src.display(), e);
+            std::process::exit(1);
+        }
+    };
+    for (path, e) in report.errors.iter() {
+        eprintln!("Not copied {}. Err: {}", path.display(), e);
+    }
+    println!(
+        "{} files copied ({} bytes), {} linked to existing contents ({} bytes saved)",
+        report.copied, report.copied_bytes, report.linked, report.linked_bytes
+    );
+    if report.skipped_symlinks > 0 {
+        println!("{} symlinks left out", report.skipped_symlinks);
+    }
+    if !report.errors.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Lists the apparent, unique and freed bytes of `dir` and its directories down to `depth`.
+fn run_du(dir: &Path, split: bool, depth: usize) {
+    let root = match dir.canonicalize() {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("Error reading {}. Err: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let (files, errors) = ddh::deduplicate_dirs(vec![root.as_path()], vec![], 0);
+    for (path, e) in errors.iter() {
+        eprintln!("Could not process {}. Err: {}", path.display(), e);
+    }
+    let attribution = if split {
+        Attribution::Split
+    } else {
+        Attribution::First
+    };
+    let usage = ddh::usage::disk_usage(&files, &root, attribution);
+    println!("{:>10} {:>10} {:>10}  path", "apparent", "unique", "freed");
+    for (path, usage) in usage.iter() {
+        let below = path
+            .strip_prefix(&root)
+            .map_or(0, |p| p.components().count());
+        if below <= depth {
+            println!(
+                "{:>10} {:>10} {:>10}  {}",
+                format_size(usage.apparent),
+                format_size(usage.unique),
+                format_size(usage.freed),
+                path.display()
+            );
+        }
+    }
+    if !errors.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Strips the attributes written by --xattrs below `directories`.
+fn run_strip_xattrs(directories: &[PathBuf]) {
+    let mut stripped = 0;
+    let mut failed = false;
+    for dir in directories.iter() {
+        let (count, errors) = ddh::xattrs::strip_tree(dir);
+        stripped += count;
+        for (path, e) in errors.iter() {
+            eprintln!("Error stripping {}. Err: {}", path.display(), e);
+        }
+        failed |= !errors.is_empty();
+    }
+    println!("Attributes removed from {} files", stripped);
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Runs the self-test and exits with status 1 if any check failed.
+fn run_selftest() {
+    let dir = std::env::temp_dir().join(format!("ddh-selftest-{}", std::process::id()));
+    let checks = match ddh::selftest::run(&dir) {
+        Ok(checks) => checks,
+        Err(e) => {
+            eprintln!("Error writing test files to {}. Err: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let mut failed = 0;
+    for check in checks.iter() {
+        match &check.result {
+            Ok(()) => println!("ok      {}", check.name),
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {}", check.name, e);
+            }
+        }
+    }
+    println!(
+        "{} of {} checks passed",
+        checks.len() - failed,
+        checks.len()
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
 }
 
-static DDH_ABOUT: &str = "Compare and contrast directories.\nExample invocation: ddh -d /home/jon/downloads /home/jon/documents -v duplicates\nExample pipe: ddh -d ~/Downloads/ -o no -v all -f json | someJsonParser.bin";
+/// Lists the differences between two fingerprints and exits with status 1 if there are any.
+fn run_compare(a: &Path, b: &Path) {
+    let read = |path: &Path| match fs::File::open(path)
+        .and_then(|f| Fingerprint::read_from(std::io::BufReader::new(f)))
+    {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            eprintln!("Error reading fingerprint {}. Err: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let comparison = ddh::fingerprint::compare(&read(a), &read(b));
+    let sections = [
+        ("Identical", &comparison.identical),
+        ("Missing", &comparison.missing),
+        ("Added", &comparison.added),
+        ("Changed", &comparison.changed),
+    ];
+    for (title, paths) in sections.iter() {
+        println!("{}: {}", title, paths.len());
+        for path in paths.iter() {
+            println!("\t{}", path.display());
+        }
+    }
+    if !(comparison.missing.is_empty()
+        && comparison.added.is_empty()
+        && comparison.changed.is_empty())
+    {
+        std::process::exit(1);
+    }
+}
 
-fn main() {
+/// Rehashes the paths read from stdin into the cache at `cache_file` once they went `quiet`,
+/// writing the cache after every batch, until stdin is closed.
+fn run_watch(
+    cache_file: &Path,
+    quiet: Duration,
+    max_batch: usize,
+    max_concurrent_reads: Option<usize>,
+) {
+    let cache = match HashCache::open(cache_file) {
+        Ok(cache) => cache,
+        Err(e) => {
+            eprintln!("Error opening cache {}. Err: {}", cache_file.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let limiter = max_concurrent_reads.map(ReadLimiter::new);
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        for line in stdin().lock().lines().map_while(Result::ok) {
+            if !line.is_empty() && sender.send(PathBuf::from(line)).is_err() {
+                return;
+            }
+        }
+    });
+    let mut queue = RescanQueue::new(quiet, max_batch);
+    let mut open = true;
+    while open || queue.next_due().is_some() {
+        let wait = queue
+            .next_due()
+            .map_or(quiet, |due| due.saturating_duration_since(Instant::now()));
+        match receiver.recv_timeout(wait) {
+            Ok(path) => queue.push(path, Instant::now()),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => open = false,
+        }
+        /* Once stdin is closed nothing postpones the pending paths any longer. */
+        let now = if open {
+            Instant::now()
+        } else {
+            Instant::now() + quiet
+        };
+        let paths = queue.ready(now);
+        if paths.is_empty() {
+            continue;
+        }
+        let report = ddh::watch::rescan_into_cache(&cache, &paths, limiter.as_ref());
+        for (path, e) in report.errors.iter() {
+            eprintln!("Could not rehash {}. Err: {}", path.display(), e);
+        }
+        if let Err(e) = cache.persist() {
+            eprintln!("Error writing cache {}. Err: {}", cache_file.display(), e);
+        }
+        let metrics = queue.metrics(Instant::now());
+        eprintln!(
+            "Rehashed {} files ({} unchanged, {} gone), {} paths pending, {} events ({} merged)",
+            report.hashed,
+            report.unchanged,
+            report.gone,
+            metrics.pending,
+            metrics.events,
+            metrics.coalesced
+        );
+    }
+}
 
-    let arguments = Args::parse();
+fn run_retry(scan: &Path, denied_file: &Path, output: &Path) {
+    let (saved, summary) = read_scan(scan);
+    let algorithm = summary.and_then(|summary| summary.hash_algorithm);
+    let hasher: Arc<dyn ContentHasher> = match algorithm.as_deref() {
+        Some(name) => match ddh::hasher::by_name(name) {
+            Some(hasher) => hasher,
+            None => {
+                eprintln!(
+                    "{} was hashed with unknown algorithm {}",
+                    scan.display(),
+                    name
+                );
+                std::process::exit(1);
+            }
+        },
+        None => Arc::new(SipHash128),
+    };
+    let denied: Vec<PathBuf> = match fs::read_to_string(denied_file) {
+        Ok(list) => list
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect(),
+        Err(e) => {
+            eprintln!("Error reading {}. Err: {}", denied_file.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let options = ScanOptions {
+        hasher: Some(hasher.clone()),
+        ..Default::default()
+    };
+    let (mut rescanned, read_errors) =
+        ddh::deduplicate_dirs_with_options(denied.clone(), Vec::new(), &options);
+    complete_hashes_with(&mut rescanned, None, hasher.as_ref());
+    let found: usize = rescanned.iter().map(|x| x.get_paths().len()).sum();
+    let merged = merge_remote_with(saved, rescanned, hasher.as_ref());
+    let groups: Vec<&Fileinfo> = merged.iter().collect();
+    let shared: Vec<&Fileinfo> = merged.iter().filter(|x| x.get_paths().len() > 1).collect();
+    let errors = read_errors.len();
+    let summary = json_summary(&merged, &shared, errors, None, Some(hasher.name()));
+    let written = fs::File::create(output).and_then(|f| {
+        let mut sink = JsonSink::new(std::io::BufWriter::new(f)).with_summary(summary);
+        ddh::output::write_results(&mut sink, &groups, &[], &[])
+    });
+    if let Err(e) = written {
+        eprintln!("Error writing results to {}. Err: {}", output.display(), e);
+        std::process::exit(1);
+    }
+    if let Err(e) = write_denied_paths(&read_errors, denied_file, None) {
+        eprintln!("Error writing {}. Err: {}", denied_file.display(), e);
+    }
+    let still_denied = read_errors
+        .iter()
+        .filter(|(_, e)| e.kind() == std::io::ErrorKind::PermissionDenied)
+        .count();
+    println!(
+        "{} files found under {} denied paths merged into {} ({} paths still denied)",
+        found,
+        denied.len(),
+        output.display(),
+        still_denied
+    );
+}
 
-    let (complete_files, read_errors): (Vec<Fileinfo>, Vec<(_, _)>) =
-        ddh::deduplicate_dirs(arguments.directories, arguments.ignore_dirs, arguments.min_size);
+fn run_merge(
+    indexes: &[PathBuf],
+    blocksize: Blocksize,
+    verbosity: Verbosity,
+    output: &str,
+    fmt: PrintFmt,
+) {
+    let mut sources: HashMap<PathBuf, String> = HashMap::new();
+    let mut all_files = Vec::new();
+    let mut algorithm: Option<String> = None;
+    for index_path in indexes.iter() {
+        let source = index_path
+            .file_name()
+            .unwrap_or(index_path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let index = fs::File::open(index_path).and_then(HashIndex::read);
+        if let Ok(index) = index.as_ref() {
+            match algorithm.as_ref() {
+                Some(algorithm) if *algorithm != index.hash_algorithm => {
+                    eprintln!(
+                        "Index {} was hashed with {}, the others with {}",
+                        index_path.display(),
+                        index.hash_algorithm,
+                        algorithm
+                    );
+                    std::process::exit(1);
+                }
+                Some(_) => {}
+                None => algorithm = Some(index.hash_algorithm.clone()),
+            }
+        }
+        match index.and_then(|index| index.into_files()) {
+            Ok(files) => {
+                files
+                    .iter()
+                    .flat_map(|x| x.get_paths().iter())
+                    .for_each(|path| {
+                        sources.insert(path.to_path_buf(), source.clone());
+                    });
+                all_files.extend(files);
+            }
+            Err(e) => {
+                eprintln!("Error reading index {}. Err: {}", index_path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let complete_files = ddh::consolidate(all_files);
     let (shared_files, unique_files): (Vec<&Fileinfo>, Vec<&Fileinfo>) = complete_files
         .par_iter()
         .partition(|&x| x.get_paths().len() > 1);
+    let decorations = GroupDecorations {
+        copy_patterns: &CopyPatterns::default(),
+        annotations: &Annotations::default(),
+        roots: &RootLabels::default(),
+        sources: &sources,
+        max_paths_per_group: None,
+        collapse_below: None,
+        numbers: NumberFormat::from_env(),
+        references: &ReferenceDirs::default(),
+        hash_algorithm: algorithm.as_deref(),
+    };
+    let context = OutputContext {
+        fmt,
+        verbosity,
+        blocksize,
+        warnings: &[],
+        summary: json_summary(
+            &complete_files,
+            &shared_files,
+            0,
+            None,
+            decorations.hash_algorithm,
+        ),
+        timings: None,
+        decorations: &decorations,
+    };
     process_full_output(
         &shared_files,
         &unique_files,
         &complete_files,
-        &read_errors,
-        arguments.output.as_str(),
-        arguments.blocksize,
-        arguments.fmt,
-        arguments.verbosity,
+        &[],
+        false,
+        output,
+        &context,
     );
 }
 
+/// Reads indexes written by `ddh agent`, from stdin for `-`, exiting unless they were hashed
+/// with `algorithm`.
+fn read_indexes(sources: &[String], algorithm: &str) -> Vec<Fileinfo> {
+    let mut remote = Vec::new();
+    for source in sources.iter() {
+        let index = if source == "-" {
+            HashIndex::read(stdin().lock())
+        } else {
+            fs::File::open(source).and_then(HashIndex::read)
+        };
+        if let Ok(index) = index.as_ref() {
+            if index.hash_algorithm != algorithm {
+                eprintln!(
+                    "Index {} was hashed with {}, pass --hash {} to compare with it",
+                    source, index.hash_algorithm, index.hash_algorithm
+                );
+                std::process::exit(1);
+            }
+        }
+        match index.and_then(|index| index.into_files()) {
+            Ok(mut files) => remote.append(&mut files),
+            Err(e) => {
+                eprintln!("Error reading index {}. Err: {}", source, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    remote
+}
+
+fn run_cache_action(action: CacheAction, cache_file: &Path) {
+    let cache = match HashCache::open(cache_file) {
+        Ok(cache) => cache,
+        Err(e) => {
+            eprintln!("Error opening cache {}. Err: {}", cache_file.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let result = match action {
+        CacheAction::Stats => cache.stats().map(|stats| {
+            println!("Cache file: {}", cache_file.display());
+            println!("Entries: {}", stats.entries);
+            println!("Size on disk: {} bytes", stats.file_size);
+            println!(
+                "Lookups: {} hits, {} misses ({:.1}% hit rate)",
+                stats.hits,
+                stats.misses,
+                stats.hit_rate() * 100.0
+            );
+        }),
+        CacheAction::Prune { max_entries } => cache.prune(max_entries).map(|report| {
+            println!(
+                "Removed {} stale and {} least recently used entries, {} remain",
+                report.stale, report.evicted, report.remaining
+            );
+        }),
+        CacheAction::Clear => cache.clear().map(|()| {
+            println!("Cleared {}", cache_file.display());
+        }),
+    };
+    if let Err(e) = result {
+        eprintln!(
+            "Error maintaining cache {}. Err: {}",
+            cache_file.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+}
+
+fn load_annotations(annotations_file: &str) -> Annotations {
+    match Annotations::load(annotations_file) {
+        Ok(annotations) => annotations,
+        Err(e) => {
+            eprintln!(
+                "Error reading annotations from {}. Err: {}",
+                annotations_file, e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn save_annotations(annotations: &Annotations, annotations_file: &str) {
+    if let Err(e) = annotations.save(annotations_file) {
+        eprintln!(
+            "Error writing annotations to {}. Err: {}",
+            annotations_file, e
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Sums up `complete_files`, of which `shared_files` are listed as groups, for JSON output.
+fn json_summary(
+    complete_files: &[Fileinfo],
+    shared_files: &[&Fileinfo],
+    errors: usize,
+    timings: Option<&ScanTimings>,
+    hash_algorithm: Option<&str>,
+) -> JsonSummary {
+    JsonSummary {
+        total_files: complete_files
+            .iter()
+            .map(|x| x.get_paths().len() as u64)
+            .sum(),
+        total_bytes: ddh::waste::total_bytes(complete_files),
+        duplicate_groups: shared_files.len() as u64,
+        duplicates: shared_files
+            .iter()
+            .map(|x| x.get_paths().len() as u64 - 1)
+            .sum(),
+        wasted_bytes: ddh::waste::duplicate_bytes(shared_files.iter().copied()),
+        scan_duration_ms: timings.map(|t| (t.discovery + t.hashing).as_millis() as u64),
+        errors: errors as u64,
+        hash_algorithm: hash_algorithm.map(str::to_string),
+    }
+}
+
+/// How the results of a scan are reported, and what is reported alongside them.
+struct OutputContext<'a> {
+    fmt: PrintFmt,
+    verbosity: Verbosity,
+    blocksize: Blocksize,
+    warnings: &'a [Warning],
+    /// Totals of the scan, as written with JSON and HTML output.
+    summary: JsonSummary,
+    /// Durations of the phases of the scan, `None` for results which weren't scanned here.
+    timings: Option<&'a ScanTimings>,
+    decorations: &'a GroupDecorations<'a>,
+}
+
 fn process_full_output(
     shared_files: &[&Fileinfo],
     unique_files: &[&Fileinfo],
     complete_files: &[Fileinfo],
     error_paths: &[(PathBuf, std::io::Error)],
+    streamed: bool,
     output: &str,
-    blocksize: Blocksize,
-    fmt: PrintFmt,
-    verbosity: Verbosity,
+    context: &OutputContext,
 ) {
-    let display_power = match blocksize {
-        Blocksize::Bytes => 0,
-        Blocksize::Kilobytes => 1,
-        Blocksize::Megabytes => 2,
-        Blocksize::Gigabytes => 3,
-    };
-    let display_divisor = 1024u64.pow(display_power);
-
-    println!(
-        "{} Total files (with duplicates): {} {:?}",
-        complete_files
-            .par_iter()
-            .map(|x| x.get_paths().len() as u64)
-            .sum::<u64>(),
-        complete_files
-            .par_iter()
-            .map(|x| (x.get_paths().len() as u64) * x.get_length())
-            .sum::<u64>()
-            / (display_divisor),
-        blocksize
-    );
-    println!(
-        "{} Total files (without duplicates): {} {:?}",
-        complete_files.len(),
-        complete_files
-            .par_iter()
-            .map(|x| x.get_length())
-            .sum::<u64>()
-            / (display_divisor),
-        blocksize
-    );
-    println!(
-        "{} Single instance files: {} {:?}",
-        unique_files.len(),
-        unique_files.par_iter().map(|x| x.get_length()).sum::<u64>() / (display_divisor),
-        blocksize
-    );
-    println!(
-        "{} Shared instance files: {} {:?} ({} instances)",
-        shared_files.len(),
-        shared_files.par_iter().map(|x| x.get_length()).sum::<u64>() / (display_divisor),
+    let OutputContext {
+        fmt,
+        verbosity,
         blocksize,
-        shared_files
-            .par_iter()
-            .map(|x| x.get_paths().len() as u64)
-            .sum::<u64>()
-    );
+        warnings,
+        timings,
+        decorations,
+        ..
+    } = *context;
+    let display_divisor = blocksize.divisor();
+    let numbers = decorations.numbers;
+
+    /* Machine readable output holds nothing but the results, so scripts can parse it, and
+    JSON and HTML output carry their own summary. */
+    if !fmt.is_machine_readable() && !matches!(fmt, PrintFmt::Html) {
+        println!(
+            "{} Total files (with duplicates): {} {:?}",
+            numbers.format(
+                complete_files
+                    .par_iter()
+                    .map(|x| x.get_paths().len() as u64)
+                    .sum::<u64>()
+            ),
+            numbers.format(
+                complete_files
+                    .par_iter()
+                    .map(|x| (x.get_paths().len() as u64) * x.get_length())
+                    .sum::<u64>()
+                    / (display_divisor)
+            ),
+            blocksize
+        );
+        println!(
+            "{} Total files (without duplicates): {} {:?}",
+            numbers.format(complete_files.len() as u64),
+            numbers.format(
+                complete_files
+                    .par_iter()
+                    .map(|x| x.get_length())
+                    .sum::<u64>()
+                    / (display_divisor)
+            ),
+            blocksize
+        );
+        println!(
+            "{} Single instance files: {} {:?}",
+            numbers.format(unique_files.len() as u64),
+            numbers.format(
+                unique_files.par_iter().map(|x| x.get_length()).sum::<u64>() / (display_divisor)
+            ),
+            blocksize
+        );
+        println!(
+            "{} Shared instance files: {} {:?} ({} instances)",
+            numbers.format(shared_files.len() as u64),
+            numbers.format(
+                shared_files.par_iter().map(|x| x.get_length()).sum::<u64>() / (display_divisor)
+            ),
+            blocksize,
+            numbers.format(
+                shared_files
+                    .par_iter()
+                    .map(|x| x.get_paths().len() as u64)
+                    .sum::<u64>()
+            )
+        );
+        for (copies, groups, wasted) in ddh::waste::copies_histogram(shared_files.iter().copied()) {
+            println!(
+                "{} Shared instance files with {} instances: {} {:?} wasted",
+                numbers.format(groups),
+                copies,
+                numbers.format(wasted / display_divisor),
+                blocksize
+            );
+        }
+        if matches!(fmt, PrintFmt::Standard) {
+            let waste = ddh::waste::waste_by_directory(shared_files, decorations.copy_patterns);
+            let mut trees: Vec<(PathBuf, u64)> =
+                ddh::waste::rolled_up(&waste).into_iter().collect();
+            trees.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+            if !trees.is_empty() {
+                println!("Directories wasting the most space (with their subdirectories):");
+            }
+            for (dir, bytes) in trees.iter().take(TOP_WASTE_DIRECTORIES) {
+                println!(
+                    "  {} {:?} {}",
+                    numbers.format(bytes / display_divisor),
+                    blocksize,
+                    dir.display()
+                );
+            }
+        }
+        if let Some(timings) = timings {
+            println!(
+                "Phase durations: discovery {}, hashing {}",
+                format_duration(timings.discovery),
+                format_duration(timings.hashing)
+            );
+        }
+        if decorations.roots.is_labelled() {
+            for name in decorations.roots.names() {
+                println!(
+                    "{} Shared instances under {}",
+                    numbers.format(
+                        shared_files
+                            .par_iter()
+                            .flat_map(|x| x.get_paths().par_iter())
+                            .filter(|path| decorations.roots.name_of(path) == Some(name))
+                            .count() as u64
+                    ),
+                    name
+                );
+            }
+            let mut placements: BTreeMap<String, usize> = BTreeMap::new();
+            shared_files
+                .iter()
+                .filter_map(|x| decorations.roots.placement(x))
+                .for_each(|placement| *placements.entry(placement).or_default() += 1);
+            for (placement, count) in placements.iter() {
+                println!(
+                    "{} Shared instance files {}",
+                    numbers.format(*count as u64),
+                    placement
+                );
+            }
+        }
+    }
+
+    if !matches!(verbosity, Verbosity::Quiet) {
+        for warning in warnings.iter() {
+            eprintln!("Warning: {}", warning);
+        }
+    }
 
     match (fmt, verbosity) {
         (_, Verbosity::Quiet) => {}
         (PrintFmt::Standard, Verbosity::Duplicates) => {
             println!("Shared instance files and instance locations");
-            shared_files.iter().for_each(|x| {
-                println!(
-                    "instances of {} with file length {}:",
-                    x.get_candidate_name(),
-                    x.get_length()
-                );
-                x.get_paths()
-                    .par_iter()
-                    .for_each(|y| println!("\t{}", y.canonicalize().unwrap().to_str().unwrap()));
-            })
+            let _ = print_shared_groups(shared_files, decorations, &mut stdout().lock());
         }
         (PrintFmt::Standard, Verbosity::All) => {
+            let footprint: usize = complete_files
+                .par_iter()
+                .map(|x| x.memory_footprint())
+                .sum();
+            println!(
+                "{} entries held in memory, {} bytes per entry",
+                complete_files.len(),
+                footprint / complete_files.len().max(1)
+            );
             println!("Single instance files");
-            unique_files.par_iter().for_each(|x| {
-                println!(
-                    "{}",
-                    x.get_paths()
-                        .iter()
-                        .next()
-                        .unwrap()
-                        .canonicalize()
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                )
-            });
+            unique_files
+                .par_iter()
+                .for_each(|x| println!("{}", x.get_paths().iter().next().unwrap().display()));
             println!("Shared instance files and instance locations");
-            shared_files.iter().for_each(|x| {
-                println!(
-                    "instances of {} with file length {}:",
-                    x.get_candidate_name(),
-                    x.get_length()
-                );
-                x.get_paths()
-                    .par_iter()
-                    .for_each(|y| println!("\t{}", y.canonicalize().unwrap().to_str().unwrap()));
-            });
+            let _ = print_shared_groups(shared_files, decorations, &mut stdout().lock());
             error_paths.iter().for_each(|x| {
                 println!(
                     "Could not process {:#?} due to error {:#?}",
@@ -187,17 +3063,75 @@ fn process_full_output(
                 );
             })
         }
-        (PrintFmt::Json, Verbosity::Duplicates) => {
-            println!(
-                "{}",
-                serde_json::to_string(shared_files).unwrap_or_else(|_| "".to_string())
-            );
+        (PrintFmt::Fdupes, _) => {
+            let mut sink = FdupesSink::new(stdout().lock());
+            let _ = ddh::output::write_results(&mut sink, shared_files, warnings, &[]);
         }
-        (PrintFmt::Json, Verbosity::All) => {
-            println!(
-                "{}",
-                serde_json::to_string(complete_files).unwrap_or_else(|_| "".to_string())
-            );
+        (PrintFmt::Dirstat, _) => {
+            let mut sink = DirstatSink::new(stdout().lock(), decorations.copy_patterns);
+            let _ = ddh::output::write_results(&mut sink, shared_files, warnings, &[]);
+        }
+        (PrintFmt::Folded, _) => {
+            let mut sink = FoldedSink::new(stdout().lock(), decorations.copy_patterns);
+            let _ = ddh::output::write_results(&mut sink, shared_files, warnings, &[]);
+        }
+        (PrintFmt::Print0, _) => {
+            let mut sink = FdupesSink::new(stdout().lock()).null_terminated();
+            let _ = ddh::output::write_results(&mut sink, shared_files, warnings, &[]);
+        }
+        (PrintFmt::Edges, _) => {
+            let mut sink = EdgeListSink::new(stdout().lock());
+            let _ = ddh::output::write_results(&mut sink, shared_files, warnings, &[]);
+        }
+        (PrintFmt::Graphml, _) => {
+            let mut sink = GraphmlSink::new(stdout().lock());
+            let _ = ddh::output::write_results(&mut sink, shared_files, warnings, &[]);
+        }
+        (PrintFmt::Html, _) => {
+            let mut sink = HtmlSink::new(stdout().lock())
+                .with_copy_patterns(decorations.copy_patterns.clone());
+            let _ = ddh::output::write_results(&mut sink, shared_files, warnings, error_paths);
+        }
+        (PrintFmt::Json, verbosity) => {
+            let all: Vec<&Fileinfo>;
+            let groups = match verbosity {
+                Verbosity::All => {
+                    all = complete_files.iter().collect();
+                    &all
+                }
+                _ => shared_files,
+            };
+            let mut sink = JsonSink::new(stdout().lock()).with_summary(context.summary.clone());
+            let _ = ddh::output::write_results(&mut sink, groups, warnings, &[]);
+            println!();
+        }
+        (PrintFmt::Jsonl, _) if streamed => {
+            let mut sink = JsonLinesSink::new(stdout().lock());
+            let _ = ddh::output::write_results(&mut sink, &[], warnings, &[]);
+        }
+        (PrintFmt::Jsonl, verbosity) => {
+            let all: Vec<&Fileinfo>;
+            let groups = match verbosity {
+                Verbosity::All => {
+                    all = complete_files.iter().collect();
+                    &all
+                }
+                _ => shared_files,
+            };
+            let mut sink = JsonLinesSink::new(stdout().lock());
+            let _ = ddh::output::write_results(&mut sink, groups, warnings, &[]);
+        }
+        (PrintFmt::Csv, verbosity) => {
+            let all: Vec<&Fileinfo>;
+            let groups = match verbosity {
+                Verbosity::All => {
+                    all = complete_files.iter().collect();
+                    &all
+                }
+                _ => shared_files,
+            };
+            let mut sink = CsvSink::new(stdout().lock());
+            let _ = ddh::output::write_results(&mut sink, groups, warnings, &[]);
         }
     }
 
@@ -242,56 +3176,327 @@ fn process_full_output(
                 },
             }
             write_results_to_file(
-                fmt,
+                destination_string,
                 shared_files,
                 unique_files,
                 complete_files,
-                destination_string,
+                context,
             );
         }
     }
 }
 
+fn print_shared_groups(
+    shared_files: &[&Fileinfo],
+    decorations: &GroupDecorations,
+    output: &mut dyn Write,
+) -> std::io::Result<()> {
+    let (listed_groups, collapsed) = decorations.collapse_small(shared_files);
+    for x in listed_groups.iter() {
+        writeln!(
+            output,
+            "instances of {} with file length {} (group {}){}:",
+            x.get_candidate_name(),
+            x.get_length(),
+            x.get_group_id().unwrap_or_default(),
+            decorations.placement_marker(x)
+        )?;
+        for note in decorations.annotations.notes(x) {
+            writeln!(output, "\t# {}", note)?;
+        }
+        let (listed, omitted) = decorations.listed_paths(x);
+        for y in listed.iter() {
+            let marker = decorations.instance_marker(y);
+            writeln!(output, "\t{}{}", y.display(), marker)?;
+        }
+        if omitted > 0 {
+            writeln!(
+                output,
+                "\t... and {} more",
+                decorations.numbers.format(omitted as u64)
+            )?;
+        }
+    }
+    if let Some(summary) = collapsed {
+        writeln!(output, "{}", summary)?;
+    }
+    Ok(())
+}
+
+/// Writes the results to `file`. If writing fails part way, e.g. when the disk fills up, the
+/// groups already written stay in the file followed by a line marking it as partial, and ddh
+/// exits with status 1 without acting on the results.
 fn write_results_to_file(
-    fmt: PrintFmt,
+    file: &str,
     shared_files: &[&Fileinfo],
     unique_files: &[&Fileinfo],
     complete_files: &[Fileinfo],
-    file: &str,
+    context: &OutputContext,
 ) {
-    let mut output = fs::File::create(file).expect("Error opening output file for writing");
-    match fmt {
-        PrintFmt::Standard => {
-            output.write_fmt(format_args!("Duplicates:\n")).unwrap();
-            for file in shared_files.iter() {
-                let title = file.get_candidate_name();
-                output.write_fmt(format_args!("{}\n", title)).unwrap();
-                for entry in file.get_paths().iter() {
-                    output
-                        .write_fmt(format_args!("\t{}\n", entry.as_path().to_str().unwrap()))
-                        .unwrap();
-                }
-            }
-            output.write_fmt(format_args!("Singletons:\n")).unwrap();
-            for file in unique_files.iter() {
-                let title = file.get_candidate_name();
-                output.write_fmt(format_args!("{}\n", title)).unwrap();
-                for entry in file.get_paths().iter() {
-                    output
-                        .write_fmt(format_args!("\t{}\n", entry.as_path().to_str().unwrap()))
-                        .unwrap();
-                }
+    let mut output = match fs::File::create(file) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Error opening {} for writing. Err: {}", file, e);
+            std::process::exit(1);
+        }
+    };
+    let written = write_results(
+        &mut output,
+        shared_files,
+        unique_files,
+        complete_files,
+        context,
+    );
+    if let Err(e) = written {
+        eprintln!("Error writing results to {}. Err: {}", file, e);
+        let marked = writeln!(
+            output,
+            "\n--- PARTIAL RESULTS: writing stopped early. Err: {}",
+            e
+        );
+        match marked {
+            Ok(()) => eprintln!("Results written so far were kept in {}", file),
+            Err(_) => eprintln!(
+                "Results written so far were kept in {}, but it could not be marked as partial",
+                file
+            ),
+        }
+        std::process::exit(1);
+    }
+    println!("{:#?} results written to {}", context.fmt, file);
+}
+
+fn write_results(
+    output: &mut impl Write,
+    shared_files: &[&Fileinfo],
+    unique_files: &[&Fileinfo],
+    complete_files: &[Fileinfo],
+    context: &OutputContext,
+) -> std::io::Result<()> {
+    let OutputContext {
+        fmt,
+        warnings,
+        ref summary,
+        decorations,
+        ..
+    } = *context;
+    let mut sink: Box<dyn OutputSink + '_> = match fmt {
+        PrintFmt::Standard => Box::new(TextSink::new(output, decorations)),
+        PrintFmt::Folded => Box::new(FoldedSink::new(output, decorations.copy_patterns)),
+        PrintFmt::Dirstat => Box::new(DirstatSink::new(output, decorations.copy_patterns)),
+        PrintFmt::Json => Box::new(JsonSink::new(output).with_summary(summary.clone())),
+        PrintFmt::Jsonl => Box::new(JsonLinesSink::new(output)),
+        PrintFmt::Csv => Box::new(CsvSink::new(output)),
+        PrintFmt::Fdupes => Box::new(FdupesSink::new(output)),
+        PrintFmt::Print0 => Box::new(FdupesSink::new(output).null_terminated()),
+        PrintFmt::Edges => Box::new(EdgeListSink::new(output)),
+        PrintFmt::Graphml => Box::new(GraphmlSink::new(output)),
+        PrintFmt::Html => {
+            Box::new(HtmlSink::new(output).with_copy_patterns(decorations.copy_patterns.clone()))
+        }
+    };
+    let groups: Vec<&Fileinfo> = match fmt {
+        PrintFmt::Json | PrintFmt::Jsonl | PrintFmt::Csv => complete_files.iter().collect(),
+        _ => shared_files.iter().chain(unique_files).copied().collect(),
+    };
+    ddh::output::write_results(sink.as_mut(), &groups, warnings, &[])
+}
+
+/// Writes duplicate groups and then single files as indented path lists.
+struct TextSink<'a, W: Write> {
+    output: W,
+    decorations: &'a GroupDecorations<'a>,
+    duplicates_started: bool,
+    singletons_started: bool,
+    warnings_started: bool,
+    /// Groups, instances and wasted bytes of the groups too small to list.
+    collapsed: (u64, u64, u64),
+}
+
+impl<'a, W: Write> TextSink<'a, W> {
+    fn new(output: W, decorations: &'a GroupDecorations<'a>) -> Self {
+        TextSink {
+            output,
+            decorations,
+            duplicates_started: false,
+            singletons_started: false,
+            warnings_started: false,
+            collapsed: (0, 0, 0),
+        }
+    }
+
+    /// Ends the duplicates section, writing its header if no group was written.
+    fn end_duplicates(&mut self) -> std::io::Result<()> {
+        if !self.duplicates_started {
+            self.output.write_all(b"Duplicates:\n")?;
+            self.duplicates_started = true;
+        }
+        if self.collapsed.0 > 0 {
+            let (groups, instances, wasted) = std::mem::take(&mut self.collapsed);
+            let summary = self
+                .decorations
+                .collapsed_summary(groups, instances, wasted);
+            self.output.write_fmt(format_args!("{}\n", summary))?;
+        }
+        Ok(())
+    }
+
+    /// Ends the duplicates section and starts the singletons section, once.
+    fn start_singletons(&mut self) -> std::io::Result<()> {
+        if !self.singletons_started {
+            self.end_duplicates()?;
+            self.output.write_all(b"Singletons:\n")?;
+            self.singletons_started = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputSink for TextSink<'_, W> {
+    fn write_group(&mut self, group: &Fileinfo) -> std::io::Result<()> {
+        let decorations = self.decorations;
+        if group.get_paths().len() < 2 {
+            self.start_singletons()?;
+            self.output
+                .write_fmt(format_args!("{}\n", group.get_candidate_name()))?;
+            for entry in group.get_paths().iter() {
+                self.output
+                    .write_fmt(format_args!("\t{}\n", entry.to_str().unwrap()))?;
             }
+            return Ok(());
+        }
+        if !self.duplicates_started {
+            self.output.write_all(b"Duplicates:\n")?;
+            self.duplicates_started = true;
+        }
+        if decorations
+            .collapse_below
+            .is_some_and(|threshold| group.get_length() < threshold)
+        {
+            self.collapsed.0 += 1;
+            self.collapsed.1 += group.get_paths().len() as u64;
+            self.collapsed.2 += ddh::waste::duplicate_bytes(std::iter::once(group));
+            return Ok(());
+        }
+        self.output.write_fmt(format_args!(
+            "{}{}\n",
+            group.get_candidate_name(),
+            decorations.placement_marker(group)
+        ))?;
+        for note in decorations.annotations.notes(group) {
+            self.output.write_fmt(format_args!("\t# {}\n", note))?;
         }
-        PrintFmt::Json => {
-            output
-                .write_fmt(format_args!(
-                    "{}",
-                    serde_json::to_string(complete_files)
-                        .unwrap_or_else(|_| "Error deserializing".to_string())
-                ))
-                .unwrap();
+        let (listed, omitted) = decorations.listed_paths(group);
+        for entry in listed.iter() {
+            self.output.write_fmt(format_args!(
+                "\t{}{}\n",
+                entry.to_str().unwrap(),
+                decorations.instance_marker(entry)
+            ))?;
+        }
+        if omitted > 0 {
+            self.output.write_fmt(format_args!(
+                "\t... and {} more\n",
+                decorations.numbers.format(omitted as u64)
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn write_warning(&mut self, warning: &Warning) -> std::io::Result<()> {
+        self.start_singletons()?;
+        if !self.warnings_started {
+            self.output.write_all(b"Warnings:\n")?;
+            self.warnings_started = true;
+        }
+        self.output.write_fmt(format_args!("\t{}\n", warning))
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.start_singletons()?;
+        self.output.flush()
+    }
+}
+
+/// Sums the wasted bytes of each directory and writes them, with the bytes wasted in the tree
+/// below each, as a table sorted by the latter once finished.
+struct DirstatSink<'a, W: Write> {
+    output: W,
+    copy_patterns: &'a CopyPatterns,
+    waste: BTreeMap<PathBuf, u64>,
+}
+
+impl<'a, W: Write> DirstatSink<'a, W> {
+    fn new(output: W, copy_patterns: &'a CopyPatterns) -> Self {
+        DirstatSink {
+            output,
+            copy_patterns,
+            waste: BTreeMap::new(),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for DirstatSink<'_, W> {
+    fn write_group(&mut self, group: &Fileinfo) -> std::io::Result<()> {
+        let waste = ddh::waste::waste_by_directory(&[group], self.copy_patterns);
+        for (dir, bytes) in waste {
+            *self.waste.entry(dir).or_default() += bytes;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        let mut trees: Vec<(PathBuf, u64)> =
+            ddh::waste::rolled_up(&self.waste).into_iter().collect();
+        trees.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+        writeln!(
+            self.output,
+            "{:>10} {:>10}  directory",
+            "wasted", "directly"
+        )?;
+        for (dir, bytes) in trees {
+            writeln!(
+                self.output,
+                "{:>10} {:>10}  {}",
+                format_size(bytes),
+                format_size(self.waste.get(&dir).copied().unwrap_or_default()),
+                dir.display()
+            )?;
+        }
+        self.output.flush()
+    }
+}
+
+/// Sums the wasted bytes of each directory and writes them as folded stacks once finished.
+struct FoldedSink<'a, W: Write> {
+    output: W,
+    copy_patterns: &'a CopyPatterns,
+    waste: BTreeMap<PathBuf, u64>,
+}
+
+impl<'a, W: Write> FoldedSink<'a, W> {
+    fn new(output: W, copy_patterns: &'a CopyPatterns) -> Self {
+        FoldedSink {
+            output,
+            copy_patterns,
+            waste: BTreeMap::new(),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for FoldedSink<'_, W> {
+    fn write_group(&mut self, group: &Fileinfo) -> std::io::Result<()> {
+        let waste = ddh::waste::waste_by_directory(&[group], self.copy_patterns);
+        for (dir, bytes) in waste {
+            *self.waste.entry(dir).or_default() += bytes;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        for line in ddh::waste::folded(&self.waste) {
+            self.output.write_fmt(format_args!("{}\n", line))?;
         }
+        self.output.flush()
     }
-    println!("{:#?} results written to {}", fmt, file);
 }
@@ -0,0 +1,79 @@
+//! Names which differ only by case or Unicode normalization.
+//!
+//! Such names live happily side by side on most Linux filesystems, but collide when the tree is
+//! synced to a case-insensitive filesystem (NTFS, APFS and HFS+ by default) or through tools
+//! which normalize names, such as macOS writing `é` decomposed as `e` and a combining accent.
+//! One of the files is then silently overwritten or renamed.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// Entries of one directory whose names fold to the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameCollision {
+    pub dir: PathBuf,
+    /// Colliding names in sorted order.
+    pub names: Vec<OsString>,
+}
+
+/// Folds `name` to the form two names share when they only differ by case or normalization:
+/// lower case in Unicode normalization form C.
+///
+/// # Examples
+/// ```
+/// use ddh::names::fold_name;
+///
+/// assert_eq!(fold_name("README.md"), fold_name("readme.MD"));
+/// assert_eq!(fold_name("caf\u{e9}.txt"), fold_name("cafe\u{301}.txt"));
+/// assert_ne!(fold_name("cafe.txt"), fold_name("caf\u{e9}.txt"));
+/// ```
+pub fn fold_name(name: &str) -> String {
+    name.to_lowercase().nfc().collect()
+}
+
+/// Finds colliding names among `paths` and the directories holding them, sorted by directory.
+///
+/// # Examples
+/// ```
+/// use std::path::Path;
+///
+/// let paths = ["/music/Album/track.flac", "/music/album/track.flac", "/music/album/TRACK.flac"];
+/// let collisions = ddh::names::name_collisions(paths.iter().map(Path::new));
+/// assert_eq!(collisions.len(), 2);
+/// assert_eq!(collisions[0].dir, Path::new("/music"));
+/// assert_eq!(collisions[0].names, vec!["Album", "album"]);
+/// assert_eq!(collisions[1].dir, Path::new("/music/album"));
+/// assert_eq!(collisions[1].names, vec!["TRACK.flac", "track.flac"]);
+/// ```
+pub fn name_collisions<'a, I>(paths: I) -> Vec<NameCollision>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    let mut seen: HashSet<&Path> = HashSet::new();
+    let mut folded: BTreeMap<(&Path, String), BTreeSet<OsString>> = BTreeMap::new();
+    for path in paths {
+        for entry in path.ancestors() {
+            if !seen.insert(entry) {
+                break;
+            }
+            /* Names which aren't valid UTF-8 have no case to fold. */
+            let name = entry.file_name().and_then(|name| name.to_str());
+            if let (Some(dir), Some(name)) = (entry.parent(), name) {
+                folded
+                    .entry((dir, fold_name(name)))
+                    .or_default()
+                    .insert(name.into());
+            }
+        }
+    }
+    folded
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|((dir, _), names)| NameCollision {
+            dir: dir.to_path_buf(),
+            names: names.into_iter().collect(),
+        })
+        .collect()
+}
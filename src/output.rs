@@ -0,0 +1,880 @@
+//! Destinations of scan results.
+//!
+//! An `OutputSink` receives the groups of a scan one at a time, then its warnings and the paths
+//! which couldn't be read, and is finished once everything was written. The `ddh` binary writes its formats
+//! through sinks, and library users can pass their own to `write_results` to send results to a
+//! database or another report format.
+
+use crate::copies::CopyPatterns;
+use crate::fileinfo::Fileinfo;
+use crate::format::format_size;
+use crate::warnings::Warning;
+use crate::waste::waste_by_directory;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Writer of scan results in some format.
+pub trait OutputSink {
+    /// Writes a duplicate group, or a single file if the group has one path.
+    fn write_group(&mut self, group: &Fileinfo) -> io::Result<()>;
+    /// Writes a warning of the scan. Formats without a place for warnings ignore them.
+    fn write_warning(&mut self, _warning: &Warning) -> io::Result<()> {
+        Ok(())
+    }
+    /// Writes a path which was left out of the results because of `error`. Formats without a
+    /// place for errors ignore them.
+    fn write_error(&mut self, _path: &Path, _error: &io::Error) -> io::Result<()> {
+        Ok(())
+    }
+    /// Completes the output once every group and error was written.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Version of the layout of JSON results written with a summary, in `schema_version`. It only
+/// changes when fields are renamed, removed or change their meaning, and every change is listed
+/// in the README; added fields keep the version.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Statistics of a scan, written ahead of its groups by `JsonSink::with_summary`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonSummary {
+    /// Files scanned, counting every path of a group.
+    pub total_files: u64,
+    /// Bytes taken up by every file scanned.
+    pub total_bytes: u64,
+    /// Groups of files with equal contents.
+    pub duplicate_groups: u64,
+    /// Instances of duplicate groups beyond the first of each.
+    pub duplicates: u64,
+    /// Bytes taken up by those instances.
+    pub wasted_bytes: u64,
+    /// How long the scan took, `None` for results which weren't scanned just now.
+    pub scan_duration_ms: Option<u64>,
+    /// Paths which couldn't be read.
+    pub errors: u64,
+    /// Name of the algorithm the full hashes were computed with, as given by
+    /// `ContentHasher::name`. Results hashed with different algorithms can't be compared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_algorithm: Option<String>,
+}
+
+/// Writes groups as a JSON array, the same as serializing the whole list at once, or with a
+/// summary as an object holding `JSON_SCHEMA_VERSION`, the summary, the array and the
+/// `warnings` of the scan, each with its `path` and `kind`. A bare array has no place for
+/// warnings.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::{JsonSink, JsonSummary};
+/// use ddh::warnings::{Warning, WarningKind};
+/// use std::path::PathBuf;
+///
+/// let summary = JsonSummary { total_files: 0, scan_duration_ms: Some(5), ..Default::default() };
+/// let warnings = [Warning { path: PathBuf::from("/a/fifo"), kind: WarningKind::SpecialFile }];
+/// let mut json = Vec::new();
+/// let groups: Vec<&Fileinfo> = Vec::new();
+/// let mut sink = JsonSink::new(&mut json).with_summary(summary);
+/// ddh::output::write_results(&mut sink, &groups, &warnings, &[]).unwrap();
+/// assert_eq!(
+///     String::from_utf8(json).unwrap(),
+///     "{\"schema_version\":1,\"summary\":{\"total_files\":0,\"total_bytes\":0,\
+///      \"duplicate_groups\":0,\"duplicates\":0,\"wasted_bytes\":0,\
+///      \"scan_duration_ms\":5,\"errors\":0},\"groups\":[],\
+///      \"warnings\":[{\"path\":\"/a/fifo\",\"kind\":\"special_file\"}]}"
+/// );
+/// ```
+#[derive(Debug)]
+pub struct JsonSink<W: Write> {
+    output: W,
+    summary: Option<JsonSummary>,
+    written: usize,
+    warnings: Vec<Warning>,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(output: W) -> Self {
+        JsonSink {
+            output,
+            summary: None,
+            written: 0,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Writes `summary` ahead of the groups, wrapping both in an object.
+    pub fn with_summary(mut self, summary: JsonSummary) -> Self {
+        self.summary = Some(summary);
+        self
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if let Some(summary) = self.summary.as_ref() {
+            write!(
+                self.output,
+                "{{\"schema_version\":{},\"summary\":",
+                JSON_SCHEMA_VERSION
+            )?;
+            serde_json::to_writer(&mut self.output, summary)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.output.write_all(b",\"groups\":")?;
+        }
+        self.output.write_all(b"[")
+    }
+}
+
+impl<W: Write> OutputSink for JsonSink<W> {
+    fn write_group(&mut self, group: &Fileinfo) -> io::Result<()> {
+        if self.written == 0 {
+            self.start()?;
+        } else {
+            self.output.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.output, group)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn write_warning(&mut self, warning: &Warning) -> io::Result<()> {
+        if self.summary.is_some() {
+            self.warnings.push(warning.clone());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.written == 0 {
+            self.start()?;
+        }
+        self.output.write_all(b"]")?;
+        if self.summary.is_some() {
+            self.output.write_all(b",\"warnings\":")?;
+            serde_json::to_writer(&mut self.output, &self.warnings)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.output.write_all(b"}")?;
+        }
+        self.output.flush()
+    }
+}
+
+/// JSON results as written by `JsonSink` with a summary.
+#[derive(Deserialize)]
+struct JsonReport {
+    schema_version: Option<u32>,
+    summary: Option<JsonSummary>,
+    groups: Vec<Fileinfo>,
+}
+
+/// Reads the groups of JSON results written by `JsonSink`, with or without a summary. Results
+/// of a later schema version than `JSON_SCHEMA_VERSION` are refused, as their fields may mean
+/// something else.
+pub fn read_json_results<R: Read>(reader: R) -> io::Result<Vec<Fileinfo>> {
+    read_json_report(reader).map(|(_, groups)| groups)
+}
+
+/// Reads JSON results like `read_json_results` along with their summary, `None` for results
+/// written without one.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::{JsonSink, JsonSummary};
+/// use std::path::PathBuf;
+///
+/// let summary = JsonSummary { hash_algorithm: Some("xxh3".to_string()), ..Default::default() };
+/// let file = Fileinfo::from_existing_hash(u128::MAX, None, 10, PathBuf::from("/a/x"));
+/// let mut json = Vec::new();
+/// ddh::output::write_results(&mut JsonSink::new(&mut json).with_summary(summary), &[&file], &[], &[])
+///     .unwrap();
+/// let (summary, groups) = ddh::output::read_json_report(json.as_slice()).unwrap();
+/// assert_eq!(summary.unwrap().hash_algorithm.as_deref(), Some("xxh3"));
+/// assert_eq!(groups[0].get_full_hash(), Some(u128::MAX));
+/// ```
+pub fn read_json_report<R: Read>(
+    mut reader: R,
+) -> io::Result<(Option<JsonSummary>, Vec<Fileinfo>)> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut json = Vec::new();
+    reader.read_to_end(&mut json)?;
+    /* Untagged enums buffer their input in a form which can't hold the 128 bit hashes, so the
+    layout is told apart by its first character instead. */
+    if json.iter().find(|c| !c.is_ascii_whitespace()) != Some(&b'{') {
+        return Ok((None, serde_json::from_slice(&json).map_err(invalid)?));
+    }
+    let report: JsonReport = serde_json::from_slice(&json).map_err(invalid)?;
+    match report.schema_version {
+        Some(version) if version > JSON_SCHEMA_VERSION => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "results of schema version {} are newer than this ddh, which reads up to {}",
+                version, JSON_SCHEMA_VERSION
+            ),
+        )),
+        _ => Ok((report.summary, report.groups)),
+    }
+}
+
+/// Writes each group as a JSON object on a line of its own, so that consumers such as `jq` can
+/// process groups one at a time while more are written. Warnings follow the groups on lines of
+/// their own, as an object holding a `warning` with its `path` and `kind`.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::JsonLinesSink;
+/// use ddh::warnings::{Warning, WarningKind};
+/// use std::path::PathBuf;
+///
+/// let groups = ddh::consolidate(vec![Fileinfo::from_existing_hash(1, None, 10, "/a/x".into())]);
+/// let groups: Vec<&Fileinfo> = groups.iter().collect();
+/// let warnings = [Warning { path: PathBuf::from("/a/fifo"), kind: WarningKind::SpecialFile }];
+/// let mut out = Vec::new();
+/// ddh::output::write_results(&mut JsonLinesSink::new(&mut out), &groups, &warnings, &[]).unwrap();
+/// let out = String::from_utf8(out).unwrap();
+/// let lines: Vec<&str> = out.lines().collect();
+/// assert_eq!(lines.len(), 2);
+/// assert!(lines[0].starts_with("{\"group_id\""));
+/// assert_eq!(lines[1], "{\"warning\":{\"path\":\"/a/fifo\",\"kind\":\"special_file\"}}");
+/// ```
+#[derive(Debug)]
+pub struct JsonLinesSink<W: Write> {
+    output: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(output: W) -> Self {
+        JsonLinesSink { output }
+    }
+}
+
+impl<W: Write> OutputSink for JsonLinesSink<W> {
+    fn write_group(&mut self, group: &Fileinfo) -> io::Result<()> {
+        serde_json::to_writer(&mut self.output, group)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.output.write_all(b"\n")?;
+        self.output.flush()
+    }
+
+    fn write_warning(&mut self, warning: &Warning) -> io::Result<()> {
+        self.output.write_all(b"{\"warning\":")?;
+        serde_json::to_writer(&mut self.output, warning)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.output.write_all(b"}\n")?;
+        self.output.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Writes one CSV row per path with the id of its group, the hash and length of its contents,
+/// the path and the number of paths in the group. Group ids are those of
+/// `Fileinfo::get_group_id`, as shown in other reports and taken by `ddh ack` and `ddh tag`;
+/// files without a full hash have empty id and hash columns. Warnings get rows of their own,
+/// holding only the path and the kind of warning in the last column. Paths which a spreadsheet
+/// would take for a formula are prefixed with `'`.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::{CsvSink, OutputSink};
+/// use ddh::warnings::{Warning, WarningKind};
+/// use std::path::PathBuf;
+///
+/// let groups = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(0xab, None, 10, PathBuf::from("/a/x")),
+///     Fileinfo::from_existing_hash(0xab, None, 10, PathBuf::from("/b/x, \"final\"")),
+///     Fileinfo::from_existing_hash(0xcd, None, 10, PathBuf::from("=cmd()")),
+/// ]);
+/// let groups: Vec<&Fileinfo> = groups.iter().collect();
+/// let warnings = [Warning { path: PathBuf::from("/a/fifo"), kind: WarningKind::SpecialFile }];
+/// let mut csv = Vec::new();
+/// ddh::output::write_results(&mut CsvSink::new(&mut csv), &groups, &warnings, &[]).unwrap();
+/// assert_eq!(
+///     String::from_utf8(csv).unwrap(),
+///     "group_id,hash,length,path,duplicate_count,warning\n\
+///      000000000000000000000000000000ab,000000000000000000000000000000ab,10,/a/x,2,\n\
+///      000000000000000000000000000000ab,000000000000000000000000000000ab,10,\"/b/x, \"\"final\"\"\",2,\n\
+///      000000000000000000000000000000cd,000000000000000000000000000000cd,10,'=cmd(),1,\n\
+///      ,,,/a/fifo,,special_file\n"
+/// );
+/// ```
+#[derive(Debug)]
+pub struct CsvSink<W: Write> {
+    output: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(output: W) -> Self {
+        CsvSink {
+            output,
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.output
+                .write_all(b"group_id,hash,length,path,duplicate_count,warning\n")?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn write_group(&mut self, group: &Fileinfo) -> io::Result<()> {
+        self.write_header()?;
+        let group_id = group.get_group_id().unwrap_or_default();
+        let hash = group
+            .get_full_hash()
+            .map(|hash| format!("{:032x}", hash))
+            .unwrap_or_default();
+        let count = group.get_paths().len();
+        for path in group.get_paths().iter() {
+            writeln!(
+                self.output,
+                "{},{},{},{},{},",
+                group_id,
+                hash,
+                group.get_length(),
+                csv_field(&path.to_string_lossy()),
+                count
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_warning(&mut self, warning: &Warning) -> io::Result<()> {
+        self.write_header()?;
+        writeln!(
+            self.output,
+            ",,,{},,{}",
+            csv_field(&warning.path.to_string_lossy()),
+            warning.kind.name()
+        )
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.output.flush()
+    }
+}
+
+/// Quotes `field` if it holds a separator, quote or line break, doubling its quotes. Fields
+/// starting like a formula are prefixed with `'` first, so spreadsheets show them as text
+/// rather than evaluating them.
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    let field: std::borrow::Cow<'_, str> = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", field).into()
+    } else {
+        field.into()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field
+    }
+}
+
+/// Writes duplicate groups the way fdupes lists them: the paths of each group on their own
+/// lines, with a blank line after each group. Single files are left out, so scripts parsing
+/// fdupes output can read it unchanged. With `null_terminated`, paths and groups end in NUL
+/// bytes instead of line breaks, as with `jdupes -0`, so that paths holding line breaks survive
+/// `xargs -0`.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::FdupesSink;
+/// use std::path::PathBuf;
+///
+/// let groups = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/a/x")),
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/b/x")),
+///     Fileinfo::from_existing_hash(2, None, 10, PathBuf::from("/a/y")),
+/// ]);
+/// let mut groups: Vec<&Fileinfo> = groups.iter().collect();
+/// groups.sort_by_key(|group| group.get_full_hash());
+/// let mut out = Vec::new();
+/// ddh::output::write_results(&mut FdupesSink::new(&mut out), &groups, &[], &[]).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), "/a/x\n/b/x\n\n");
+///
+/// let mut out = Vec::new();
+/// let mut sink = FdupesSink::new(&mut out).null_terminated();
+/// ddh::output::write_results(&mut sink, &groups, &[], &[]).unwrap();
+/// assert_eq!(out, b"/a/x\0/b/x\0\0");
+/// ```
+#[derive(Debug)]
+pub struct FdupesSink<W: Write> {
+    output: W,
+    terminator: u8,
+}
+
+impl<W: Write> FdupesSink<W> {
+    pub fn new(output: W) -> Self {
+        FdupesSink {
+            output,
+            terminator: b'\n',
+        }
+    }
+
+    /// Ends paths and groups in NUL bytes instead of line breaks.
+    pub fn null_terminated(mut self) -> Self {
+        self.terminator = b'\0';
+        self
+    }
+}
+
+impl<W: Write> OutputSink for FdupesSink<W> {
+    fn write_group(&mut self, group: &Fileinfo) -> io::Result<()> {
+        if group.get_paths().len() < 2 {
+            return Ok(());
+        }
+        for path in group.get_paths().iter() {
+            self.output.write_all(path.as_os_str().as_encoded_bytes())?;
+            self.output.write_all(&[self.terminator])?;
+        }
+        self.output.write_all(&[self.terminator])
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Writes duplicate relationships as a weighted edge list in CSV, for graph tools such as
+/// networkx, igraph or Gephi: one `source,target,weight` row for every path of a group but the
+/// first, linking it to the first, with the length of the contents as weight. Linking each path
+/// to one other keeps the edges linear in the number of paths while leaving every group a
+/// connected component. Single files are left out.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::EdgeListSink;
+/// use std::path::PathBuf;
+///
+/// let groups = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/a/x")),
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/b/x")),
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/c/x")),
+///     Fileinfo::from_existing_hash(2, None, 10, PathBuf::from("/a/y")),
+/// ]);
+/// let groups: Vec<&Fileinfo> = groups.iter().collect();
+/// let mut out = Vec::new();
+/// ddh::output::write_results(&mut EdgeListSink::new(&mut out), &groups, &[], &[]).unwrap();
+/// let out = String::from_utf8(out).unwrap();
+/// assert!(out.starts_with("source,target,weight\n"));
+/// assert_eq!(out.lines().count(), 3);
+/// ```
+#[derive(Debug)]
+pub struct EdgeListSink<W: Write> {
+    output: W,
+    header_written: bool,
+}
+
+impl<W: Write> EdgeListSink<W> {
+    pub fn new(output: W) -> Self {
+        EdgeListSink {
+            output,
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.output.write_all(b"source,target,weight\n")?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputSink for EdgeListSink<W> {
+    fn write_group(&mut self, group: &Fileinfo) -> io::Result<()> {
+        self.write_header()?;
+        let mut paths = group.get_paths().iter();
+        let first = match paths.next() {
+            Some(first) => csv_field(&first.to_string_lossy()).into_owned(),
+            None => return Ok(()),
+        };
+        for path in paths {
+            writeln!(
+                self.output,
+                "{},{},{}",
+                first,
+                csv_field(&path.to_string_lossy()),
+                group.get_length()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.output.flush()
+    }
+}
+
+/// Writes duplicate relationships as an undirected GraphML graph: a node per path, with the
+/// path as `path`, and an edge from the first path of each group to every other one, with the
+/// length of the contents as `weight` and the group, numbered from one, as `group`. Edges are
+/// laid out as by `EdgeListSink`. Single files are left out.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::GraphmlSink;
+/// use std::path::PathBuf;
+///
+/// let groups = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/a/x")),
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/b/<x>")),
+/// ]);
+/// let groups: Vec<&Fileinfo> = groups.iter().collect();
+/// let mut out = Vec::new();
+/// ddh::output::write_results(&mut GraphmlSink::new(&mut out), &groups, &[], &[]).unwrap();
+/// let out = String::from_utf8(out).unwrap();
+/// assert!(out.contains("<data key=\"path\">/b/&lt;x&gt;</data>"));
+/// assert!(out.contains("<edge source=\"n0\" target=\"n1\">"));
+/// assert!(out.trim_end().ends_with("</graphml>"));
+/// ```
+#[derive(Debug)]
+pub struct GraphmlSink<W: Write> {
+    output: W,
+    nodes: u64,
+    groups: u64,
+    header_written: bool,
+}
+
+impl<W: Write> GraphmlSink<W> {
+    pub fn new(output: W) -> Self {
+        GraphmlSink {
+            output,
+            nodes: 0,
+            groups: 0,
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.output.write_all(
+                b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n\
+                <key id=\"path\" for=\"node\" attr.name=\"path\" attr.type=\"string\"/>\n\
+                <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"long\"/>\n\
+                <key id=\"group\" for=\"edge\" attr.name=\"group\" attr.type=\"long\"/>\n\
+                <graph id=\"duplicates\" edgedefault=\"undirected\">\n",
+            )?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> OutputSink for GraphmlSink<W> {
+    fn write_group(&mut self, group: &Fileinfo) -> io::Result<()> {
+        self.write_header()?;
+        if group.get_paths().len() < 2 {
+            return Ok(());
+        }
+        self.groups += 1;
+        let first = self.nodes;
+        for path in group.get_paths().iter() {
+            writeln!(
+                self.output,
+                "<node id=\"n{}\"><data key=\"path\">{}</data></node>",
+                self.nodes,
+                html_escape(&path.to_string_lossy())
+            )?;
+            self.nodes += 1;
+        }
+        for node in first + 1..self.nodes {
+            writeln!(
+                self.output,
+                "<edge source=\"n{}\" target=\"n{}\"><data key=\"weight\">{}</data>\
+                 <data key=\"group\">{}</data></edge>",
+                first,
+                node,
+                group.get_length(),
+                self.groups
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.output.write_all(b"</graph>\n</graphml>\n")?;
+        self.output.flush()
+    }
+}
+
+/// Styles of HTML reports.
+const HTML_STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:2em}\
+th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left;vertical-align:top}\
+th{background:#eee;cursor:pointer;user-select:none}\
+th[data-order=asc]::after{content:' \\25B2'}th[data-order=desc]::after{content:' \\25BC'}\
+td.number{text-align:right;white-space:nowrap}summary{cursor:pointer}\
+ul{margin:.3em 0;padding-left:1.2em}code{font-size:.9em}";
+
+/// Sorts the rows of a table by the column whose header was clicked, by the `data-value` of
+/// its cells where they have one.
+const HTML_SCRIPT: &str = "document.querySelectorAll('table.sortable th').forEach(function(th){\
+th.addEventListener('click',function(){\
+var body=th.closest('table').tBodies[0],column=th.cellIndex,ascending=th.dataset.order!=='asc';\
+th.closest('tr').querySelectorAll('th').forEach(function(other){delete other.dataset.order;});\
+th.dataset.order=ascending?'asc':'desc';\
+var key=function(row){var cell=row.cells[column];return cell.dataset.value||cell.textContent;};\
+Array.from(body.rows).sort(function(a,b){var x=key(a),y=key(b);\
+var order=isNaN(x)||isNaN(y)?x.localeCompare(y):x-y;return ascending?order:-order;})\
+.forEach(function(row){body.appendChild(row);});});});";
+
+/// Writes a standalone HTML report for people to read in a browser: a summary, a table of the
+/// duplicate groups with their paths folded away, and a table of the space wasted in each
+/// directory as counted by `waste::waste_by_directory`, followed by warnings and errors. Tables
+/// sort by any column when its header is clicked. Single files are left out.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::HtmlSink;
+/// use std::path::PathBuf;
+///
+/// let groups = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 2048, PathBuf::from("/a/report.pdf")),
+///     Fileinfo::from_existing_hash(1, None, 2048, PathBuf::from("/b/report <final>.pdf")),
+/// ]);
+/// let groups: Vec<&Fileinfo> = groups.iter().collect();
+/// let mut html = Vec::new();
+/// ddh::output::write_results(&mut HtmlSink::new(&mut html), &groups, &[], &[]).unwrap();
+/// let html = String::from_utf8(html).unwrap();
+/// assert!(html.starts_with("<!DOCTYPE html>"));
+/// assert!(html.contains("<li>/b/report &lt;final&gt;.pdf</li>"));
+/// assert!(html.contains("Duplicate groups: 1, wasted: 2.0 KiB"));
+/// ```
+#[derive(Debug)]
+pub struct HtmlSink<W: Write> {
+    output: W,
+    copy_patterns: CopyPatterns,
+    groups: Vec<(u64, Option<u128>, Vec<PathBuf>)>,
+    waste: BTreeMap<PathBuf, u64>,
+    warnings: Vec<String>,
+    errors: Vec<String>,
+}
+
+impl<W: Write> HtmlSink<W> {
+    pub fn new(output: W) -> Self {
+        HtmlSink {
+            output,
+            copy_patterns: CopyPatterns::default(),
+            groups: Vec::new(),
+            waste: BTreeMap::new(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Counts waste with `copy_patterns` deciding which instance of each group is kept.
+    pub fn with_copy_patterns(mut self, copy_patterns: CopyPatterns) -> Self {
+        self.copy_patterns = copy_patterns;
+        self
+    }
+
+    fn write_groups(&mut self) -> io::Result<()> {
+        writeln!(
+            self.output,
+            "<h2>Duplicate groups</h2>\n<table class=\"sortable\">\n<thead><tr><th>Size</th>\
+             <th>Copies</th><th>Wasted</th><th>Hash</th><th>Paths</th></tr></thead>\n<tbody>"
+        )?;
+        for (length, hash, paths) in self.groups.iter() {
+            let wasted = length * (paths.len() as u64 - 1);
+            write!(
+                self.output,
+                "<tr><td class=\"number\" data-value=\"{}\">{}</td><td class=\"number\">{}</td>\
+                 <td class=\"number\" data-value=\"{}\">{}</td><td><code>{}</code></td>\
+                 <td><details><summary>{} (and {} more)</summary><ul>",
+                length,
+                format_size(*length),
+                paths.len(),
+                wasted,
+                format_size(wasted),
+                hash.map(|hash| format!("{:032x}", hash))
+                    .unwrap_or_default(),
+                html_escape(&paths[0].to_string_lossy()),
+                paths.len() - 1
+            )?;
+            for path in paths.iter() {
+                write!(
+                    self.output,
+                    "<li>{}</li>",
+                    html_escape(&path.to_string_lossy())
+                )?;
+            }
+            writeln!(self.output, "</ul></details></td></tr>")?;
+        }
+        writeln!(self.output, "</tbody>\n</table>")
+    }
+
+    fn write_waste(&mut self) -> io::Result<()> {
+        writeln!(
+            self.output,
+            "<h2>Wasted space by directory</h2>\n<table class=\"sortable\">\n<thead><tr>\
+             <th>Directory</th><th>Wasted</th></tr></thead>\n<tbody>"
+        )?;
+        for (dir, bytes) in self.waste.iter() {
+            writeln!(
+                self.output,
+                "<tr><td>{}</td><td class=\"number\" data-value=\"{}\">{}</td></tr>",
+                html_escape(&dir.to_string_lossy()),
+                bytes,
+                format_size(*bytes)
+            )?;
+        }
+        writeln!(self.output, "</tbody>\n</table>")
+    }
+
+    fn write_list(&mut self, title: &str, items: &[String]) -> io::Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        writeln!(self.output, "<h2>{}</h2>\n<ul>", title)?;
+        for item in items.iter() {
+            writeln!(self.output, "<li>{}</li>", html_escape(item))?;
+        }
+        writeln!(self.output, "</ul>")
+    }
+}
+
+impl<W: Write> OutputSink for HtmlSink<W> {
+    fn write_group(&mut self, group: &Fileinfo) -> io::Result<()> {
+        if group.get_paths().len() < 2 {
+            return Ok(());
+        }
+        for (dir, bytes) in waste_by_directory(&[group], &self.copy_patterns) {
+            *self.waste.entry(dir).or_default() += bytes;
+        }
+        let mut paths: Vec<PathBuf> = group.get_paths().iter().map(|p| p.to_path_buf()).collect();
+        paths.sort();
+        self.groups
+            .push((group.get_length(), group.get_full_hash(), paths));
+        Ok(())
+    }
+
+    fn write_warning(&mut self, warning: &Warning) -> io::Result<()> {
+        self.warnings.push(warning.to_string());
+        Ok(())
+    }
+
+    fn write_error(&mut self, path: &Path, error: &io::Error) -> io::Result<()> {
+        self.errors.push(format!("{}: {}", path.display(), error));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        /* Largest waste first, as a report is read from the top. */
+        self.groups
+            .sort_by_key(|(length, _, paths)| std::cmp::Reverse(length * (paths.len() as u64 - 1)));
+        let wasted: u64 = self.waste.values().sum();
+        writeln!(
+            self.output,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Duplicate files report</title>\n<style>{}</style>\n</head>\n<body>\n\
+             <h1>Duplicate files report</h1>\n<p>Duplicate groups: {}, wasted: {}</p>",
+            HTML_STYLE,
+            self.groups.len(),
+            format_size(wasted)
+        )?;
+        self.write_groups()?;
+        self.write_waste()?;
+        let warnings = std::mem::take(&mut self.warnings);
+        self.write_list("Warnings", &warnings)?;
+        let errors = std::mem::take(&mut self.errors);
+        self.write_list("Errors", &errors)?;
+        writeln!(
+            self.output,
+            "<script>{}</script>\n</body>\n</html>",
+            HTML_SCRIPT
+        )?;
+        self.output.flush()
+    }
+}
+
+/// Escapes the characters of `text` which HTML or XML would read as markup.
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes `groups`, `warnings` and then `errors` to `sink` and finishes it, stopping at the
+/// first error.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::output::OutputSink;
+/// use std::path::PathBuf;
+///
+/// /// Counts duplicate instances instead of listing them.
+/// #[derive(Default)]
+/// struct Counter {
+///     instances: usize,
+///     finished: bool,
+/// }
+///
+/// impl OutputSink for Counter {
+///     fn write_group(&mut self, group: &Fileinfo) -> std::io::Result<()> {
+///         self.instances += group.get_paths().len();
+///         Ok(())
+///     }
+///     fn finish(&mut self) -> std::io::Result<()> {
+///         self.finished = true;
+///         Ok(())
+///     }
+/// }
+///
+/// let groups = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/a/x")),
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/b/x")),
+/// ]);
+/// let groups: Vec<&Fileinfo> = groups.iter().collect();
+/// let mut counter = Counter::default();
+/// ddh::output::write_results(&mut counter, &groups, &[], &[]).unwrap();
+/// assert_eq!((counter.instances, counter.finished), (2, true));
+/// ```
+pub fn write_results(
+    sink: &mut dyn OutputSink,
+    groups: &[&Fileinfo],
+    warnings: &[Warning],
+    errors: &[(PathBuf, io::Error)],
+) -> io::Result<()> {
+    for group in groups.iter() {
+        sink.write_group(group)?;
+    }
+    for warning in warnings.iter() {
+        sink.write_warning(warning)?;
+    }
+    for (path, error) in errors.iter() {
+        sink.write_error(path, error)?;
+    }
+    sink.finish()
+}
@@ -0,0 +1,96 @@
+//! Encoding of the paths of an agent index.
+//!
+//! Files found by a scan share long directory prefixes. A `PathTable` stores every directory
+//! once as a link to its parent plus its own name, and a `CompactPath` refers to its directory
+//! by index, so a path costs its file name and an index rather than a full copy of the prefix.
+//! The agent index is written in this form so that large indexes stay small on the wire, and
+//! the receiving side rebuilds full paths from it. Collections held in memory during a scan
+//! don't use the table: they hand out their paths by reference, so each keeps its own.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path};
+
+/// Index of a directory in a `PathTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DirId(u32);
+
+impl DirId {
+    /// Gets the position of the directory in `PathTable::dirs`.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A path stored as a directory in a `PathTable` and a file name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompactPath {
+    dir: Option<DirId>,
+    name: Box<OsStr>,
+}
+
+impl CompactPath {
+    /// Gets the final component of the path.
+    pub fn file_name(&self) -> &OsStr {
+        &self.name
+    }
+    /// Gets the directory holding the path, or `None` for a path without one.
+    pub fn dir(&self) -> Option<DirId> {
+        self.dir
+    }
+}
+
+/// Table of interned directory prefixes.
+#[derive(Debug, Default)]
+pub struct PathTable {
+    dirs: Vec<(Option<DirId>, Box<OsStr>)>,
+    /* Keyed by a hash of the name so that each name is only stored once, in `dirs`. */
+    lookup: HashMap<(Option<DirId>, u64), Vec<DirId>>,
+}
+
+impl PathTable {
+    /// Creates an empty table.
+    pub fn new() -> Self {
+        PathTable::default()
+    }
+    /// Stores the directories of `path` and returns its compact form.
+    pub fn intern(&mut self, path: &Path) -> CompactPath {
+        let mut components: Vec<Component> = path.components().collect();
+        let name = match components.pop() {
+            Some(last) => last.as_os_str().into(),
+            None => OsStr::new("").into(),
+        };
+        let PathTable { dirs, lookup } = self;
+        let mut dir = None;
+        for component in components {
+            let component = component.as_os_str();
+            let candidates = lookup.entry((dir, name_hash(component))).or_default();
+            let existing = candidates
+                .iter()
+                .find(|&&DirId(id)| &*dirs[id as usize].1 == component);
+            dir = Some(match existing {
+                Some(&id) => id,
+                None => {
+                    let id = DirId(dirs.len() as u32);
+                    candidates.push(id);
+                    dirs.push((dir, component.into()));
+                    id
+                }
+            });
+        }
+        CompactPath { dir, name }
+    }
+    /// Iterates over the stored directories as their parent and name, in the order they were
+    /// added. A parent always comes before its children.
+    pub fn dirs(&self) -> impl Iterator<Item = (Option<DirId>, &OsStr)> {
+        self.dirs.iter().map(|(parent, name)| (*parent, &**name))
+    }
+}
+
+fn name_hash(name: &OsStr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
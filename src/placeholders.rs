@@ -0,0 +1,66 @@
+//! Online-only files of cloud storage clients.
+//!
+//! OneDrive, Dropbox and iCloud Drive keep files which haven't been opened lately in the cloud
+//! only, leaving a placeholder which looks like the file but downloads it when read. Hashing
+//! such files would pull the whole synced tree down, so scans leave them out unless
+//! `ScanOptions::hydrate_placeholders` is set. They are reported with the other errors of the
+//! scan, and `is_skipped` tells them apart.
+//!
+//! Placeholders are recognised by the recall and offline attributes on Windows and by the
+//! dataless flag on macOS. Other platforms have no such marker, so nothing is skipped there.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::Metadata;
+use std::io;
+
+/// Attributes of files whose contents aren't on the local disk, from `winnt.h`: offline,
+/// recall on open and recall on data access.
+#[cfg(windows)]
+const REMOTE_ATTRIBUTES: u32 = 0x1000 | 0x4_0000 | 0x40_0000;
+
+/// Message of errors reporting a skipped placeholder.
+pub(crate) const SKIPPED_MESSAGE: &str = "online-only file, skipped to avoid downloading it";
+
+#[derive(Debug)]
+struct PlaceholderSkipped;
+
+impl fmt::Display for PlaceholderSkipped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(SKIPPED_MESSAGE)
+    }
+}
+
+impl Error for PlaceholderSkipped {}
+
+/// Returns true if the file of `meta` is a placeholder of a cloud storage client whose contents
+/// would be downloaded when read.
+///
+/// # Examples
+/// ```
+/// let meta = std::fs::metadata(std::env::current_exe().unwrap()).unwrap();
+/// assert!(!ddh::placeholders::is_placeholder(&meta));
+/// ```
+pub fn is_placeholder(meta: &Metadata) -> bool {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        meta.file_attributes() & REMOTE_ATTRIBUTES != 0
+    }
+    #[cfg(not(windows))]
+    {
+        crate::macos::is_dataless(meta)
+    }
+}
+
+/// Error recorded for a placeholder left out of a scan.
+pub(crate) fn skipped_error() -> io::Error {
+    io::Error::other(PlaceholderSkipped)
+}
+
+/// Returns true if `error` reports a placeholder left out of a scan rather than a failure.
+pub fn is_skipped(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<PlaceholderSkipped>())
+}
@@ -0,0 +1,260 @@
+//! Rules mapping duplicate groups to actions, for policy driven cleanup with `ddh apply`.
+//!
+//! A policy file holds a JSON list of rules which are tried in order. The first rule matching a
+//! group decides what happens to it and groups no rule matches are left alone:
+//!
+//! ```json
+//! {
+//!     "rules": [
+//!         { "name": "downloads", "under": "~/Downloads", "copies_elsewhere": true, "action": "delete" },
+//!         { "under": "/music", "extensions": ["flac"], "action": "hard-link", "keep": "oldest" }
+//!     ]
+//! }
+//! ```
+
+use crate::actions::KeepPolicy;
+use crate::copies::CopyPatterns;
+use crate::fileinfo::Fileinfo;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Change made to the instances of a group which aren't kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyAction {
+    Delete,
+    HardLink,
+    Reflink,
+}
+
+/// Filter selecting duplicate groups and the action taken on them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Name shown when the rule is applied.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Only groups with an instance below this directory, and only those instances are changed.
+    /// A leading `~` stands for the home directory.
+    #[serde(default)]
+    pub under: Option<PathBuf>,
+    /// Only groups which also have an instance outside `under`. One of those is kept, so every
+    /// instance below `under` is changed.
+    #[serde(default)]
+    pub copies_elsewhere: bool,
+    /// Only groups of files with one of these extensions, compared ignoring case.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Only groups of files at least this many bytes long.
+    #[serde(default)]
+    pub min_size: u64,
+    pub action: PolicyAction,
+    /// Instance of each group kept, `first-arg-dir` by default.
+    #[serde(default)]
+    pub keep: Option<KeepPolicy>,
+}
+
+/// Ordered rules of a policy file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+}
+
+/// Action a policy takes on one group.
+#[derive(Debug)]
+pub struct PolicyDecision {
+    /// Index of the rule which matched.
+    pub rule: usize,
+    /// Instances the action applies to, including the one kept.
+    pub group: Fileinfo,
+    pub keep: PathBuf,
+    pub action: PolicyAction,
+}
+
+impl Rule {
+    /// Name of the rule, or its position in the policy file counting from one.
+    pub fn label(&self, index: usize) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("#{}", index + 1))
+    }
+
+    /// Decides what this rule does with `group`: `None` if it doesn't match, otherwise the
+    /// instances to act on and the one to keep, or `Some(None)` if it matches but nothing
+    /// below `under` can be changed.
+    fn decide(
+        &self,
+        group: &Fileinfo,
+        search_dirs: &[PathBuf],
+        copies: &CopyPatterns,
+    ) -> Option<Option<(Fileinfo, PathBuf)>> {
+        if group.get_length() < self.min_size {
+            return None;
+        }
+        let paths: Vec<&Path> = group.get_paths().iter().map(|path| &**path).collect();
+        if !self.extensions.is_empty() && !paths.iter().all(|path| self.has_extension(path)) {
+            return None;
+        }
+        let keep_policy = self.keep.unwrap_or(KeepPolicy::FirstArgDir);
+        let under = match self.under.as_ref() {
+            Some(under) => under,
+            None => {
+                let keep = keep_policy
+                    .choose(group, search_dirs, copies)?
+                    .to_path_buf();
+                return Some(Some((subset(group, paths), keep)));
+            }
+        };
+        let (inside, outside): (Vec<&Path>, Vec<&Path>) =
+            paths.iter().partition(|path| path.starts_with(under));
+        if inside.is_empty() || (self.copies_elsewhere && outside.is_empty()) {
+            return None;
+        }
+        let candidates = if self.copies_elsewhere {
+            subset(group, outside)
+        } else {
+            subset(group, paths)
+        };
+        let keep = match keep_policy.choose(&candidates, search_dirs, copies) {
+            Some(keep) => keep.to_path_buf(),
+            None => return Some(None),
+        };
+        let mut changed: Vec<&Path> = inside.into_iter().filter(|path| **path != keep).collect();
+        if changed.is_empty() {
+            return Some(None);
+        }
+        changed.insert(0, &keep);
+        Some(Some((subset(group, changed), keep)))
+    }
+
+    fn has_extension(&self, path: &Path) -> bool {
+        let extension = match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => extension,
+            None => return false,
+        };
+        self.extensions.iter().any(|wanted| {
+            wanted
+                .trim_start_matches('.')
+                .eq_ignore_ascii_case(extension)
+        })
+    }
+}
+
+impl Policy {
+    /// Loads a policy file, expanding `~` and resolving symlinks in the `under` directories so
+    /// they compare with the canonical paths of scan results.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut policy: Policy = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for rule in policy.rules.iter_mut() {
+            if let Some(under) = rule.under.take() {
+                let under = expand_home(under);
+                rule.under = Some(fs::canonicalize(&under).unwrap_or(under));
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Decides the action on each of `groups` from the first rule matching it. Groups which no
+    /// rule matches, or whose matching rule leaves nothing to change, get no decision.
+    /// `search_dirs` are the canonical search directories in the order they were given.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::copies::CopyPatterns;
+    /// use ddh::fileinfo::Fileinfo;
+    /// use ddh::policy::{Policy, PolicyAction, Rule};
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// let rule = |under: &str, copies_elsewhere, extensions: &[&str], action| Rule {
+    ///     name: None,
+    ///     under: Some(PathBuf::from(under)),
+    ///     copies_elsewhere,
+    ///     extensions: extensions.iter().map(|e| e.to_string()).collect(),
+    ///     min_size: 0,
+    ///     action,
+    ///     keep: None,
+    /// };
+    /// let policy = Policy {
+    ///     rules: vec![
+    ///         rule("/home/jon/Downloads", true, &[], PolicyAction::Delete),
+    ///         rule("/music", false, &["flac"], PolicyAction::HardLink),
+    ///     ],
+    /// };
+    /// let groups = vec![
+    ///     ddh::consolidate(vec![
+    ///         Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/home/jon/Downloads/a.pdf")),
+    ///         Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/home/jon/Documents/a.pdf")),
+    ///         Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/home/jon/Desktop/a.pdf")),
+    ///     ]),
+    ///     ddh::consolidate(vec![
+    ///         Fileinfo::from_existing_hash(2, None, 10, PathBuf::from("/music/a/song.flac")),
+    ///         Fileinfo::from_existing_hash(2, None, 10, PathBuf::from("/music/b/song.flac")),
+    ///     ]),
+    ///     ddh::consolidate(vec![
+    ///         Fileinfo::from_existing_hash(3, None, 10, PathBuf::from("/music/a/cover.jpg")),
+    ///         Fileinfo::from_existing_hash(3, None, 10, PathBuf::from("/music/b/cover.jpg")),
+    ///     ]),
+    /// ];
+    /// let groups: Vec<&Fileinfo> = groups.iter().map(|group| &group[0]).collect();
+    ///
+    /// let roots = [PathBuf::from("/home/jon"), PathBuf::from("/music")];
+    /// let decisions = policy.decide(&groups, &roots, &CopyPatterns::default());
+    /// assert_eq!(decisions.len(), 2);
+    /// // Only the instance in Downloads goes; the Desktop copy is kept.
+    /// assert_eq!(decisions[0].keep, Path::new("/home/jon/Desktop/a.pdf"));
+    /// assert_eq!(decisions[0].group.get_paths().len(), 2);
+    /// assert_eq!(decisions[1].action, PolicyAction::HardLink);
+    /// assert_eq!(decisions[1].keep, Path::new("/music/a/song.flac"));
+    /// ```
+    pub fn decide(
+        &self,
+        groups: &[&Fileinfo],
+        search_dirs: &[PathBuf],
+        copies: &CopyPatterns,
+    ) -> Vec<PolicyDecision> {
+        groups
+            .iter()
+            .filter_map(|group| {
+                self.rules.iter().enumerate().find_map(|(index, rule)| {
+                    rule.decide(group, search_dirs, copies).map(|decision| {
+                        decision.map(|(group, keep)| PolicyDecision {
+                            rule: index,
+                            group,
+                            keep,
+                            action: rule.action,
+                        })
+                    })
+                })
+            })
+            .flatten()
+            .collect()
+    }
+}
+
+/// Builds a collection of the same contents as `group` holding only `paths`.
+fn subset(group: &Fileinfo, paths: Vec<&Path>) -> Fileinfo {
+    let mut paths = paths.into_iter().map(Path::to_path_buf);
+    let mut subset = Fileinfo::new(
+        group.get_full_hash(),
+        group.get_partial_hash(),
+        *group.get_metadata(),
+        paths.next().unwrap_or_default(),
+    );
+    subset
+        .file_paths
+        .extend(paths.map(PathBuf::into_boxed_path));
+    subset
+}
+
+fn expand_home(path: PathBuf) -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path,
+    }
+}
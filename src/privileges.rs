@@ -0,0 +1,82 @@
+//! Guards for runs with elevated privileges.
+//!
+//! As root or Administrator nothing stops a removal from reaching into the files the OS runs
+//! on, so such runs start from conservative defaults: changes need an extra flag on top of
+//! `--execute`, and instances in system directories are never touched.
+
+use std::path::Path;
+
+/// Directories holding the files of the OS, which elevated runs never change.
+#[cfg(unix)]
+pub const SYSTEM_DIRS: &[&str] = &[
+    "/bin",
+    "/boot",
+    "/dev",
+    "/etc",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/proc",
+    "/sbin",
+    "/sys",
+    "/usr",
+    "/var/lib",
+    "/Library",
+    "/System",
+    "/private/etc",
+    "/private/var/db",
+];
+
+/// Directories holding the files of the OS, which elevated runs never change.
+#[cfg(windows)]
+pub const SYSTEM_DIRS: &[&str] = &[
+    r"C:\Windows",
+    r"C:\Program Files",
+    r"C:\Program Files (x86)",
+    r"C:\ProgramData",
+];
+
+/// Directories holding the files of the OS, which elevated runs never change.
+#[cfg(not(any(unix, windows)))]
+pub const SYSTEM_DIRS: &[&str] = &[];
+
+#[cfg(windows)]
+#[link(name = "shell32")]
+extern "system" {
+    fn IsUserAnAdmin() -> i32;
+}
+
+/// Returns true if the process runs as root, or as an Administrator on Windows.
+#[cfg(unix)]
+pub fn is_elevated() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Returns true if the process runs as root, or as an Administrator on Windows.
+#[cfg(windows)]
+pub fn is_elevated() -> bool {
+    unsafe { IsUserAnAdmin() != 0 }
+}
+
+/// Returns true if the process runs as root, or as an Administrator on Windows.
+#[cfg(not(any(unix, windows)))]
+pub fn is_elevated() -> bool {
+    false
+}
+
+/// Returns true if `path` is in one of the `SYSTEM_DIRS`.
+///
+/// # Examples
+/// ```
+/// # #[cfg(unix)]
+/// # {
+/// use std::path::Path;
+///
+/// assert!(ddh::privileges::is_system_path(Path::new("/usr/lib/libc.so.6")));
+/// assert!(!ddh::privileges::is_system_path(Path::new("/usrdata/photo.jpg")));
+/// assert!(!ddh::privileges::is_system_path(Path::new("/home/jon/photo.jpg")));
+/// # }
+/// ```
+pub fn is_system_path(path: &Path) -> bool {
+    SYSTEM_DIRS.iter().any(|dir| path.starts_with(dir))
+}
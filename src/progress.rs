@@ -0,0 +1,40 @@
+//! Progress of a scan, reported while it runs.
+//!
+//! Scans given a sender in `ScanOptions::progress`, or run with `deduplicate_dirs_with_progress`,
+//! emit events from the threads doing the work: the files found while walking the directories,
+//! then the files hashed and the bytes of candidate duplicates settled, along with any errors.
+//! Receivers draw them however they like; the `ddh` binary shows them as progress bars on
+//! stderr.
+
+use crate::fileinfo::HashMode;
+use std::path::PathBuf;
+
+/// Event of a running scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    /// A file was found by the directory walk. Only sent with
+    /// `ScanOptions::report_discovered_files`.
+    FileDiscovered(PathBuf),
+    /// Files found so far by the directory walk, sent every thousand or so files and once the
+    /// walk is done.
+    Discovered(u64),
+    /// The walk is done and hashing starts, with this many bytes of candidates to settle.
+    /// Files whose length no other file shares aren't candidates.
+    Hashing { total_bytes: u64 },
+    /// A file started being hashed. Hashes found in the cache or computed by an external
+    /// command aren't reported.
+    HashStarted { path: PathBuf, mode: HashMode },
+    /// A file was hashed, or couldn't be read when `hash` is `None`.
+    HashFinished {
+        path: PathBuf,
+        mode: HashMode,
+        hash: Option<u128>,
+    },
+    /// Bytes of candidates settled, either hashed in full or told apart by their first block.
+    Hashed(u64),
+    /// A path was left out of the results because of an error.
+    Error { path: PathBuf, message: String },
+}
+
+/// Number of files found between two `Progress::Discovered` events.
+pub(crate) const DISCOVERY_STEP: u64 = 1024;
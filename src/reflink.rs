@@ -0,0 +1,169 @@
+//! Copy-on-write clones of files.
+//!
+//! A clone shares its blocks with the original until either is written to, so replacing a
+//! duplicate with a clone reclaims its space while both paths stay independent files. Linux
+//! clones with the `FICLONE` ioctl (btrfs, XFS, bcachefs) and macOS with `clonefile` (APFS).
+//! Other platforms, and filesystems without clone support, report an error instead.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+/// Creates `dst` as a clone of `src`. `dst` must not exist yet.
+#[cfg(target_os = "linux")]
+pub fn clone_file(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let source = File::open(src)?;
+    let clone = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dst)?;
+    if unsafe { libc::ioctl(clone.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == -1 {
+        let e = io::Error::last_os_error();
+        drop(clone);
+        let _ = fs::remove_file(dst);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Creates `dst` as a clone of `src`. `dst` must not exist yet.
+#[cfg(target_os = "macos")]
+pub fn clone_file(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let src = CString::new(src.as_os_str().as_bytes())?;
+    let dst = CString::new(dst.as_os_str().as_bytes())?;
+    if unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Creates `dst` as a clone of `src`. `dst` must not exist yet.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn clone_file(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are not supported on this platform",
+    ))
+}
+
+/// Replaces `target` with a clone of `src`, keeping the owner, group, permissions, times and
+/// extended attributes of `target`. The clone is made next to `target` and renamed over it, so
+/// `target` is never missing.
+///
+/// # Examples
+/// ```no_run
+/// use std::path::Path;
+///
+/// fn main() -> std::io::Result<()> {
+/// ddh::reflink::replace_with_clone(Path::new("/mnt/btrfs/a.iso"), Path::new("/mnt/btrfs/b.iso"))?;
+/// Ok(())
+/// }
+/// ```
+pub fn replace_with_clone(src: &Path, target: &Path) -> io::Result<()> {
+    let original = File::open(target)?;
+    let temporary = crate::actions::sibling_path(target, ".ddh-clone")?;
+    clone_file(src, &temporary)?;
+    let replaced = File::open(&temporary)
+        .and_then(|clone| copy_attributes(&original, &clone))
+        .and_then(|_| fs::rename(&temporary, target));
+    if replaced.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    replaced
+}
+
+/// Gives `to` the extended attributes, owner, group, permissions and times of `from`. The
+/// owner goes before the permissions since changing it clears the set-id bits, and the times
+/// go last since the other changes touch them.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn copy_attributes(from: &File, to: &File) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+    let meta = from.metadata()?;
+    copy_xattrs(from, to)?;
+    let fd = to.as_raw_fd();
+    let check = |result: libc::c_int| match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    };
+    check(unsafe { libc::fchown(fd, meta.uid(), meta.gid()) })?;
+    check(unsafe { libc::fchmod(fd, (meta.mode() & 0o7777) as libc::mode_t) })?;
+    let time = |sec: i64, nsec: i64| libc::timespec {
+        tv_sec: sec as libc::time_t,
+        tv_nsec: nsec as _,
+    };
+    let times = [
+        time(meta.atime(), meta.atime_nsec()),
+        time(meta.mtime(), meta.mtime_nsec()),
+    ];
+    check(unsafe { libc::futimens(fd, times.as_ptr()) })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn copy_attributes(from: &File, to: &File) -> io::Result<()> {
+    to.set_permissions(from.metadata()?.permissions())
+}
+
+/// Copies every extended attribute of `from` to `to`. Filesystems without extended attributes
+/// have none to copy.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn copy_xattrs(from: &File, to: &File) -> io::Result<()> {
+    use std::ffi::CStr;
+    use std::os::unix::io::AsRawFd;
+    let (src, dst) = (from.as_raw_fd(), to.as_raw_fd());
+    let unsupported = |e: &io::Error| e.raw_os_error() == Some(libc::ENOTSUP);
+    /* Each call is made once for the size and once for the data. */
+    let list = |buffer: &mut [u8]| {
+        let (data, size) = (buffer.as_mut_ptr() as *mut libc::c_char, buffer.len());
+        #[cfg(target_os = "linux")]
+        let listed = unsafe { libc::flistxattr(src, data, size) };
+        #[cfg(target_os = "macos")]
+        let listed = unsafe { libc::flistxattr(src, data, size, 0) };
+        listed
+    };
+    let listed = list(&mut []);
+    if listed < 0 {
+        let e = io::Error::last_os_error();
+        return if unsupported(&e) { Ok(()) } else { Err(e) };
+    }
+    let mut names = vec![0u8; listed as usize];
+    let listed = list(&mut names);
+    if listed < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(listed as usize);
+    for name in names.split_inclusive(|&b| b == 0) {
+        let name = CStr::from_bytes_with_nul(name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let get = |buffer: &mut [u8]| {
+            let (data, size) = (buffer.as_mut_ptr() as *mut libc::c_void, buffer.len());
+            #[cfg(target_os = "linux")]
+            let read = unsafe { libc::fgetxattr(src, name.as_ptr(), data, size) };
+            #[cfg(target_os = "macos")]
+            let read = unsafe { libc::fgetxattr(src, name.as_ptr(), data, size, 0, 0) };
+            read
+        };
+        let read = get(&mut []);
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut value = vec![0u8; read as usize];
+        let read = get(&mut value);
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        value.truncate(read as usize);
+        let (data, size) = (value.as_ptr() as *const libc::c_void, value.len());
+        #[cfg(target_os = "linux")]
+        let set = unsafe { libc::fsetxattr(dst, name.as_ptr(), data, size, 0) };
+        #[cfg(target_os = "macos")]
+        let set = unsafe { libc::fsetxattr(dst, name.as_ptr(), data, size, 0, 0) };
+        if set == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Results of a previous scan, reused when the same scan runs again and nothing changed.
+//!
+//! Tweaking report flags on a big tree otherwise means rescanning it every time. Saved results
+//! are keyed by the scan parameters and hold the metadata of every file in them and of the
+//! directories holding those files. They are reused only while that metadata is unchanged and
+//! they are younger than a given age, which checking takes one `stat` per entry instead of a
+//! walk and hashing.
+//!
+//! Directories without any scanned file aren't tracked, so files added to them go unnoticed
+//! until the saved results expire.
+
+use crate::fileinfo::{FileMetadata, Fileinfo};
+use crate::placeholders;
+use crate::ScanOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Version of the saved results format. Files written with another version are never reused.
+const RESULTS_VERSION: u32 = 1;
+
+/// Duplicate groups and unique files of a scan along with the paths it couldn't read.
+type Results = (Vec<Fileinfo>, Vec<(PathBuf, io::Error)>);
+
+#[derive(Serialize)]
+struct SavingScan<'a> {
+    version: u32,
+    key: &'a str,
+    saved_at: u64,
+    stamps: Vec<(PathBuf, FileMetadata)>,
+    files: &'a [Fileinfo],
+    errors: Vec<(&'a Path, Option<i32>, String)>,
+}
+
+#[derive(Deserialize)]
+struct SavedScan {
+    version: u32,
+    key: String,
+    saved_at: u64,
+    stamps: Vec<(PathBuf, FileMetadata)>,
+    files: Vec<Fileinfo>,
+    /// Paths with the OS error code, if any, and message of their error.
+    errors: Vec<(PathBuf, Option<i32>, String)>,
+}
+
+/// Identifies a scan by its canonical search directories, ignore entries and the options which
+/// change its results.
+pub fn scan_key<P: AsRef<Path>>(
+    search_dirs: &[P],
+    ignore_dirs: &[P],
+    options: &ScanOptions,
+) -> String {
+    let dirs: Vec<PathBuf> = search_dirs
+        .iter()
+        .map(|dir| fs::canonicalize(dir).unwrap_or_else(|_| dir.as_ref().to_path_buf()))
+        .collect();
+    let ignored: Vec<&Path> = ignore_dirs.iter().map(AsRef::as_ref).collect();
+    format!(
+        "{:?} {:?} {} {:?} {:?} {} {} {} {} {:?} {} {} {:?} {}",
+        dirs,
+        ignored,
+        options.min_size,
+        options.hasher,
+        options.hash_command,
+        options.resolve_symlinks,
+        options.follow_symlinks,
+        options.hydrate_placeholders,
+        options.one_file_system,
+        options.max_depth,
+        options.respect_gitignore,
+        options.skip_hidden,
+        options.file_filters,
+        options.paranoid
+    )
+}
+
+/// Saves the results of the scan identified by `key` to `path`.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    key: &str,
+    files: &[Fileinfo],
+    errors: &[(PathBuf, io::Error)],
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let paths: BTreeSet<&Path> = files
+        .iter()
+        .flat_map(|file| file.get_paths().iter())
+        .flat_map(|path| {
+            let dir = path.parent();
+            std::iter::once(&**path).chain(dir)
+        })
+        .collect();
+    let saving = SavingScan {
+        version: RESULTS_VERSION,
+        key,
+        saved_at: seconds(SystemTime::now()),
+        stamps: paths
+            .into_iter()
+            .filter_map(|path| Some((path.to_path_buf(), fs::metadata(path).ok()?.into())))
+            .collect(),
+        files,
+        errors: errors
+            .iter()
+            .map(|(path, e)| (path.as_path(), e.raw_os_error(), e.to_string()))
+            .collect(),
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut tmp = File::create(&tmp_path)?;
+    serde_json::to_writer(&mut tmp, &saving)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    tmp.flush()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Loads the results saved at `path` if they were saved for `key` less than `max_age` ago and
+/// none of the files and directories they cover changed since. Missing, stale or unreadable
+/// results yield `None`. Errors of the saved scan which didn't come from the OS come back
+/// with their message only, apart from skipped placeholders.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+///
+/// let (dirs, ignored) = (vec!["/srv/media"], vec![]);
+/// let options = ddh::ScanOptions::default();
+/// let key = ddh::results::scan_key(&dirs, &ignored, &options);
+/// let (files, errors) = match ddh::results::load("results.json", &key, Duration::from_secs(600)) {
+///     Some(saved) => saved,
+///     None => {
+///         let (files, errors) = ddh::deduplicate_dirs_with_options(dirs, ignored, &options);
+///         ddh::results::save("results.json", &key, &files, &errors).unwrap();
+///         (files, errors)
+///     }
+/// };
+/// ```
+pub fn load<P: AsRef<Path>>(path: P, key: &str, max_age: Duration) -> Option<Results> {
+    let contents = fs::read_to_string(path).ok()?;
+    let saved: SavedScan = serde_json::from_str(&contents).ok()?;
+    let age = seconds(SystemTime::now()).saturating_sub(saved.saved_at);
+    if saved.version != RESULTS_VERSION || saved.key != key || age > max_age.as_secs() {
+        return None;
+    }
+    let unchanged = saved.stamps.iter().all(|(path, stamp)| {
+        fs::metadata(path).is_ok_and(|meta| FileMetadata::from(meta) == *stamp)
+    });
+    if !unchanged {
+        return None;
+    }
+    let errors = saved
+        .errors
+        .into_iter()
+        .map(|(path, code, message)| match code {
+            Some(code) => (path, io::Error::from_raw_os_error(code)),
+            None if message == placeholders::SKIPPED_MESSAGE => {
+                (path, placeholders::skipped_error())
+            }
+            None => (path, io::Error::other(message)),
+        })
+        .collect();
+    Some((saved.files, errors))
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,323 @@
+//! Labels for search roots used to describe where the instances of a group live.
+//!
+//! A root given as `label=path` is reported under its label, so a comparison of a backup drive
+//! against a home directory reads as `backup-only`, `live-only` or `both` rather than as a list
+//! of mount points.
+//!
+//! Reference directories are searched for matches like the other roots, but their instances
+//! are never removed nor counted as waste, as with fdupes' protected directories.
+
+use crate::fileinfo::Fileinfo;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// A directory to search along with its optional label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanRoot {
+    pub label: Option<String>,
+    pub path: PathBuf,
+}
+
+impl ScanRoot {
+    /// Parses a root given as `path` or `label=path`. Labels may contain letters, digits, `-`
+    /// and `_`, and an argument which exists as a path is never treated as labelled.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::roots::ScanRoot;
+    ///
+    /// let root = ScanRoot::parse("backup=/mnt/usb1");
+    /// assert_eq!(root.label.as_deref(), Some("backup"));
+    /// assert_eq!(root.path, std::path::Path::new("/mnt/usb1"));
+    /// assert_eq!(ScanRoot::parse("/home/jon").label, None);
+    /// ```
+    pub fn parse(arg: &str) -> Self {
+        let unlabelled = ScanRoot {
+            label: None,
+            path: PathBuf::from(arg),
+        };
+        let (label, path) = match arg.split_once('=') {
+            Some(split) => split,
+            None => return unlabelled,
+        };
+        let valid_label = !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        if !valid_label || path.is_empty() || Path::new(arg).exists() {
+            return unlabelled;
+        }
+        ScanRoot {
+            label: Some(label.to_string()),
+            path: PathBuf::from(path),
+        }
+    }
+    /// Gets the label of the root, falling back to its path.
+    pub fn name(&self) -> String {
+        match &self.label {
+            Some(label) => label.clone(),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+/// Resolves instance paths to the labels of the roots they were found under.
+#[derive(Debug, Default, Clone)]
+pub struct RootLabels {
+    roots: Vec<(String, PathBuf)>,
+    labelled: bool,
+}
+
+impl RootLabels {
+    /// Resolves the locations of `roots`. Roots which can't be resolved are left out.
+    pub fn new(roots: &[ScanRoot]) -> Self {
+        RootLabels {
+            roots: roots
+                .iter()
+                .filter_map(|root| Some((root.name(), root.path.canonicalize().ok()?)))
+                .collect(),
+            labelled: roots.iter().any(|root| root.label.is_some()),
+        }
+    }
+    /// Resolves the locations of `roots` with `resolve`, naming unlabelled roots by their
+    /// resolved location rather than the path they were given as, for reports about paths
+    /// rewritten the same way. Roots which can't be resolved are left out.
+    pub fn resolve_with(roots: &[ScanRoot], resolve: impl Fn(&Path) -> Option<PathBuf>) -> Self {
+        RootLabels {
+            roots: roots
+                .iter()
+                .filter_map(|root| {
+                    let path = resolve(&root.path)?;
+                    let name = match &root.label {
+                        Some(label) => label.clone(),
+                        None => path.display().to_string(),
+                    };
+                    Some((name, path))
+                })
+                .collect(),
+            labelled: roots.iter().any(|root| root.label.is_some()),
+        }
+    }
+    /// Returns true if any root was given a label.
+    pub fn is_labelled(&self) -> bool {
+        self.labelled
+    }
+    /// Gets the distinct root names in the order the roots were given.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (name, _) in self.roots.iter() {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+    /// Gets the name of the innermost root containing the canonical path `path`.
+    pub fn name_of(&self, path: &Path) -> Option<&str> {
+        self.roots
+            .iter()
+            .filter(|(_, root)| path.starts_with(root))
+            .max_by_key(|(_, root)| root.components().count())
+            .map(|(name, _)| name.as_str())
+    }
+    /// Describes which roots hold instances of `group`: `<name>-only` for a single root,
+    /// `both` or `all` when every root does, and names joined by `+` otherwise.
+    pub fn placement(&self, group: &Fileinfo) -> Option<String> {
+        let present: BTreeSet<&str> = group
+            .get_paths()
+            .iter()
+            .filter_map(|path| self.name_of(path))
+            .collect();
+        let names: Vec<&str> = self
+            .names()
+            .into_iter()
+            .filter(|name| present.contains(name))
+            .collect();
+        match (names.len(), self.names().len()) {
+            (0, _) => None,
+            (1, _) => Some(format!("{}-only", names[0])),
+            (2, 2) => Some("both".to_string()),
+            (n, total) if n == total => Some("all".to_string()),
+            _ => Some(names.join("+")),
+        }
+    }
+    /// Lists the paths below the root named `root`, by its name or its path, whose contents
+    /// have no instance anywhere else, with their lengths, in sorted order: the files a backup
+    /// in the other roots lacks. Instances outside every root, such as those of remote indexes,
+    /// count as elsewhere. `None` when no root goes by `root`.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::fileinfo::Fileinfo;
+    /// use ddh::roots::{RootLabels, ScanRoot};
+    ///
+    /// let dir = std::env::temp_dir().join("ddh_unique_to_example");
+    /// std::fs::create_dir_all(dir.join("live")).unwrap();
+    /// std::fs::create_dir_all(dir.join("backup")).unwrap();
+    /// let dir = dir.canonicalize().unwrap();
+    /// let roots = RootLabels::new(&[
+    ///     ScanRoot::parse(&format!("live={}", dir.join("live").display())),
+    ///     ScanRoot::parse(&format!("backup={}", dir.join("backup").display())),
+    /// ]);
+    /// let files = ddh::consolidate(vec![
+    ///     Fileinfo::from_existing_hash(1, None, 10, dir.join("live/a.txt")),
+    ///     Fileinfo::from_existing_hash(1, None, 10, dir.join("backup/a.txt")),
+    ///     Fileinfo::from_existing_hash(2, None, 10, dir.join("live/b.txt")),
+    ///     Fileinfo::from_existing_hash(2, None, 10, dir.join("live/c.txt")),
+    /// ]);
+    /// let missing = roots.unique_to(&files, "live").unwrap();
+    /// assert_eq!(missing, [(&*dir.join("live/b.txt"), 10), (&*dir.join("live/c.txt"), 10)]);
+    /// assert!(roots.unique_to(&files, "backup").unwrap().is_empty());
+    /// assert!(roots.unique_to(&files, "elsewhere").is_none());
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn unique_to<'a>(&self, files: &'a [Fileinfo], root: &str) -> Option<Vec<(&'a Path, u64)>> {
+        let canonical = Path::new(root).canonicalize().ok();
+        let name = self
+            .roots
+            .iter()
+            .find(|(name, path)| name == root || Some(path) == canonical.as_ref())
+            .map(|(name, _)| name.as_str())?;
+        let mut unique: Vec<(&Path, u64)> = files
+            .iter()
+            .filter(|file| {
+                file.get_paths()
+                    .iter()
+                    .all(|path| self.name_of(path) == Some(name))
+            })
+            .flat_map(|file| {
+                file.get_paths()
+                    .iter()
+                    .map(move |path| (&**path, file.get_length()))
+            })
+            .collect();
+        unique.sort();
+        Some(unique)
+    }
+    /// Returns true if the instances of `group` live in more than one branch, a branch being a
+    /// directory `depth` levels below the innermost root holding an instance. Instances directly
+    /// inside a root or a shallower directory share a branch with their siblings. With a depth
+    /// of zero only groups spanning several roots qualify.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::fileinfo::Fileinfo;
+    /// use ddh::roots::{RootLabels, ScanRoot};
+    /// use std::path::PathBuf;
+    ///
+    /// let roots = RootLabels::new(&[ScanRoot::parse("/")]);
+    /// let group = |paths: &[&str]| {
+    ///     let files = paths
+    ///         .iter()
+    ///         .map(|p| Fileinfo::from_existing_hash(1, None, 1, PathBuf::from(p)))
+    ///         .collect();
+    ///     ddh::consolidate(files).remove(0)
+    /// };
+    /// let siblings = group(&["/exports/v1/a.png", "/exports/v2/a.png"]);
+    /// let branches = group(&["/exports/v1/a.png", "/photos/a.png"]);
+    /// assert!(!roots.spans_branches(&siblings, 1));
+    /// assert!(roots.spans_branches(&siblings, 2));
+    /// assert!(roots.spans_branches(&branches, 1));
+    /// ```
+    pub fn spans_branches(&self, group: &Fileinfo, depth: usize) -> bool {
+        let branches: BTreeSet<PathBuf> = group
+            .get_paths()
+            .iter()
+            .map(|path| self.branch_of(path, depth))
+            .collect();
+        branches.len() > 1
+    }
+    fn branch_of(&self, path: &Path, depth: usize) -> PathBuf {
+        let dir = path.parent().unwrap_or(path);
+        let root = self
+            .roots
+            .iter()
+            .map(|(_, root)| root)
+            .filter(|root| dir.starts_with(root))
+            .max_by_key(|root| root.components().count());
+        match root.and_then(|root| Some((root, dir.strip_prefix(root).ok()?))) {
+            Some((root, below)) => root.join(below.components().take(depth).collect::<PathBuf>()),
+            /* Paths outside every root, such as those of remote indexes, count from the top. */
+            None => dir.components().take(depth + 1).collect(),
+        }
+    }
+}
+
+/// Directories searched for matches whose files are never removed nor counted as waste, as a
+/// reference to clean other directories against.
+#[derive(Debug, Default, Clone)]
+pub struct ReferenceDirs {
+    dirs: Vec<PathBuf>,
+}
+
+impl ReferenceDirs {
+    /// Resolves the locations of `dirs`. Directories which can't be resolved are left out.
+    pub fn new(dirs: &[PathBuf]) -> Self {
+        ReferenceDirs {
+            dirs: dirs
+                .iter()
+                .filter_map(|dir| dir.canonicalize().ok())
+                .collect(),
+        }
+    }
+    /// Rewrites the reference directories with `f`, for reports about paths rewritten the same
+    /// way.
+    pub fn map_paths(&self, f: impl Fn(&Path) -> PathBuf) -> Self {
+        ReferenceDirs {
+            dirs: self.dirs.iter().map(|dir| f(dir)).collect(),
+        }
+    }
+    /// Returns true if there are no reference directories.
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty()
+    }
+    /// Returns true if the canonical path `path` is below a reference directory.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.dirs.iter().any(|dir| path.starts_with(dir))
+    }
+    /// Narrows `group` down to the instances which may be removed, preceded by one instance
+    /// below a reference directory standing in for all of them, the first in sorted order.
+    /// Groups held only by reference directories shrink to that instance and so no longer count
+    /// as duplicates.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::fileinfo::Fileinfo;
+    /// use ddh::roots::ReferenceDirs;
+    /// use std::path::{Path, PathBuf};
+    ///
+    /// let dir = std::env::temp_dir().join("ddh_reference_example");
+    /// std::fs::create_dir_all(dir.join("archive")).unwrap();
+    /// let dir = dir.canonicalize().unwrap();
+    /// let references = ReferenceDirs::new(&[dir.join("archive")]);
+    /// let group = ddh::consolidate(vec![
+    ///     Fileinfo::from_existing_hash(1, None, 10, dir.join("downloads/a.zip")),
+    ///     Fileinfo::from_existing_hash(1, None, 10, dir.join("archive/2020/a.zip")),
+    ///     Fileinfo::from_existing_hash(1, None, 10, dir.join("archive/2019/a.zip")),
+    /// ])
+    /// .remove(0);
+    /// let group = references.narrow(group);
+    /// let kept = dir.join("archive/2019/a.zip");
+    /// assert_eq!(group.get_paths(), &[kept.clone().into(), dir.join("downloads/a.zip").into()]);
+    /// assert_eq!(references.instance_of(&group), Some(kept.as_path()));
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn narrow(&self, mut group: Fileinfo) -> Fileinfo {
+        let (mut references, others): (Vec<Box<Path>>, Vec<Box<Path>>) = group
+            .file_paths
+            .drain(..)
+            .partition(|path| self.contains(path));
+        references.sort();
+        group.file_paths = references.into_iter().take(1).chain(others).collect();
+        group
+    }
+    /// Gets the instance of a group narrowed by `narrow` which stands in for the reference
+    /// directories, if any.
+    pub fn instance_of<'a>(&self, group: &'a Fileinfo) -> Option<&'a Path> {
+        group
+            .get_paths()
+            .first()
+            .map(|path| &**path)
+            .filter(|path| self.contains(path))
+    }
+}
@@ -0,0 +1,169 @@
+//! Checks that a build hashes and groups files correctly on the platform it runs on.
+//!
+//! `run` writes files of known contents below a directory and scans them with every built-in
+//! hash algorithm, once as usual and once comparing candidates byte by byte, expecting the same
+//! groups each time. The files include near duplicates differing only in their first or last
+//! byte, which partial hashes can't tell apart. Each algorithm must also hash data fed in pieces
+//! as it hashes it in one go, and SHA-256 and BLAKE3 must produce their published digests.
+
+use crate::fileinfo::Fileinfo;
+use crate::hasher::{Blake3, ContentHasher, Sha256, SipHash128, Xxh3};
+use crate::ScanOptions;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Length of the large test files, long enough to need a partial and a full hash.
+const LARGE_LENGTH: usize = 100_000;
+
+/// Outcome of one check.
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    /// What went wrong, if anything.
+    pub result: Result<(), String>,
+}
+
+/// Runs every check in a new directory `dir`, which is removed again afterwards.
+///
+/// # Examples
+/// ```
+/// let dir = std::env::temp_dir().join("ddh_selftest_example");
+/// let checks = ddh::selftest::run(&dir).unwrap();
+/// for check in checks.iter() {
+///     assert!(check.result.is_ok(), "{}: {:?}", check.name, check.result);
+/// }
+/// ```
+pub fn run(dir: &Path) -> io::Result<Vec<Check>> {
+    fs::create_dir(dir)?;
+    let written = write_files(dir);
+    let checks = written.map(|_| {
+        let mut checks = Vec::new();
+        for (name, hasher) in backends() {
+            checks.push(Check {
+                name: format!("{} known answers", name),
+                result: check_known_answers(name, hasher.as_ref()),
+            });
+            for paranoid in [false, true] {
+                let options = ScanOptions {
+                    hasher: (name != "sip128").then(|| hasher.clone()),
+                    paranoid,
+                    ..Default::default()
+                };
+                checks.push(Check {
+                    name: format!("{} scan{}", name, if paranoid { ", paranoid" } else { "" }),
+                    result: check_groups(dir, &options),
+                });
+            }
+        }
+        checks
+    });
+    let removed = fs::remove_dir_all(dir);
+    let checks = checks?;
+    removed.map(|_| checks)
+}
+
+/// Gets the built-in hash algorithms by the names the `ddh` binary knows them by.
+fn backends() -> Vec<(&'static str, Arc<dyn ContentHasher>)> {
+    vec![
+        ("sip128", Arc::new(SipHash128)),
+        ("xxh3", Arc::new(Xxh3)),
+        ("sha256", Arc::new(Sha256)),
+        ("blake3", Arc::new(Blake3)),
+    ]
+}
+
+fn large_contents() -> Vec<u8> {
+    (0..LARGE_LENGTH).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+/// Writes the test files below `dir`.
+fn write_files(dir: &Path) -> io::Result<()> {
+    let large = large_contents();
+    let mut head = large.clone();
+    head[0] ^= 1;
+    let mut tail = large.clone();
+    tail[LARGE_LENGTH - 1] ^= 1;
+    fs::create_dir_all(dir.join("small"))?;
+    fs::create_dir_all(dir.join("large/sub"))?;
+    fs::write(dir.join("small/a.txt"), "small duplicate\n")?;
+    fs::write(dir.join("small/b.txt"), "small duplicate\n")?;
+    fs::write(dir.join("small/c.txt"), "small Duplicate\n")?;
+    fs::write(dir.join("large/one.bin"), &large)?;
+    fs::write(dir.join("large/three.bin"), &large)?;
+    fs::write(dir.join("large/sub/two.bin"), &large)?;
+    fs::write(dir.join("large/head.bin"), head)?;
+    fs::write(dir.join("large/tail.bin"), tail)?;
+    fs::write(dir.join("unique.bin"), &large[..5000])
+}
+
+/// Gets the groups `write_files` should produce, as paths relative to its directory.
+fn expected_groups() -> BTreeSet<BTreeSet<PathBuf>> {
+    let group = |paths: &[&str]| paths.iter().map(PathBuf::from).collect();
+    vec![
+        group(&["small/a.txt", "small/b.txt"]),
+        group(&["large/one.bin", "large/sub/two.bin", "large/three.bin"]),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// Scans `dir` with `options` and compares the groups found with the expected ones.
+fn check_groups(dir: &Path, options: &ScanOptions) -> Result<(), String> {
+    let (files, errors) = crate::deduplicate_dirs_with_options(vec![dir], vec![], options);
+    if let Some((path, error)) = errors.first() {
+        return Err(format!("{}: {}", path.display(), error));
+    }
+    let scanned: usize = files.iter().map(|file| file.get_paths().len()).sum();
+    if scanned != 9 {
+        return Err(format!("found {} files of 9", scanned));
+    }
+    let relative = |group: &Fileinfo| -> BTreeSet<PathBuf> {
+        group
+            .get_paths()
+            .iter()
+            .map(|path| path.strip_prefix(dir).unwrap_or(path).to_path_buf())
+            .collect()
+    };
+    let found: BTreeSet<BTreeSet<PathBuf>> = files
+        .iter()
+        .filter(|file| file.get_paths().len() > 1)
+        .map(relative)
+        .collect();
+    if found != expected_groups() {
+        return Err(format!(
+            "expected the groups {:?}, found {:?}",
+            expected_groups(),
+            found
+        ));
+    }
+    Ok(())
+}
+
+/// Compares the hashes of `hasher` with published digests, and hashes in pieces with hashes
+/// in one go.
+fn check_known_answers(name: &str, hasher: &dyn ContentHasher) -> Result<(), String> {
+    let known = match name {
+        "sha256" => Some(0xba7816bf8f01cfea414140de5dae2223),
+        "blake3" => Some(0x6437b3ac38465133ffb63b75273a8db5),
+        _ => None,
+    };
+    if let Some(known) = known {
+        let hash = hasher.hash_bytes(b"abc");
+        if hash != known {
+            return Err(format!(
+                "hashed \"abc\" to {:032x} instead of {:032x}",
+                hash, known
+            ));
+        }
+    }
+    let large = large_contents();
+    let mut state = hasher.begin();
+    large.chunks(4093).for_each(|chunk| state.update(chunk));
+    if state.finish() != hasher.hash_bytes(&large) {
+        return Err("hashing in pieces differs from hashing in one go".to_string());
+    }
+    Ok(())
+}
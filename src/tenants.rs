@@ -0,0 +1,168 @@
+//! Scoping of shared scan results to the users allowed to see them.
+//!
+//! A host scanning directories of several users answers each of them from the same results,
+//! restricted to the directories of their scope. A scopes file lists each scope with the token
+//! its clients present:
+//!
+//! ```json
+//! {
+//!     "scopes": [
+//!         { "name": "alice", "token": "9f2c...", "directories": ["/srv/home/alice", "/srv/shared"] },
+//!         { "name": "bob", "token": "71ab...", "directories": ["/srv/home/bob"] }
+//!     ]
+//! }
+//! ```
+//!
+//! `ddh query` answers one client at a time, e.g. as the forced command of an SSH key: it reads
+//! the token from stdin, authorizes it with `Scopes::authorize` and only returns what
+//! `Scope::restrict` leaves of the groups of a saved scan.
+
+use crate::fileinfo::{FileMetadata, Fileinfo};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directories one set of clients may query.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scope {
+    pub name: String,
+    /// Secret presented by the clients of this scope.
+    token: String,
+    /// Canonical directories whose files the clients may see.
+    pub directories: Vec<PathBuf>,
+}
+
+/// Scopes of a scopes file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scopes {
+    pub scopes: Vec<Scope>,
+}
+
+impl Scopes {
+    /// Loads a scopes file, resolving symlinks in the directories so they compare with the
+    /// canonical paths of scan results. Scopes with an empty token are rejected, since any
+    /// client could present it.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut scopes: Scopes = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for scope in scopes.scopes.iter_mut() {
+            if scope.token.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("scope {} has an empty token", scope.name),
+                ));
+            }
+            for dir in scope.directories.iter_mut() {
+                if let Ok(canonical) = fs::canonicalize(&dir) {
+                    *dir = canonical;
+                }
+            }
+        }
+        Ok(scopes)
+    }
+
+    /// Finds the scope whose token is `token`. Digests of the tokens are compared in constant
+    /// time, against every scope, so neither their contents nor their lengths can be guessed
+    /// from response times.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::tenants::{Scope, Scopes};
+    ///
+    /// let scopes = Scopes {
+    ///     scopes: vec![
+    ///         Scope::new("alice", "secret", vec![]),
+    ///         Scope::new("bob", "other secret", vec![]),
+    ///     ],
+    /// };
+    /// assert_eq!(scopes.authorize("other secret").unwrap().name, "bob");
+    /// assert!(scopes.authorize("secre").is_none());
+    /// ```
+    pub fn authorize(&self, token: &str) -> Option<&Scope> {
+        let presented = Sha256::digest(token.as_bytes());
+        self.scopes.iter().fold(None, |found, scope| {
+            let matches = constant_time_eq(&Sha256::digest(scope.token.as_bytes()), &presented);
+            found.or(matches.then_some(scope))
+        })
+    }
+}
+
+impl Scope {
+    /// Creates a scope of `directories`, which should be canonical, for clients presenting
+    /// `token`.
+    pub fn new(name: &str, token: &str, directories: Vec<PathBuf>) -> Self {
+        Scope {
+            name: name.to_string(),
+            token: token.to_string(),
+            directories,
+        }
+    }
+
+    /// Restricts `group` to the instances within this scope. Groups with fewer than two such
+    /// instances yield `None`, so clients learn nothing about copies outside their scope, not
+    /// even that there are any.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::fileinfo::Fileinfo;
+    /// use ddh::tenants::Scope;
+    /// use std::path::PathBuf;
+    ///
+    /// let group = ddh::consolidate(vec![
+    ///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/srv/home/alice/a.iso")),
+    ///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/srv/shared/a.iso")),
+    ///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/srv/home/bob/a.iso")),
+    /// ]);
+    /// let alice = Scope::new("alice", "secret", vec![
+    ///     PathBuf::from("/srv/home/alice"),
+    ///     PathBuf::from("/srv/shared"),
+    /// ]);
+    /// let bob = Scope::new("bob", "other secret", vec![PathBuf::from("/srv/home/bob")]);
+    ///
+    /// assert_eq!(alice.restrict(&group[0]).unwrap().get_paths().len(), 2);
+    /// assert!(bob.restrict(&group[0]).is_none());
+    /// ```
+    pub fn restrict(&self, group: &Fileinfo) -> Option<Fileinfo> {
+        let mut visible = group
+            .get_paths()
+            .iter()
+            .filter(|path| self.directories.iter().any(|dir| path.starts_with(dir)))
+            .map(|path| path.to_path_buf());
+        let first = visible.next()?;
+        let mut restricted = Fileinfo::new(
+            group.get_full_hash(),
+            group.get_partial_hash(),
+            /* The metadata may be that of an instance outside the scope. */
+            FileMetadata::with_size(group.get_length()),
+            first,
+        );
+        restricted
+            .file_paths
+            .extend(visible.map(PathBuf::into_boxed_path));
+        if restricted.get_paths().len() < 2 {
+            return None;
+        }
+        Some(restricted)
+    }
+}
+
+/* Leaves the token out so it doesn't end up in logs. */
+impl fmt::Debug for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("name", &self.name)
+            .field("directories", &self.directories)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Compares `a` and `b` in a time which only depends on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,222 @@
+//! Limits on how much IO the engine has in flight.
+//!
+//! Hashing runs on every available core, but a spinning disk (or a network share) serves a
+//! handful of concurrent readers far better than dozens. A `ReadLimiter` caps the number of
+//! files being read at once, so the disk isn't made to seek back and forth between dozens.
+//! A limiter may also hold reads back during a `PauseWindow`, such as the office hours of a NAS
+//! serving users by day, so scans running for days only read at night.
+
+use crate::cancel::CancellationHandle;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a paused read or walk sleeps before looking at the clock and handles again.
+pub(crate) const PAUSE_POLL: Duration = Duration::from_millis(250);
+
+/// Counting semaphore bounding the number of files read concurrently.
+#[derive(Debug)]
+pub struct ReadLimiter {
+    available: Mutex<usize>,
+    released: Condvar,
+    pause: Option<PauseWindow>,
+    /// Whether `pause` held reads back when last looked up, as `second << 1 | paused`, the
+    /// second counting from `created` and starting at one so that zero means never. The local
+    /// time is only looked up once a second, however many reads start.
+    pause_state: AtomicU64,
+    created: Instant,
+    /// Handle ending pauses early when the scan is cancelled.
+    cancel: Option<CancellationHandle>,
+}
+
+impl ReadLimiter {
+    /// Creates a limiter allowing `max_concurrent` reads at a time (at least one).
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::throttle::ReadLimiter;
+    ///
+    /// let limiter = ReadLimiter::new(2);
+    /// let first = limiter.acquire();
+    /// let second = limiter.acquire();
+    /// drop(first);
+    /// let third = limiter.acquire();
+    /// ```
+    pub fn new(max_concurrent: usize) -> Self {
+        ReadLimiter {
+            available: Mutex::new(max_concurrent.max(1)),
+            released: Condvar::new(),
+            pause: None,
+            pause_state: AtomicU64::new(0),
+            created: Instant::now(),
+            cancel: None,
+        }
+    }
+    /// Holds reads back while the local time is within `window`. Reads already started carry
+    /// on. Once `cancel` is cancelled reads no longer wait, so the scan can wind down.
+    pub fn paused_during(self, window: PauseWindow, cancel: Option<CancellationHandle>) -> Self {
+        ReadLimiter {
+            pause: Some(window),
+            cancel,
+            ..self
+        }
+    }
+    /// Blocks until a file may be read. Reading may continue until the permit is dropped.
+    pub fn acquire(&self) -> ReadPermit<'_> {
+        let cancelled = || self.cancel.as_ref().is_some_and(|c| c.is_cancelled());
+        while self.in_window() && !cancelled() {
+            thread::sleep(PAUSE_POLL);
+        }
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        ReadPermit { limiter: self }
+    }
+    /// Returns true if the local time is within the pause window, looking it up at most once
+    /// a second.
+    fn in_window(&self) -> bool {
+        let window = match self.pause {
+            Some(window) => window,
+            None => return false,
+        };
+        let second = self.created.elapsed().as_secs() + 1;
+        let state = self.pause_state.load(Ordering::Relaxed);
+        if state >> 1 == second {
+            return state & 1 == 1;
+        }
+        let paused = window.contains(local_minute_of_day());
+        self.pause_state
+            .store(second << 1 | u64::from(paused), Ordering::Relaxed);
+        paused
+    }
+}
+
+/// Permission to perform one read, returned to the limiter on drop.
+#[derive(Debug)]
+pub struct ReadPermit<'a> {
+    limiter: &'a ReadLimiter,
+}
+
+impl Drop for ReadPermit<'_> {
+    fn drop(&mut self) {
+        *self.limiter.available.lock().unwrap() += 1;
+        self.limiter.released.notify_one();
+    }
+}
+
+/// Daily span of local time, given as `HH:MM-HH:MM`. A span ending before it starts runs over
+/// midnight. The start is within the window, the end isn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PauseWindow {
+    /// Minutes after midnight.
+    start: u32,
+    end: u32,
+}
+
+impl PauseWindow {
+    /// Returns true if `minute`, counted from midnight, is within the window.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::throttle::PauseWindow;
+    ///
+    /// let office: PauseWindow = "09:00-17:00".parse().unwrap();
+    /// assert!(office.contains(9 * 60) && office.contains(16 * 60 + 59));
+    /// assert!(!office.contains(17 * 60) && !office.contains(3 * 60));
+    /// let night: PauseWindow = "22:30-06:00".parse().unwrap();
+    /// assert!(night.contains(23 * 60) && night.contains(60) && !night.contains(12 * 60));
+    /// assert!("17:00-17:00".parse::<PauseWindow>().is_err());
+    /// assert!("9-17".parse::<PauseWindow>().is_err());
+    /// ```
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for PauseWindow {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid window {:?}, expected HH:MM-HH:MM", s);
+        let minutes = |time: &str| {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let hours: u32 = hours.parse().ok().filter(|h| *h < 24)?;
+            let minutes: u32 = minutes.parse().ok().filter(|m| *m < 60)?;
+            Some(hours * 60 + minutes)
+        };
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let window = PauseWindow {
+            start: minutes(start).ok_or_else(invalid)?,
+            end: minutes(end).ok_or_else(invalid)?,
+        };
+        if window.start == window.end {
+            return Err(format!("window {:?} is empty", s));
+        }
+        Ok(window)
+    }
+}
+
+/// Minutes since midnight in the local time zone.
+#[cfg(unix)]
+fn local_minute_of_day() -> u32 {
+    let now = unix_seconds() as libc::time_t;
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut local) }.is_null() {
+        return utc_minute_of_day();
+    }
+    (local.tm_hour * 60 + local.tm_min) as u32
+}
+
+/// `SYSTEMTIME` of the Windows API.
+#[cfg(windows)]
+#[repr(C)]
+struct WinSystemTime {
+    year: u16,
+    month: u16,
+    day_of_week: u16,
+    day: u16,
+    hour: u16,
+    minute: u16,
+    second: u16,
+    milliseconds: u16,
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetLocalTime(system_time: *mut WinSystemTime);
+}
+
+/// Minutes since midnight in the local time zone.
+#[cfg(windows)]
+fn local_minute_of_day() -> u32 {
+    let mut local: WinSystemTime = unsafe { std::mem::zeroed() };
+    unsafe { GetLocalTime(&mut local) };
+    u32::from(local.hour) * 60 + u32::from(local.minute)
+}
+
+/// Minutes since midnight, in UTC where the local time zone isn't known.
+#[cfg(not(any(unix, windows)))]
+fn local_minute_of_day() -> u32 {
+    utc_minute_of_day()
+}
+
+#[cfg(not(windows))]
+fn utc_minute_of_day() -> u32 {
+    (unix_seconds() % 86_400 / 60) as u32
+}
+
+#[cfg(not(windows))]
+fn unix_seconds() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
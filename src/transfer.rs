@@ -0,0 +1,222 @@
+//! Copies of directory trees which don't store any contents twice.
+//!
+//! `copy_tree` copies a tree like `cp -r`, except that files whose contents the destination
+//! already holds are hard linked or cloned from the file there instead of written again. Files
+//! copied earlier in the same run count as well, so duplicates within the source end up stored
+//! once. Contents are compared byte by byte before linking, so equal hashes alone never make
+//! two files one. Hard links share permissions and timestamps with the file they link to, while
+//! clones stay independent files but need a filesystem such as btrfs, XFS or APFS; files which
+//! can't be linked are copied.
+//!
+//! Symlinks of the source are left out and counted, other special files are reported as errors.
+
+use crate::agent::complete_hashes;
+use crate::fileinfo::{hash_file, HashMode};
+use crate::reflink;
+use crate::utils::contents_equal;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How files whose contents are already at the destination are created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Hard links to the existing file.
+    Hard,
+    /// Copy-on-write clones of the existing file.
+    Reflink,
+}
+
+/// Outcome of a `copy_tree`.
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    /// Files whose contents were written.
+    pub copied: u64,
+    pub copied_bytes: u64,
+    /// Files linked to contents already at the destination.
+    pub linked: u64,
+    pub linked_bytes: u64,
+    /// Symlinks of the source, which are left out.
+    pub skipped_symlinks: u64,
+    /// Source entries which weren't copied, and why.
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+/// A regular file of the source tree.
+struct SourceFile {
+    relative: PathBuf,
+    length: u64,
+    hash: Option<u128>,
+}
+
+/// Copies the tree below `src` into `dst`, creating `dst` if needed, and links files whose
+/// contents are already below `dst` or were copied before. Existing files of `dst` are never
+/// overwritten; sources colliding with one are reported as errors. A `dst` inside `src` is
+/// refused, as the copy would end up copying itself.
+///
+/// # Examples
+/// ```
+/// use ddh::transfer::{copy_tree, LinkMode};
+///
+/// fn main() -> std::io::Result<()> {
+/// let dir = std::env::temp_dir().join("ddh_transfer_example");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// std::fs::create_dir_all(dir.join("src/sub"))?;
+/// std::fs::create_dir_all(dir.join("dst"))?;
+/// std::fs::write(dir.join("dst/old.txt"), "kept before")?;
+/// std::fs::write(dir.join("src/a.txt"), "kept before")?;
+/// std::fs::write(dir.join("src/sub/b.txt"), "twice in the source")?;
+/// std::fs::write(dir.join("src/sub/c.txt"), "twice in the source")?;
+///
+/// let report = copy_tree(&dir.join("src"), &dir.join("dst"), LinkMode::Hard)?;
+/// assert_eq!((report.copied, report.linked), (1, 2));
+/// assert_eq!(std::fs::read_to_string(dir.join("dst/a.txt"))?, "kept before");
+/// assert_eq!(std::fs::read_to_string(dir.join("dst/sub/c.txt"))?, "twice in the source");
+/// assert!(copy_tree(&dir.join("src"), &dir.join("src/sub/copy"), LinkMode::Hard).is_err());
+/// std::fs::remove_dir_all(&dir)?;
+/// Ok(())
+/// }
+/// ```
+pub fn copy_tree(src: &Path, dst: &Path, mode: LinkMode) -> io::Result<CopyReport> {
+    if resolve(dst)?.starts_with(src.canonicalize()?) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "destination is inside the source",
+        ));
+    }
+    let mut report = CopyReport::default();
+    let (dirs, mut files) = walk(src, &mut report)?;
+
+    let mut lengths: HashMap<u64, usize> = HashMap::new();
+    for file in files.iter() {
+        *lengths.entry(file.length).or_default() += 1;
+    }
+    let mut existing = Vec::new();
+    if dst.is_dir() {
+        let (scanned, _) = crate::deduplicate_dirs(vec![dst], vec![], 1);
+        existing = scanned;
+        let wanted: HashSet<u64> = lengths.keys().copied().collect();
+        complete_hashes(&mut existing, Some(&wanted));
+    }
+    let mut stored: HashMap<(u64, u128), PathBuf> = HashMap::new();
+    for file in existing.iter() {
+        if let (Some(hash), Some(path)) = (file.get_full_hash(), file.get_paths().first()) {
+            stored.insert((file.get_length(), hash), path.to_path_buf());
+        }
+    }
+
+    /* Only files which may share their contents with another are worth hashing. */
+    let candidates: HashSet<u64> = stored.keys().map(|(length, _)| *length).collect();
+    files.par_iter_mut().for_each(|file| {
+        let shared = candidates.contains(&file.length) || lengths[&file.length] > 1;
+        if shared && file.length > 0 {
+            file.hash = hash_file(&src.join(&file.relative), HashMode::Full);
+        }
+    });
+
+    fs::create_dir_all(dst)?;
+    for dir in dirs.iter() {
+        if let Err(e) = fs::create_dir_all(dst.join(dir)) {
+            report.errors.push((src.join(dir), e));
+        }
+    }
+    for file in files.iter() {
+        let source = src.join(&file.relative);
+        let target = dst.join(&file.relative);
+        if fs::symlink_metadata(&target).is_ok() {
+            report.errors.push((
+                source,
+                io::Error::new(io::ErrorKind::AlreadyExists, "destination already exists"),
+            ));
+            continue;
+        }
+        let key = file.hash.map(|hash| (file.length, hash));
+        let original = key
+            .and_then(|key| stored.get(&key))
+            .filter(|original| contents_equal(&source, original).unwrap_or(false));
+        let linked = original.is_some_and(|original| match mode {
+            LinkMode::Hard => fs::hard_link(original, &target).is_ok(),
+            LinkMode::Reflink => reflink::clone_file(original, &target).is_ok(),
+        });
+        if linked {
+            report.linked += 1;
+            report.linked_bytes += file.length;
+            continue;
+        }
+        match fs::copy(&source, &target) {
+            Ok(_) => {
+                report.copied += 1;
+                report.copied_bytes += file.length;
+                if let Some(key) = key {
+                    stored.entry(key).or_insert(target);
+                }
+            }
+            Err(e) => report.errors.push((source, e)),
+        }
+    }
+    Ok(report)
+}
+
+/// Resolves symlinks in `path`, which may not exist yet, by resolving its closest existing
+/// ancestor.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    match path.canonicalize() {
+        Ok(resolved) => Ok(resolved),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => Ok(resolve(parent)?.join(name)),
+            _ => std::env::current_dir(),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Lists the directories and regular files below `root`, relative to it and in name order,
+/// counting symlinks and reporting other entries in `report`.
+fn walk(root: &Path, report: &mut CopyReport) -> io::Result<(Vec<PathBuf>, Vec<SourceFile>)> {
+    let errors = &mut report.errors;
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(root.join(&dir)) {
+            Ok(entries) => entries,
+            Err(e) if dir.as_os_str().is_empty() => return Err(e),
+            Err(e) => {
+                errors.push((root.join(&dir), e));
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    errors.push((root.join(&dir), e));
+                    continue;
+                }
+            };
+            let relative = dir.join(entry.file_name());
+            match entry.metadata() {
+                Ok(meta) if meta.is_dir() => {
+                    dirs.push(relative.clone());
+                    pending.push(relative);
+                }
+                Ok(meta) if meta.is_file() => files.push(SourceFile {
+                    relative,
+                    length: meta.len(),
+                    hash: None,
+                }),
+                Ok(meta) if meta.file_type().is_symlink() => report.skipped_symlinks += 1,
+                Ok(_) => errors.push((
+                    entry.path(),
+                    io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"),
+                )),
+                Err(e) => errors.push((entry.path(), e)),
+            }
+        }
+    }
+    dirs.sort();
+    files.sort_by(|a, b| a.relative.cmp(&b.relative));
+    Ok((dirs, files))
+}
@@ -0,0 +1,306 @@
+//! Directories whose whole trees are duplicates of each other.
+//!
+//! Every directory below the searched roots gets a Merkle hash covering the names of its entries,
+//! the contents of its files and the hashes of its subdirectories, so two directories with equal
+//! hashes hold the same names with the same contents all the way down. Contents are told apart
+//! by the groups of a scan, so only the files the scan found count: files it skipped, such as
+//! ones below the minimum size, ignored ones and symlinks, and empty directories, are left out of
+//! the comparison.
+//!
+//! Directories which are nearly identical, such as backups where a few files changed, are found
+//! by the bytes of contents their trees share instead, as counted from the groups of the scan.
+
+use crate::fileinfo::Fileinfo;
+use siphasher::sip128::{Hasher128, SipHasher};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+/// Directories with identical trees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeGroup {
+    /// Bytes of the files in one of the trees.
+    pub size: u64,
+    /// Number of files in one of the trees.
+    pub files: u64,
+    /// The directories, in sorted order.
+    pub dirs: Vec<PathBuf>,
+}
+
+impl TreeGroup {
+    /// Counts the bytes taken up by all trees but one.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.dirs.len() as u64).saturating_sub(1)
+    }
+}
+
+/// Two directories whose trees share contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    /// The directory sorting first.
+    pub a: PathBuf,
+    pub b: PathBuf,
+    /// Bytes of the distinct contents in the tree of `a`.
+    pub a_size: u64,
+    /// Bytes of the distinct contents in the tree of `b`.
+    pub b_size: u64,
+    /// Bytes of the distinct contents in both trees.
+    pub shared: u64,
+}
+
+impl Overlap {
+    /// Gets the shared bytes as a fraction of the larger tree, from 0 to 1.
+    pub fn similarity(&self) -> f64 {
+        match self.a_size.max(self.b_size) {
+            0 => 0.0,
+            larger => self.shared as f64 / larger as f64,
+        }
+    }
+}
+
+/// Entry of a directory, keyed by its name.
+enum Entry {
+    /// A file, by the index of its group.
+    File(usize),
+    Dir,
+}
+
+/// Summed up tree of a directory.
+#[derive(Clone, Copy)]
+struct Tree {
+    hash: u128,
+    size: u64,
+    files: u64,
+}
+
+/// Finds the directories at or below `roots` whose trees of `files` are identical, the groups of
+/// a scan of `roots` with the paths the scan found. Only the highest identical directories are
+/// reported: a group is left out when the parents of all its directories are identical as well.
+/// Groups come sorted by the bytes they waste, largest first.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::PathBuf;
+///
+/// let files = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/data/a/docs/report.txt")),
+///     Fileinfo::from_existing_hash(1, None, 10, PathBuf::from("/data/b/docs/report.txt")),
+///     Fileinfo::from_existing_hash(2, None, 20, PathBuf::from("/data/a/docs/old/notes.txt")),
+///     Fileinfo::from_existing_hash(2, None, 20, PathBuf::from("/data/b/docs/old/notes.txt")),
+///     Fileinfo::from_existing_hash(3, None, 5, PathBuf::from("/data/b/todo.txt")),
+/// ]);
+/// let groups = ddh::trees::duplicate_trees(&files, &[PathBuf::from("/data")]);
+/// assert_eq!(groups.len(), 1);
+/// assert_eq!(groups[0].dirs, [PathBuf::from("/data/a/docs"), PathBuf::from("/data/b/docs")]);
+/// assert_eq!((groups[0].size, groups[0].files), (30, 2));
+/// ```
+pub fn duplicate_trees(files: &[Fileinfo], roots: &[PathBuf]) -> Vec<TreeGroup> {
+    let within = |dir: &Path| roots.iter().any(|root| dir.starts_with(root));
+    let mut entries: BTreeMap<PathBuf, BTreeMap<OsString, Entry>> = BTreeMap::new();
+    for (index, file) in files.iter().enumerate() {
+        for path in file.get_paths().iter() {
+            let mut child: &Path = path;
+            let mut entry = Entry::File(index);
+            while let (Some(dir), Some(name)) = (child.parent(), child.file_name()) {
+                if !within(dir) {
+                    break;
+                }
+                let known = entries.contains_key(dir);
+                entries
+                    .entry(dir.to_path_buf())
+                    .or_default()
+                    .insert(name.to_os_string(), entry);
+                /* The directories above one already seen are already known as well. */
+                if known {
+                    break;
+                }
+                child = dir;
+                entry = Entry::Dir;
+            }
+        }
+    }
+    /* Deepest directories first, so every subdirectory is summed up before its parent. */
+    let mut dirs: Vec<&PathBuf> = entries.keys().collect();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    let mut trees: HashMap<&Path, Tree> = HashMap::new();
+    for dir in dirs {
+        let mut hasher = SipHasher::new();
+        let (mut size, mut count) = (0, 0);
+        for (name, entry) in entries[dir].iter() {
+            hasher.write(name.to_string_lossy().as_bytes());
+            hasher.write_u8(0);
+            match entry {
+                Entry::File(index) => {
+                    hasher.write_u8(b'f');
+                    hasher.write_u64(*index as u64);
+                    size += files[*index].get_length();
+                    count += 1;
+                }
+                Entry::Dir => {
+                    let tree = trees[dir.join(name).as_path()];
+                    hasher.write_u8(b'd');
+                    hasher.write_u128(tree.hash);
+                    size += tree.size;
+                    count += tree.files;
+                }
+            }
+        }
+        let tree = Tree {
+            hash: hasher.finish128().into(),
+            size,
+            files: count,
+        };
+        trees.insert(dir.as_path(), tree);
+    }
+    let mut by_hash: HashMap<u128, Vec<&Path>> = HashMap::new();
+    for (dir, tree) in trees.iter() {
+        by_hash.entry(tree.hash).or_default().push(dir);
+    }
+    by_hash.retain(|_, dirs| dirs.len() > 1);
+    let duplicated = |dir: &Path| {
+        dir.parent()
+            .and_then(|parent| trees.get(parent))
+            .is_some_and(|tree| by_hash.contains_key(&tree.hash))
+    };
+    let mut groups: Vec<TreeGroup> = by_hash
+        .values()
+        .filter(|dirs| !dirs.iter().all(|dir| duplicated(dir)))
+        .map(|dirs| {
+            let tree = trees[dirs[0]];
+            let mut dirs: Vec<PathBuf> = dirs.iter().map(|dir| dir.to_path_buf()).collect();
+            dirs.sort();
+            TreeGroup {
+                size: tree.size,
+                files: tree.files,
+                dirs,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.wasted()
+            .cmp(&a.wasted())
+            .then_with(|| a.dirs.cmp(&b.dirs))
+    });
+    groups
+}
+
+/// Finds the pairs of directories at or below `roots` whose trees of `files`, the groups of a
+/// scan of `roots`, share at least `threshold` of the bytes of the larger tree. Each contents
+/// counts once per tree however many copies the tree holds. Directories holding one another are
+/// never paired, and a pair is left out when the parents of its directories form a pair too.
+/// Pairs come sorted by the bytes they share, largest first. Only directories sharing one of
+/// the largest contents of both trees are compared, so the lower `threshold`, the more pairs
+/// are.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::PathBuf;
+///
+/// let files = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 90, PathBuf::from("/data/photos/a.jpg")),
+///     Fileinfo::from_existing_hash(1, None, 90, PathBuf::from("/data/backup/a.jpg")),
+///     Fileinfo::from_existing_hash(2, None, 10, PathBuf::from("/data/photos/b.jpg")),
+///     Fileinfo::from_existing_hash(3, None, 5, PathBuf::from("/data/backup/b.jpg")),
+/// ]);
+/// let pairs = ddh::trees::similar_dirs(&files, &[PathBuf::from("/data")], 0.8);
+/// assert_eq!(pairs.len(), 1);
+/// assert_eq!(pairs[0].a, PathBuf::from("/data/backup"));
+/// assert_eq!(pairs[0].b, PathBuf::from("/data/photos"));
+/// assert_eq!((pairs[0].a_size, pairs[0].b_size, pairs[0].shared), (95, 100, 90));
+/// assert_eq!(pairs[0].similarity(), 0.9);
+/// ```
+pub fn similar_dirs(files: &[Fileinfo], roots: &[PathBuf], threshold: f64) -> Vec<Overlap> {
+    let within = |dir: &Path| roots.iter().any(|root| dir.starts_with(root));
+    let mut sizes: HashMap<&Path, u64> = HashMap::new();
+    let mut contents: HashMap<&Path, Vec<usize>> = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        let dirs: BTreeSet<&Path> = file
+            .get_paths()
+            .iter()
+            .flat_map(|path| path.ancestors().skip(1).take_while(|dir| within(dir)))
+            .collect();
+        for dir in dirs.into_iter() {
+            *sizes.entry(dir).or_default() += file.get_length();
+            if file.get_paths().len() > 1 {
+                contents.entry(dir).or_default().push(index);
+            }
+        }
+    }
+    /* Two trees sharing `threshold` of the larger one share one of the largest contents of
+    each, those without which less than `threshold` of the tree is left. Only directories
+    sharing one of those are compared, so small files copied all over the tree don't pair up
+    every directory above them. */
+    let order = |index: &usize| (Reverse(files[*index].get_length()), *index);
+    let mut largest: HashMap<usize, Vec<&Path>> = HashMap::new();
+    for (dir, indices) in contents.iter_mut() {
+        indices.sort_by_key(order);
+        let floor = threshold * sizes[dir] as f64;
+        let mut rest: u64 = indices.iter().map(|index| files[*index].get_length()).sum();
+        for index in indices.iter() {
+            if (rest as f64) < floor {
+                break;
+            }
+            largest.entry(*index).or_default().push(*dir);
+            rest -= files[*index].get_length();
+        }
+    }
+    let mut candidates: HashSet<(&Path, &Path)> = HashSet::new();
+    for dirs in largest.values_mut() {
+        dirs.sort();
+        for (index, a) in dirs.iter().enumerate() {
+            for b in dirs[index + 1..].iter() {
+                if !b.starts_with(a) && !a.starts_with(b) {
+                    candidates.insert((a, b));
+                }
+            }
+        }
+    }
+    let shared = |a: &[usize], b: &[usize]| {
+        let (mut x, mut y, mut shared) = (0, 0, 0);
+        while x < a.len() && y < b.len() {
+            match order(&a[x]).cmp(&order(&b[y])) {
+                Ordering::Less => x += 1,
+                Ordering::Greater => y += 1,
+                Ordering::Equal => {
+                    shared += files[a[x]].get_length();
+                    x += 1;
+                    y += 1;
+                }
+            }
+        }
+        shared
+    };
+    let pairs: Vec<Overlap> = candidates
+        .into_iter()
+        .map(|(a, b)| Overlap {
+            a: a.to_path_buf(),
+            b: b.to_path_buf(),
+            a_size: sizes[a],
+            b_size: sizes[b],
+            shared: shared(&contents[a], &contents[b]),
+        })
+        .filter(|pair| pair.shared > 0 && pair.similarity() >= threshold)
+        .collect();
+    let paired: HashSet<(&Path, &Path)> = pairs
+        .iter()
+        .map(|pair| (pair.a.as_path(), pair.b.as_path()))
+        .collect();
+    let mut reported: Vec<Overlap> = pairs
+        .iter()
+        .filter(|pair| match (pair.a.parent(), pair.b.parent()) {
+            (Some(a), Some(b)) => !paired.contains(&(a, b)) && !paired.contains(&(b, a)),
+            _ => true,
+        })
+        .cloned()
+        .collect();
+    reported.sort_by(|x, y| {
+        y.shared
+            .cmp(&x.shared)
+            .then_with(|| (&x.a, &x.b).cmp(&(&y.a, &y.b)))
+    });
+    reported
+}
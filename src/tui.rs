@@ -0,0 +1,282 @@
+//! Interactive review of duplicate groups in the terminal.
+//!
+//! Groups are listed by wasted space next to the instances of the selected group. A group can
+//! be marked to have its duplicates removed or replaced by hard links to the instance being
+//! kept. Nothing is changed while reviewing: the marks are returned once committed and
+//! confirmed, and the caller applies them with `actions::Executor`.
+//!
+//! Only built with the `tui` feature.
+
+use crate::copies::CopyPatterns;
+use crate::fileinfo::Fileinfo;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::path::Path;
+
+const HELP: &str =
+    "j/k move  tab switch pane  enter keep instance  d delete  l hard link  u unmark  c commit  q quit";
+
+/// Change to make to the duplicates of a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    /// Remove every instance but the kept one.
+    Delete,
+    /// Replace every instance but the kept one with a hard link to it.
+    HardLink,
+}
+
+/// A group marked during review.
+#[derive(Debug)]
+pub struct Decision<'a> {
+    pub group: &'a Fileinfo,
+    /// Instance which survives.
+    pub keep: &'a Path,
+    pub mark: Mark,
+}
+
+struct Entry<'a> {
+    group: &'a Fileinfo,
+    /// Instances in sorted order.
+    paths: Vec<&'a Path>,
+    waste: u64,
+    keep: usize,
+    mark: Option<Mark>,
+}
+
+#[derive(PartialEq)]
+enum Focus {
+    Groups,
+    Instances,
+}
+
+struct Review<'a> {
+    entries: Vec<Entry<'a>>,
+    groups: ListState,
+    instances: ListState,
+    focus: Focus,
+    confirming: bool,
+}
+
+/// Shows `groups` for review until the user commits their marks or quits, in which case
+/// nothing is returned. The instance kept by default is the one duplicate reports keep.
+pub fn review<'a>(
+    groups: &[&'a Fileinfo],
+    patterns: &CopyPatterns,
+) -> io::Result<Vec<Decision<'a>>> {
+    let mut entries: Vec<Entry> = groups
+        .iter()
+        .map(|&group| {
+            let mut paths: Vec<&Path> = group.get_paths().iter().map(|path| &**path).collect();
+            paths.sort();
+            Entry {
+                group,
+                /* Same as `prefer_originals` over the sorted paths. */
+                keep: paths
+                    .iter()
+                    .position(|path| !patterns.is_copy(path))
+                    .unwrap_or(0),
+                paths,
+                waste: crate::waste::duplicate_bytes(std::iter::once(group)),
+                mark: None,
+            }
+        })
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.waste));
+    let mut review = Review {
+        entries,
+        groups: ListState::default(),
+        instances: ListState::default(),
+        focus: Focus::Groups,
+        confirming: false,
+    };
+    if !review.entries.is_empty() {
+        review.groups.select(Some(0));
+        review.instances.select(Some(0));
+    }
+    let mut terminal = ratatui::init();
+    let result = review.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl<'a> Review<'a> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Vec<Decision<'a>>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            if self.confirming {
+                if key.code == KeyCode::Char('y') {
+                    return Ok(self.decisions());
+                }
+                self.confirming = false;
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(Vec::new()),
+                KeyCode::Down | KeyCode::Char('j') => self.step(1),
+                KeyCode::Up | KeyCode::Char('k') => self.step(-1),
+                KeyCode::PageDown => self.step(20),
+                KeyCode::PageUp => self.step(-20),
+                KeyCode::Tab | KeyCode::Left | KeyCode::Right => {
+                    self.focus = match self.focus {
+                        Focus::Groups => Focus::Instances,
+                        Focus::Instances => Focus::Groups,
+                    }
+                }
+                KeyCode::Enter | KeyCode::Char(' ') if self.focus == Focus::Instances => {
+                    let instance = self.instances.selected();
+                    if let (Some(entry), Some(instance)) = (self.selected_mut(), instance) {
+                        entry.keep = instance;
+                    }
+                }
+                KeyCode::Char('d') => self.mark(Some(Mark::Delete)),
+                KeyCode::Char('l') => self.mark(Some(Mark::HardLink)),
+                KeyCode::Char('u') => self.mark(None),
+                KeyCode::Char('c') => self.confirming = self.marked() > 0,
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+        let bold = Style::new().add_modifier(Modifier::BOLD);
+        let (groups_border, instances_border) = match self.focus {
+            Focus::Groups => (bold, Style::new()),
+            Focus::Instances => (Style::new(), bold),
+        };
+
+        let groups: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                ListItem::new(format!(
+                    "{:<7}{} ({} bytes wasted, {} instances)",
+                    mark_label(entry.mark),
+                    entry.group.get_candidate_name(),
+                    entry.waste,
+                    entry.paths.len()
+                ))
+            })
+            .collect();
+        let groups = List::new(groups)
+            .block(
+                Block::bordered()
+                    .title(format!("Duplicate groups ({})", self.entries.len()))
+                    .border_style(groups_border),
+            )
+            .highlight_style(highlight)
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(groups, left, &mut self.groups);
+
+        let instances: Vec<ListItem> = match self.selected() {
+            Some(entry) => entry
+                .paths
+                .iter()
+                .enumerate()
+                .map(|(i, path)| {
+                    let label = match entry.mark {
+                        _ if i == entry.keep => "keep",
+                        Some(Mark::Delete) => "delete",
+                        Some(Mark::HardLink) => "link",
+                        None => "",
+                    };
+                    ListItem::new(format!("{:<7}{}", label, path.display()))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let instances = List::new(instances)
+            .block(
+                Block::bordered()
+                    .title("Instances")
+                    .border_style(instances_border),
+            )
+            .highlight_style(highlight)
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(instances, right, &mut self.instances);
+
+        let status_line = if self.confirming {
+            format!(
+                "Apply the marks on {} groups? y to confirm, any other key to go back",
+                self.marked()
+            )
+        } else {
+            format!("{} marked  {}", self.marked(), HELP)
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+
+    fn selected(&self) -> Option<&Entry<'a>> {
+        self.entries.get(self.groups.selected()?)
+    }
+
+    fn selected_mut(&mut self) -> Option<&mut Entry<'a>> {
+        self.entries.get_mut(self.groups.selected()?)
+    }
+
+    /// Moves the selection in the focused pane by `offset` rows.
+    fn step(&mut self, offset: isize) {
+        let (state, len) = match self.focus {
+            Focus::Groups => (&mut self.groups, self.entries.len()),
+            Focus::Instances => {
+                let len = self.selected().map_or(0, |entry| entry.paths.len());
+                (&mut self.instances, len)
+            }
+        };
+        if len == 0 {
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        state.select(Some((current + offset).clamp(0, len as isize - 1) as usize));
+        if self.focus == Focus::Groups {
+            self.instances.select(Some(0));
+        }
+    }
+
+    fn mark(&mut self, mark: Option<Mark>) {
+        if let Some(entry) = self.selected_mut() {
+            entry.mark = mark;
+        }
+    }
+
+    fn marked(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.mark.is_some())
+            .count()
+    }
+
+    fn decisions(&self) -> Vec<Decision<'a>> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                Some(Decision {
+                    group: entry.group,
+                    keep: entry.paths.get(entry.keep)?,
+                    mark: entry.mark?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn mark_label(mark: Option<Mark>) -> &'static str {
+    match mark {
+        Some(Mark::Delete) => "[del]",
+        Some(Mark::HardLink) => "[link]",
+        None => "",
+    }
+}
@@ -0,0 +1,112 @@
+//! Disk usage of directory trees with duplicated contents counted once.
+//!
+//! `du` counts every copy of a file, so it can't tell how much deleting a directory would free
+//! when its files have copies elsewhere. `disk_usage` reports three sizes for each directory:
+//! the apparent bytes of every path below it, hard links included; the unique bytes, where each
+//! contents counts once, either all at its first path in sorted order or split evenly between
+//! its paths; and the freed bytes, the contents stored nowhere but below the directory, which
+//! deleting it would free.
+
+use crate::fileinfo::Fileinfo;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// How the bytes of contents with several paths are counted towards unique bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attribution {
+    /// All of them at the first path in sorted order.
+    First,
+    /// Evenly between the paths, the remainder going to the first.
+    Split,
+}
+
+/// Sizes of the files below a directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Every path counted in full, like `du --apparent-size --count-links`.
+    pub apparent: u64,
+    /// Each contents counted once, as told by an `Attribution`.
+    pub unique: u64,
+    /// Contents with no path outside the directory.
+    pub freed: u64,
+}
+
+/// Sums the usage of `files`, each a collection of paths with equal contents, for `root` and
+/// every directory below it holding files. Paths outside `root` aren't counted, but keep the
+/// contents they share from counting as freed.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use ddh::usage::{disk_usage, Attribution, DiskUsage};
+/// use std::path::{Path, PathBuf};
+///
+/// let files = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 100, PathBuf::from("/data/a/x")),
+///     Fileinfo::from_existing_hash(1, None, 100, PathBuf::from("/data/b/x")),
+///     Fileinfo::from_existing_hash(2, None, 30, PathBuf::from("/data/b/y")),
+/// ]);
+/// let usage = disk_usage(&files, Path::new("/data"), Attribution::First);
+/// assert_eq!(usage[Path::new("/data")], DiskUsage { apparent: 230, unique: 130, freed: 130 });
+/// assert_eq!(usage[Path::new("/data/a")], DiskUsage { apparent: 100, unique: 100, freed: 0 });
+/// assert_eq!(usage[Path::new("/data/b")], DiskUsage { apparent: 130, unique: 30, freed: 30 });
+///
+/// let split = disk_usage(&files, Path::new("/data"), Attribution::Split);
+/// assert_eq!(split[Path::new("/data/b")].unique, 80);
+/// ```
+pub fn disk_usage(
+    files: &[Fileinfo],
+    root: &Path,
+    attribution: Attribution,
+) -> BTreeMap<PathBuf, DiskUsage> {
+    let mut usage: BTreeMap<PathBuf, DiskUsage> = BTreeMap::new();
+    for file in files.iter() {
+        let length = file.get_length();
+        let mut paths: Vec<&Path> = file.get_paths().iter().map(|path| &**path).collect();
+        paths.sort();
+        let count = paths.len() as u64;
+        for (index, path) in paths.iter().enumerate() {
+            let unique = match (attribution, index) {
+                (Attribution::First, 0) => length,
+                (Attribution::First, _) => 0,
+                (Attribution::Split, 0) => length / count + length % count,
+                (Attribution::Split, _) => length / count,
+            };
+            for dir in path
+                .parent()
+                .into_iter()
+                .flat_map(|dir| dirs_up_to(dir, root))
+            {
+                let entry = usage.entry(dir.to_path_buf()).or_default();
+                entry.apparent += length;
+                entry.unique += unique;
+            }
+        }
+        if let Some(common) = common_dir(&paths) {
+            for dir in dirs_up_to(&common, root) {
+                usage.entry(dir.to_path_buf()).or_default().freed += length;
+            }
+        }
+    }
+    usage
+}
+
+/// Lists `dir` and the directories above it up to `root`, none if `dir` isn't below `root`.
+fn dirs_up_to<'a>(dir: &'a Path, root: &'a Path) -> impl Iterator<Item = &'a Path> + 'a {
+    let below = dir.starts_with(root);
+    dir.ancestors()
+        .take_while(move |dir| below && dir.starts_with(root))
+}
+
+/// Gets the deepest directory holding all of `paths`.
+fn common_dir(paths: &[&Path]) -> Option<PathBuf> {
+    let mut common = paths.first()?.parent()?.to_path_buf();
+    for path in paths.iter().skip(1) {
+        while !path.starts_with(&common) {
+            if !common.pop() {
+                return None;
+            }
+        }
+    }
+    Some(common)
+}
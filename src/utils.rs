@@ -1,38 +1,224 @@
+//! Streaming helpers for reading files in fixed size chunks.
+
+use crate::throttle::ReadLimiter;
 use std::fs::File;
 use std::io::{self, Read};
+use std::path::Path;
+
+/// Number of bytes of each file held in memory at once while comparing contents.
+pub const COMPARE_WINDOW: usize = 1 << 20;
 
+/// Iterator over the contents of a file in chunks of a fixed size.
+///
+/// Every chunk is full except possibly the last. After a read error the error is yielded once
+/// and iteration ends. Iterating allocates a `Vec` per chunk; `next_chunk` reads into an
+/// internal buffer instead, which `with_buffer` and `into_buffer` allow reusing across files.
+///
+/// # Examples
+/// ```
+/// use ddh::utils::ChunkIter;
+/// use std::fs::{self, File};
+///
+/// fn main() -> std::io::Result<()> {
+/// let path = std::env::temp_dir().join("ddh_chunk_iter_example");
+/// fs::write(&path, b"abcdefghij")?;
+/// let chunks: Vec<Vec<u8>> = ChunkIter::new(File::open(&path)?, 4).collect::<Result<_, _>>()?;
+/// assert_eq!(chunks, [b"abcd".to_vec(), b"efgh".to_vec(), b"ij".to_vec()]);
+/// fs::remove_file(&path)?;
+/// Ok(())
+/// }
+/// ```
+#[derive(Debug)]
 pub struct ChunkIter {
     f: File,
     chunk_len: usize,
+    buffer: Vec<u8>,
+    done: bool,
 }
 
 impl ChunkIter {
+    /// Creates an iterator reading `len` bytes at a time. A length of zero is treated as one.
     pub fn new(f: File, len: usize) -> Self {
+        ChunkIter::with_buffer(f, len, Vec::new())
+    }
+    /// Creates an iterator which reads into `buffer`, so a buffer can be reused across files.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::utils::ChunkIter;
+    /// use std::fs::{self, File};
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let dir = std::env::temp_dir();
+    /// let paths = [dir.join("ddh_chunk_reuse_a"), dir.join("ddh_chunk_reuse_b")];
+    /// fs::write(&paths[0], vec![1u8; 10_000])?;
+    /// fs::write(&paths[1], vec![2u8; 5_000])?;
+    /// let mut buffer = Vec::new();
+    /// let mut total = 0;
+    /// for path in paths.iter() {
+    ///     let mut chunks = ChunkIter::with_buffer(File::open(path)?, 4096, buffer);
+    ///     while let Some(chunk) = chunks.next_chunk() {
+    ///         total += chunk?.len();
+    ///     }
+    ///     buffer = chunks.into_buffer();
+    ///     fs::remove_file(path)?;
+    /// }
+    /// assert_eq!(total, 15_000);
+    /// assert!(buffer.capacity() >= 4096);
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn with_buffer(f: File, len: usize, mut buffer: Vec<u8>) -> Self {
+        let chunk_len = len.max(1);
+        buffer.clear();
+        buffer.reserve(chunk_len);
         ChunkIter {
-            f: f,
-            chunk_len: len,
+            f,
+            chunk_len,
+            buffer,
+            done: false,
         }
     }
-}
-
-impl Iterator for ChunkIter {
-    type Item = Result<Vec<u8>, io::Error>;
-    fn next(&mut self) -> Option<Result<Vec<u8>, io::Error>> {
-        let mut buffer = Vec::with_capacity(self.chunk_len);
+    /// Gets the number of bytes read per chunk.
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+    /// Reads the next chunk into the internal buffer without allocating.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::utils::ChunkIter;
+    /// use std::fs::{self, File};
+    ///
+    /// fn main() -> std::io::Result<()> {
+    /// let path = std::env::temp_dir().join("ddh_chunk_next_example");
+    /// fs::write(&path, b"abcdef")?;
+    /// let mut chunks = ChunkIter::new(File::open(&path)?, 4);
+    /// assert_eq!(chunks.next_chunk().unwrap()?, b"abcd");
+    /// assert_eq!(chunks.next_chunk().unwrap()?, b"ef");
+    /// assert!(chunks.next_chunk().is_none());
+    /// assert!(chunks.next_chunk().is_none());
+    /// fs::remove_file(&path)?;
+    /// Ok(())
+    /// }
+    /// ```
+    pub fn next_chunk(&mut self) -> Option<Result<&[u8], io::Error>> {
+        if self.done {
+            return None;
+        }
+        self.buffer.clear();
         match self
             .f
             .by_ref()
             .take(self.chunk_len as u64)
-            .read_to_end(&mut buffer)
+            .read_to_end(&mut self.buffer)
         {
-            Ok(i) => {
-                if i == 0 {
-                    return None;
-                } else {
-                    Some(Ok(buffer))
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(_) => Some(Ok(&self.buffer)),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+    /// Gives back the internal buffer for reuse.
+    pub fn into_buffer(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Yields an error at most once: reading a directory, for example, fails on the first chunk
+/// and then ends.
+///
+/// # Examples
+/// ```
+/// # #[cfg(unix)]
+/// # fn main() -> std::io::Result<()> {
+/// use ddh::utils::ChunkIter;
+/// use std::fs::File;
+///
+/// let mut chunks = ChunkIter::new(File::open(std::env::temp_dir())?, 16);
+/// assert!(chunks.next().unwrap().is_err());
+/// assert!(chunks.next().is_none());
+/// # Ok(())
+/// # }
+/// # #[cfg(not(unix))]
+/// # fn main() {}
+/// ```
+impl Iterator for ChunkIter {
+    type Item = Result<Vec<u8>, io::Error>;
+    fn next(&mut self) -> Option<Result<Vec<u8>, io::Error>> {
+        self.next_chunk().map(|chunk| chunk.map(|c| c.to_vec()))
+    }
+}
+
+/// Compares the contents of two files byte by byte. Files are read in windows of
+/// `COMPARE_WINDOW` bytes, so memory use stays the same however large the files are, and the
+/// comparison stops at the first window which differs.
+///
+/// # Examples
+/// ```
+/// use std::fs;
+///
+/// fn main() -> std::io::Result<()> {
+/// let dir = std::env::temp_dir();
+/// let (a, b) = (dir.join("ddh_compare_a"), dir.join("ddh_compare_b"));
+/// let mut contents = vec![7u8; 8192];
+/// fs::write(&a, &contents)?;
+/// fs::write(&b, &contents)?;
+/// assert!(ddh::utils::contents_equal(&a, &b)?);
+/// contents[8191] = b'x';
+/// fs::write(&b, &contents)?;
+/// assert!(!ddh::utils::contents_equal(&a, &b)?);
+/// fs::remove_file(&a)?;
+/// fs::remove_file(&b)?;
+/// Ok(())
+/// }
+/// ```
+pub fn contents_equal(a: &Path, b: &Path) -> io::Result<bool> {
+    compare_files(a, b, None).map_err(|failed| match failed {
+        Unreadable::First(e) | Unreadable::Second(e) => e,
+    })
+}
+
+/// Which of the two files of a comparison couldn't be opened or read.
+#[derive(Debug)]
+pub(crate) enum Unreadable {
+    First(io::Error),
+    Second(io::Error),
+}
+
+/// Compares files like `contents_equal` while holding a permit from `limiter`, telling which
+/// file failed on errors.
+pub(crate) fn compare_files(
+    a: &Path,
+    b: &Path,
+    limiter: Option<&ReadLimiter>,
+) -> Result<bool, Unreadable> {
+    let file_a = File::open(a).map_err(Unreadable::First)?;
+    let file_b = File::open(b).map_err(Unreadable::Second)?;
+    let length_a = file_a.metadata().map_err(Unreadable::First)?.len();
+    if length_a != file_b.metadata().map_err(Unreadable::Second)?.len() {
+        return Ok(false);
+    }
+    let _permit = limiter.map(|l| l.acquire());
+    let mut chunks_a = ChunkIter::new(file_a, COMPARE_WINDOW);
+    let mut chunks_b = ChunkIter::new(file_b, COMPARE_WINDOW);
+    loop {
+        match (chunks_a.next_chunk(), chunks_b.next_chunk()) {
+            (None, None) => return Ok(true),
+            (Some(Err(e)), _) => return Err(Unreadable::First(e)),
+            (_, Some(Err(e))) => return Err(Unreadable::Second(e)),
+            (Some(Ok(window_a)), Some(Ok(window_b))) => {
+                if window_a != window_b {
+                    return Ok(false);
                 }
             }
-            Err(e) => Some(Err(e)),
+            /* One of the files changed length while it was being read. */
+            _ => return Ok(false),
         }
     }
 }
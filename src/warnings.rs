@@ -0,0 +1,69 @@
+//! Findings of a scan which are worth a look but, unlike errors, leave its results intact.
+//!
+//! Scans given a sender in `ScanOptions::warnings` send a `Warning` when they skip a special
+//! file such as a socket, FIFO or device, and when a file changes while it is being hashed, in
+//! which case its hash may match neither its old nor its new contents.
+
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::path::PathBuf;
+
+/// What a scan noticed about a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// The file is neither a regular file, a directory nor a symlink and was skipped.
+    SpecialFile,
+    /// The size or modification time of the file changed while it was hashed.
+    ChangedDuringScan,
+}
+
+/// Finding about the file at `path`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Warning {
+    pub path: PathBuf,
+    pub kind: WarningKind,
+}
+
+impl WarningKind {
+    /// Gets the name machine readable output gives the warning, such as `special_file`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningKind::SpecialFile => "special_file",
+            WarningKind::ChangedDuringScan => "changed_during_scan",
+        }
+    }
+}
+
+impl Serialize for WarningKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WarningKind::SpecialFile => write!(f, "special file skipped"),
+            WarningKind::ChangedDuringScan => write!(f, "changed while it was scanned"),
+        }
+    }
+}
+
+/// Writes the path and what was noticed about it.
+///
+/// # Examples
+/// ```
+/// use ddh::warnings::{Warning, WarningKind};
+/// use std::path::PathBuf;
+///
+/// let warning = Warning {
+///     path: PathBuf::from("/srv/share/app.sock"),
+///     kind: WarningKind::SpecialFile,
+/// };
+/// assert_eq!(warning.to_string(), "/srv/share/app.sock: special file skipped");
+/// ```
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)
+    }
+}
@@ -0,0 +1,204 @@
+//! Where duplicated space is located.
+//!
+//! Every group keeps one instance: the first clean-named path in sorted order, as chosen by
+//! `CopyPatterns::prefer_originals`. The length of each other instance counts as waste in the
+//! directory holding it.
+
+use crate::copies::CopyPatterns;
+use crate::fileinfo::Fileinfo;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Gets the instances of `group` which count as waste, in sorted order.
+pub fn wasted_instances<'a>(group: &'a Fileinfo, patterns: &CopyPatterns) -> Vec<&'a Path> {
+    let mut paths: Vec<&Path> = group.get_paths().iter().map(|path| &**path).collect();
+    paths.sort();
+    let kept = patterns
+        .prefer_originals(&paths)
+        .first()
+        .map(|path| path.to_path_buf());
+    let mut wasted: Vec<&Path> = paths
+        .into_iter()
+        .filter(|path| Some(*path) != kept.as_deref())
+        .collect();
+    wasted.sort();
+    wasted
+}
+
+/// Sums wasted bytes by the directory directly holding each wasted instance.
+///
+/// # Examples
+/// ```
+/// use ddh::copies::CopyPatterns;
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::{Path, PathBuf};
+///
+/// let files = vec![
+///     Fileinfo::from_existing_hash(1, None, 100, PathBuf::from("/photos/a.jpg")),
+///     Fileinfo::from_existing_hash(1, None, 100, PathBuf::from("/backup/a.jpg")),
+///     Fileinfo::from_existing_hash(1, None, 100, PathBuf::from("/backup/old/a.jpg")),
+/// ];
+/// let groups = ddh::consolidate(files);
+/// let groups: Vec<&Fileinfo> = groups.iter().collect();
+/// let waste = ddh::waste::waste_by_directory(&groups, &CopyPatterns::default());
+/// assert_eq!(waste[Path::new("/photos")], 100);
+/// assert_eq!(waste[Path::new("/backup/old")], 100);
+/// assert!(!waste.contains_key(Path::new("/backup")));
+/// ```
+pub fn waste_by_directory(groups: &[&Fileinfo], patterns: &CopyPatterns) -> BTreeMap<PathBuf, u64> {
+    let mut waste: BTreeMap<PathBuf, u64> = BTreeMap::new();
+    for group in groups.iter() {
+        for path in wasted_instances(group, patterns) {
+            let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+            *waste.entry(dir).or_default() += group.get_length();
+        }
+    }
+    waste
+}
+
+/// Adds the wasted bytes of each directory of `waste` to every directory above it, up to the
+/// deepest directory holding them all, so that each directory counts the waste of its whole tree.
+///
+/// # Examples
+/// ```
+/// use std::collections::BTreeMap;
+/// use std::path::{Path, PathBuf};
+///
+/// let mut waste = BTreeMap::new();
+/// waste.insert(PathBuf::from("/home/downloads"), 120u64);
+/// waste.insert(PathBuf::from("/home/photos/2019"), 40);
+/// waste.insert(PathBuf::from("/home/photos/2019/raw"), 10);
+/// let trees = ddh::waste::rolled_up(&waste);
+/// assert_eq!(trees[Path::new("/home")], 170);
+/// assert_eq!(trees[Path::new("/home/photos")], 50);
+/// assert_eq!(trees[Path::new("/home/photos/2019")], 50);
+/// assert!(!trees.contains_key(Path::new("/")));
+/// ```
+pub fn rolled_up(waste: &BTreeMap<PathBuf, u64>) -> BTreeMap<PathBuf, u64> {
+    let mut common = match waste.keys().next() {
+        Some(dir) => dir.clone(),
+        None => return BTreeMap::new(),
+    };
+    for dir in waste.keys() {
+        while !dir.starts_with(&common) {
+            if !common.pop() {
+                break;
+            }
+        }
+    }
+    let mut trees: BTreeMap<PathBuf, u64> = BTreeMap::new();
+    for (dir, bytes) in waste.iter() {
+        for ancestor in dir
+            .ancestors()
+            .take_while(|ancestor| ancestor.starts_with(&common))
+        {
+            *trees.entry(ancestor.to_path_buf()).or_default() += bytes;
+        }
+    }
+    trees
+}
+
+/// Formats wasted bytes per directory as folded stacks (`dir;sub;leaf bytes`), the input format
+/// of flamegraph and most treemap tools. Lines are sorted so output is reproducible.
+///
+/// # Examples
+/// ```
+/// use std::collections::BTreeMap;
+/// use std::path::PathBuf;
+///
+/// let mut waste = BTreeMap::new();
+/// waste.insert(PathBuf::from("/backup/old"), 100u64);
+/// assert_eq!(ddh::waste::folded(&waste), ["/;backup;old 100"]);
+/// ```
+pub fn folded(waste: &BTreeMap<PathBuf, u64>) -> Vec<String> {
+    waste
+        .iter()
+        .map(|(dir, bytes)| {
+            let stack: Vec<String> = dir
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().replace(';', "_"))
+                .collect();
+            format!("{} {}", stack.join(";"), bytes)
+        })
+        .collect()
+}
+
+/// Counts the bytes taken up by all instances but one of each group.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::PathBuf;
+///
+/// let groups = ddh::consolidate(vec![
+///     Fileinfo::from_existing_hash(1, None, 100, PathBuf::from("/a/x")),
+///     Fileinfo::from_existing_hash(1, None, 100, PathBuf::from("/b/x")),
+///     Fileinfo::from_existing_hash(2, None, 50, PathBuf::from("/c/y")),
+/// ]);
+/// assert_eq!(ddh::waste::duplicate_bytes(groups.iter()), 100);
+/// assert_eq!(ddh::waste::total_bytes(groups.iter()), 250);
+/// ```
+pub fn duplicate_bytes<'a>(groups: impl IntoIterator<Item = &'a Fileinfo>) -> u64 {
+    groups
+        .into_iter()
+        .map(|group| group.get_length() * (group.get_paths().len() as u64).saturating_sub(1))
+        .sum()
+}
+
+/// Counts the bytes taken up by every instance of every group.
+pub fn total_bytes<'a>(groups: impl IntoIterator<Item = &'a Fileinfo>) -> u64 {
+    groups
+        .into_iter()
+        .map(|group| group.get_length() * group.get_paths().len() as u64)
+        .sum()
+}
+
+/// Instance counts grouped by `copies_histogram`, as the smallest count of each bucket.
+const COPY_BUCKETS: &[usize] = &[2, 3, 4, 5, 10, 100];
+
+/// Counts duplicate groups by their number of instances, in buckets of 2, 3, 4, 5 to 9, 10 to
+/// 99 and 100 or more, along with the bytes wasted by the groups of each bucket. Mass
+/// duplication of a few files shows up in the upper buckets, pairwise copies of many files in
+/// the first one. Buckets without groups are left out.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use std::path::PathBuf;
+///
+/// let mut files = Vec::new();
+/// for i in 0..12 {
+///     files.push(Fileinfo::from_existing_hash(1, None, 10, PathBuf::from(format!("/a/{}", i))));
+/// }
+/// files.push(Fileinfo::from_existing_hash(2, None, 50, PathBuf::from("/b/x")));
+/// files.push(Fileinfo::from_existing_hash(2, None, 50, PathBuf::from("/c/x")));
+/// let groups = ddh::consolidate(files);
+/// let histogram = ddh::waste::copies_histogram(groups.iter());
+/// assert_eq!(histogram, [("2".to_string(), 1, 50), ("10-99".to_string(), 1, 110)]);
+/// ```
+pub fn copies_histogram<'a>(
+    groups: impl IntoIterator<Item = &'a Fileinfo>,
+) -> Vec<(String, u64, u64)> {
+    let mut buckets = vec![(0u64, 0u64); COPY_BUCKETS.len()];
+    for group in groups.into_iter() {
+        let copies = group.get_paths().len();
+        if let Some(bucket) = COPY_BUCKETS.iter().rposition(|&start| copies >= start) {
+            buckets[bucket].0 += 1;
+            buckets[bucket].1 += duplicate_bytes(std::iter::once(group));
+        }
+    }
+    buckets
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (groups, _))| *groups > 0)
+        .map(|(i, (groups, wasted))| {
+            let start = COPY_BUCKETS[i];
+            let label = match COPY_BUCKETS.get(i + 1) {
+                Some(&next) if next == start + 1 => start.to_string(),
+                Some(&next) => format!("{}-{}", start, next - 1),
+                None => format!("{}+", start),
+            };
+            (label, groups, wasted)
+        })
+        .collect()
+}
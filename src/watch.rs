@@ -0,0 +1,219 @@
+//! Rescanning changed files into the hash cache as filesystem events come in.
+//!
+//! Busy directories such as build outputs change the same files many times a second. Events
+//! are collected in a `RescanQueue`, which merges events for the same path and only releases a
+//! path once it has been quiet for a while, in batches of bounded size. Released paths are
+//! rehashed by `rescan_into_cache`, which skips files the cache already knows in their current
+//! state, so the next scan finds every hash ready.
+//!
+//! ddh doesn't subscribe to filesystem events itself; callers feed the queue from their
+//! watcher of choice, as `ddh watch` does with the paths written to its stdin.
+
+use crate::cache::HashCache;
+use crate::fileinfo::{Fileinfo, HashMode};
+use crate::hasher::SipHash128;
+use crate::throttle::ReadLimiter;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Paths waiting to be rescanned after a filesystem event.
+#[derive(Debug)]
+pub struct RescanQueue {
+    quiet: Duration,
+    max_batch: usize,
+    /// Time of the latest event for each pending path.
+    pending: HashMap<PathBuf, Instant>,
+    events: u64,
+    coalesced: u64,
+    released: u64,
+}
+
+/// State of a `RescanQueue`, for monitoring how far rescans lag behind the events.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BacklogMetrics {
+    /// Events received.
+    pub events: u64,
+    /// Events merged into an event already pending for the same path.
+    pub coalesced: u64,
+    /// Paths released for rescanning.
+    pub released: u64,
+    /// Paths waiting to be released.
+    pub pending: usize,
+    /// Time the longest waiting path has been quiet for.
+    pub oldest: Option<Duration>,
+}
+
+/// Outcome of `rescan_into_cache`.
+#[derive(Debug, Default)]
+pub struct RescanReport {
+    /// Files hashed and stored in the cache.
+    pub hashed: usize,
+    /// Files whose hashes the cache already held.
+    pub unchanged: usize,
+    /// Paths which no longer hold a regular file.
+    pub gone: usize,
+    /// Paths which couldn't be read.
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+impl RescanQueue {
+    /// Creates a queue releasing paths once no event came in for them during `quiet`, at most
+    /// `max_batch` (at least one) at a time.
+    ///
+    /// # Examples
+    /// ```
+    /// use ddh::watch::RescanQueue;
+    /// use std::path::PathBuf;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let start = Instant::now();
+    /// let at = |ms| start + Duration::from_millis(ms);
+    /// let mut queue = RescanQueue::new(Duration::from_millis(500), 2);
+    /// queue.push(PathBuf::from("/build/a.o"), at(0));
+    /// queue.push(PathBuf::from("/build/b.o"), at(100));
+    /// queue.push(PathBuf::from("/build/a.o"), at(400));
+    /// queue.push(PathBuf::from("/build/c.o"), at(450));
+    ///
+    /// assert_eq!(queue.ready(at(700)), vec![PathBuf::from("/build/b.o")]);
+    /// assert_eq!(queue.next_due(), Some(at(900)));
+    /// assert_eq!(queue.ready(at(1000)).len(), 2);
+    /// let metrics = queue.metrics(at(1000));
+    /// assert_eq!((metrics.events, metrics.coalesced, metrics.pending), (4, 1, 0));
+    /// ```
+    pub fn new(quiet: Duration, max_batch: usize) -> Self {
+        RescanQueue {
+            quiet,
+            max_batch: max_batch.max(1),
+            pending: HashMap::new(),
+            events: 0,
+            coalesced: 0,
+            released: 0,
+        }
+    }
+
+    /// Records an event for `path` which happened at `at`, postponing its rescan.
+    pub fn push(&mut self, path: PathBuf, at: Instant) {
+        self.events += 1;
+        if self.pending.insert(path, at).is_some() {
+            self.coalesced += 1;
+        }
+    }
+
+    /// Takes the paths which have been quiet long enough at `now`, the longest waiting first.
+    pub fn ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut quiet: Vec<(Instant, PathBuf)> = self
+            .pending
+            .iter()
+            .filter(|(_, &last)| now.saturating_duration_since(last) >= self.quiet)
+            .map(|(path, &last)| (last, path.clone()))
+            .collect();
+        quiet.sort();
+        quiet.truncate(self.max_batch);
+        for (_, path) in quiet.iter() {
+            self.pending.remove(path);
+        }
+        self.released += quiet.len() as u64;
+        quiet.into_iter().map(|(_, path)| path).collect()
+    }
+
+    /// Gets the time at which the next path becomes ready, if any is pending.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.values().min().map(|&last| last + self.quiet)
+    }
+
+    /// Reports the state of the queue at `now`.
+    pub fn metrics(&self, now: Instant) -> BacklogMetrics {
+        BacklogMetrics {
+            events: self.events,
+            coalesced: self.coalesced,
+            released: self.released,
+            pending: self.pending.len(),
+            oldest: self
+                .pending
+                .values()
+                .min()
+                .map(|&last| now.saturating_duration_since(last)),
+        }
+    }
+}
+
+/// Hashes the files at `paths` like a scan would and stores their hashes in `cache`, reading
+/// at most as many files at once as `limiter` allows. Files whose hashes the cache already
+/// holds are skipped. Call `HashCache::persist` afterwards to write the changes to disk.
+///
+/// Entries of removed or replaced files stay in the cache until it is pruned, but they are
+/// never used since their inode or modification time no longer matches.
+///
+/// # Examples
+/// ```
+/// use ddh::cache::HashCache;
+/// use ddh::watch::rescan_into_cache;
+///
+/// fn main() -> std::io::Result<()> {
+/// let dir = std::env::temp_dir().join("ddh_rescan_example");
+/// std::fs::create_dir_all(&dir)?;
+/// let (file, removed) = (dir.join("out.bin"), dir.join("removed.bin"));
+/// std::fs::write(&file, vec![7; 10_000])?;
+/// let _ = std::fs::remove_file(&removed);
+///
+/// let cache = HashCache::in_memory();
+/// let report = rescan_into_cache(&cache, &[file.clone(), removed], None);
+/// assert_eq!((report.hashed, report.gone), (1, 1));
+/// let report = rescan_into_cache(&cache, &[file], None);
+/// assert_eq!((report.hashed, report.unchanged), (0, 1));
+/// std::fs::remove_dir_all(&dir)?;
+/// Ok(())
+/// }
+/// ```
+pub fn rescan_into_cache(
+    cache: &HashCache,
+    paths: &[PathBuf],
+    limiter: Option<&ReadLimiter>,
+) -> RescanReport {
+    let report = Mutex::new(RescanReport::default());
+    paths.par_iter().for_each(|path| {
+        let meta = match fs::metadata(path) {
+            Ok(meta) if meta.is_file() => meta,
+            Ok(_) => {
+                report.lock().unwrap().gone += 1;
+                return;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                report.lock().unwrap().gone += 1;
+                return;
+            }
+            Err(e) => {
+                report.lock().unwrap().errors.push((path.clone(), e));
+                return;
+            }
+        };
+        let length = meta.len();
+        let mut file = Fileinfo::new(None, None, meta, path.clone());
+        if let Some((Some(_), Some(_))) = cache.lookup(&file) {
+            report.lock().unwrap().unchanged += 1;
+            return;
+        }
+        /* Same hashes as a scan: files of one block have their partial hash as full hash. */
+        let partial = file.generate_hash_limited(HashMode::Partial, &SipHash128, limiter);
+        let full = if length <= 4096 {
+            partial
+        } else {
+            file.generate_hash_limited(HashMode::Full, &SipHash128, limiter)
+        };
+        if partial.is_none() || full.is_none() {
+            let e = io::Error::other("could not read file");
+            report.lock().unwrap().errors.push((path.clone(), e));
+            return;
+        }
+        file.set_partial_hash(partial);
+        file.set_full_hash(full);
+        cache.insert(&file);
+        report.lock().unwrap().hashed += 1;
+    });
+    report.into_inner().unwrap()
+}
@@ -0,0 +1,240 @@
+//! Hashes stored in extended attributes of the files themselves.
+//!
+//! `tag_files` writes the full hash of each file to `user.ddh.hash`, as 32 hex digits of its
+//! SipHash-1-3 hash, and the time of the scan to `user.ddh.scanned`, in seconds since the
+//! epoch. Other tools can read them from there. Scans with `ScanOptions::xattr_hashes` read
+//! them instead of hashing files again, provided the file wasn't modified since the scan which
+//! wrote them and belongs to the user running the scan. `strip_tree` removes both attributes
+//! again.
+//!
+//! The owner of a file can write any attributes to it, so the attributes of files owned by
+//! others are never trusted. A forged hash would otherwise group unrelated files, which
+//! `--delete` and `--link` act on.
+//!
+//! Extended attributes are supported on Linux and macOS, on filesystems which allow them for
+//! the user namespace. Copies made with tools preserving attributes carry them along, which is
+//! harmless as long as their modification times are preserved as well or updated.
+
+use crate::fileinfo::{FileMetadata, Fileinfo};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Attribute holding the full hash of a file.
+pub const HASH_ATTRIBUTE: &str = "user.ddh.hash";
+/// Attribute holding the time of the scan which hashed a file.
+pub const SCANNED_ATTRIBUTE: &str = "user.ddh.scanned";
+
+/// Writes the full hashes of `files` to the attributes of each of their paths, with `scanned`
+/// as the time of the scan, which should be when it started. Files without a full hash are
+/// left alone. Returns the paths which couldn't be tagged.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::SystemTime;
+///
+/// let started = SystemTime::now();
+/// let (files, _) = ddh::deduplicate_dirs(vec!["/home/jon"], vec![], 0);
+/// for (path, e) in ddh::xattrs::tag_files(&files, started) {
+///     eprintln!("Not tagged {}: {}", path.display(), e);
+/// }
+/// ```
+pub fn tag_files(files: &[Fileinfo], scanned: SystemTime) -> Vec<(PathBuf, io::Error)> {
+    let scanned = scanned
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+        .to_string();
+    let mut errors = Vec::new();
+    for file in files.iter() {
+        let hash = match file.get_full_hash() {
+            Some(hash) => format!("{:032x}", hash),
+            None => continue,
+        };
+        for path in file.get_paths().iter() {
+            let tagged = sys::set(path, SCANNED_ATTRIBUTE, scanned.as_bytes())
+                .and_then(|_| sys::set(path, HASH_ATTRIBUTE, hash.as_bytes()));
+            if let Err(e) = tagged {
+                errors.push((path.to_path_buf(), e));
+            }
+        }
+    }
+    errors
+}
+
+/// Reads the hash stored in the attributes of the file at `path`, whose current metadata is
+/// `metadata`. `None` without attributes, if the file was modified in or after the second the
+/// scan which wrote them started, or if the effective user doesn't own it.
+///
+/// # Examples
+/// ```
+/// use ddh::fileinfo::Fileinfo;
+/// use std::time::{Duration, SystemTime};
+///
+/// let path = std::env::temp_dir().join("ddh_xattrs_example");
+/// std::fs::write(&path, "tagged").unwrap();
+/// let file = Fileinfo::from_existing_hash(0xab, None, 6, path.clone());
+/// let later = SystemTime::now() + Duration::from_secs(2);
+/// if ddh::xattrs::tag_files(&[file], later).is_empty() {
+///     let metadata = ddh::fileinfo::FileMetadata::from(&std::fs::metadata(&path).unwrap());
+///     assert_eq!(ddh::xattrs::read_hash(&path, &metadata), Some(0xab));
+///     assert_eq!(ddh::xattrs::strip(&path).unwrap(), true);
+///     assert_eq!(ddh::xattrs::read_hash(&path, &metadata), None);
+/// }
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn read_hash(path: &Path, metadata: &FileMetadata) -> Option<u128> {
+    if metadata.owner.is_none() || metadata.owner != sys::euid() {
+        return None;
+    }
+    let read = |name| -> Option<String> {
+        let value = sys::get(path, name).ok()??;
+        String::from_utf8(value).ok()
+    };
+    let scanned: i64 = read(SCANNED_ATTRIBUTE)?.parse().ok()?;
+    if metadata.mtime? >= scanned {
+        return None;
+    }
+    u128::from_str_radix(&read(HASH_ATTRIBUTE)?, 16).ok()
+}
+
+/// Removes the attributes of the file at `path`, telling whether it had any.
+pub fn strip(path: &Path) -> io::Result<bool> {
+    let hash = sys::remove(path, HASH_ATTRIBUTE)?;
+    let scanned = sys::remove(path, SCANNED_ATTRIBUTE)?;
+    Ok(hash || scanned)
+}
+
+/// Removes the attributes of every file below `root`, without following symlinks. Returns the
+/// number of files which had any and the paths which couldn't be stripped or read.
+pub fn strip_tree(root: &Path) -> (u64, Vec<(PathBuf, io::Error)>) {
+    let mut stripped = 0;
+    let mut errors = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_dir() => match fs::read_dir(&path) {
+                Ok(entries) => {
+                    for entry in entries {
+                        match entry {
+                            Ok(entry) => pending.push(entry.path()),
+                            Err(e) => errors.push((path.clone(), e)),
+                        }
+                    }
+                }
+                Err(e) => errors.push((path, e)),
+            },
+            Ok(meta) if meta.is_file() => match strip(&path) {
+                Ok(true) => stripped += 1,
+                Ok(false) => {}
+                Err(e) => errors.push((path, e)),
+            },
+            Ok(_) => {}
+            Err(e) => errors.push((path, e)),
+        }
+    }
+    (stripped, errors)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[cfg(target_os = "linux")]
+    const MISSING: libc::c_int = libc::ENODATA;
+    #[cfg(target_os = "macos")]
+    const MISSING: libc::c_int = libc::ENOATTR;
+
+    fn c_strings(path: &Path, name: &str) -> io::Result<(CString, CString)> {
+        Ok((
+            CString::new(path.as_os_str().as_bytes())?,
+            CString::new(name)?,
+        ))
+    }
+
+    fn missing(e: &io::Error) -> bool {
+        e.raw_os_error() == Some(MISSING)
+    }
+
+    pub(super) fn euid() -> Option<u32> {
+        Some(unsafe { libc::geteuid() })
+    }
+
+    /// Reads attribute `name`, `None` if the file doesn't have it.
+    pub(super) fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let (path, name) = c_strings(path, name)?;
+        /* The attributes of ddh are short, so one call with a generous buffer reads them. */
+        let mut value = vec![0u8; 256];
+        let (data, size) = (value.as_mut_ptr() as *mut libc::c_void, value.len());
+        #[cfg(target_os = "linux")]
+        let read = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), data, size) };
+        #[cfg(target_os = "macos")]
+        let read = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), data, size, 0, 0) };
+        if read < 0 {
+            let e = io::Error::last_os_error();
+            return if missing(&e) { Ok(None) } else { Err(e) };
+        }
+        value.truncate(read as usize);
+        Ok(Some(value))
+    }
+
+    pub(super) fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let (path, name) = c_strings(path, name)?;
+        let (data, size) = (value.as_ptr() as *const libc::c_void, value.len());
+        #[cfg(target_os = "linux")]
+        let set = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), data, size, 0) };
+        #[cfg(target_os = "macos")]
+        let set = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), data, size, 0, 0) };
+        match set {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes attribute `name`, telling whether the file had it.
+    pub(super) fn remove(path: &Path, name: &str) -> io::Result<bool> {
+        let (path, name) = c_strings(path, name)?;
+        #[cfg(target_os = "linux")]
+        let removed = unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) };
+        #[cfg(target_os = "macos")]
+        let removed = unsafe { libc::removexattr(path.as_ptr(), name.as_ptr(), 0) };
+        if removed == -1 {
+            let e = io::Error::last_os_error();
+            return if missing(&e) { Ok(false) } else { Err(e) };
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod sys {
+    use std::io;
+    use std::path::Path;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "extended attributes are not supported on this platform",
+        )
+    }
+
+    pub(super) fn euid() -> Option<u32> {
+        None
+    }
+
+    pub(super) fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    pub(super) fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn remove(_path: &Path, _name: &str) -> io::Result<bool> {
+        Ok(false)
+    }
+}
@@ -0,0 +1,56 @@
+//! Sharing a hash cache between users. Handing files to another user takes root, so these
+//! tests do nothing for other users.
+#![cfg(unix)]
+
+use ddh::cache::HashCache;
+use std::fs;
+use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+use std::path::PathBuf;
+
+const OTHER_UID: u32 = 4242;
+
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Creates a cache in a directory of group 0 and hands it to another user, with the setgid
+/// bit of the directory set or not.
+fn cache_of_other_user(name: &str, setgid: bool) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ddh_cache_{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    chown(&dir, None, Some(0)).unwrap();
+    let mode = if setgid { 0o2770 } else { 0o770 };
+    fs::set_permissions(&dir, fs::Permissions::from_mode(mode)).unwrap();
+    let path = dir.join("hashes.json");
+    HashCache::open(&path).unwrap().persist().unwrap();
+    chown(&path, Some(OTHER_UID), None).unwrap();
+    path
+}
+
+#[test]
+fn opens_cache_of_group_member() {
+    if !is_root() {
+        return;
+    }
+    let path = cache_of_other_user("shared", true);
+    let meta = fs::metadata(&path).unwrap();
+    assert_eq!((meta.mode() & 0o777, meta.gid()), (0o660, 0));
+    let opened = HashCache::open(&path);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    assert!(opened.is_ok(), "{:?}", opened.err());
+}
+
+#[test]
+fn refuses_cache_of_other_user_outside_shared_directory() {
+    if !is_root() {
+        return;
+    }
+    let path = cache_of_other_user("unshared", false);
+    let opened = HashCache::open(&path);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    assert_eq!(
+        opened.err().map(|e| e.kind()),
+        Some(std::io::ErrorKind::PermissionDenied)
+    );
+}
@@ -0,0 +1,46 @@
+//! Keep policies prefer the instance with the clean name over copies of it in its directory.
+
+use ddh::actions::KeepPolicy;
+use ddh::copies::CopyPatterns;
+use ddh::fileinfo::Fileinfo;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn never_keeps_copy_over_original() {
+    let dir = std::env::temp_dir().join("ddh_keep_policy_copies");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let paths: Vec<_> = ["a (1).jpg", "a - Copy.jpg", "a.jpg"]
+        .iter()
+        .map(|name| dir.join(name))
+        .collect();
+    for path in paths.iter() {
+        fs::write(path, "same").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_modified(modified))
+            .unwrap();
+    }
+    let group = ddh::consolidate(
+        paths
+            .iter()
+            .map(|path| Fileinfo::from_existing_hash(1, None, 4, path.clone()))
+            .collect(),
+    )
+    .remove(0);
+    let roots = [dir.clone()];
+    for policy in [
+        KeepPolicy::Newest,
+        KeepPolicy::Oldest,
+        KeepPolicy::FirstArgDir,
+        KeepPolicy::ShortestPath,
+    ] {
+        let kept = policy.choose(&group, &roots, &CopyPatterns::default());
+        assert_eq!(kept, Some(dir.join("a.jpg").as_path()), "{}", policy);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,37 @@
+//! The memory held for each file of a scan, as reported with `-v all`.
+
+use ddh::fileinfo::Fileinfo;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn reports_bytes_per_entry() {
+    let root = std::env::temp_dir().join("ddh_memory_per_entry");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let root = root.canonicalize().unwrap();
+    let paths = [root.join("a"), root.join("bb")];
+    fs::write(&paths[0], "1").unwrap();
+    fs::write(&paths[1], "22").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ddh"))
+        .arg("-d")
+        .arg(&root)
+        .args(["-o", "no", "--no-progress", "--no-cache", "-v", "all"])
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&root).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let reported = stdout
+        .lines()
+        .find(|line| line.contains("entries held in memory"))
+        .unwrap();
+    /* Each entry holds a single path without spare capacity, behind a pointer and a length. */
+    let names: usize = paths.iter().map(|path| path.as_os_str().len()).sum();
+    let per_entry =
+        std::mem::size_of::<Fileinfo>() + std::mem::size_of::<Box<Path>>() + names / paths.len();
+    assert_eq!(
+        reported,
+        format!("2 entries held in memory, {} bytes per entry", per_entry)
+    );
+}
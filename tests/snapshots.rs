@@ -0,0 +1,104 @@
+//! Snapshots of the machine readable output formats, which scripts rely on not changing.
+//!
+//! Each test scans a small tree of its own with `--deterministic`, so results come out in a
+//! fixed order and without timings, and replaces the location of the tree with `[ROOT]`.
+//! Review changed snapshots with `cargo insta review`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Creates a tree holding a group of three copies, one of two, an empty pair and a unique file.
+fn sample_tree(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("ddh_snapshot_{}", name));
+    let _ = fs::remove_dir_all(&root);
+    for dir in ["photos", "backup/photos", "docs"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for path in [
+        "photos/beach.jpg",
+        "backup/photos/beach.jpg",
+        "docs/beach copy.jpg",
+    ] {
+        fs::write(root.join(path), "not really a photo of a beach").unwrap();
+    }
+    for path in ["docs/report.txt", "backup/report, final.txt"] {
+        fs::write(root.join(path), "quarterly numbers").unwrap();
+    }
+    for path in ["docs/empty", "backup/empty"] {
+        fs::write(root.join(path), "").unwrap();
+    }
+    fs::write(root.join("docs/notes.txt"), "only one of these").unwrap();
+    root.canonicalize().unwrap()
+}
+
+/// Scans `root` with `args` and returns what ddh printed on stdout.
+fn ddh(root: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_ddh"))
+        .arg("-d")
+        .arg(root)
+        .args(["-o", "no", "--no-progress", "--no-cache", "--deterministic"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    fs::remove_dir_all(root).unwrap();
+    stdout.replace(&root.display().to_string(), "[ROOT]")
+}
+
+#[test]
+fn json() {
+    let root = sample_tree("json");
+    insta::assert_snapshot!(ddh(&root, &["-f", "json", "-v", "all"]));
+}
+
+#[test]
+fn json_lines() {
+    let root = sample_tree("json_lines");
+    insta::assert_snapshot!(ddh(&root, &["-f", "jsonl", "-v", "duplicates"]));
+}
+
+#[test]
+fn csv() {
+    let root = sample_tree("csv");
+    insta::assert_snapshot!(ddh(&root, &["-f", "csv", "-v", "duplicates"]));
+}
+
+#[test]
+fn fdupes() {
+    let root = sample_tree("fdupes");
+    insta::assert_snapshot!(ddh(&root, &["-f", "fdupes", "-v", "duplicates"]));
+}
+
+#[test]
+fn edges() {
+    let root = sample_tree("edges");
+    insta::assert_snapshot!(ddh(&root, &["-f", "edges", "-v", "duplicates"]));
+}
+
+#[cfg(feature = "dev-tools")]
+#[test]
+fn fixture_tree() {
+    use ddh::fixtures::{generate, stable_report, FixtureSpec};
+
+    let root = std::env::temp_dir().join("ddh_snapshot_fixture_tree");
+    let _ = fs::remove_dir_all(&root);
+    let spec = FixtureSpec {
+        seed: 7,
+        symlinks: false,
+        ..Default::default()
+    };
+    generate(&root, &spec).unwrap();
+    let options = ddh::ScanOptions {
+        deterministic: true,
+        ..Default::default()
+    };
+    let report = stable_report(&root, &options).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+    insta::assert_snapshot!(report);
+}